@@ -0,0 +1,249 @@
+use crate::{
+    settings::{Action, Actions, ClientSettings},
+    PlayerInfo,
+};
+use agarlib::*;
+use bevy::prelude::*;
+use std::collections::HashMap;
+
+// the sounds are synthesized at startup, so neither build ships audio files
+const SAMPLE_RATE: u32 = 22050;
+const VOLUME_STEP: f32 = 0.1;
+// seconds; the ambient clip is queued again this often, so muting stops it within a loop
+const AMBIENT_LENGTH: f64 = 4.0;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum Sound {
+    // the player's cell grew
+    Pop,
+    // the player's cell was eaten
+    Sting,
+    Ambient,
+}
+
+fn gain(settings: &ClientSettings) -> f32 {
+    if settings.muted {
+        0.0
+    } else {
+        settings.volume
+    }
+}
+
+fn envelope(t: f32, attack: f32, decay: f32) -> f32 {
+    (t / attack).min(1.0) * (-t / decay).exp()
+}
+
+fn samples(sound: Sound) -> Vec<f32> {
+    let tau = std::f32::consts::PI * 2.0;
+    let (length, wave): (f32, Box<dyn Fn(f32) -> f32>) = match sound {
+        // a short downward chirp
+        Sound::Pop => (
+            0.08,
+            Box::new(move |t| {
+                let pitch = 900.0 - 5000.0 * t;
+                (tau * pitch * t).sin() * envelope(t, 0.005, 0.03)
+            }),
+        ),
+        // two falling tones
+        Sound::Sting => (
+            0.7,
+            Box::new(move |t| {
+                let pitch = if t < 0.25 { 440.0 } else { 220.0 };
+                (tau * pitch * t).sin() * envelope(t % 0.25, 0.01, 0.2) * 0.8
+            }),
+        ),
+        // a quiet chord that fades at both ends so the clips join up
+        Sound::Ambient => (
+            AMBIENT_LENGTH as f32,
+            Box::new(move |t| {
+                let fade = (t / 0.5).min(1.0).min((AMBIENT_LENGTH as f32 - t) / 0.5);
+                let chord: f32 = [110.0, 165.0, 220.0]
+                    .iter()
+                    .map(|pitch| (tau * pitch * t).sin())
+                    .sum();
+                chord * fade * 0.05
+            }),
+        ),
+    };
+
+    let count = (length * SAMPLE_RATE as f32) as usize;
+    (0..count)
+        .map(|i| wave(i as f32 / SAMPLE_RATE as f32))
+        .collect()
+}
+
+// 16 bit mono pcm in a wav container
+fn wav(samples: &[f32], gain: f32) -> Vec<u8> {
+    let data_len = samples.len() as u32 * 2;
+    let mut bytes = Vec::with_capacity(44 + data_len as usize);
+    bytes.extend_from_slice(b"RIFF");
+    bytes.extend_from_slice(&(36 + data_len).to_le_bytes());
+    bytes.extend_from_slice(b"WAVEfmt ");
+    bytes.extend_from_slice(&16u32.to_le_bytes());
+    bytes.extend_from_slice(&1u16.to_le_bytes());
+    bytes.extend_from_slice(&1u16.to_le_bytes());
+    bytes.extend_from_slice(&SAMPLE_RATE.to_le_bytes());
+    bytes.extend_from_slice(&(SAMPLE_RATE * 2).to_le_bytes());
+    bytes.extend_from_slice(&2u16.to_le_bytes());
+    bytes.extend_from_slice(&16u16.to_le_bytes());
+    bytes.extend_from_slice(b"data");
+    bytes.extend_from_slice(&data_len.to_le_bytes());
+    for sample in samples {
+        let sample = (sample * gain).max(-1.0).min(1.0);
+        bytes.extend_from_slice(&((sample * i16::MAX as f32) as i16).to_le_bytes());
+    }
+    bytes
+}
+
+// sounds waiting for `play_system`
+#[derive(Default)]
+pub struct SoundQueue(Vec<Sound>);
+
+// turns what the network code sees into sounds, and the keys into settings
+pub fn audio_system(
+    time: Res<Time>,
+    actions: Res<Actions>,
+    mut settings: ResMut<ClientSettings>,
+    mut queue: ResMut<SoundQueue>,
+    mut last_size: Local<Option<f32>>,
+    mut was_dead: Local<bool>,
+    mut next_ambient: Local<f64>,
+    player: Res<PlayerInfo>,
+    agars: Query<(&Agar, &UpdateContext)>,
+) {
+    let (volume, muted) = (settings.volume, settings.muted);
+    if actions.just_pressed(Action::Mute) {
+        settings.muted = !settings.muted;
+    }
+    if actions.just_pressed(Action::VolumeDown) {
+        settings.volume = (settings.volume - VOLUME_STEP).max(0.0);
+    }
+    if actions.just_pressed(Action::VolumeUp) {
+        settings.volume = (settings.volume + VOLUME_STEP).min(1.0);
+    }
+    if (settings.volume, settings.muted) != (volume, muted) {
+        info!(
+            "Volume {:.0}%{}",
+            settings.volume * 100.0,
+            if settings.muted { " (muted)" } else { "" }
+        );
+        settings.save();
+    }
+
+    let size = player.id.and_then(|id| {
+        agars
+            .iter()
+            .find(|(_, context)| context.id == id)
+            .map(|(agar, _)| agar.size)
+    });
+    if let (Some(size), Some(last)) = (size, *last_size) {
+        if size > last {
+            queue.0.push(Sound::Pop);
+        }
+    }
+    *last_size = size;
+
+    let dead = player.death.is_some();
+    if dead && !*was_dead {
+        queue.0.push(Sound::Sting);
+    }
+    *was_dead = dead;
+
+    let now = time.seconds_since_startup();
+    if player.owner.is_some() && now >= *next_ambient {
+        *next_ambient = now + AMBIENT_LENGTH;
+        queue.0.push(Sound::Ambient);
+    }
+}
+
+// the synthesized clips at the current gain; rebuilt when the settings change
+#[cfg(not(target_arch = "wasm32"))]
+#[derive(Default)]
+pub struct SoundBank {
+    gain: Option<f32>,
+    clips: HashMap<Sound, Handle<bevy::audio::AudioSource>>,
+}
+
+#[cfg(not(target_arch = "wasm32"))]
+pub fn play_system(
+    settings: Res<ClientSettings>,
+    mut queue: ResMut<SoundQueue>,
+    mut bank: ResMut<SoundBank>,
+    audio: Res<Audio>,
+    mut sources: ResMut<Assets<bevy::audio::AudioSource>>,
+) {
+    let gain = gain(&settings);
+    let sounds: Vec<_> = queue.0.drain(..).collect();
+    if gain <= 0.0 {
+        return;
+    }
+    if bank.gain != Some(gain) {
+        bank.gain = Some(gain);
+        for (_, clip) in bank.clips.drain() {
+            sources.remove(clip);
+        }
+    }
+
+    for sound in sounds {
+        let clip = bank.clips.entry(sound).or_insert_with(|| {
+            sources.add(bevy::audio::AudioSource {
+                bytes: wav(&samples(sound), gain).into(),
+            })
+        });
+        audio.play(clip.clone());
+    }
+}
+
+// the browser plays the clips from object urls through audio elements
+#[cfg(target_arch = "wasm32")]
+#[derive(Default)]
+pub struct SoundBank {
+    gain: Option<f32>,
+    clips: HashMap<Sound, String>,
+}
+
+#[cfg(target_arch = "wasm32")]
+fn object_url(bytes: &[u8]) -> Option<String> {
+    let parts = js_sys::Array::new();
+    parts.push(&js_sys::Uint8Array::from(bytes));
+    let mut options = web_sys::BlobPropertyBag::new();
+    options.type_("audio/wav");
+    let blob = web_sys::Blob::new_with_u8_array_sequence_and_options(&parts, &options).ok()?;
+    web_sys::Url::create_object_url_with_blob(&blob).ok()
+}
+
+#[cfg(target_arch = "wasm32")]
+pub fn play_system(
+    settings: Res<ClientSettings>,
+    mut queue: ResMut<SoundQueue>,
+    mut bank: ResMut<SoundBank>,
+) {
+    let gain = gain(&settings);
+    let sounds: Vec<_> = queue.0.drain(..).collect();
+    if gain <= 0.0 {
+        return;
+    }
+    if bank.gain != Some(gain) {
+        bank.gain = Some(gain);
+        for (_, url) in bank.clips.drain() {
+            let _ = web_sys::Url::revoke_object_url(&url);
+        }
+    }
+
+    for sound in sounds {
+        let url = match bank.clips.get(&sound) {
+            Some(url) => url.clone(),
+            None => match object_url(&wav(&samples(sound), gain)) {
+                Some(url) => bank.clips.entry(sound).or_insert(url).clone(),
+                None => {
+                    warn!("Unable to prepare {:?}", sound);
+                    continue;
+                }
+            },
+        };
+        // browsers refuse to play before the first click or key press; nothing to do then
+        if let Ok(element) = web_sys::HtmlAudioElement::new_with_src(&url) {
+            let _ = element.play();
+        }
+    }
+}
@@ -0,0 +1,255 @@
+use crate::{
+    menu::{AppState, UiFont},
+    pause::PauseMenu,
+    settings::{Action, ClientSettings},
+    PlayerInfo,
+};
+use agarlib::*;
+use bevy::{prelude::*, ui::FocusPolicy};
+use bevy_networking_turbulence::NetworkResource;
+
+// the server cuts longer lines anyway; see `chat_max_len`
+const MAX_CHAT_LEN: usize = 200;
+// lines kept in the log; older ones go
+const MAX_LINES: usize = 8;
+// seconds a line stays fully visible, then fades out over `FADE_TIME`
+const SHOW_TIME: f64 = 8.0;
+const FADE_TIME: f64 = 2.0;
+
+const ANNOUNCEMENT_COLOR: Color = Color::rgb(1.0, 0.85, 0.3);
+const OWN_COLOR: Color = Color::rgb(0.6, 0.85, 1.0);
+const TEAM_COLOR: Color = Color::rgb(0.55, 0.95, 0.55);
+
+// a `ChatLine` as it arrived, spawned by the network or the replay code for `chat_system`
+// to put in the log
+pub struct ChatReceived {
+    pub from: Option<EntityId>,
+    pub name: String,
+    pub text: String,
+    pub team: bool,
+}
+
+// the line being typed; while it's open the rest of the game doesn't see the keyboard
+#[derive(Default)]
+pub struct ChatInput {
+    pub open: bool,
+    // the line goes to the player's team only
+    team: bool,
+    text: String,
+}
+
+struct ChatLog;
+struct ChatField;
+struct ChatEntry {
+    at: f64,
+    color: Color,
+}
+
+pub fn chat_setup(
+    commands: &mut Commands,
+    mut input: ResMut<ChatInput>,
+    font: Res<UiFont>,
+    mut materials: ResMut<Assets<ColorMaterial>>,
+) {
+    *input = ChatInput::default();
+
+    let font = font.0.clone();
+    let transparent = materials.add(Color::rgba(0.0, 0.0, 0.0, 0.0).into());
+
+    // drawn by the ui camera, above the overlay; clicks pass through to what's under it,
+    // e.g. the death screen's buttons
+    commands
+        .spawn(NodeBundle {
+            style: Style {
+                position_type: PositionType::Absolute,
+                position: Rect {
+                    left: Val::Px(10.0),
+                    bottom: Val::Px(140.0),
+                    ..Default::default()
+                },
+                flex_direction: FlexDirection::ColumnReverse,
+                ..Default::default()
+            },
+            material: transparent.clone(),
+            ..Default::default()
+        })
+        .with(FocusPolicy::Pass)
+        .with_children(|chat| {
+            chat.spawn(NodeBundle {
+                style: Style {
+                    flex_direction: FlexDirection::ColumnReverse,
+                    ..Default::default()
+                },
+                material: transparent,
+                ..Default::default()
+            })
+            .with(FocusPolicy::Pass)
+            .with(ChatLog);
+            chat.spawn(TextBundle {
+                text: Text {
+                    value: String::new(),
+                    font,
+                    style: TextStyle {
+                        font_size: 18.0,
+                        color: Color::WHITE,
+                        ..Default::default()
+                    },
+                },
+                style: Style {
+                    display: Display::None,
+                    ..Default::default()
+                },
+                ..Default::default()
+            })
+            .with(ChatField);
+        });
+}
+
+// runs before the game's systems: `Action::Chat` (or `Action::TeamChat`) opens the field,
+// Enter sends the line, Escape drops it, and meanwhile the keys are taken away from everyone
+// else
+pub fn chat_input_system(
+    state: Res<State<AppState>>,
+    settings: Res<ClientSettings>,
+    mut input: ResMut<ChatInput>,
+    mut keys: ResMut<Input<KeyCode>>,
+    buttons: Res<Input<MouseButton>>,
+    mut reader: Local<EventReader<ReceivedCharacter>>,
+    characters: Res<Events<ReceivedCharacter>>,
+    mut net: ResMut<NetworkResource>,
+    formats: Res<WireFormats>,
+    mut stats: ResMut<NetworkStats>,
+    pause: Res<PauseMenu>,
+) {
+    // only players talk; the menu has fields of its own
+    if *state.current() != AppState::Playing {
+        reader.iter(&characters).for_each(drop);
+        input.open = false;
+        return;
+    }
+
+    let enter = keys.just_pressed(KeyCode::Return);
+    if !input.open {
+        // whatever was typed before opening isn't for the chat
+        reader.iter(&characters).for_each(drop);
+        let chat = settings.just_pressed(&keys, &buttons, Action::Chat);
+        let team = settings.just_pressed(&keys, &buttons, Action::TeamChat);
+        if (chat || team) && !pause.open {
+            input.open = true;
+            input.team = team && !chat;
+            input.text.clear();
+            *keys = Input::default();
+        }
+        return;
+    }
+
+    for event in reader.iter(&characters) {
+        if !event.char.is_control() && input.text.chars().count() < MAX_CHAT_LEN {
+            input.text.push(event.char);
+        }
+    }
+    if keys.just_pressed(KeyCode::Back) {
+        input.text.pop();
+    }
+    if enter {
+        let text = input.text.trim().to_string();
+        if !text.is_empty() {
+            let message = if input.team {
+                ClientMessage::TeamChat(text)
+            } else {
+                ClientMessage::Chat(text)
+            };
+            broadcast_wire(&mut net, &formats, &message);
+            stats.sent(&message);
+        }
+    }
+    if enter || keys.just_pressed(KeyCode::Escape) {
+        input.open = false;
+        input.text.clear();
+    }
+    *keys = Input::default();
+}
+
+// moves new lines into the log and fades them out; an open field brings them all back
+pub fn chat_system(
+    commands: &mut Commands,
+    time: Res<Time>,
+    input: Res<ChatInput>,
+    player: Res<PlayerInfo>,
+    font: Res<UiFont>,
+    received: Query<(Entity, &ChatReceived)>,
+    logs: Query<(Entity, Option<&Children>), With<ChatLog>>,
+    mut entries: Query<(&ChatEntry, &mut Text), Without<ChatField>>,
+    mut fields: Query<(&mut Text, &mut Style), With<ChatField>>,
+) {
+    let now = time.seconds_since_startup();
+    let (log, children) = match logs.iter().next() {
+        Some((log, children)) => (log, children.map_or(vec![], |children| children.to_vec())),
+        None => return,
+    };
+
+    let font = font.0.clone();
+    let mut added = vec![];
+    for (entity, line) in received.iter() {
+        commands.despawn(entity);
+        let (value, color) = match line.from {
+            None => (format!("[server] {}", line.text), ANNOUNCEMENT_COLOR),
+            Some(_) if line.team => (format!("[team] {}: {}", line.name, line.text), TEAM_COLOR),
+            Some(from) if Some(from) == player.id => {
+                (format!("{}: {}", line.name, line.text), OWN_COLOR)
+            }
+            Some(_) => (format!("{}: {}", line.name, line.text), Color::WHITE),
+        };
+        let entry = commands
+            .spawn(TextBundle {
+                text: Text {
+                    value,
+                    font: font.clone(),
+                    style: TextStyle {
+                        font_size: 18.0,
+                        color,
+                        ..Default::default()
+                    },
+                },
+                ..Default::default()
+            })
+            .with(ChatEntry { at: now, color })
+            .current_entity()
+            .unwrap();
+        added.push(entry);
+    }
+    if !added.is_empty() {
+        commands.push_children(log, &added);
+        let excess = (children.len() + added.len()).saturating_sub(MAX_LINES);
+        for &old in children.iter().take(excess) {
+            commands.despawn_recursive(old);
+        }
+    }
+
+    for (entry, mut text) in entries.iter_mut() {
+        let age = now - entry.at;
+        let alpha = if input.open {
+            1.0
+        } else {
+            (1.0 - (age - SHOW_TIME) / FADE_TIME).max(0.0).min(1.0) as f32
+        };
+        text.style.color = entry.color;
+        text.style.color.set_a(alpha);
+    }
+
+    for (mut text, mut style) in fields.iter_mut() {
+        style.display = if input.open {
+            Display::Flex
+        } else {
+            Display::None
+        };
+        let value = if input.team {
+            format!("[team] > {}_", input.text)
+        } else {
+            format!("> {}_", input.text)
+        };
+        if input.open && text.value != value {
+            text.value = value;
+        }
+    }
+}
@@ -0,0 +1,132 @@
+use agarlib::{parse_identity, IdentityKey};
+
+#[derive(Debug, Clone, Default)]
+pub struct ClientArgs {
+    pub server: Option<String>,
+    pub name: String,
+    pub password: Option<String>,
+    pub region: Option<String>,
+    // the server's identity, from its `/status`; its key exchange has to be signed with it
+    pub server_key: Option<IdentityKey>,
+    // mark the biggest cells on the minimap
+    pub leaders: bool,
+    // steer with the keyboard; the menu can switch it too
+    pub keyboard: bool,
+    // below `SKIN_COUNT`; the saved skin otherwise
+    pub skin: Option<u8>,
+    // ask the server for fewer updates and no feeds; the saved setting otherwise
+    pub low_bandwidth: bool,
+    // a replay file to watch instead of connecting; desktop only
+    pub replay: Option<String>,
+}
+
+impl ClientArgs {
+    #[cfg(not(target_arch = "wasm32"))]
+    pub fn parse() -> Self {
+        use clap::{App, Arg};
+
+        let matches = App::new("agarcli")
+            .about("Agar game client")
+            .arg(
+                Arg::with_name("server")
+                    .long("server")
+                    .value_name("ADDR")
+                    .help("Server to connect to, as host[:port]"),
+            )
+            .arg(
+                Arg::with_name("name")
+                    .long("name")
+                    .value_name("NAME")
+                    .help("Player name"),
+            )
+            .arg(
+                Arg::with_name("password")
+                    .long("password")
+                    .value_name("PASSWORD")
+                    .help("Password of a private server"),
+            )
+            .arg(
+                Arg::with_name("region")
+                    .long("region")
+                    .value_name("REGION")
+                    .help("Region to play in, e.g. eu"),
+            )
+            .arg(
+                Arg::with_name("server-key")
+                    .long("server-key")
+                    .value_name("HEX")
+                    .validator(|key| {
+                        parse_identity(&key)
+                            .map(|_| ())
+                            .ok_or_else(|| "expected 64 hex digits".to_string())
+                    })
+                    .help("Identity the server has to prove, from its /status"),
+            )
+            .arg(
+                Arg::with_name("leaders")
+                    .long("leaders")
+                    .help("Show the biggest cells on the minimap"),
+            )
+            .arg(
+                Arg::with_name("keyboard")
+                    .long("keyboard")
+                    .help("Steer with WASD or the arrow keys instead of the mouse"),
+            )
+            .arg(
+                Arg::with_name("skin")
+                    .long("skin")
+                    .value_name("N")
+                    .help("Skin to wear, from 0 to 15"),
+            )
+            .arg(
+                Arg::with_name("low-bandwidth")
+                    .long("low-bandwidth")
+                    .help("Ask for fewer updates and draw feeds from rough counts"),
+            )
+            .arg(
+                Arg::with_name("replay")
+                    .long("replay")
+                    .value_name("FILE")
+                    .help("Watch a replay recorded by the server instead of playing"),
+            )
+            .get_matches();
+
+        Self {
+            server: matches.value_of("server").map(String::from),
+            name: matches.value_of("name").unwrap_or_default().to_string(),
+            password: matches.value_of("password").map(String::from),
+            region: matches.value_of("region").map(String::from),
+            server_key: matches.value_of("server-key").and_then(parse_identity),
+            leaders: matches.is_present("leaders"),
+            keyboard: matches.is_present("keyboard"),
+            skin: matches.value_of("skin").and_then(|skin| skin.parse().ok()),
+            low_bandwidth: matches.is_present("low-bandwidth"),
+            replay: matches.value_of("replay").map(String::from),
+        }
+    }
+
+    // the browser has no command line; take `?server=...&name=...&password=...&region=...&skin=...`
+    // `&serverkey=...` and the `leaders`, `keyboard` and `lowbandwidth` flags from the page url
+    #[cfg(target_arch = "wasm32")]
+    pub fn parse() -> Self {
+        let params = web_sys::window()
+            .and_then(|window| window.location().search().ok())
+            .and_then(|search| web_sys::UrlSearchParams::new_with_str(&search).ok());
+        let get = |key| params.as_ref().and_then(|params| params.get(key));
+        let has = |key| params.as_ref().map_or(false, |params| params.has(key));
+
+        Self {
+            server: get("server"),
+            name: get("name").unwrap_or_default(),
+            password: get("password"),
+            region: get("region"),
+            // a malformed key pins nothing any server can sign for, rather than nothing at all
+            server_key: get("serverkey").map(|key| parse_identity(&key).unwrap_or_default()),
+            leaders: has("leaders"),
+            keyboard: has("keyboard"),
+            skin: get("skin").and_then(|skin| skin.parse().ok()),
+            low_bandwidth: has("lowbandwidth"),
+            replay: None,
+        }
+    }
+}
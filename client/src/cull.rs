@@ -0,0 +1,49 @@
+use crate::predict::Predicted;
+use agarlib::*;
+use bevy::{
+    prelude::*,
+    render::{camera::Camera, render_graph::base::camera::CAMERA_2D},
+};
+
+// world units past the edge of the view that are still drawn, so cells coming into view
+// between two runs don't pop in
+const MARGIN: f32 = 100.0;
+
+// hides replicated cells and feeds well outside the view, and shows them again when they
+// come back; each hidden entity is a draw call saved
+pub fn cull_system(
+    windows: Res<Windows>,
+    cameras: Query<(&Camera, &Transform)>,
+    // eaten ones stay hidden until the server says otherwise
+    mut entities: Query<
+        (&Transform, &Sprite, &mut Visible),
+        (With<UpdateContext>, Without<Predicted>),
+    >,
+) {
+    let view = cameras
+        .iter()
+        .find(|(camera, _)| camera.name.as_deref() == Some(CAMERA_2D))
+        .map(|(_, transform)| (transform.translation.truncate(), transform.scale.x));
+    let (center, zoom) = match view {
+        Some(view) => view,
+        None => return,
+    };
+    let window = windows
+        .get_primary()
+        .map_or(Vec2::new(WINDOW_WIDTH, WINDOW_HEIGHT), |window| {
+            Vec2::new(window.width(), window.height())
+        });
+    let half = window * zoom / 2.0 + Vec2::new(MARGIN, MARGIN);
+
+    for (transform, sprite, mut visible) in entities.iter_mut() {
+        // the sprite's size bounds a cell's circle; feeds draw at a size of 1 and fit in
+        // the margin
+        let reach = half + sprite.size;
+        let offset = transform.translation.truncate() - center;
+        let shown = offset.x.abs() <= reach.x && offset.y.abs() <= reach.y;
+        // only a change is written, so the renderer doesn't see every entity as changed
+        if visible.is_visible != shown {
+            visible.is_visible = shown;
+        }
+    }
+}
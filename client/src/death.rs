@@ -0,0 +1,208 @@
+use crate::{
+    menu::UiFont,
+    settings::{Action, Actions},
+    PlayerInfo,
+};
+use agarlib::*;
+use bevy::{app::AppExit, prelude::*};
+use bevy_networking_turbulence::NetworkResource;
+
+pub const LIFE_STATE: &str = "life_state";
+
+// seconds before an unanswered respawn request is sent again
+const RESPAWN_RETRY: f64 = 2.0;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum Life {
+    Alive,
+    // eaten; the death screen is up until the player respawns
+    Dead,
+}
+
+// everything spawned for the death screen, despawned on respawn
+struct DeathRoot;
+// the stats and buttons; spectating removes them
+struct DeathPanel;
+struct DeathHint;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum DeathButton {
+    Respawn,
+    Spectate,
+    Quit,
+}
+
+// follows `PlayerInfo::death`, which the network code sets and clears
+pub fn life_system(player: Res<PlayerInfo>, mut state: ResMut<State<Life>>) {
+    let life = if player.death.is_some() {
+        Life::Dead
+    } else {
+        Life::Alive
+    };
+    if *state.current() != life && state.next() != Some(&life) {
+        if let Err(err) = state.set_next(life) {
+            warn!("Unable to switch to {:?}: {:?}", life, err);
+        }
+    }
+}
+
+pub fn death_setup(
+    commands: &mut Commands,
+    font: Res<UiFont>,
+    mut materials: ResMut<Assets<ColorMaterial>>,
+    player: Res<PlayerInfo>,
+    leaderboard: Res<Leaderboard>,
+) {
+    let death = match &player.death {
+        Some(death) => death,
+        None => return,
+    };
+    // the leaderboard only knows the top of the world
+    let eater = leaderboard
+        .entries
+        .iter()
+        .find(|entry| entry.id == death.eater)
+        .map_or("a bigger cell", |entry| entry.name.as_str());
+    let survived = death.survived.max(0.0) as u64;
+
+    let font = font.0.clone();
+    let text = |value: &str, font_size: f32| TextBundle {
+        text: Text {
+            value: value.to_string(),
+            font: font.clone(),
+            style: TextStyle {
+                font_size,
+                color: Color::WHITE,
+                ..Default::default()
+            },
+        },
+        style: Style {
+            margin: Rect::all(Val::Px(6.0)),
+            ..Default::default()
+        },
+        ..Default::default()
+    };
+    let transparent = materials.add(Color::rgba(0.0, 0.0, 0.0, 0.0).into());
+    let panel = materials.add(Color::rgba(0.0, 0.0, 0.0, 0.6).into());
+    let button = materials.add(Color::rgb(0.15, 0.15, 0.15).into());
+
+    // drawn by the hud's ui camera
+    commands
+        .spawn(NodeBundle {
+            style: Style {
+                size: Size::new(Val::Percent(100.0), Val::Percent(100.0)),
+                flex_direction: FlexDirection::ColumnReverse,
+                justify_content: JustifyContent::Center,
+                align_items: AlignItems::Center,
+                ..Default::default()
+            },
+            material: transparent.clone(),
+            ..Default::default()
+        })
+        .with(DeathRoot)
+        .with_children(|root| {
+            root.spawn(NodeBundle {
+                style: Style {
+                    flex_direction: FlexDirection::ColumnReverse,
+                    align_items: AlignItems::Center,
+                    padding: Rect::all(Val::Px(16.0)),
+                    ..Default::default()
+                },
+                material: panel,
+                ..Default::default()
+            })
+            .with(DeathPanel)
+            .with_children(|panel| {
+                panel.spawn(text(&format!("Eaten by {}", eater), 32.0));
+                panel.spawn(text(&format!("Final mass {:.0}", death.mass), 20.0));
+                panel.spawn(text(
+                    &format!("Survived {}m {:02}s", survived / 60, survived % 60),
+                    20.0,
+                ));
+                panel
+                    .spawn(NodeBundle {
+                        material: transparent.clone(),
+                        ..Default::default()
+                    })
+                    .with_children(|row| {
+                        let buttons = [
+                            (DeathButton::Respawn, "Respawn"),
+                            (DeathButton::Spectate, "Spectate"),
+                            (DeathButton::Quit, "Quit"),
+                        ];
+                        for &(kind, label) in buttons.iter() {
+                            row.spawn(ButtonBundle {
+                                style: Style {
+                                    margin: Rect::all(Val::Px(4.0)),
+                                    ..Default::default()
+                                },
+                                material: button.clone(),
+                                ..Default::default()
+                            })
+                            .with(kind)
+                            .with_children(|button| {
+                                button.spawn(text(label, 24.0));
+                            });
+                        }
+                    });
+            });
+            root.spawn(text("", 20.0)).with(DeathHint);
+        });
+}
+
+pub fn death_system(
+    commands: &mut Commands,
+    mut requested: Local<Option<f64>>,
+    time: Res<Time>,
+    mut player: ResMut<PlayerInfo>,
+    mut net: ResMut<NetworkResource>,
+    formats: Res<WireFormats>,
+    mut stats: ResMut<NetworkStats>,
+    actions: Res<Actions>,
+    pads: Res<Input<GamepadButton>>,
+    mut exit: ResMut<Events<AppExit>>,
+    clicks: Query<(&Interaction, &DeathButton), Mutated<Interaction>>,
+    panels: Query<Entity, With<DeathPanel>>,
+    mut hints: Query<&mut Text, With<DeathHint>>,
+) {
+    // Enter, unless rebound, opens the chat
+    let mut respawn = player.spectating && actions.just_pressed(Action::Respawn);
+    // no focus to move around here, so the south button just respawns
+    respawn |= pads
+        .get_just_pressed()
+        .any(|GamepadButton(_, button)| *button == GamepadButtonType::South);
+
+    for (interaction, button) in clicks.iter() {
+        if *interaction != Interaction::Clicked {
+            continue;
+        }
+        match button {
+            DeathButton::Respawn => respawn = true,
+            DeathButton::Spectate => {
+                player.spectating = true;
+                for panel in panels.iter() {
+                    commands.despawn_recursive(panel);
+                }
+                for mut hint in hints.iter_mut() {
+                    hint.value = "Spectating; drag or WASD to pan, wheel to zoom, Space for the next player, R to respawn".to_string();
+                }
+            }
+            DeathButton::Quit => exit.send(AppExit),
+        }
+    }
+
+    let now = time.seconds_since_startup();
+    if respawn && requested.map_or(true, |at| now - at > RESPAWN_RETRY) {
+        info!("Asking to respawn");
+        *requested = Some(now);
+        let message = ClientMessage::Respawn;
+        broadcast_wire(&mut net, &formats, &message);
+        stats.sent(&message);
+    }
+}
+
+pub fn death_cleanup(commands: &mut Commands, roots: Query<Entity, With<DeathRoot>>) {
+    for entity in roots.iter() {
+        commands.despawn_recursive(entity);
+    }
+}
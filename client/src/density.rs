@@ -0,0 +1,88 @@
+use agarlib::*;
+use bevy::prelude::*;
+use std::collections::HashSet;
+
+// world units across a dot
+const DOT_SIZE: f32 = 8.0;
+const DOT_COLOR: Color = Color::rgb(0.7, 0.7, 0.7);
+
+// stands in for the `index`th feed `FeedDensity` counts in grid cell `cell`
+struct DensityDot {
+    cell: usize,
+    index: u8,
+}
+
+// where in its cell a dot goes, as a share of the cell; the same for the same dot, so dots
+// stay put while their cell's count does
+fn scatter(cell: usize, index: u8) -> Vec2 {
+    // splitmix64
+    let mut z = ((cell as u64) << 8 | index as u64).wrapping_add(0x9e37_79b9_7f4a_7c15);
+    z = (z ^ (z >> 30)).wrapping_mul(0xbf58_476d_1ce4_e5b9);
+    z = (z ^ (z >> 27)).wrapping_mul(0x94d0_49bb_1331_11eb);
+    z ^= z >> 31;
+    Vec2::new(
+        (z & 0xffff) as f32 / 65536.0,
+        (z >> 16 & 0xffff) as f32 / 65536.0,
+    )
+}
+
+// low-bandwidth players get no feeds, only how many there are where; as many plain dots as
+// each part of the world holds are drawn instead
+pub fn density_system(
+    commands: &mut Commands,
+    density: Res<FeedDensity>,
+    rules: Res<GameRules>,
+    mut drawn: Local<(FeedDensity, Vec2)>,
+    mut material: Local<Option<Handle<ColorMaterial>>>,
+    mut materials: ResMut<Assets<ColorMaterial>>,
+    dots: Query<(Entity, &DensityDot)>,
+) {
+    let world = Vec2::new(rules.world_width, rules.world_height);
+    if drawn.0 == *density && drawn.1 == world {
+        return;
+    }
+    // dots of another grid or world are all in the wrong place
+    let moved = (drawn.0.columns, drawn.0.rows, drawn.1) != (density.columns, density.rows, world);
+    *drawn = (density.clone(), world);
+
+    let mut kept = HashSet::new();
+    for (entity, dot) in dots.iter() {
+        let count = density.counts.get(dot.cell).copied().unwrap_or(0);
+        if !moved && dot.index < count {
+            kept.insert((dot.cell, dot.index));
+        } else {
+            commands.despawn(entity);
+        }
+    }
+
+    let material = material
+        .get_or_insert_with(|| materials.add(DOT_COLOR.into()))
+        .clone();
+    let cell_size = density.cell_size(&rules);
+    for (cell, &count) in density.counts.iter().enumerate() {
+        let origin = density.cell_origin(cell, &rules);
+        for index in (0..count).filter(|&index| !kept.contains(&(cell, index))) {
+            let at = origin + scatter(cell, index) * cell_size;
+            commands
+                .spawn(SpriteBundle {
+                    sprite: Sprite::new(Vec2::new(DOT_SIZE, DOT_SIZE)),
+                    material: material.clone(),
+                    transform: Transform::from_translation(at.extend(0.0)),
+                    ..Default::default()
+                })
+                .with(DensityDot { cell, index });
+        }
+    }
+}
+
+// the dots go with the game; the next game starts without a density
+pub fn density_cleanup(
+    commands: &mut Commands,
+    mut density: ResMut<FeedDensity>,
+    dots: Query<Entity, With<DensityDot>>,
+) {
+    *density = FeedDensity::default();
+    for entity in dots.iter() {
+        commands.despawn(entity);
+    }
+}
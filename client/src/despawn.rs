@@ -0,0 +1,53 @@
+use agarlib::*;
+use bevy::prelude::*;
+
+// seconds a removed cell or pellet takes to shrink and fade away
+const FADE_TIME: f32 = 0.2;
+
+// a cell or pellet the server removed, on its way out
+pub struct Despawning(pub Timer);
+
+// takes `entity` out of the replicated world, so a reused id gets a new entity and nothing
+// counts it anymore, and leaves it to `despawn_system`
+pub fn fade_out(commands: &mut Commands, entity: Entity) {
+    commands.remove_one::<UpdateContext>(entity);
+    commands.insert_one(entity, Despawning(Timer::from_seconds(FADE_TIME, false)));
+}
+
+// shrinks and fades what's despawning, then despawns it
+pub fn despawn_system(
+    commands: &mut Commands,
+    time: Res<Time>,
+    mut materials: ResMut<Assets<ColorMaterial>>,
+    added: Query<Entity, Added<Despawning>>,
+    mut despawning: Query<(
+        Entity,
+        &mut Despawning,
+        &mut Transform,
+        &mut Handle<ColorMaterial>,
+    )>,
+) {
+    for (entity, mut despawning, mut transform, mut material) in despawning.iter_mut() {
+        // a copy of its own, so the fade leaves the material shared with others alone
+        if added.get(entity).is_ok() {
+            let copy = materials.get(&*material).map(|old| ColorMaterial {
+                color: old.color,
+                texture: old.texture.clone(),
+            });
+            if let Some(copy) = copy {
+                *material = materials.add(copy);
+            }
+        }
+
+        despawning.0.tick(time.delta_seconds());
+        if despawning.0.finished() {
+            commands.despawn(entity);
+            continue;
+        }
+        let left = 1.0 - despawning.0.percent();
+        transform.scale = Vec3::new(left, left, 1.0);
+        if let Some(material) = materials.get_mut(&*material) {
+            material.color.set_a(left);
+        }
+    }
+}
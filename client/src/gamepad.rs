@@ -0,0 +1,72 @@
+use crate::pause::PauseMenu;
+use agarlib::*;
+use bevy::prelude::*;
+use bevy_networking_turbulence::NetworkResource;
+use std::collections::HashSet;
+
+// stick tilts below this count as centered; worn sticks rarely rest at zero
+const STICK_DEADZONE: f32 = 0.15;
+// pixels the steering point has to move before it's worth another message
+const STICK_STEP: f32 = 2.0;
+
+// the left stick of any connected pad steers; split and eject have no buttons until the
+// game has them
+pub fn gamepad_system(
+    mut net: ResMut<NetworkResource>,
+    formats: Res<WireFormats>,
+    mut stats: ResMut<NetworkStats>,
+    mut pads: Local<HashSet<Gamepad>>,
+    mut last: Local<Option<Vec2>>,
+    mut reader: Local<EventReader<GamepadEvent>>,
+    events: Res<Events<GamepadEvent>>,
+    axes: Res<Axis<GamepadAxis>>,
+    pause: Res<PauseMenu>,
+) {
+    for GamepadEvent(pad, event) in reader.iter(&events) {
+        match event {
+            GamepadEventType::Connected => {
+                info!("Gamepad {:?} connected", pad);
+                pads.insert(*pad);
+            }
+            GamepadEventType::Disconnected => {
+                info!("Gamepad {:?} disconnected", pad);
+                pads.remove(pad);
+            }
+            _ => {}
+        }
+    }
+
+    if pause.open {
+        return;
+    }
+
+    let center = stick_to_input(Vec2::zero(), STICK_DEADZONE);
+    let position = pads
+        .iter()
+        .map(|&pad| {
+            let axis = |axis| axes.get(GamepadAxis(pad, axis)).unwrap_or(0.0);
+            let stick = Vec2::new(
+                axis(GamepadAxisType::LeftStickX),
+                axis(GamepadAxisType::LeftStickY),
+            );
+            stick_to_input(stick, STICK_DEADZONE)
+        })
+        .find(|&position| position != center);
+
+    // a released stick stops the cell once, then leaves the mouse in charge
+    let position = match (position, *last) {
+        (Some(position), Some(last)) if position.distance(last) < STICK_STEP => return,
+        (Some(position), _) => position,
+        (None, Some(_)) => center,
+        (None, None) => return,
+    };
+    *last = if position == center {
+        None
+    } else {
+        Some(position)
+    };
+
+    let message = ClientMessage::Input(position);
+    broadcast_wire(&mut net, &formats, &message);
+    stats.sent(&message);
+}
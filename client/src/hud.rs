@@ -0,0 +1,121 @@
+use crate::{menu::UiFont, record::Recorder, PlayerInfo};
+use agarlib::*;
+use bevy::prelude::*;
+
+// the in-game ui camera, which draws the minimap too, and the hud's column of text
+struct HudRoot;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum HudLine {
+    Mass,
+    Score,
+    Rank,
+    Players,
+    // empty unless a replay is being recorded
+    Recording,
+}
+
+const LINES: [HudLine; 5] = [
+    HudLine::Mass,
+    HudLine::Score,
+    HudLine::Rank,
+    HudLine::Players,
+    HudLine::Recording,
+];
+
+pub fn hud_setup(
+    commands: &mut Commands,
+    font: Res<UiFont>,
+    mut materials: ResMut<Assets<ColorMaterial>>,
+) {
+    let font = font.0.clone();
+    let transparent = materials.add(Color::rgba(0.0, 0.0, 0.0, 0.0).into());
+
+    commands
+        .spawn(CameraUiBundle::default())
+        .with(HudRoot)
+        .spawn(NodeBundle {
+            style: Style {
+                position_type: PositionType::Absolute,
+                position: Rect {
+                    left: Val::Px(10.0),
+                    top: Val::Px(10.0),
+                    ..Default::default()
+                },
+                flex_direction: FlexDirection::ColumnReverse,
+                ..Default::default()
+            },
+            material: transparent,
+            ..Default::default()
+        })
+        .with(HudRoot)
+        .with_children(|hud| {
+            for &line in LINES.iter() {
+                hud.spawn(TextBundle {
+                    text: Text {
+                        value: String::new(),
+                        font: font.clone(),
+                        style: TextStyle {
+                            font_size: 20.0,
+                            color: if line == HudLine::Recording {
+                                Color::RED
+                            } else {
+                                Color::WHITE
+                            },
+                            ..Default::default()
+                        },
+                    },
+                    ..Default::default()
+                })
+                .with(line);
+            }
+        });
+}
+
+pub fn hud_system(
+    time: Res<Time>,
+    recorder: Res<Recorder>,
+    mut player: ResMut<PlayerInfo>,
+    agars: Query<(&Agar, &UpdateContext)>,
+    mut lines: Query<(&HudLine, &mut Text)>,
+) {
+    // every player has one cell, and every cell in the player's world is replicated,
+    // far ones through the overview
+    let sizes: Vec<_> = agars.iter().map(|(agar, _)| agar.size).collect();
+    let own = player.id.and_then(|id| {
+        agars
+            .iter()
+            .find(|(_, context)| context.id == id)
+            .map(|(agar, _)| agar.size)
+    });
+    if let Some(size) = own {
+        player.best = player.best.max(size);
+    }
+
+    for (line, mut text) in lines.iter_mut() {
+        let value = match (*line, own) {
+            (HudLine::Mass, Some(size)) => format!("Mass {:.0}", size),
+            (HudLine::Mass, None) => match &player.death {
+                Some(death) => format!("Eaten at mass {:.0}", death.mass),
+                None => "Connecting".to_string(),
+            },
+            (HudLine::Score, _) => format!("Score {:.0}", player.best),
+            (HudLine::Rank, Some(size)) => {
+                let rank = sizes.iter().filter(|&&other| other > size).count() + 1;
+                format!("Rank {}/{}", rank, sizes.len())
+            }
+            (HudLine::Rank, None) => String::new(),
+            (HudLine::Players, _) => format!("Players {}", sizes.len()),
+            (HudLine::Recording, _) => match recorder.elapsed(time.seconds_since_startup()) {
+                Some(elapsed) => {
+                    let elapsed = elapsed as u64;
+                    format!("REC {}:{:02}", elapsed / 60, elapsed % 60)
+                }
+                None => String::new(),
+            },
+        };
+        if text.value != value {
+            text.value = value;
+        }
+    }
+}
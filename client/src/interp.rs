@@ -0,0 +1,223 @@
+use crate::{predict::Predictions, PlayerInfo};
+use agarlib::*;
+use bevy::prelude::*;
+use std::collections::VecDeque;
+
+// seconds the drawn world trails the server's clock, so a cell usually has a snapshot on
+// either side of it
+pub const INTERP_DELAY: f64 = 0.1;
+// how far past its newest snapshot a cell keeps going when snapshots are late, in seconds
+const MAX_EXTRAPOLATION: f64 = 0.25;
+// seconds the jump back onto the snapshots is eased over, once they come again
+const CORRECTION_TIME: f32 = 0.1;
+const MAX_SAMPLES: usize = 32;
+// seconds a change of size takes to show
+const SIZE_TWEEN: f32 = 0.2;
+
+// world units a second the agar's steering moves it at, before boosts, as the server's
+// `movement_system` has it; `None` for cells from the overview, which come without steering
+fn steering(agar: &Agar, rules: &GameRules) -> Option<Vec3> {
+    if agar.velocity == Vec2::zero() {
+        return None;
+    }
+    let velocity = input_to_velocity(
+        &agar.velocity,
+        agar.max_velocity,
+        camera_zoom(rules, agar.size),
+    );
+    Some(velocity).filter(|velocity| velocity.x.is_finite() && velocity.y.is_finite())
+}
+
+// where an agar's snapshots put it, oldest first
+pub struct Interpolation {
+    samples: VecDeque<(u32, Vec3)>,
+    // the steering of the newest snapshot
+    velocity: Option<Vec3>,
+    // the newest frame the drawn position has run past, and where it was drawn
+    ahead: Option<(u32, Vec3)>,
+    // what's left of the jump from a guess back onto the snapshots
+    correction: Vec3,
+}
+
+impl Interpolation {
+    pub fn new(frame: u32, translation: Vec3, agar: &Agar, rules: &GameRules) -> Self {
+        let mut samples = VecDeque::new();
+        samples.push_back((frame, translation));
+        Self {
+            samples,
+            velocity: steering(agar, rules),
+            ahead: None,
+            correction: Vec3::zero(),
+        }
+    }
+
+    pub fn push(&mut self, frame: u32, translation: Vec3, agar: &Agar, rules: &GameRules) {
+        if self
+            .samples
+            .back()
+            .map_or(false, |(last, _)| *last >= frame)
+        {
+            return;
+        }
+        self.velocity = steering(agar, rules);
+        self.samples.push_back((frame, translation));
+        if self.samples.len() > MAX_SAMPLES {
+            self.samples.pop_front();
+        }
+    }
+
+    pub fn latest(&self) -> Vec3 {
+        self.samples
+            .back()
+            .map_or(Vec3::zero(), |(_, translation)| *translation)
+    }
+
+    // world units a second between the two newest samples
+    pub fn speed(&self, tick_rate: f64) -> f32 {
+        let len = self.samples.len();
+        if len < 2 {
+            return 0.0;
+        }
+        let ((from, a), (to, b)) = (self.samples[len - 2], self.samples[len - 1]);
+        a.distance(b) * (tick_rate / (to - from) as f64) as f32
+    }
+
+    // the position at `frame` between the samples around it, and whether it's past the
+    // newest. past it, the agar carries on along the steering it was sent with (along the
+    // last two samples without one) for at most `max_ahead` frames, then stops
+    fn at(&mut self, frame: f64, max_ahead: f64, tick_rate: f64, map: &GameMap) -> (Vec3, bool) {
+        // the sample before `frame` is the oldest one still needed
+        while self.samples.len() > 2 && self.samples[1].0 as f64 <= frame {
+            self.samples.pop_front();
+        }
+
+        let len = self.samples.len();
+        let (to, b) = match self.samples.back() {
+            Some(newest) => *newest,
+            None => return (Vec3::zero(), false),
+        };
+        if frame > to as f64 {
+            let ahead = (frame - to as f64).min(max_ahead);
+            let position = match self.velocity {
+                Some(velocity) => b + velocity * map.boost(b) * (ahead / tick_rate) as f32,
+                None if len < 2 => b,
+                None => {
+                    let (from, a) = self.samples[len - 2];
+                    b + (b - a) * (ahead / (to - from) as f64) as f32
+                }
+            };
+            return (position, true);
+        }
+
+        let (from, a) = self.samples[0];
+        if frame <= from as f64 || len < 2 {
+            return (a, false);
+        }
+        let t = (frame - from as f64) / (to - from) as f64;
+        (a + (b - a) * t as f32, false)
+    }
+
+    // where to draw the agar for a position from `at`: a guess that snapshots catch up with
+    // is eased onto them over `CORRECTION_TIME` instead of jumping
+    fn settle(&mut self, position: Vec3, ahead: bool, delta: f32) -> Vec3 {
+        let newest = self.samples.back().map_or(0, |(newest, _)| *newest);
+        if let Some((past, drawn)) = self.ahead {
+            if past != newest {
+                self.correction = drawn - position;
+            }
+        }
+        self.correction *= (1.0 - delta / CORRECTION_TIME).max(0.0);
+
+        let drawn = position + self.correction;
+        self.ahead = if ahead { Some((newest, drawn)) } else { None };
+        drawn
+    }
+}
+
+// the radius an agar is drawn with, easing toward `Agar::size`; the agar itself keeps the
+// server's size for everything else
+pub struct SizeTween {
+    from: f32,
+    to: f32,
+    elapsed: f32,
+}
+
+impl SizeTween {
+    pub fn new(size: f32) -> Self {
+        Self {
+            from: size,
+            to: size,
+            elapsed: SIZE_TWEEN,
+        }
+    }
+
+    // eases out, so growth starts quickly and settles
+    fn radius(&self) -> f32 {
+        let t = (self.elapsed / SIZE_TWEEN).min(1.0);
+        self.from + (self.to - self.from) * (1.0 - (1.0 - t).powi(2))
+    }
+}
+
+// moves every agar to where it was `INTERP_DELAY` ago by the server's clock; when snapshots
+// stall, agars run on for up to `MAX_EXTRAPOLATION` rather than freezing and then jumping
+pub fn interpolation_system(
+    time: Res<Time>,
+    clock: Res<ClockSync>,
+    player: Res<PlayerInfo>,
+    map: Res<GameMap>,
+    rules: Res<GameRules>,
+    mut agars: Query<(&Agar, &mut Interpolation, &mut Transform)>,
+) {
+    let frame = clock
+        .server_time(time.seconds_since_startup())
+        .map(|now| clock.server_time_to_frame(now - INTERP_DELAY));
+    // nothing moves in a paused match
+    let max_ahead = if player.paused {
+        0.0
+    } else {
+        MAX_EXTRAPOLATION * clock.tick_rate
+    };
+    let delta = time.delta_seconds();
+
+    for (agar, mut interpolation, mut transform) in agars.iter_mut() {
+        transform.translation = match frame {
+            Some(frame) => {
+                let (position, ahead) = interpolation.at(frame, max_ahead, clock.tick_rate, &map);
+                // guesses stay out of walls and inside the world, like the server's moves
+                let position = if ahead {
+                    rules.clamp(map.push_out(rules.clamp(position), agar.size))
+                } else {
+                    position
+                };
+                interpolation.settle(position, ahead, delta)
+            }
+            // no clock yet, so snapshots are drawn as they come
+            None => interpolation.latest(),
+        };
+    }
+}
+
+// grows and shrinks the drawn agars toward their sizes over `SIZE_TWEEN`; a size that changes
+// again midway starts over from what's on screen. the player's cell includes the feeds it's
+// predicted to have eaten
+pub fn size_system(
+    time: Res<Time>,
+    player: Res<PlayerInfo>,
+    predictions: Res<Predictions>,
+    mut agars: Query<(&Agar, Option<&UpdateContext>, &mut SizeTween, &mut Sprite)>,
+) {
+    for (agar, context, mut tween, mut sprite) in agars.iter_mut() {
+        let size = match (context, player.id) {
+            (Some(context), Some(id)) if context.id == id => predictions.size(agar.size),
+            _ => agar.size,
+        };
+        if (size - tween.to).abs() > f32::EPSILON {
+            tween.from = tween.radius();
+            tween.to = size;
+            tween.elapsed = 0.0;
+        }
+        tween.elapsed += time.delta_seconds();
+        let radius = tween.radius();
+        sprite.size = Vec2::new(radius * 2.0, radius * 2.0);
+    }
+}
@@ -0,0 +1,128 @@
+use crate::{menu::UiFont, settings::ClientSettings};
+use agarlib::*;
+use bevy::{
+    prelude::*,
+    render::{camera::Camera, render_graph::base::camera::CAMERA_2D},
+};
+use std::collections::HashMap;
+
+// the label's height next to its cell's radius, and the smallest worth drawing on screen
+const LABEL_SCALE: f32 = 0.5;
+const MIN_FONT_SIZE: f32 = 8.0;
+// screen distances from the followed cell where labels start to fade and are gone, in
+// window widths
+const FADE_START: f32 = 0.25;
+const FADE_END: f32 = 0.6;
+// rough width of a glyph next to the font size, to center the text over the cell
+const GLYPH_WIDTH: f32 = 0.55;
+
+// names by cell, from `ClientMessage::Names`
+#[derive(Default)]
+pub struct Names(pub HashMap<EntityId, String>);
+
+impl Names {
+    pub fn receive(&mut self, baseline: bool, names: HashMap<EntityId, String>) {
+        if baseline {
+            self.0 = names;
+        } else {
+            self.0.extend(names);
+        }
+    }
+}
+
+struct NameLabel(EntityId);
+
+// a ui text per named cell, moved over it every frame; drawn by the hud's ui camera
+pub fn labels_system(
+    commands: &mut Commands,
+    font: Res<UiFont>,
+    names: Res<Names>,
+    settings: Res<ClientSettings>,
+    windows: Res<Windows>,
+    cameras: Query<(&Camera, &Transform)>,
+    agars: Query<(&Agar, &UpdateContext, &Transform)>,
+    mut labels: Query<(Entity, &NameLabel, &mut Style, &mut Text)>,
+) {
+    let view = cameras
+        .iter()
+        .find(|(camera, _)| camera.name.as_deref() == Some(CAMERA_2D))
+        .map(|(_, transform)| (transform.translation.truncate(), transform.scale.x));
+    let (center, zoom) = match view {
+        Some(view) => view,
+        None => return,
+    };
+    let window = windows
+        .get_primary()
+        .map_or(Vec2::new(WINDOW_WIDTH, WINDOW_HEIGHT), |window| {
+            Vec2::new(window.width(), window.height())
+        });
+    let (fade_start, fade_end) = (FADE_START * window.x, FADE_END * window.x);
+
+    let mut cells: HashMap<_, _> = agars
+        .iter()
+        .filter(|_| settings.labels)
+        .filter_map(|(agar, context, transform)| {
+            let name = names.0.get(&context.id)?;
+            Some((
+                context.id,
+                (name, agar.size, transform.translation.truncate()),
+            ))
+        })
+        .collect();
+
+    for (entity, label, mut style, mut text) in labels.iter_mut() {
+        let (name, size, position) = match cells.remove(&label.0) {
+            Some(cell) => cell,
+            None => {
+                commands.despawn(entity);
+                continue;
+            }
+        };
+
+        // the camera's scale is how much world a pixel shows
+        let offset = (position - center) / zoom;
+        let font_size = size * LABEL_SCALE / zoom;
+        let fade = 1.0 - ((offset.length() - fade_start) / (fade_end - fade_start)).max(0.0);
+        if font_size < MIN_FONT_SIZE || fade <= 0.0 {
+            style.display = Display::None;
+            continue;
+        }
+
+        let width = name.chars().count() as f32 * font_size * GLYPH_WIDTH;
+        style.display = Display::Flex;
+        style.position.left = Val::Px(window.x / 2.0 + offset.x - width / 2.0);
+        style.position.bottom = Val::Px(window.y / 2.0 + offset.y - font_size / 2.0);
+        text.style.font_size = font_size;
+        text.style.color.set_a(fade.min(1.0));
+        if text.value != *name {
+            text.value = name.clone();
+        }
+    }
+
+    // named cells that came into view; placed on the next frame
+    if cells.is_empty() {
+        return;
+    }
+    let font = font.0.clone();
+    for (id, (name, ..)) in cells {
+        commands
+            .spawn(TextBundle {
+                text: Text {
+                    value: name.clone(),
+                    font: font.clone(),
+                    style: TextStyle {
+                        font_size: MIN_FONT_SIZE,
+                        color: Color::WHITE,
+                        ..Default::default()
+                    },
+                },
+                style: Style {
+                    position_type: PositionType::Absolute,
+                    display: Display::None,
+                    ..Default::default()
+                },
+                ..Default::default()
+            })
+            .with(NameLabel(id));
+    }
+}
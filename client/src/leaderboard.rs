@@ -0,0 +1,132 @@
+use crate::{
+    menu::UiFont,
+    settings::{Action, Actions},
+    PlayerInfo,
+};
+use agarlib::*;
+use bevy::prelude::*;
+
+// rows in the panel, and in the full scoreboard toggled with Tab unless rebound
+const TOP_ROWS: usize = 10;
+const FULL_ROWS: usize = LEADERBOARD_LEN;
+
+const TEXT_COLOR: Color = Color::WHITE;
+const OWN_COLOR: Color = Color::rgb(1.0, 0.85, 0.2);
+
+struct LeaderboardRow(usize);
+// the player's own row when it isn't among the ones shown, else the player count
+struct LeaderboardFooter;
+
+fn row(rank: usize, entry: &LeaderboardEntry) -> String {
+    let name = if entry.name.is_empty() {
+        "(unnamed)"
+    } else {
+        &entry.name
+    };
+    format!("{:>2}. {:<16} {:>4.0}", rank + 1, name, entry.size)
+}
+
+pub fn leaderboard_setup(
+    commands: &mut Commands,
+    font: Res<UiFont>,
+    mut materials: ResMut<Assets<ColorMaterial>>,
+) {
+    let font = font.0.clone();
+    let text = |font_size: f32| TextBundle {
+        text: Text {
+            value: String::new(),
+            font: font.clone(),
+            style: TextStyle {
+                font_size,
+                color: TEXT_COLOR,
+                ..Default::default()
+            },
+        },
+        ..Default::default()
+    };
+    let panel = materials.add(Color::rgba(0.0, 0.0, 0.0, 0.4).into());
+
+    // drawn by the hud's ui camera
+    commands
+        .spawn(NodeBundle {
+            style: Style {
+                position_type: PositionType::Absolute,
+                position: Rect {
+                    right: Val::Px(10.0),
+                    top: Val::Px(10.0),
+                    ..Default::default()
+                },
+                flex_direction: FlexDirection::ColumnReverse,
+                padding: Rect::all(Val::Px(6.0)),
+                ..Default::default()
+            },
+            material: panel,
+            ..Default::default()
+        })
+        .with_children(|panel| {
+            let mut title = text(20.0);
+            title.text.value = "Leaderboard".to_string();
+            panel.spawn(title);
+            for rank in 0..FULL_ROWS {
+                panel.spawn(text(16.0)).with(LeaderboardRow(rank));
+            }
+            panel.spawn(text(16.0)).with(LeaderboardFooter);
+        });
+}
+
+pub fn leaderboard_system(
+    mut full: Local<bool>,
+    actions: Res<Actions>,
+    player: Res<PlayerInfo>,
+    leaderboard: Res<Leaderboard>,
+    mut lines: Query<(
+        &mut Text,
+        &mut Style,
+        Option<&LeaderboardRow>,
+        Option<&LeaderboardFooter>,
+    )>,
+) {
+    if actions.just_pressed(Action::Scoreboard) {
+        *full = !*full;
+    }
+    let shown = if *full { FULL_ROWS } else { TOP_ROWS };
+    let entries = &leaderboard.entries;
+    let own = entries.iter().position(|entry| Some(entry.id) == player.id);
+
+    for (mut text, mut style, rank, footer) in lines.iter_mut() {
+        let (value, color) = match (rank, footer) {
+            (Some(&LeaderboardRow(rank)), _) => match entries.get(rank) {
+                Some(entry) if rank < shown => (
+                    row(rank, entry),
+                    if own == Some(rank) {
+                        OWN_COLOR
+                    } else {
+                        TEXT_COLOR
+                    },
+                ),
+                _ => (String::new(), TEXT_COLOR),
+            },
+            (_, Some(_)) => match own {
+                Some(rank) if rank >= shown => (row(rank, &entries[rank]), OWN_COLOR),
+                _ => (format!("{} players", leaderboard.total), TEXT_COLOR),
+            },
+            _ => continue,
+        };
+
+        // empty rows take no room
+        let display = if value.is_empty() {
+            Display::None
+        } else {
+            Display::Flex
+        };
+        if style.display != display {
+            style.display = display;
+        }
+        if text.value != value {
+            text.value = value;
+        }
+        if text.style.color != color {
+            text.style.color = color;
+        }
+    }
+}
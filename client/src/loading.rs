@@ -0,0 +1,220 @@
+use crate::{
+    menu::{AppState, Menu, UiFont},
+    settings::{Action, ClientSettings},
+    status::{ConnectionStatus, Status},
+    PlayerInfo,
+};
+use agarlib::*;
+use bevy::prelude::*;
+
+// seconds from connecting to giving up on the `LoginAck`; past `LOST_TIMEOUT`, so a server
+// that never answered at all is reported as unreachable instead
+const LOGIN_TIMEOUT: f64 = 15.0;
+const SPINNER: [&str; 4] = ["|", "/", "-", "\\"];
+// spinner frames a second
+const SPIN_RATE: f64 = 8.0;
+// seconds each tip stays up
+const TIP_TIME: f64 = 5.0;
+
+// when the wait for `LoginAck` started
+#[derive(Default)]
+pub struct Loading {
+    since: Option<f64>,
+}
+
+// covers the empty world until the server lets the player in
+struct LoadingRoot;
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum LoadingText {
+    Title,
+    Spinner,
+    Detail,
+    Tip,
+}
+// shown once the login timed out
+struct BackButton;
+
+fn tips(settings: &ClientSettings) -> Vec<String> {
+    vec![
+        "Cells can eat cells a good deal smaller than them".to_string(),
+        format!(
+            "{} shows the full scoreboard",
+            settings.bindings(Action::Scoreboard)
+        ),
+        format!(
+            "The wheel, {} and {} zoom the view",
+            settings.bindings(Action::ZoomIn),
+            settings.bindings(Action::ZoomOut)
+        ),
+        format!("{} opens the chat", settings.bindings(Action::Chat)),
+        format!(
+            "{} talks to your team in team mode",
+            settings.bindings(Action::TeamChat)
+        ),
+        format!(
+            "{} shows the connection diagnostics",
+            settings.bindings(Action::Overlay)
+        ),
+        format!(
+            "{} records a replay of the game",
+            settings.bindings(Action::Record)
+        ),
+        "Every key can be rebound in the menu".to_string(),
+        "Eaten? Spectate until you're ready to jump back in".to_string(),
+        "On mobile data, try --low-bandwidth".to_string(),
+    ]
+}
+
+pub fn loading_setup(
+    commands: &mut Commands,
+    mut loading: ResMut<Loading>,
+    font: Res<UiFont>,
+    mut materials: ResMut<Assets<ColorMaterial>>,
+) {
+    *loading = Loading::default();
+
+    let font = font.0.clone();
+    let text = |value: &str, font_size: f32| TextBundle {
+        text: Text {
+            value: value.to_string(),
+            font: font.clone(),
+            style: TextStyle {
+                font_size,
+                color: Color::WHITE,
+                ..Default::default()
+            },
+        },
+        style: Style {
+            margin: Rect::all(Val::Px(8.0)),
+            ..Default::default()
+        },
+        ..Default::default()
+    };
+    let background = materials.add(Color::rgb(0.12, 0.12, 0.12).into());
+    let button = materials.add(Color::rgb(0.25, 0.25, 0.25).into());
+
+    // drawn by the hud's ui camera
+    commands
+        .spawn(NodeBundle {
+            style: Style {
+                size: Size::new(Val::Percent(100.0), Val::Percent(100.0)),
+                position_type: PositionType::Absolute,
+                flex_direction: FlexDirection::ColumnReverse,
+                justify_content: JustifyContent::Center,
+                align_items: AlignItems::Center,
+                ..Default::default()
+            },
+            material: background,
+            ..Default::default()
+        })
+        .with(LoadingRoot)
+        .with_children(|root| {
+            root.spawn(text("", 48.0)).with(LoadingText::Title);
+            root.spawn(text("", 48.0)).with(LoadingText::Spinner);
+            root.spawn(text("", 20.0)).with(LoadingText::Detail);
+            root.spawn(text("", 16.0)).with(LoadingText::Tip);
+            root.spawn(ButtonBundle {
+                style: Style {
+                    display: Display::None,
+                    margin: Rect::all(Val::Px(8.0)),
+                    ..Default::default()
+                },
+                material: button,
+                ..Default::default()
+            })
+            .with(BackButton)
+            .with_children(|button| {
+                button.spawn(text("Back to the menu", 24.0));
+            });
+        });
+}
+
+// from connecting until `LoginAck`: a spinner, the server and a tip; a login that isn't
+// answered in `LOGIN_TIMEOUT` turns it into an error with a way back to the menu. the status
+// panel takes over for lost, unreachable and rejected connections
+pub fn loading_system(
+    mut waiting: ResMut<Loading>,
+    time: Res<Time>,
+    player: Res<PlayerInfo>,
+    settings: Res<ClientSettings>,
+    config: Res<NetworkConfig>,
+    status: Res<ConnectionStatus>,
+    mut menu: ResMut<Menu>,
+    mut state: ResMut<State<AppState>>,
+    clicks: Query<&Interaction, (Mutated<Interaction>, With<BackButton>)>,
+    mut roots: Query<&mut Style, (With<LoadingRoot>, Without<BackButton>)>,
+    mut buttons: Query<&mut Style, With<BackButton>>,
+    mut texts: Query<(&mut Text, &LoadingText)>,
+) {
+    let now = time.seconds_since_startup();
+    let loading = !player.logged_in
+        && match status.status {
+            Status::Connecting => status.attempt == 0,
+            Status::Connected | Status::Degraded => true,
+            _ => false,
+        };
+    let since = match (loading, waiting.since) {
+        (false, _) => None,
+        (true, Some(since)) => Some(since),
+        (true, None) => Some(now),
+    };
+    waiting.since = since;
+    let timed_out = since.map_or(false, |since| now - since > LOGIN_TIMEOUT);
+
+    let addr = client_addr(&config).map_or("the server".to_string(), |addr| addr.to_string());
+    if timed_out
+        && clicks
+            .iter()
+            .any(|&interaction| interaction == Interaction::Clicked)
+    {
+        warn!("No login answer from {}", addr);
+        if state.next().is_none() {
+            menu.error = Some(format!("{} never answered the login", addr));
+            if let Err(err) = state.set_next(AppState::Menu) {
+                warn!("Unable to return to the menu: {:?}", err);
+            }
+        }
+    }
+
+    let display = |shown: bool| if shown { Display::Flex } else { Display::None };
+    for mut style in roots.iter_mut() {
+        if style.display != display(loading) {
+            style.display = display(loading);
+        }
+    }
+    for mut style in buttons.iter_mut() {
+        if style.display != display(timed_out) {
+            style.display = display(timed_out);
+        }
+    }
+    if !loading {
+        return;
+    }
+
+    let elapsed = since.map_or(0.0, |since| now - since);
+    let tips = tips(&settings);
+    for (mut text, kind) in texts.iter_mut() {
+        let value = match kind {
+            LoadingText::Title if timed_out => "Login timed out".to_string(),
+            LoadingText::Title => "agar".to_string(),
+            LoadingText::Spinner if timed_out => String::new(),
+            LoadingText::Spinner => {
+                SPINNER[(elapsed * SPIN_RATE) as usize % SPINNER.len()].to_string()
+            }
+            LoadingText::Detail if timed_out => {
+                format!("{} took the connection but never let the player in", addr)
+            }
+            LoadingText::Detail if status.status == Status::Connecting => {
+                format!("Connecting to {}...", addr)
+            }
+            LoadingText::Detail => format!("Logging in to {}...", addr),
+            LoadingText::Tip if timed_out => String::new(),
+            LoadingText::Tip => {
+                format!("Tip: {}", tips[(elapsed / TIP_TIME) as usize % tips.len()])
+            }
+        };
+        if text.value != value {
+            text.value = value;
+        }
+    }
+}
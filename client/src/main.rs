@@ -1,47 +1,376 @@
+// bevy systems take their resources and queries as parameters
+#![allow(clippy::too_many_arguments, clippy::type_complexity)]
+
+mod audio;
+mod chat;
+mod cli;
+mod cull;
+mod death;
+mod density;
+mod despawn;
+mod gamepad;
+mod hud;
+mod interp;
+mod labels;
+mod leaderboard;
+mod loading;
+mod map;
+mod menu;
+mod minimap;
+mod overlay;
+mod palette;
+mod particles;
+mod pause;
+mod ping;
+mod playback;
+mod predict;
+mod recent;
+mod reconnect;
+mod record;
+mod settings;
+mod skins;
+mod spectate;
+mod status;
+mod storage;
+mod touch;
+
 use agarlib::*;
-use bevy::{prelude::*, render::camera::Camera};
+use bevy::{
+    input::mouse::MouseWheel,
+    prelude::*,
+    render::{camera::Camera, render_graph::base::camera::CAMERA_2D},
+};
 use bevy_networking_turbulence::{NetworkEvent, NetworkResource};
 use bevy_prototype_lyon::prelude::*;
-use std::collections::HashMap;
+use cli::ClientArgs;
+use death::{Life, LIFE_STATE};
+use menu::{AppState, APP_STATE};
+use settings::{Action, Actions, ClientSettings};
+use std::collections::{HashMap, HashSet};
 
 fn main() {
-    App::build().add_plugin(AgarCli).run();
+    let args = ClientArgs::parse();
+
+    App::build().add_plugin(AgarCli { args }).run();
+}
+
+#[derive(Debug, Clone)]
+struct DeathInfo {
+    eater: EntityId,
+    mass: f32,
+    // seconds since the cell spawned
+    survived: f64,
 }
 
 #[derive(Default)]
 struct PlayerInfo {
+    name: String,
+    password: Option<String>,
+    region: Option<String>,
+    // index into `AGAR_COLORS`; the server picks when `None`
+    color: Option<u8>,
+    // below `SKIN_COUNT`
+    skin: Option<u8>,
+    // asked of the server at login; see `FeedState::density`
+    low_bandwidth: bool,
+    // steer with WASD or the arrow keys instead of the mouse
+    keyboard: bool,
+    // the wheel's zoom on top of `camera_zoom`; see `GameRules::clamp_zoom`
+    zoom: f32,
     id: Option<EntityId>,
+    death: Option<DeathInfo>,
+    rejection: Option<LoginRejection>,
+    owner: Option<u64>,
+    // the server froze the match
+    paused: bool,
+    // the biggest the player's cell has been since logging in
+    best: f32,
+    // when the player's cell spawned
+    spawned: f64,
+    // dead, and following the biggest cell around
+    spectating: bool,
+    // the server answered the login on this connection
+    logged_in: bool,
 }
 
+// seconds a feed request may go unanswered before it is sent again, e.g. when the server
+// dropped it while the player was between worlds
+const FEED_REQUEST_TIMEOUT: f64 = 2.0;
+
 #[derive(Default)]
 struct FeedState {
-    feeds: u64,
+    revision: u64,
+    // when the pending request went out
+    requested: Option<f64>,
+    ids: HashSet<EntityId>,
+    // the server sends `FeedDensity` instead of the feeds
+    density: bool,
 }
 
-struct AgarCli;
+struct AgarCli {
+    args: ClientArgs,
+}
 
 impl Plugin for AgarCli {
     fn build(&self, app: &mut AppBuilder) {
+        let settings = ClientSettings::load();
+        let name = if self.args.name.is_empty() {
+            settings.name.clone()
+        } else {
+            self.args.name.clone()
+        };
+        // a replay that won't load leaves the player at the menu, saying why
+        let mut menu = menu::Menu::default();
+        let mut state = AppState::Menu;
+        let mut rules = GameRules::default();
+        if let Some(path) = &self.args.replay {
+            match playback::Playback::load(path) {
+                Ok(playback) => {
+                    state = AppState::Replay;
+                    rules = playback.rules();
+                    app.add_resource(playback);
+                }
+                Err(err) => {
+                    menu.error = Some(format!("Unable to play {}: {:#}", path, err));
+                }
+            }
+        }
+
         app.add_resource(WindowDescriptor {
             width: WINDOW_WIDTH as f32,
             height: WINDOW_HEIGHT as f32,
+            vsync: settings.vsync,
+            ..Default::default()
+        })
+        // the renderer reads it as it starts
+        .add_resource(Msaa {
+            samples: settings.msaa,
+        })
+        .add_resource(PlayerInfo {
+            name,
+            password: self.args.password.clone(),
+            region: self.args.region.clone(),
+            color: settings.color,
+            skin: self.args.skin.or(settings.skin),
+            low_bandwidth: self.args.low_bandwidth || settings.low_bandwidth,
+            keyboard: self.args.keyboard,
+            zoom: 1.0,
             ..Default::default()
         })
-        .add_resource(PlayerInfo::default())
+        .add_resource(settings)
+        .add_resource(menu)
+        .add_resource(minimap::Minimap {
+            leaders: self.args.leaders,
+        })
+        .add_resource(State::new(state))
+        .add_stage_after(stage::UPDATE, APP_STATE, StateStage::<AppState>::default())
+        .on_state_enter(APP_STATE, AppState::Menu, menu::menu_setup.system())
+        .on_state_update(APP_STATE, AppState::Menu, menu::menu_system.system())
+        .on_state_update(APP_STATE, AppState::Menu, menu::bindings_system.system())
+        .on_state_exit(APP_STATE, AppState::Menu, menu::menu_cleanup.system())
+        .on_state_enter(APP_STATE, AppState::Playing, menu::connect_system.system())
+        .on_state_enter(APP_STATE, AppState::Playing, hud::hud_setup.system())
+        .on_state_enter(
+            APP_STATE,
+            AppState::Playing,
+            overlay::overlay_setup.system(),
+        )
+        .on_state_update(
+            APP_STATE,
+            AppState::Playing,
+            overlay::overlay_system.system(),
+        )
+        .on_state_update(APP_STATE, AppState::Playing, hud::hud_system.system())
+        .add_resource(loading::Loading::default())
+        .on_state_enter(
+            APP_STATE,
+            AppState::Playing,
+            loading::loading_setup.system(),
+        )
+        .on_state_update(
+            APP_STATE,
+            AppState::Playing,
+            loading::loading_system.system(),
+        )
+        .on_state_enter(APP_STATE, AppState::Playing, status::status_setup.system())
+        .on_state_update(APP_STATE, AppState::Playing, status::status_system.system())
+        .on_state_update(
+            APP_STATE,
+            AppState::Playing,
+            reconnect::reconnect_system.system(),
+        )
+        .on_state_exit(
+            APP_STATE,
+            AppState::Playing,
+            reconnect::leave_cleanup.system(),
+        )
+        .on_state_update(APP_STATE, AppState::Playing, labels::labels_system.system())
+        .add_stage_after(
+            stage::PRE_UPDATE,
+            settings::ACTIONS,
+            SystemStage::parallel(),
+        )
+        .add_resource(settings::Actions::default())
+        .add_system_to_stage(settings::ACTIONS, settings::actions_system.system())
+        .add_resource(pause::PauseMenu::default())
+        .add_system_to_stage(stage::PRE_UPDATE, pause::pause_input_system.system())
+        .add_resource(chat::ChatInput::default())
+        .add_system_to_stage(stage::PRE_UPDATE, chat::chat_input_system.system())
+        .on_state_enter(APP_STATE, AppState::Playing, chat::chat_setup.system())
+        .on_state_update(APP_STATE, AppState::Playing, chat::chat_system.system())
+        .on_state_enter(APP_STATE, AppState::Replay, chat::chat_setup.system())
+        .on_state_update(APP_STATE, AppState::Replay, chat::chat_system.system())
+        .on_state_update(APP_STATE, AppState::Playing, death::life_system.system())
+        .add_resource(State::new(Life::Alive))
+        .add_stage_after(APP_STATE, LIFE_STATE, StateStage::<Life>::default())
+        .on_state_enter(LIFE_STATE, Life::Dead, death::death_setup.system())
+        .on_state_update(LIFE_STATE, Life::Dead, death::death_system.system())
+        .on_state_exit(LIFE_STATE, Life::Dead, death::death_cleanup.system())
+        .on_state_enter(APP_STATE, AppState::Playing, pause::pause_setup.system())
+        .on_state_update(APP_STATE, AppState::Playing, pause::pause_system.system())
+        .add_resource(record::Recorder::default())
+        .on_state_update(APP_STATE, AppState::Playing, record::record_system.system())
+        .on_state_exit(
+            APP_STATE,
+            AppState::Playing,
+            record::record_cleanup.system(),
+        )
+        .on_state_enter(
+            APP_STATE,
+            AppState::Playing,
+            leaderboard::leaderboard_setup.system(),
+        )
+        .on_state_update(
+            APP_STATE,
+            AppState::Playing,
+            leaderboard::leaderboard_system.system(),
+        )
+        .on_state_enter(
+            APP_STATE,
+            AppState::Playing,
+            minimap::minimap_setup.system(),
+        )
+        .on_state_update(
+            APP_STATE,
+            AppState::Playing,
+            minimap::minimap_system.system(),
+        )
+        .add_resource(FeedDensity::default())
+        .on_state_update(
+            APP_STATE,
+            AppState::Playing,
+            density::density_system.system(),
+        )
+        .on_state_exit(
+            APP_STATE,
+            AppState::Playing,
+            density::density_cleanup.system(),
+        )
+        .add_resource(predict::Predictions::default())
+        .on_state_update(
+            APP_STATE,
+            AppState::Playing,
+            predict::predict_system.system(),
+        )
+        .on_state_enter(APP_STATE, AppState::Playing, ping::ping_setup.system())
+        .on_state_update(APP_STATE, AppState::Playing, ping::ping_system.system())
+        .on_state_enter(
+            APP_STATE,
+            AppState::Replay,
+            playback::playback_setup.system(),
+        )
+        .on_state_update(
+            APP_STATE,
+            AppState::Replay,
+            playback::playback_system.system(),
+        )
+        .on_state_update(APP_STATE, AppState::Replay, labels::labels_system.system())
+        .on_state_enter(
+            APP_STATE,
+            AppState::Replay,
+            leaderboard::leaderboard_setup.system(),
+        )
+        .on_state_update(
+            APP_STATE,
+            AppState::Replay,
+            leaderboard::leaderboard_system.system(),
+        )
+        .on_state_enter(APP_STATE, AppState::Replay, minimap::minimap_setup.system())
+        .on_state_update(
+            APP_STATE,
+            AppState::Replay,
+            minimap::minimap_system.system(),
+        )
         .add_resource(FeedState::default())
-        .add_plugins(bevy_webgl2::DefaultPlugins)
-        .add_resource(ClearColor(Color::rgb(0.3, 0.3, 0.3)))
-        .add_startup_system(camera_setup.system())
-        .add_system_to_stage(stage::PRE_UPDATE, handle_messages.system())
-        .add_system(input_system.system())
-        .add_system(camera_system.system())
-        .add_system(handle_packets.system())
-        .add_plugin(NetworkPlugin { server: false });
+        .add_resource(ClockSync::default())
+        .add_resource(NetworkEntities::default())
+        .add_resource(rules)
+        .add_resource(GameMap::default())
+        .add_resource(Leaderboard::default())
+        .add_resource(labels::Names::default())
+        .add_resource(status::ConnectionStatus::default());
+
+        // the browser draws through webgl2, the desktop through wgpu in a winit window
+        #[cfg(target_arch = "wasm32")]
+        app.add_plugins(bevy_webgl2::DefaultPlugins);
+        #[cfg(not(target_arch = "wasm32"))]
+        app.add_plugins(DefaultPlugins);
+
+        // the browser build plays through audio elements instead; see `audio::play_system`
+        #[cfg(not(target_arch = "wasm32"))]
+        if app.resources().get::<Audio>().is_none() {
+            app.add_plugin(bevy::audio::AudioPlugin);
+        }
+
+        app.add_resource(ClearColor(Color::rgb(0.3, 0.3, 0.3)))
+            .init_resource::<menu::UiFont>()
+            .add_startup_system(camera_setup.system())
+            .add_system_to_stage(stage::PRE_UPDATE, handle_messages.system())
+            .add_system(input_system.system())
+            .add_resource(touch::TouchQueue::install())
+            .add_system(touch::touch_system.system())
+            .add_system(gamepad::gamepad_system.system())
+            .add_resource(audio::SoundQueue::default())
+            .add_resource(audio::SoundBank::default())
+            .add_system(audio::audio_system.system())
+            .add_system(audio::play_system.system())
+            .add_system(time_sync_system.system())
+            .add_system(interp::interpolation_system.system())
+            .add_system(interp::size_system.system())
+            .add_system(camera_system.system())
+            .add_system(cull::cull_system.system())
+            .add_resource(skins::SkinAtlas::default())
+            .add_resource(skins::Palette::default())
+            .add_startup_system(skins::skins_setup.system())
+            .add_system(skins::skins_system.system())
+            .add_resource(palette::FeedLooks::default())
+            .add_system(palette::palette_system.system())
+            .add_system(despawn::despawn_system.system())
+            .add_resource(particles::Sparks::default())
+            .add_system(particles::particles_system.system())
+            .add_resource(spectate::Spectator::default())
+            .add_system(spectate::spectate_system.system())
+            .add_system(map::map_system.system())
+            .add_system(handle_packets.system())
+            .add_plugin(
+                NetworkPluginBuilder::new()
+                    .server(false)
+                    .format(WireFormat::Varint)
+                    .encryption(true)
+                    .pinned(self.args.server_key)
+                    .manual_connect()
+                    .addresses(self.args.server.clone())
+                    .build(),
+            );
     }
 }
 
 fn handle_packets(
-    mut net: ResMut<NetworkResource>,
+    mut queue: ResMut<SendQueue>,
+    mut formats: ResMut<WireFormats>,
+    token: Res<RedirectToken>,
+    player: Res<PlayerInfo>,
+    config: Res<NetworkConfig>,
     mut state: ResMut<NetworkReader>,
     network_events: Res<Events<NetworkEvent>>,
 ) {
@@ -52,11 +381,19 @@ fn handle_packets(
         };
 
         info!("Logging in");
-        match net.send_message(*handle, ClientMessage::Login) {
-            Ok(Some(msg)) => error!("unable to send login message: {:?}", msg),
-            Err(err) => error!("unable to send login message: {}", err),
-            _ => {}
-        }
+        let login = LoginRequest {
+            formats: WIRE_FORMATS.to_vec(),
+            profile: config.profile.fingerprint(),
+            public_key: formats.begin_key_exchange(*handle),
+            token: token.0.clone(),
+            name: player.name.clone(),
+            resume: player.owner.is_some(),
+            region: player.region.clone(),
+            color: player.color,
+            skin: player.skin,
+            low_bandwidth: player.low_bandwidth,
+        };
+        queue.push(*handle, ClientMessage::Login(login));
     }
 }
 
@@ -64,174 +401,694 @@ fn camera_setup(commands: &mut Commands) {
     commands.spawn(Camera2dBundle::default());
 }
 
+// per second; how quickly the zoom follows the cell's size
+const ZOOM_SPEED: f32 = 2.0;
+// per second; how quickly the camera catches up with the cell between snapshots
+const FOLLOW_SPEED: f32 = 10.0;
+// further than this the camera jumps, e.g. on respawn
+const FOLLOW_SNAP: f32 = WINDOW_WIDTH;
+
 fn camera_system(
-    player: Res<PlayerInfo>,
+    time: Res<Time>,
+    rules: Res<GameRules>,
+    mut player: ResMut<PlayerInfo>,
+    actions: Res<Actions>,
+    mut reader: Local<EventReader<MouseWheel>>,
+    events: Res<Events<MouseWheel>>,
     mut cameras: Query<(&Camera, &mut Transform)>,
     agars: Query<(&Agar, &UpdateContext, &Transform)>,
 ) {
-    let id = match player.id {
-        Some(id) => id,
+    let steps = spectate::zoom_steps(&actions, &mut reader, &events);
+    // spectators steer the camera themselves; see `spectate::spectate_system`
+    if player.spectating || player.id.is_none() {
+        return;
+    }
+    // kept within the rules, so turning back from a bound takes effect right away
+    player.zoom = rules.clamp_zoom(spectate::zoom_by(player.zoom, steps));
+    let own = agars
+        .iter()
+        .find(|(_, context, _)| Some(context.id) == player.id);
+    let (agar, transform) = match own {
+        Some((agar, _, transform)) => (agar, transform),
         None => return,
     };
 
-    for (_camera, mut camera_transform) in cameras.iter_mut() {
-        for (_agar, context, transform) in agars.iter() {
-            if context.id == id && camera_transform.translation != transform.translation {
-                camera_transform.translation = transform.translation.clone();
-                break;
-            }
+    for (camera, mut camera_transform) in cameras.iter_mut() {
+        // leave the ui camera alone
+        if camera.name.as_deref() != Some(CAMERA_2D) {
+            continue;
         }
+        ease_camera(
+            &mut camera_transform,
+            transform.translation,
+            camera_zoom(&rules, agar.size) * rules.clamp_zoom(player.zoom),
+            time.delta_seconds(),
+        );
     }
 }
 
+// eases toward the cell rather than stepping with every snapshot
+fn ease_camera(camera: &mut Transform, target: Vec3, zoom: f32, delta: f32) {
+    let current = camera.translation;
+    if current.distance(target) > FOLLOW_SNAP {
+        camera.translation = target;
+    } else if current != target {
+        let t = 1.0 - (-FOLLOW_SPEED * delta).exp();
+        camera.translation = current + (target - current) * t;
+    }
+
+    // the view is the camera's scale, so zooming out means scaling up
+    let current = camera.scale.x;
+    if (zoom - current).abs() > 0.001 {
+        let t = 1.0 - (-ZOOM_SPEED * delta).exp();
+        let current = current + (zoom - current) * t;
+        camera.scale = Vec3::new(current, current, 1.0);
+    }
+}
+
+// the cursor position a held direction stands for: as far out as the window allows, or the
+// center when nothing is held
+fn key_direction(actions: &Actions) -> Vec2 {
+    let held = |action| actions.pressed(action);
+    let mut direction = Vec2::zero();
+    if held(Action::Up) {
+        direction.y += 1.0;
+    }
+    if held(Action::Down) {
+        direction.y -= 1.0;
+    }
+    if held(Action::Left) {
+        direction.x -= 1.0;
+    }
+    if held(Action::Right) {
+        direction.x += 1.0;
+    }
+
+    let center = neutral_cursor();
+    if direction == Vec2::zero() {
+        center
+    } else {
+        center + direction.normalize() * (WINDOW_WIDTH.min(WINDOW_HEIGHT) / 2.0 - 1.0)
+    }
+}
+
+// the window's size in the logical pixels cursor positions come in, which stay put as
+// the scale factor changes
+#[cfg(not(target_arch = "wasm32"))]
+fn view_size(windows: &Windows) -> Option<Vec2> {
+    let window = windows.get_primary()?;
+    Some(Vec2::new(window.width(), window.height()))
+}
+
+// the page may stretch the canvas away from the size bevy gave it; cursor positions follow
+// the canvas as shown
+#[cfg(target_arch = "wasm32")]
+fn view_size(windows: &Windows) -> Option<Vec2> {
+    let shown = web_sys::window()
+        .and_then(|window| window.document())
+        .and_then(|document| document.query_selector("canvas").ok().flatten())
+        .map(|canvas| canvas.get_bounding_client_rect())
+        .filter(|rect| rect.width() > 0.0 && rect.height() > 0.0)
+        .map(|rect| Vec2::new(rect.width() as f32, rect.height() as f32));
+    shown.or_else(|| {
+        let window = windows.get_primary()?;
+        Some(Vec2::new(window.width(), window.height()))
+    })
+}
+
 fn input_system(
     mut net: ResMut<NetworkResource>,
+    formats: Res<WireFormats>,
+    mut stats: ResMut<NetworkStats>,
+    player: Res<PlayerInfo>,
+    rules: Res<GameRules>,
+    actions: Res<Actions>,
+    windows: Res<Windows>,
+    mut last_key: Local<Option<Vec2>>,
+    mut last_cursor: Local<Option<(Vec2, f32)>>,
     mut reader: Local<EventReader<CursorMoved>>,
     events: Res<Events<CursorMoved>>,
+    pause: Res<pause::PauseMenu>,
 ) {
+    // the server keeps steering toward the last input while the escape menu is up
+    if pause.open {
+        reader.iter(&events).for_each(drop);
+        return;
+    }
+    let mut inputs = vec![];
+    // the server sees the view at the zoom the cell's size gives it, so the cursor is
+    // scaled to point at the same spot there; a new zoom sends it again
+    let zoom = rules.clamp_zoom(player.zoom);
+    let mut cursor = last_cursor.map(|(cursor, _)| cursor);
     for event in reader.iter(&events) {
-        net.broadcast_message(ClientMessage::Input(event.position.clone()));
+        if !player.keyboard {
+            let size = view_size(&windows).unwrap_or(Vec2::new(WINDOW_WIDTH, WINDOW_HEIGHT));
+            cursor = Some(normalize_cursor(event.position, size));
+        }
+    }
+    if let Some(cursor) = cursor.filter(|_| !player.keyboard) {
+        if *last_cursor != Some((cursor, zoom)) {
+            *last_cursor = Some((cursor, zoom));
+            inputs.push(zoom_cursor(cursor, zoom));
+        }
+    }
+    // only changes are sent; the server keeps steering toward the last one
+    if player.keyboard {
+        let position = key_direction(&actions);
+        if *last_key != Some(position) {
+            *last_key = Some(position);
+            inputs.push(position);
+        }
+    }
+
+    for position in inputs {
+        let message = ClientMessage::Input(position);
+        broadcast_wire(&mut net, &formats, &message);
+        stats.sent(&message);
+    }
+}
+
+fn time_sync_system(
+    time: Res<Time>,
+    player: Res<PlayerInfo>,
+    mut clock: ResMut<ClockSync>,
+    mut net: ResMut<NetworkResource>,
+    formats: Res<WireFormats>,
+    mut stats: ResMut<NetworkStats>,
+) {
+    // logged in, even if eaten since
+    if player.owner.is_none() {
+        return;
+    }
+
+    let now = time.seconds_since_startup();
+    if clock.should_sync(now) {
+        let message = ClientMessage::TimeSyncRequest(now);
+        broadcast_wire(&mut net, &formats, &message);
+        stats.sent(&message);
     }
 }
 
 fn handle_messages(
     commands: &mut Commands,
     mut net: ResMut<NetworkResource>,
-    mut materials: ResMut<Assets<ColorMaterial>>,
+    (mut materials, mut palette): (ResMut<Assets<ColorMaterial>>, ResMut<skins::Palette>),
     mut player: ResMut<PlayerInfo>,
     mut meshes: ResMut<Assets<Mesh>>,
-    mut agars: Query<(
-        Entity,
-        &mut Agar,
-        &mut Sprite,
-        &mut UpdateContext,
-        &mut Transform,
-    )>,
-    feeds: Query<(Entity, &Feed, &UpdateContext)>,
+    mut agars: AgarQuery,
+    mut entities: ResMut<NetworkEntities>,
     mut feed_state: ResMut<FeedState>,
+    mut clock: ResMut<ClockSync>,
+    time: Res<Time>,
+    mut queue: ResMut<SendQueue>,
+    mut warnings: ResMut<Events<NetworkWarning>>,
+    mut formats: ResMut<WireFormats>,
+    // paired, as a system takes at most 16 parameters
+    (mut stats, mut recorder, mut names): (
+        ResMut<NetworkStats>,
+        ResMut<record::Recorder>,
+        ResMut<labels::Names>,
+    ),
+    mut states: ResMut<ConnectionStates>,
+    mut rules: ResMut<GameRules>,
 ) {
     let mut feed_requests = vec![];
+    let mut pongs = vec![];
+    let mut credentials = vec![];
+    let mut owners = vec![];
 
     for (handle, connection) in net.connections.iter_mut() {
-        let channels = connection.channels().unwrap();
+        let channels = match connection.channels() {
+            Some(channels) => channels,
+            None => {
+                debug!("Channels for [{}] are not ready", handle);
+                warnings.send(NetworkWarning::ChannelsUnavailable(*handle));
+                continue;
+            }
+        };
+
+        let mut agars_to_despawn = vec![];
 
-        let mut feeds_to_despawn = vec![];
+        while let Some(client_message) = recv_wire::<ClientMessage>(channels, &mut formats, *handle)
+        {
+            let client_message = match client_message {
+                Ok(client_message) => client_message,
+                Err(err) => {
+                    warn!("Malformed ClientMessage on [{}]: {}", handle, err);
+                    continue;
+                }
+            };
+            states.heard(*handle, time.seconds_since_startup());
+            stats.connection(*handle).received(&client_message);
+            recorder.message(&client_message);
 
-        while let Some(client_message) = channels.recv::<ClientMessage>() {
             match client_message {
-                ClientMessage::LoginAck(id) => {
+                ClientMessage::LoginAck {
+                    id,
+                    format,
+                    rules: server_rules,
+                    public_key,
+                    tick_rate,
+                    low_bandwidth,
+                } => {
+                    formats.set(*handle, format);
+                    if let Err(err) = formats.complete_key_exchange(*handle, public_key.as_ref()) {
+                        error!("Refusing the server: {}", err);
+                        player.rejection = Some(LoginRejection::UntrustedServer);
+                        continue;
+                    }
+                    *rules = server_rules;
+                    player.id = Some(id);
+                    // frames of an earlier server don't count at this one's rate
+                    *clock = ClockSync::new(tick_rate);
+                    if player.low_bandwidth && !low_bandwidth {
+                        warn!("The server doesn't allow low bandwidth; getting every feed");
+                    }
+                    if low_bandwidth {
+                        // feeds from an earlier connection won't be kept up to date
+                        apply_feeds(commands, &mut feed_state, &mut entities, true, vec![]);
+                    } else {
+                        commands.insert_resource(FeedDensity::default());
+                    }
+                    feed_state.density = low_bandwidth;
+                    player.logged_in = true;
+                    player.death = None;
+                    player.best = 0.0;
+                    player.spawned = time.seconds_since_startup();
+                }
+                ClientMessage::Respawned(id) => {
+                    info!("Respawned as {}", id);
                     player.id = Some(id);
+                    player.death = None;
+                    player.best = 0.0;
+                    player.spawned = time.seconds_since_startup();
+                    player.spectating = false;
+                    // a refused respawn doesn't hold up a later one
+                    player.rejection = None;
+                }
+                ClientMessage::ChatLine {
+                    from,
+                    name,
+                    text,
+                    team,
+                } => {
+                    match from {
+                        Some(_) if team => info!("[team] {}: {}", name, text),
+                        Some(_) => info!("{}: {}", name, text),
+                        None => info!("[server] {}", text),
+                    }
+                    commands.spawn((chat::ChatReceived {
+                        from,
+                        name,
+                        text,
+                        team,
+                    },));
+                }
+                ClientMessage::Map(map) => {
+                    commands.insert_resource(map);
+                }
+                ClientMessage::Leaderboard(leaderboard) => {
+                    commands.insert_resource(leaderboard);
+                }
+                ClientMessage::Names {
+                    baseline,
+                    names: received,
+                } => names.receive(baseline, received),
+                ClientMessage::FeedDensity(density) => {
+                    commands.insert_resource(density);
+                }
+                ClientMessage::Rules(server_rules) => {
+                    info!("Rules changed: {:?}", server_rules);
+                    *rules = server_rules;
+                }
+                ClientMessage::ServerShuttingDown { seconds } => {
+                    warn!("Server shutting down in {} seconds", seconds);
+                    commands.spawn((chat::ChatReceived {
+                        from: None,
+                        name: String::new(),
+                        text: format!("Server shutting down in {} seconds", seconds),
+                        team: false,
+                    },));
                 }
-                ClientMessage::FeedResponse(updates) => {
-                    info!("Receive updates: {:?}", updates);
-
-                    for update in updates {
-                        match update {
-                            FeedUpdate::Spawn(feed) => {
-                                let color = match feed.color {
-                                    FeedColor::Red => Color::rgb(0.8, 0.2, 0.2),
-                                    FeedColor::Green => Color::rgb(0.2, 0.8, 0.2),
-                                    FeedColor::Blue => Color::rgb(0.2, 0.2, 0.8),
-                                };
-
-                                let material = materials.add(color.into());
-
-                                commands
-                                    .spawn(primitive(
-                                        material.clone(),
-                                        &mut meshes,
-                                        ShapeType::Circle(10.0),
-                                        TessellationMode::Fill(&FillOptions::default()),
-                                        feed.translation.into(),
-                                    ))
-                                    .with(Feed { color: feed.color })
-                                    .with(UpdateContext {
-                                        id: feed.id,
-                                        frame: 0,
-                                    });
-                            }
-                            FeedUpdate::Despawn(id) => {
-                                feeds_to_despawn.push(id);
-                            }
+                ClientMessage::LoginRejected(rejection) => {
+                    error!("Login rejected: {:?}", rejection);
+                    player.rejection = Some(rejection);
+                }
+                ClientMessage::CredentialsRequired { public_key } => {
+                    if let Err(err) = formats.complete_key_exchange(*handle, public_key.as_ref()) {
+                        error!("Refusing the server: {}", err);
+                        player.rejection = Some(LoginRejection::UntrustedServer);
+                        continue;
+                    }
+                    // never in the clear; without encryption the server can't be answered
+                    match &player.password {
+                        Some(password) if formats.encrypted(*handle) => {
+                            credentials.push((*handle, password.clone()));
+                        }
+                        _ => {
+                            error!("The server is private and no password can be sent");
+                            player.rejection = Some(LoginRejection::Restricted);
                         }
                     }
                 }
-                _ => continue,
-            }
-        }
+                ClientMessage::OwnerRequired { public_key } => {
+                    if let Err(err) = formats.complete_key_exchange(*handle, public_key.as_ref()) {
+                        error!("Refusing the server: {}", err);
+                        player.rejection = Some(LoginRejection::UntrustedServer);
+                        continue;
+                    }
+                    // in the clear only to a server that runs without encryption altogether
+                    match player.owner {
+                        Some(owner) if formats.encrypted(*handle) || public_key.is_none() => {
+                            owners.push((*handle, owner));
+                        }
+                        _ => error!("The server asked for an owner token that can't be sent"),
+                    }
+                }
+                ClientMessage::Owner(owner) => {
+                    player.owner = Some(owner);
+                }
+                ClientMessage::PlayerEaten {
+                    victim,
+                    eater,
+                    victim_mass,
+                } => {
+                    if player.id == Some(victim) {
+                        info!("Eaten by {} at mass {}", eater, victim_mass);
+                        player.death = Some(DeathInfo {
+                            eater,
+                            mass: victim_mass,
+                            survived: time.seconds_since_startup() - player.spawned,
+                        });
+                        // the server hands the id out again
+                        player.id = None;
+                    }
+                }
+                ClientMessage::TimeSyncResponse {
+                    client_time,
+                    server_time,
+                    server_frame,
+                } => {
+                    clock.update(
+                        client_time,
+                        server_time,
+                        server_frame,
+                        time.seconds_since_startup(),
+                    );
+                    states.rtt(*handle, clock.rtt);
+                }
+                ClientMessage::EntityDespawned(id) => {
+                    if let Some(entity) = entities.remove(id) {
+                        despawn::fade_out(commands, entity);
+                    }
+                    agars_to_despawn.push(id);
+                }
+                ClientMessage::Ping(sent) => {
+                    pongs.push((*handle, sent));
+                }
+                ClientMessage::FeedResponse {
+                    revision,
+                    baseline,
+                    complete,
+                    updates,
+                } => {
+                    game_event!("Receive {} feed updates", updates.len());
 
-        // Despawn feeds
-        for (entity, _feed, context) in feeds.iter() {
-            if feeds_to_despawn.contains(&context.id) {
-                commands.despawn(entity);
+                    apply_feeds(commands, &mut feed_state, &mut entities, baseline, updates);
+
+                    if complete {
+                        feed_state.revision = revision;
+                        feed_state.requested = None;
+                    }
+                }
+                _ => continue,
             }
         }
 
-        // to avoid double spawn
-        let mut agars_to_spawn = HashMap::new();
         let mut feed_request_num = None;
+        let mut snapshots = vec![];
+        // the newest whole snapshot around the player's own cell
+        let mut whole: Option<(u32, HashSet<EntityId>, Vec3)> = None;
 
-        while let Some(mut state_message) = channels.recv::<GameStateMessage>() {
-            let message_frame = state_message.frame;
-
-            // update all agars
-            for (entity, mut agar, mut sprite, mut context, mut transform) in agars.iter_mut() {
-                if let Some(update) = state_message.agars.remove(&context.id) {
-                    if context.frame >= message_frame {
-                        continue;
-                    }
-                    context.frame = message_frame;
-                    sprite.size.x = update.agar.size * 2.0;
-                    sprite.size.y = update.agar.size * 2.0;
-                    info!("Agar size: {:?}", sprite.size);
-                    *agar = update.agar;
-                    transform.translation = update.translation;
-                } else {
-                    commands.despawn(entity);
+        while let Some(state_message) =
+            recv_wire::<GameStateMessage>(channels, &mut formats, *handle)
+        {
+            let state_message = match state_message {
+                Ok(state_message) => state_message,
+                Err(err) => {
+                    warn!("Malformed GameStateMessage on [{}]: {}", handle, err);
+                    continue;
                 }
+            };
+            states.heard(*handle, time.seconds_since_startup());
+            let connection = stats.connection(*handle);
+            connection.received(&state_message);
+            connection.snapshot_received(state_message.seq);
+            recorder.state(&state_message);
+
+            if state_message.paused != player.paused {
+                player.paused = state_message.paused;
+                info!("Match {}", if player.paused { "paused" } else { "resumed" });
             }
 
-            for (id, update) in state_message.agars.drain() {
-                agars_to_spawn.insert(id, (message_frame, update));
+            if !feed_state.density
+                && feed_state.revision < state_message.feeds
+                && feed_state.requested.map_or(true, |at| {
+                    time.seconds_since_startup() - at > FEED_REQUEST_TIMEOUT
+                })
+            {
+                feed_request_num = Some(feed_state.revision);
+                feed_state.requested = Some(time.seconds_since_startup());
             }
 
-            if feed_state.feeds < state_message.feeds {
-                if feed_request_num.is_none() {
-                    feed_request_num = Some(feed_state.feeds);
+            let own = player
+                .id
+                .and_then(|id| state_message.agars.get(&id))
+                .map(|update| update.translation);
+            match own {
+                Some(own)
+                    if !state_message.partial
+                        && whole
+                            .as_ref()
+                            .map_or(true, |(frame, _, _)| *frame < state_message.frame) =>
+                {
+                    let listed = state_message.agars.keys().copied().collect();
+                    whole = Some((state_message.frame, listed, own));
                 }
-                feed_state.feeds = state_message.feeds;
+                _ => {}
             }
+
+            snapshots.push((state_message.frame, state_message.agars));
+        }
+
+        while let Some(overview) = recv_wire::<OverviewMessage>(channels, &mut formats, *handle) {
+            let overview = match overview {
+                Ok(overview) => overview,
+                Err(err) => {
+                    warn!("Malformed OverviewMessage on [{}]: {}", handle, err);
+                    continue;
+                }
+            };
+            stats.connection(*handle).received(&overview);
+
+            let snapshot = overview
+                .cells
+                .iter()
+                .map(|cell| (cell.id, cell.update(&rules)))
+                .collect();
+            recorder.overview(overview.frame, &snapshot, player.paused);
+            snapshots.push((overview.frame, snapshot));
         }
 
         if let Some(num) = feed_request_num {
             feed_requests.push((*handle, num));
         }
 
-        // spawn new agars
-        for (id, (message_frame, update)) in agars_to_spawn {
-            let material = materials.add(Color::rgb(0.8, 0.0, 0.0).into());
-            commands
-                .spawn(primitive(
-                    material.clone(),
-                    &mut meshes,
-                    ShapeType::Circle(1.0),
-                    TessellationMode::Fill(&FillOptions::default()),
-                    update.translation.into(),
-                ))
-                .with(update.agar.clone())
-                .with(UpdateContext {
-                    id,
-                    frame: message_frame,
-                });
+        apply_snapshots(
+            commands,
+            &mut agars,
+            &mut entities,
+            (&mut materials, &mut palette),
+            &mut meshes,
+            &rules,
+            snapshots,
+            &agars_to_despawn,
+        );
+        if let Some((frame, listed, own)) = whole {
+            reconcile_snapshot(commands, &mut agars, &mut entities, frame, &listed, own);
         }
     }
 
+    for (handle, sent) in pongs {
+        queue.push(handle, ClientMessage::Pong(sent));
+    }
+
+    for (handle, password) in credentials {
+        queue.push(handle, ClientMessage::Credentials(password));
+    }
+
+    for (handle, owner) in owners {
+        queue.push(handle, ClientMessage::Owner(owner));
+    }
+
     for (handle, num) in feed_requests {
-        info!("Requesting feed {}", num);
-        match net.send_message(handle, ClientMessage::FeedRequest(num)) {
-            Ok(Some(msg)) => error!("unable to send feed request to server: {:?}", msg),
-            Err(err) => error!("unable to send feed request to server: {}", err),
-            _ => {}
+        game_event!("Requesting feed {}", num);
+        queue.push(handle, ClientMessage::FeedRequest(num));
+    }
+}
+
+// feeds from a `FeedResponse`, or from a replay
+fn apply_feeds(
+    commands: &mut Commands,
+    feed_state: &mut FeedState,
+    entities: &mut NetworkEntities,
+    baseline: bool,
+    updates: Vec<FeedUpdate>,
+) {
+    if baseline {
+        for id in feed_state.ids.drain() {
+            if let Some(entity) = entities.remove(id) {
+                commands.despawn(entity);
+            }
         }
     }
+
+    for update in updates {
+        match update {
+            FeedUpdate::Spawn(feed) => {
+                if !feed_state.ids.insert(feed.id) {
+                    continue;
+                }
+
+                // `palette_system` picks its material and shape
+                let entity = commands
+                    .spawn(SpriteBundle {
+                        sprite: Sprite::new(Vec2::new(1.0, 1.0)),
+                        transform: Transform::from_translation(feed.translation),
+                        ..Default::default()
+                    })
+                    .with(Feed { color: feed.color })
+                    .with(UpdateContext {
+                        id: feed.id,
+                        frame: 0,
+                    })
+                    .current_entity()
+                    .unwrap();
+                entities.insert(feed.id, entity);
+            }
+            FeedUpdate::Despawn(id) => {
+                feed_state.ids.remove(&id);
+                if let Some(entity) = entities.remove(id) {
+                    despawn::fade_out(commands, entity);
+                }
+            }
+        }
+    }
+}
+
+type AgarQuery<'a> = Query<
+    'a,
+    (
+        Entity,
+        &'a mut Agar,
+        &'a mut UpdateContext,
+        &'a mut interp::Interpolation,
+    ),
+>;
+
+// world units a cell must be inside the near tier by before a whole snapshot leaving it out
+// despawns it
+const RECONCILE_MARGIN: f32 = 100.0;
+
+// a whole snapshot lists every cell near the player's own, so cells the client still has
+// there that it leaves out are gone, even if their despawn never came. partial snapshots
+// only list what changed and say nothing about what's gone
+fn reconcile_snapshot(
+    commands: &mut Commands,
+    agars: &mut AgarQuery,
+    entities: &mut NetworkEntities,
+    frame: u32,
+    listed: &HashSet<EntityId>,
+    own: Vec3,
+) {
+    for (_entity, agar, context, interpolation) in agars.iter_mut() {
+        if listed.contains(&context.id) || context.frame > frame {
+            continue;
+        }
+        // where the server saw it may be a little off from where the client last did
+        let distance = interpolation.latest().distance(own) + RECONCILE_MARGIN;
+        if in_far_tier(distance, agar.size) {
+            continue;
+        }
+        if let Some(entity) = entities.remove(context.id) {
+            despawn::fade_out(commands, entity);
+        }
+    }
+}
+
+// snapshots of one run in the order they arrived, from the network or a replay; cells
+// despawned meanwhile aren't spawned again
+fn apply_snapshots(
+    commands: &mut Commands,
+    agars: &mut AgarQuery,
+    entities: &mut NetworkEntities,
+    (materials, palette): (&mut Assets<ColorMaterial>, &mut skins::Palette),
+    meshes: &mut Assets<Mesh>,
+    rules: &GameRules,
+    snapshots: Vec<(u32, HashMap<EntityId, AgarUpdate>)>,
+    agars_to_despawn: &[EntityId],
+) {
+    // to avoid double spawn
+    let mut agars_to_spawn = HashMap::new();
+
+    for (message_frame, mut snapshot) in snapshots {
+        // update all agars
+        for (_entity, mut agar, mut context, mut interpolation) in agars.iter_mut() {
+            if let Some(update) = snapshot.remove(&context.id) {
+                if context.frame >= message_frame {
+                    continue;
+                }
+                context.frame = message_frame;
+                game_event!("Agar {} size: {}", context.id, update.agar.size);
+                // `skins_system` follows changes to the color and the skin, and
+                // `size_system` eases the drawn size toward the new one
+                *agar = update.agar;
+                interpolation.push(message_frame, update.translation, &agar, rules);
+            }
+        }
+
+        for (id, update) in snapshot.drain() {
+            if !agars_to_despawn.contains(&id) {
+                agars_to_spawn.insert(id, (message_frame, update));
+            }
+        }
+    }
+
+    // spawn new agars
+    for (id, (message_frame, update)) in agars_to_spawn {
+        let material = palette.material(materials, update.agar.color);
+        let entity = commands
+            .spawn(primitive(
+                material.clone(),
+                meshes,
+                ShapeType::Circle(1.0),
+                TessellationMode::Fill(&FillOptions::default()),
+                update.translation.into(),
+            ))
+            .with(update.agar.clone())
+            .with(interp::Interpolation::new(
+                message_frame,
+                update.translation,
+                &update.agar,
+                rules,
+            ))
+            .with(interp::SizeTween::new(update.agar.size))
+            .with(UpdateContext {
+                id,
+                frame: message_frame,
+            })
+            .current_entity()
+            .unwrap();
+        entities.insert(id, entity);
+    }
 }
@@ -0,0 +1,110 @@
+use agarlib::*;
+use bevy::prelude::*;
+use bevy_prototype_lyon::prelude::*;
+
+// drawn from the server's `GameMap` and world size; replaced when either changes
+pub struct MapShape;
+
+// pixels; cells are clamped to the world, so the wall sits just outside it
+const BORDER_WIDTH: f32 = 8.0;
+
+fn spawn_shape(
+    commands: &mut Commands,
+    meshes: &mut Assets<Mesh>,
+    material: Handle<ColorMaterial>,
+    shape: &Shape,
+    z: f32,
+) {
+    let (shape, translation) = match *shape {
+        Shape::Circle { x, y, radius } => (ShapeType::Circle(radius), Vec3::new(x, y, z)),
+        Shape::Rect {
+            x,
+            y,
+            width,
+            height,
+        } => (
+            ShapeType::Rectangle {
+                width,
+                height,
+                origin: RectangleOrigin::BottomLeft,
+            },
+            Vec3::new(x, y, z),
+        ),
+    };
+
+    commands
+        .spawn(primitive(
+            material,
+            meshes,
+            shape,
+            TessellationMode::Fill(&FillOptions::default()),
+            translation,
+        ))
+        .with(MapShape);
+}
+
+pub fn map_system(
+    commands: &mut Commands,
+    mut drawn: Local<Option<(GameMap, Vec2)>>,
+    map: Res<GameMap>,
+    rules: Res<GameRules>,
+    mut meshes: ResMut<Assets<Mesh>>,
+    mut materials: ResMut<Assets<ColorMaterial>>,
+    shapes: Query<Entity, With<MapShape>>,
+) {
+    let size = Vec2::new(rules.world_width, rules.world_height);
+    if matches!(&*drawn, Some((old, old_size)) if *old == *map && *old_size == size) {
+        return;
+    }
+    *drawn = Some((map.clone(), size));
+    info!("Map {:?}: {}x{}", map.name, size.x, size.y);
+
+    for entity in shapes.iter() {
+        commands.despawn(entity);
+    }
+
+    // a lighter floor shows where the world ends
+    let floor = materials.add(Color::rgb(0.35, 0.35, 0.35).into());
+    let world = Shape::Rect {
+        x: 0.0,
+        y: 0.0,
+        width: size.x,
+        height: size.y,
+    };
+    spawn_shape(commands, &mut meshes, floor, &world, -1.0);
+
+    // and a wall around it shows where cells stop
+    let border = materials.add(Color::rgb(0.8, 0.3, 0.3).into());
+    commands
+        .spawn(primitive(
+            border,
+            &mut meshes,
+            ShapeType::Rectangle {
+                width: size.x + BORDER_WIDTH,
+                height: size.y + BORDER_WIDTH,
+                origin: RectangleOrigin::BottomLeft,
+            },
+            TessellationMode::Stroke(&StrokeOptions::default().with_line_width(BORDER_WIDTH)),
+            Vec3::new(-BORDER_WIDTH / 2.0, -BORDER_WIDTH / 2.0, 0.5),
+        ))
+        .with(MapShape);
+
+    // boost zones under the feeds, obstacles above them, viruses over the cells
+    let boost = materials.add(Color::rgba(0.9, 0.8, 0.2, 0.3).into());
+    for zone in &map.boosts {
+        spawn_shape(commands, &mut meshes, boost.clone(), &zone.area, -0.5);
+    }
+    let obstacle = materials.add(Color::rgb(0.15, 0.15, 0.15).into());
+    for shape in &map.obstacles {
+        spawn_shape(commands, &mut meshes, obstacle.clone(), shape, 0.5);
+    }
+    let spiky = materials.add(Color::rgba(0.2, 0.9, 0.2, 0.8).into());
+    for virus in &map.viruses {
+        let shape = Shape::Circle {
+            x: virus.x,
+            y: virus.y,
+            radius: virus.size,
+        };
+        spawn_shape(commands, &mut meshes, spiky.clone(), &shape, 2.0);
+    }
+}
@@ -0,0 +1,494 @@
+use crate::{
+    recent,
+    settings::{self, Action, ClientSettings},
+    PlayerInfo,
+};
+use agarlib::*;
+use bevy::prelude::*;
+use bevy_networking_turbulence::NetworkResource;
+
+pub const APP_STATE: &str = "app_state";
+
+const MAX_NAME_LEN: usize = 16;
+const MAX_SERVER_LEN: usize = 64;
+// bundled, so the browser build needs no asset server
+const FONT: &[u8] = include_bytes!("../assets/fonts/DejaVuSansMono.ttf");
+
+// the bundled font, loaded once for every piece of ui text
+pub struct UiFont(pub Handle<Font>);
+
+impl FromResources for UiFont {
+    fn from_resources(resources: &Resources) -> Self {
+        let mut fonts = resources.get_mut::<Assets<Font>>().unwrap();
+        UiFont(fonts.add(Font::try_from_bytes(FONT.to_vec()).expect("the bundled font is valid")))
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum AppState {
+    // picking a name, a color and a server; not connected yet
+    Menu,
+    Playing,
+    // watching a recorded match; see `playback`
+    Replay,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Field {
+    Name,
+    Server,
+}
+
+impl Default for Field {
+    fn default() -> Self {
+        Field::Name
+    }
+}
+
+// what the menu is editing besides the player's identity
+#[derive(Default)]
+pub struct Menu {
+    focus: Field,
+    // `host[:port]`; empty for the build-time default
+    server: String,
+    recent: Vec<String>,
+    // why the last game ended, shown when the menu comes back
+    pub error: Option<String>,
+    // waiting for the key or mouse button to bind it to; the fields take no typing meanwhile
+    rebinding: Option<Action>,
+}
+
+// everything spawned for the menu, despawned when the game starts
+struct MenuRoot;
+struct NameField;
+struct ServerField;
+struct ErrorLine;
+struct RecentServer(String);
+struct ColorButton(u8);
+// switches between mouse and keyboard steering; the label is its child
+struct ControlsButton;
+struct ControlsLabel;
+struct PlayButton;
+// one per action, listing its bindings; clicking it rebinds the action
+struct BindingButton(Action);
+struct BindingLabel(Action);
+
+fn swatch_style(selected: bool) -> Style {
+    let size = if selected { 44.0 } else { 32.0 };
+    Style {
+        size: Size::new(Val::Px(size), Val::Px(size)),
+        margin: Rect::all(Val::Px(6.0)),
+        ..Default::default()
+    }
+}
+
+// the next swatch over, from either end when none is picked
+fn step_color(color: Option<u8>, forward: bool) -> u8 {
+    let colors = AGAR_COLORS.len() as u8;
+    match (color, forward) {
+        (None, true) => 0,
+        (None, false) => colors - 1,
+        (Some(color), true) => (color + 1) % colors,
+        (Some(color), false) => (color + colors - 1) % colors,
+    }
+}
+
+fn controls_label(keyboard: bool) -> &'static str {
+    if keyboard {
+        "Steer with WASD / arrows"
+    } else {
+        "Steer with the mouse"
+    }
+}
+
+fn binding_label(settings: &ClientSettings, action: Action, rebinding: bool) -> String {
+    if rebinding {
+        format!("{:?}: press a key", action)
+    } else {
+        format!("{:?}: {}", action, settings.bindings(action))
+    }
+}
+
+fn field_label(value: &str, focused: bool) -> String {
+    match (value.is_empty(), focused) {
+        (true, false) => "(default)".to_string(),
+        (_, true) => format!("{}_", value),
+        (_, false) => value.to_string(),
+    }
+}
+
+pub fn menu_setup(
+    commands: &mut Commands,
+    font: Res<UiFont>,
+    mut materials: ResMut<Assets<ColorMaterial>>,
+    player: Res<PlayerInfo>,
+    settings: Res<ClientSettings>,
+    config: Res<NetworkConfig>,
+    menu: Res<Menu>,
+) {
+    let recent = recent::load();
+    let error = menu.error.clone().unwrap_or_default();
+    let server = config
+        .addresses
+        .first()
+        .or_else(|| recent.first())
+        .cloned()
+        .unwrap_or_default();
+
+    let font = font.0.clone();
+    let text = |value: &str, font_size: f32| TextBundle {
+        text: Text {
+            value: value.to_string(),
+            font: font.clone(),
+            style: TextStyle {
+                font_size,
+                color: Color::WHITE,
+                ..Default::default()
+            },
+        },
+        style: Style {
+            margin: Rect::all(Val::Px(8.0)),
+            ..Default::default()
+        },
+        ..Default::default()
+    };
+    let transparent = materials.add(Color::rgba(0.0, 0.0, 0.0, 0.0).into());
+    let swatches: Vec<_> = (0..AGAR_COLORS.len() as u8)
+        .map(|color| (color, materials.add(agar_color(color).into())))
+        .collect();
+    let button = materials.add(Color::rgb(0.15, 0.15, 0.15).into());
+
+    commands
+        .spawn(CameraUiBundle::default())
+        .with(MenuRoot)
+        .spawn(NodeBundle {
+            style: Style {
+                size: Size::new(Val::Percent(100.0), Val::Percent(100.0)),
+                flex_direction: FlexDirection::ColumnReverse,
+                justify_content: JustifyContent::Center,
+                align_items: AlignItems::Center,
+                ..Default::default()
+            },
+            material: transparent.clone(),
+            ..Default::default()
+        })
+        .with(MenuRoot)
+        .with_children(|menu| {
+            menu.spawn(text("agar", 64.0));
+            menu.spawn(text("Name", 20.0));
+            menu.spawn(text(&field_label(&player.name, true), 32.0))
+                .with(NameField)
+                .with(Interaction::default());
+            menu.spawn(text("Server (Tab to switch)", 20.0));
+            menu.spawn(text(&field_label(&server, false), 32.0))
+                .with(ServerField)
+                .with(Interaction::default());
+            for entry in &recent {
+                menu.spawn(ButtonBundle {
+                    style: Style {
+                        margin: Rect::all(Val::Px(2.0)),
+                        ..Default::default()
+                    },
+                    material: button.clone(),
+                    ..Default::default()
+                })
+                .with(RecentServer(entry.clone()))
+                .with_children(|button| {
+                    button.spawn(text(entry, 16.0));
+                });
+            }
+            menu.spawn(NodeBundle {
+                style: Style {
+                    align_items: AlignItems::Center,
+                    margin: Rect::all(Val::Px(16.0)),
+                    ..Default::default()
+                },
+                material: transparent.clone(),
+                ..Default::default()
+            })
+            .with_children(|row| {
+                for (color, material) in swatches {
+                    row.spawn(ButtonBundle {
+                        style: swatch_style(Some(color) == player.color),
+                        material,
+                        ..Default::default()
+                    })
+                    .with(ColorButton(color));
+                }
+            });
+            menu.spawn(ButtonBundle {
+                style: Style {
+                    margin: Rect::all(Val::Px(4.0)),
+                    ..Default::default()
+                },
+                material: button.clone(),
+                ..Default::default()
+            })
+            .with(ControlsButton)
+            .with_children(|button| {
+                button
+                    .spawn(text(controls_label(player.keyboard), 20.0))
+                    .with(ControlsLabel);
+            });
+            menu.spawn(ButtonBundle {
+                style: Style {
+                    size: Size::new(Val::Px(160.0), Val::Px(56.0)),
+                    justify_content: JustifyContent::Center,
+                    align_items: AlignItems::Center,
+                    ..Default::default()
+                },
+                material: button.clone(),
+                ..Default::default()
+            })
+            .with(PlayButton)
+            .with_children(|button| {
+                button.spawn(text("Play", 32.0));
+            });
+            menu.spawn(text(&error, 20.0)).with(ErrorLine);
+            menu.spawn(text("Controls (click one to rebind it)", 16.0));
+            menu.spawn(NodeBundle {
+                style: Style {
+                    size: Size::new(Val::Px(760.0), Val::Auto),
+                    flex_wrap: FlexWrap::Wrap,
+                    justify_content: JustifyContent::Center,
+                    ..Default::default()
+                },
+                material: transparent,
+                ..Default::default()
+            })
+            .with_children(|row| {
+                for &action in Action::ALL.iter() {
+                    row.spawn(ButtonBundle {
+                        style: Style {
+                            margin: Rect::all(Val::Px(2.0)),
+                            ..Default::default()
+                        },
+                        material: button.clone(),
+                        ..Default::default()
+                    })
+                    .with(BindingButton(action))
+                    .with_children(|button| {
+                        button
+                            .spawn(text(&binding_label(&settings, action, false), 12.0))
+                            .with(BindingLabel(action));
+                    });
+                }
+            });
+        });
+
+    commands.insert_resource(Menu {
+        focus: Field::Name,
+        server,
+        recent,
+        error: None,
+        rebinding: None,
+    });
+}
+
+pub fn menu_system(
+    mut player: ResMut<PlayerInfo>,
+    mut settings: ResMut<ClientSettings>,
+    mut menu: ResMut<Menu>,
+    mut config: ResMut<NetworkConfig>,
+    mut state: ResMut<State<AppState>>,
+    mut reader: Local<EventReader<ReceivedCharacter>>,
+    characters: Res<Events<ReceivedCharacter>>,
+    keys: Res<Input<KeyCode>>,
+    pads: Res<Input<GamepadButton>>,
+    mut texts: Query<(
+        &mut Text,
+        Option<&NameField>,
+        Option<&ServerField>,
+        Option<&ErrorLine>,
+        Option<&ControlsLabel>,
+    )>,
+    mut swatches: Query<(&ColorButton, &mut Style)>,
+    clicks: Query<
+        (
+            &Interaction,
+            Option<&ColorButton>,
+            Option<&RecentServer>,
+            Option<&NameField>,
+            Option<&ServerField>,
+            Option<&ControlsButton>,
+            Option<&PlayButton>,
+        ),
+        Mutated<Interaction>,
+    >,
+) {
+    // `bindings_system` has the keyboard
+    if menu.rebinding.is_some() {
+        reader.iter(&characters).for_each(drop);
+        return;
+    }
+
+    let menu = &mut *menu;
+    let mut name = player.name.clone();
+    let mut focus = menu.focus;
+    let mut play = keys.just_pressed(KeyCode::Return);
+    // `Some(None)` leaves the color to the server
+    let mut picked = None;
+    let mut switch_field = keys.just_pressed(KeyCode::Tab);
+
+    // the d-pad picks the color and the field, the south button plays
+    for GamepadButton(_, button) in pads.get_just_pressed() {
+        match button {
+            GamepadButtonType::DPadLeft => picked = Some(Some(step_color(player.color, false))),
+            GamepadButtonType::DPadRight => picked = Some(Some(step_color(player.color, true))),
+            GamepadButtonType::DPadUp | GamepadButtonType::DPadDown => switch_field = true,
+            GamepadButtonType::South | GamepadButtonType::Start => play = true,
+            _ => {}
+        }
+    }
+
+    if switch_field {
+        focus = match focus {
+            Field::Name => Field::Server,
+            Field::Server => Field::Name,
+        };
+    }
+    for (interaction, color, server, name_field, server_field, controls, play_button) in
+        clicks.iter()
+    {
+        if *interaction != Interaction::Clicked {
+            continue;
+        }
+        // the picked swatch again leaves it to the server
+        if let Some(color) = color {
+            picked = Some(Some(color.0).filter(|&color| player.color != Some(color)));
+        }
+        if let Some(server) = server {
+            menu.server = server.0.clone();
+        }
+        if name_field.is_some() {
+            focus = Field::Name;
+        }
+        if server_field.is_some() {
+            focus = Field::Server;
+        }
+        if controls.is_some() {
+            player.keyboard = !player.keyboard;
+        }
+        play |= play_button.is_some();
+    }
+    if let Some(color) = picked {
+        player.color = color;
+        for (swatch, mut style) in swatches.iter_mut() {
+            *style = swatch_style(Some(swatch.0) == color);
+        }
+    }
+
+    let (value, max_len) = match focus {
+        Field::Name => (&mut name, MAX_NAME_LEN),
+        Field::Server => (&mut menu.server, MAX_SERVER_LEN),
+    };
+    for event in reader.iter(&characters) {
+        if !event.char.is_control() && value.chars().count() < max_len {
+            value.push(event.char);
+        }
+    }
+    if keys.just_pressed(KeyCode::Back) {
+        value.pop();
+    }
+    menu.focus = focus;
+    player.name = name;
+
+    let mut error = None;
+    if play {
+        config.addresses = if menu.server.trim().is_empty() {
+            vec![]
+        } else {
+            vec![menu.server.trim().to_string()]
+        };
+        match client_addr(&config) {
+            Some(addr) => {
+                info!("Playing as {:?} on {}", player.name, addr);
+                if (&settings.name, settings.color) != (&player.name, player.color) {
+                    settings.name = player.name.clone();
+                    settings.color = player.color;
+                    settings.save();
+                }
+                if !menu.server.trim().is_empty() {
+                    recent::remember(&mut menu.recent, menu.server.trim());
+                }
+                if let Err(err) = state.set_next(AppState::Playing) {
+                    warn!("Unable to leave the menu: {:?}", err);
+                }
+            }
+            None => error = Some(format!("Unknown server {:?}", menu.server)),
+        }
+    }
+
+    for (mut text, name_field, server_field, error_line, controls) in texts.iter_mut() {
+        let value = if name_field.is_some() {
+            field_label(&player.name, menu.focus == Field::Name)
+        } else if server_field.is_some() {
+            field_label(&menu.server, menu.focus == Field::Server)
+        } else if controls.is_some() {
+            controls_label(player.keyboard).to_string()
+        } else if let (Some(_), Some(error)) = (error_line, &error) {
+            error.clone()
+        } else {
+            continue;
+        };
+        if text.value != value {
+            text.value = value;
+        }
+    }
+}
+
+// runs after `menu_system`: binds the first key or mouse button pressed after a click on an
+// action, or keeps the old bindings on Escape
+pub fn bindings_system(
+    mut settings: ResMut<ClientSettings>,
+    mut menu: ResMut<Menu>,
+    keys: Res<Input<KeyCode>>,
+    buttons: Res<Input<MouseButton>>,
+    clicks: Query<(&Interaction, &BindingButton), Mutated<Interaction>>,
+    mut labels: Query<(&mut Text, &BindingLabel)>,
+) {
+    if let Some(action) = menu.rebinding {
+        // the click that started it was the frame before
+        let name = keys
+            .get_just_pressed()
+            .find(|&&key| key != KeyCode::Escape)
+            .and_then(|&key| settings::key_name(key))
+            .or_else(|| {
+                buttons
+                    .get_just_pressed()
+                    .find_map(|&button| settings::button_name(button))
+            });
+        if let Some(name) = name {
+            info!("Binding {:?} to {}", action, name);
+            settings.rebind(action, name);
+            settings.save();
+            menu.rebinding = None;
+        } else if keys.just_pressed(KeyCode::Escape) {
+            menu.rebinding = None;
+        }
+    } else {
+        for (interaction, button) in clicks.iter() {
+            if *interaction == Interaction::Clicked {
+                menu.rebinding = Some(button.0);
+            }
+        }
+    }
+
+    for (mut text, label) in labels.iter_mut() {
+        let value = binding_label(&settings, label.0, menu.rebinding == Some(label.0));
+        if text.value != value {
+            text.value = value;
+        }
+    }
+}
+
+pub fn menu_cleanup(commands: &mut Commands, roots: Query<Entity, With<MenuRoot>>) {
+    for entity in roots.iter() {
+        commands.despawn_recursive(entity);
+    }
+}
+
+// the player has an identity now
+pub fn connect_system(mut net: ResMut<NetworkResource>, config: Res<NetworkConfig>) {
+    connect(&mut net, &config);
+}
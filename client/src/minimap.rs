@@ -0,0 +1,161 @@
+use crate::PlayerInfo;
+use agarlib::*;
+use bevy::prelude::*;
+
+// pixels along the longer side of the world
+pub const MINIMAP_SIZE: f32 = 150.0;
+pub const MINIMAP_MARGIN: f32 = 10.0;
+pub const FRAME_WIDTH: f32 = 2.0;
+const DOT_SIZE: f32 = 6.0;
+// the biggest cells shown besides the player's own
+const LEADERS: usize = 3;
+
+// `leaders` marks the biggest cells on the minimap too
+#[derive(Debug, Clone, Default)]
+pub struct Minimap {
+    pub leaders: bool,
+}
+
+// the minimap's frame, spawned once the game starts
+struct MinimapRoot;
+// the world, sized to its aspect ratio
+struct MinimapWorld;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum MinimapDot {
+    Player,
+    // rank among the cells other than the player's, biggest first
+    Leader(usize),
+}
+
+fn dot(material: Handle<ColorMaterial>) -> NodeBundle {
+    NodeBundle {
+        style: Style {
+            position_type: PositionType::Absolute,
+            size: Size::new(Val::Px(DOT_SIZE), Val::Px(DOT_SIZE)),
+            display: Display::None,
+            ..Default::default()
+        },
+        material,
+        ..Default::default()
+    }
+}
+
+// the world in pixels; the longer side gets `MINIMAP_SIZE`
+fn world_size(rules: &GameRules) -> Vec2 {
+    let longer = rules.world_width.max(rules.world_height).max(1.0);
+    Vec2::new(rules.world_width, rules.world_height) * (MINIMAP_SIZE / longer)
+}
+
+pub fn minimap_setup(
+    commands: &mut Commands,
+    mut materials: ResMut<Assets<ColorMaterial>>,
+    minimap: Res<Minimap>,
+    rules: Res<GameRules>,
+) {
+    let size = world_size(&rules);
+    let frame = materials.add(Color::rgba(0.8, 0.8, 0.8, 0.6).into());
+    let world = materials.add(Color::rgba(0.1, 0.1, 0.1, 0.6).into());
+    let player = materials.add(Color::WHITE.into());
+    let leader = materials.add(Color::rgb(0.9, 0.3, 0.2).into());
+
+    // drawn by the hud's ui camera
+    commands
+        .spawn(NodeBundle {
+            style: Style {
+                position_type: PositionType::Absolute,
+                position: Rect {
+                    right: Val::Px(MINIMAP_MARGIN),
+                    bottom: Val::Px(MINIMAP_MARGIN),
+                    ..Default::default()
+                },
+                padding: Rect::all(Val::Px(FRAME_WIDTH)),
+                ..Default::default()
+            },
+            material: frame,
+            ..Default::default()
+        })
+        .with(MinimapRoot)
+        .with_children(|frame| {
+            frame
+                .spawn(NodeBundle {
+                    style: Style {
+                        size: Size::new(Val::Px(size.x), Val::Px(size.y)),
+                        ..Default::default()
+                    },
+                    material: world,
+                    ..Default::default()
+                })
+                .with(MinimapWorld)
+                .with_children(|world| {
+                    if minimap.leaders {
+                        for rank in 0..LEADERS {
+                            world
+                                .spawn(dot(leader.clone()))
+                                .with(MinimapDot::Leader(rank));
+                        }
+                    }
+                    // last, so it's drawn over the leaders
+                    world.spawn(dot(player)).with(MinimapDot::Player);
+                });
+        });
+}
+
+pub fn minimap_system(
+    player: Res<PlayerInfo>,
+    rules: Res<GameRules>,
+    agars: Query<(&Agar, &UpdateContext, &Transform)>,
+    mut nodes: Query<(&mut Style, Option<&MinimapDot>, Option<&MinimapWorld>)>,
+) {
+    let size = world_size(&rules);
+
+    let mut own = None;
+    let mut others = vec![];
+    for (agar, context, transform) in agars.iter() {
+        if Some(context.id) == player.id {
+            own = Some(transform.translation);
+        } else {
+            others.push((agar.size, transform.translation));
+        }
+    }
+    others.sort_by(|a, b| b.0.partial_cmp(&a.0).unwrap_or(std::cmp::Ordering::Equal));
+
+    for (mut style, dot, world) in nodes.iter_mut() {
+        // the world may have been resized
+        if world.is_some() {
+            let world = Size::new(Val::Px(size.x), Val::Px(size.y));
+            if style.size != world {
+                style.size = world;
+            }
+            continue;
+        }
+        let dot = match dot {
+            Some(dot) => dot,
+            None => continue,
+        };
+
+        let translation = match *dot {
+            MinimapDot::Player => own,
+            MinimapDot::Leader(rank) => others.get(rank).map(|(_, translation)| *translation),
+        };
+        match translation {
+            Some(translation) => {
+                // centered on the cell, kept inside the world
+                let x = translation.x / rules.world_width.max(1.0) * size.x;
+                let y = translation.y / rules.world_height.max(1.0) * size.y;
+                let half = DOT_SIZE / 2.0;
+                style.display = Display::Flex;
+                style.position = Rect {
+                    left: Val::Px(x.max(half).min(size.x - half) - half),
+                    bottom: Val::Px(y.max(half).min(size.y - half) - half),
+                    ..Default::default()
+                };
+            }
+            None => {
+                if style.display != Display::None {
+                    style.display = Display::None;
+                }
+            }
+        }
+    }
+}
@@ -0,0 +1,178 @@
+use crate::{
+    menu::UiFont,
+    settings::{Action, Actions},
+    PlayerInfo,
+};
+use agarlib::*;
+use bevy::prelude::*;
+
+// seconds between refreshes of the rates
+const REFRESH_INTERVAL: f64 = 1.0;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum OverlayLine {
+    Fps,
+    Rtt,
+    Snapshots,
+    Delay,
+    Bandwidth,
+    Dropped,
+}
+
+const LINES: [OverlayLine; 6] = [
+    OverlayLine::Fps,
+    OverlayLine::Rtt,
+    OverlayLine::Snapshots,
+    OverlayLine::Delay,
+    OverlayLine::Bandwidth,
+    OverlayLine::Dropped,
+];
+
+// rates over the last `REFRESH_INTERVAL`, from the totals in `NetworkStats`
+#[derive(Default)]
+pub struct Overlay {
+    visible: bool,
+    since: f64,
+    frames: u32,
+    snapshots: u64,
+    bytes_in: u64,
+    bytes_out: u64,
+    fps: f64,
+    snapshot_rate: f64,
+    in_rate: f64,
+    out_rate: f64,
+}
+
+pub fn overlay_setup(
+    commands: &mut Commands,
+    font: Res<UiFont>,
+    mut materials: ResMut<Assets<ColorMaterial>>,
+) {
+    let font = font.0.clone();
+    let transparent = materials.add(Color::rgba(0.0, 0.0, 0.0, 0.0).into());
+
+    // drawn by the hud's ui camera
+    commands
+        .spawn(NodeBundle {
+            style: Style {
+                position_type: PositionType::Absolute,
+                position: Rect {
+                    left: Val::Px(10.0),
+                    bottom: Val::Px(10.0),
+                    ..Default::default()
+                },
+                flex_direction: FlexDirection::ColumnReverse,
+                ..Default::default()
+            },
+            material: transparent,
+            ..Default::default()
+        })
+        .with_children(|overlay| {
+            for &line in LINES.iter() {
+                overlay
+                    .spawn(TextBundle {
+                        text: Text {
+                            value: String::new(),
+                            font: font.clone(),
+                            style: TextStyle {
+                                font_size: 16.0,
+                                color: Color::rgb(0.6, 1.0, 0.6),
+                                ..Default::default()
+                            },
+                        },
+                        ..Default::default()
+                    })
+                    .with(line);
+            }
+        });
+}
+
+// F3, unless rebound, shows and hides it
+pub fn overlay_system(
+    mut overlay: Local<Overlay>,
+    time: Res<Time>,
+    actions: Res<Actions>,
+    player: Res<PlayerInfo>,
+    clock: Res<ClockSync>,
+    stats: Res<NetworkStats>,
+    agars: Query<&UpdateContext, With<Agar>>,
+    mut lines: Query<(&OverlayLine, &mut Text)>,
+) {
+    if actions.just_pressed(Action::Overlay) {
+        overlay.visible = !overlay.visible;
+    }
+    overlay.frames += 1;
+
+    let now = time.seconds_since_startup();
+    let elapsed = now - overlay.since;
+    if elapsed >= REFRESH_INTERVAL {
+        let snapshots: u64 = stats
+            .connections
+            .values()
+            .map(|connection| connection.snapshots_received)
+            .sum();
+        let bytes_in: u64 = stats
+            .connections
+            .values()
+            .map(|connection| connection.bytes_received)
+            .sum();
+        let bytes_out: u64 = stats.bytes_sent.values().sum();
+
+        overlay.fps = overlay.frames as f64 / elapsed;
+        overlay.snapshot_rate = snapshots.saturating_sub(overlay.snapshots) as f64 / elapsed;
+        overlay.in_rate = bytes_in.saturating_sub(overlay.bytes_in) as f64 / elapsed;
+        overlay.out_rate = bytes_out.saturating_sub(overlay.bytes_out) as f64 / elapsed;
+        overlay.frames = 0;
+        overlay.snapshots = snapshots;
+        overlay.bytes_in = bytes_in;
+        overlay.bytes_out = bytes_out;
+        overlay.since = now;
+    }
+
+    // how old the newest snapshot is; cells are drawn `INTERP_DELAY` behind the server's clock
+    let delay = agars
+        .iter()
+        .filter(|context| player.id.map_or(true, |id| context.id == id))
+        .map(|context| context.frame)
+        .max()
+        .and_then(|frame| clock.frame_to_local_time(frame))
+        .map(|at| now - at);
+    let dropped: u64 = stats
+        .connections
+        .values()
+        .map(|connection| connection.snapshots_dropped)
+        .sum();
+    let resends: u64 = stats
+        .connections
+        .values()
+        .map(|connection| connection.resends)
+        .sum();
+
+    for (line, mut text) in lines.iter_mut() {
+        let value = if !overlay.visible {
+            String::new()
+        } else {
+            match line {
+                OverlayLine::Fps => format!("fps {:.0}", overlay.fps),
+                OverlayLine::Rtt => format!("rtt {:.0}ms", clock.rtt * 1000.0),
+                OverlayLine::Snapshots => format!("snapshots {:.1}/s", overlay.snapshot_rate),
+                OverlayLine::Delay => match delay {
+                    Some(delay) => format!("delay {:.0}ms", delay * 1000.0),
+                    None => "delay -".to_string(),
+                },
+                OverlayLine::Bandwidth => format!(
+                    "in {:.1}KB/s out {:.1}KB/s",
+                    overlay.in_rate / 1024.0,
+                    overlay.out_rate / 1024.0
+                ),
+                OverlayLine::Dropped => format!(
+                    "dropped {} snapshots {} messages, {} resends",
+                    dropped, stats.dropped, resends
+                ),
+            }
+        };
+        if text.value != value {
+            text.value = value;
+        }
+    }
+}
@@ -0,0 +1,111 @@
+use crate::{despawn::Despawning, settings::ClientSettings};
+use agarlib::*;
+use bevy::prelude::*;
+use bevy_prototype_lyon::prelude::*;
+
+// world units; the radius a feed is drawn with
+const FEED_RADIUS: f32 = 10.0;
+
+// what a feed of each color is drawn as
+fn feed_look(color: FeedColor, colorblind: bool) -> (Color, ShapeType) {
+    if !colorblind {
+        let color = match color {
+            FeedColor::Red => Color::rgb(0.8, 0.2, 0.2),
+            FeedColor::Green => Color::rgb(0.2, 0.8, 0.2),
+            FeedColor::Blue => Color::rgb(0.2, 0.2, 0.8),
+        };
+        return (color, ShapeType::Circle(FEED_RADIUS));
+    }
+
+    // the okabe-ito colors, told apart by their shapes as well
+    match color {
+        FeedColor::Red => (Color::rgb(0.84, 0.37, 0.0), ShapeType::Circle(FEED_RADIUS)),
+        FeedColor::Green => (
+            Color::rgb(0.0, 0.62, 0.45),
+            ShapeType::Rectangle {
+                width: FEED_RADIUS * 1.6,
+                height: FEED_RADIUS * 1.6,
+                origin: RectangleOrigin::Center,
+            },
+        ),
+        FeedColor::Blue => {
+            let corner = |angle: f32| {
+                let (y, x) = angle.to_radians().sin_cos();
+                point(x * FEED_RADIUS * 1.2, y * FEED_RADIUS * 1.2)
+            };
+            (
+                Color::rgb(0.35, 0.7, 0.9),
+                ShapeType::Triangle(corner(90.0), corner(210.0), corner(330.0)),
+            )
+        }
+    }
+}
+
+fn index(color: FeedColor) -> usize {
+    match color {
+        FeedColor::Red => 0,
+        FeedColor::Green => 1,
+        FeedColor::Blue => 2,
+    }
+}
+
+// one material and mesh per feed color, shared by all feeds of it
+#[derive(Default)]
+pub struct FeedLooks {
+    // the mode they were made for
+    colorblind: Option<bool>,
+    looks: Vec<(Handle<ColorMaterial>, Handle<Mesh>)>,
+}
+
+impl FeedLooks {
+    pub fn material(&self, color: FeedColor) -> Option<Handle<ColorMaterial>> {
+        self.looks
+            .get(index(color))
+            .map(|(material, _)| material.clone())
+    }
+}
+
+// draws new feeds, and every feed again once `colorblind` changes in the settings
+pub fn palette_system(
+    settings: Res<ClientSettings>,
+    mut looks: ResMut<FeedLooks>,
+    mut materials: ResMut<Assets<ColorMaterial>>,
+    mut meshes: ResMut<Assets<Mesh>>,
+    // fading feeds keep the copy of their material the fade works on
+    mut feeds: Query<
+        (Entity, &Feed, &mut Handle<ColorMaterial>, &mut Handle<Mesh>),
+        Without<Despawning>,
+    >,
+    added: Query<Entity, Added<Feed>>,
+) {
+    let restyle = looks.colorblind != Some(settings.colorblind);
+    if restyle {
+        looks.colorblind = Some(settings.colorblind);
+        looks.looks = [FeedColor::Red, FeedColor::Green, FeedColor::Blue]
+            .iter()
+            .map(|&color| {
+                let (color, shape) = feed_look(color, settings.colorblind);
+                let material = materials.add(color.into());
+                // only the tessellated mesh is kept
+                let mesh = primitive(
+                    material.clone(),
+                    &mut meshes,
+                    shape,
+                    TessellationMode::Fill(&FillOptions::default()),
+                    Vec3::zero(),
+                )
+                .mesh;
+                (material, mesh)
+            })
+            .collect();
+    }
+
+    for (entity, feed, mut material, mut mesh) in feeds.iter_mut() {
+        if !restyle && added.get(entity).is_err() {
+            continue;
+        }
+        let (new_material, new_mesh) = &looks.looks[index(feed.color)];
+        *material = new_material.clone();
+        *mesh = new_mesh.clone();
+    }
+}
@@ -0,0 +1,192 @@
+use crate::{
+    despawn::Despawning, interp::Interpolation, palette::FeedLooks, settings::ClientSettings,
+};
+use agarlib::*;
+use bevy::prelude::*;
+use std::collections::HashMap;
+
+// sparks alive at once; bursts past it are dropped, e.g. when a replay seeks ahead
+const MAX_PARTICLES: usize = 600;
+// the most sparks of a burst player
+const MAX_PLAYER_SPARKS: usize = 48;
+// seconds a trail dot lives
+const TRAIL_LIFE: f32 = 0.3;
+// the share of its speed a spark loses a second
+const DRAG: f32 = 3.0;
+// cells going this share of their top speed or faster, e.g. in a boost zone, leave a trail
+const TRAIL_SPEED: f32 = 0.9;
+// seconds between trail dots
+const TRAIL_INTERVAL: f64 = 0.05;
+
+// how the sparks of a burst fly; `speed` in world units a second, `life` in seconds
+struct Burst {
+    count: usize,
+    speed: f32,
+    size: f32,
+    life: f32,
+}
+
+const PELLET_BURST: Burst = Burst {
+    count: 8,
+    speed: 120.0,
+    size: 4.0,
+    life: 0.4,
+};
+
+struct Particle {
+    velocity: Vec2,
+    size: f32,
+    age: f32,
+    life: f32,
+}
+
+// a flat material per agar color, shared by all its sparks
+#[derive(Default)]
+pub struct Sparks {
+    materials: HashMap<u8, Handle<ColorMaterial>>,
+    next_trail: f64,
+}
+
+impl Sparks {
+    fn material(
+        &mut self,
+        materials: &mut Assets<ColorMaterial>,
+        color: u8,
+    ) -> Handle<ColorMaterial> {
+        self.materials
+            .entry(color)
+            .or_insert_with(|| materials.add(agar_color(color).into()))
+            .clone()
+    }
+}
+
+fn spark(
+    commands: &mut Commands,
+    material: Handle<ColorMaterial>,
+    at: Vec3,
+    velocity: Vec2,
+    size: f32,
+    life: f32,
+) {
+    commands
+        .spawn(SpriteBundle {
+            sprite: Sprite::new(Vec2::new(size, size)),
+            material,
+            transform: Transform::from_translation(at),
+            ..Default::default()
+        })
+        .with(Particle {
+            velocity,
+            size,
+            age: 0.0,
+            life,
+        });
+}
+
+// sparks flying out of a circle of `radius` around `at`, over what's there
+fn burst(
+    commands: &mut Commands,
+    material: Handle<ColorMaterial>,
+    at: Vec3,
+    radius: f32,
+    burst: &Burst,
+) {
+    for i in 0..burst.count {
+        let angle =
+            (i as f32 + rand::random::<f32>()) / burst.count as f32 * std::f32::consts::PI * 2.0;
+        let (y, x) = angle.sin_cos();
+        let direction = Vec2::new(x, y);
+        spark(
+            commands,
+            material.clone(),
+            at + (direction * radius * rand::random::<f32>()).extend(0.1),
+            direction * burst.speed * (0.5 + rand::random::<f32>()),
+            burst.size,
+            burst.life,
+        );
+    }
+}
+
+// bursts what the server removed, drops dots behind fast cells, and moves, shrinks and
+// despawns the sparks
+pub fn particles_system(
+    commands: &mut Commands,
+    time: Res<Time>,
+    settings: Res<ClientSettings>,
+    clock: Res<ClockSync>,
+    mut sparks: ResMut<Sparks>,
+    feed_looks: Res<FeedLooks>,
+    mut materials: ResMut<Assets<ColorMaterial>>,
+    eaten: Query<(&Transform, Option<&Agar>, Option<&Feed>), Added<Despawning>>,
+    cells: Query<(&Agar, &Interpolation, &Transform), Without<Despawning>>,
+    mut particles: Query<(Entity, &mut Particle, &mut Sprite, &mut Transform)>,
+) {
+    let delta = time.delta_seconds();
+    let mut alive = 0;
+    for (entity, mut particle, mut sprite, mut transform) in particles.iter_mut() {
+        particle.age += delta;
+        if particle.age >= particle.life {
+            commands.despawn(entity);
+            continue;
+        }
+        alive += 1;
+        transform.translation += (particle.velocity * delta).extend(0.0);
+        particle.velocity *= (1.0 - DRAG * delta).max(0.0);
+        let size = particle.size * (1.0 - particle.age / particle.life);
+        sprite.size = Vec2::new(size, size);
+    }
+
+    for (transform, agar, feed) in eaten.iter() {
+        if !settings.particles || alive >= MAX_PARTICLES {
+            continue;
+        }
+        let at = transform.translation;
+        match (agar, feed) {
+            (Some(agar), _) => {
+                // bigger cells burst into more, bigger and faster sparks
+                let player = Burst {
+                    count: (8 + (agar.size / 4.0) as usize).min(MAX_PLAYER_SPARKS),
+                    speed: 150.0 + agar.size,
+                    size: (agar.size * 0.2).max(4.0).min(24.0),
+                    life: 0.9,
+                };
+                // skinned cells carry the atlas in their material
+                let material = sparks.material(&mut materials, agar.color);
+                burst(commands, material, at, agar.size, &player);
+                alive += player.count;
+            }
+            (None, Some(feed)) => {
+                if let Some(material) = feed_looks.material(feed.color) {
+                    burst(commands, material, at, 0.0, &PELLET_BURST);
+                    alive += PELLET_BURST.count;
+                }
+            }
+            (None, None) => {}
+        }
+    }
+
+    let now = time.seconds_since_startup();
+    if !settings.particles || now < sparks.next_trail {
+        return;
+    }
+    sparks.next_trail = now + TRAIL_INTERVAL;
+    for (agar, interpolation, transform) in cells.iter() {
+        if alive >= MAX_PARTICLES {
+            break;
+        }
+        if interpolation.speed(clock.tick_rate) < agar.max_velocity * TRAIL_SPEED {
+            continue;
+        }
+        let material = sparks.material(&mut materials, agar.color);
+        // under the cell, left where it was
+        spark(
+            commands,
+            material,
+            transform.translation - Vec3::new(0.0, 0.0, 0.1),
+            Vec2::zero(),
+            agar.size,
+            TRAIL_LIFE,
+        );
+        alive += 1;
+    }
+}
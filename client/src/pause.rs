@@ -0,0 +1,292 @@
+use crate::{
+    chat::ChatInput,
+    menu::{AppState, UiFont},
+    settings::ClientSettings,
+    PlayerInfo,
+};
+use agarlib::*;
+use bevy::prelude::*;
+use bevy_networking_turbulence::NetworkResource;
+
+// the escape menu; the server keeps the match going, so only the keys, the mouse and the
+// sticks stop reaching the game while it's open
+#[derive(Default)]
+pub struct PauseMenu {
+    pub open: bool,
+    // the settings page instead of the buttons
+    settings: bool,
+    // `Logout` went out; the menu comes on the next frame, once it's flushed
+    leaving: bool,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum PauseNode {
+    Root,
+    Buttons,
+    Settings,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum PauseButton {
+    Resume,
+    Settings,
+    Disconnect,
+    Back,
+    Toggle(Toggle),
+}
+
+// the settings that take effect in the middle of a game; the rest are in the menu
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Toggle {
+    Labels,
+    Particles,
+    Colorblind,
+    Sound,
+}
+
+impl Toggle {
+    const ALL: [Toggle; 4] = [
+        Toggle::Labels,
+        Toggle::Particles,
+        Toggle::Colorblind,
+        Toggle::Sound,
+    ];
+
+    fn label(self, settings: &ClientSettings) -> String {
+        let (name, on) = match self {
+            Toggle::Labels => ("Names over cells", settings.labels),
+            Toggle::Particles => ("Particles", settings.particles),
+            Toggle::Colorblind => ("Colorblind feeds", settings.colorblind),
+            Toggle::Sound => ("Sound", !settings.muted),
+        };
+        format!("{}: {}", name, if on { "on" } else { "off" })
+    }
+
+    fn flip(self, settings: &mut ClientSettings) {
+        let value = match self {
+            Toggle::Labels => &mut settings.labels,
+            Toggle::Particles => &mut settings.particles,
+            Toggle::Colorblind => &mut settings.colorblind,
+            Toggle::Sound => &mut settings.muted,
+        };
+        *value = !*value;
+    }
+}
+
+struct ToggleLabel(Toggle);
+
+fn display(shown: bool) -> Display {
+    if shown {
+        Display::Flex
+    } else {
+        Display::None
+    }
+}
+
+pub fn pause_setup(
+    commands: &mut Commands,
+    font: Res<UiFont>,
+    mut materials: ResMut<Assets<ColorMaterial>>,
+    settings: Res<ClientSettings>,
+) {
+    let font = font.0.clone();
+    let text = |value: &str, font_size: f32| TextBundle {
+        text: Text {
+            value: value.to_string(),
+            font: font.clone(),
+            style: TextStyle {
+                font_size,
+                color: Color::WHITE,
+                ..Default::default()
+            },
+        },
+        style: Style {
+            margin: Rect::all(Val::Px(6.0)),
+            ..Default::default()
+        },
+        ..Default::default()
+    };
+    let shade = materials.add(Color::rgba(0.0, 0.0, 0.0, 0.5).into());
+    let transparent = materials.add(Color::rgba(0.0, 0.0, 0.0, 0.0).into());
+    let button = materials.add(Color::rgb(0.15, 0.15, 0.15).into());
+    let button_style = Style {
+        size: Size::new(Val::Px(320.0), Val::Auto),
+        justify_content: JustifyContent::Center,
+        margin: Rect::all(Val::Px(4.0)),
+        ..Default::default()
+    };
+    let page = |kind| NodeBundle {
+        style: Style {
+            display: display(kind == PauseNode::Buttons),
+            flex_direction: FlexDirection::ColumnReverse,
+            align_items: AlignItems::Center,
+            ..Default::default()
+        },
+        material: transparent.clone(),
+        ..Default::default()
+    };
+
+    // drawn by the hud's ui camera, over the game and its death screen, whose buttons it
+    // keeps from being clicked
+    commands
+        .spawn(NodeBundle {
+            style: Style {
+                display: Display::None,
+                size: Size::new(Val::Percent(100.0), Val::Percent(100.0)),
+                position_type: PositionType::Absolute,
+                flex_direction: FlexDirection::ColumnReverse,
+                justify_content: JustifyContent::Center,
+                align_items: AlignItems::Center,
+                ..Default::default()
+            },
+            material: shade,
+            ..Default::default()
+        })
+        .with(PauseNode::Root)
+        .with_children(|root| {
+            root.spawn(text("Paused", 40.0));
+            root.spawn(text("The match goes on; Escape gets back to it", 16.0));
+            root.spawn(page(PauseNode::Buttons))
+                .with(PauseNode::Buttons)
+                .with_children(|page| {
+                    let buttons = [
+                        (PauseButton::Resume, "Resume"),
+                        (PauseButton::Settings, "Settings"),
+                        (PauseButton::Disconnect, "Disconnect"),
+                    ];
+                    for &(kind, label) in buttons.iter() {
+                        page.spawn(ButtonBundle {
+                            style: button_style.clone(),
+                            material: button.clone(),
+                            ..Default::default()
+                        })
+                        .with(kind)
+                        .with_children(|button| {
+                            button.spawn(text(label, 24.0));
+                        });
+                    }
+                });
+            root.spawn(page(PauseNode::Settings))
+                .with(PauseNode::Settings)
+                .with_children(|page| {
+                    for &toggle in Toggle::ALL.iter() {
+                        page.spawn(ButtonBundle {
+                            style: button_style.clone(),
+                            material: button.clone(),
+                            ..Default::default()
+                        })
+                        .with(PauseButton::Toggle(toggle))
+                        .with_children(|button| {
+                            button
+                                .spawn(text(&toggle.label(&settings), 20.0))
+                                .with(ToggleLabel(toggle));
+                        });
+                    }
+                    page.spawn(text("Keys are bound in the menu", 16.0));
+                    page.spawn(ButtonBundle {
+                        style: button_style.clone(),
+                        material: button.clone(),
+                        ..Default::default()
+                    })
+                    .with(PauseButton::Back)
+                    .with_children(|button| {
+                        button.spawn(text("Back", 24.0));
+                    });
+                });
+        });
+}
+
+// runs before the chat: Escape (or a pad's start button) opens and closes the menu unless the
+// chat has the keyboard, and while it's open the keys are taken away from everyone else
+pub fn pause_input_system(
+    state: Res<State<AppState>>,
+    player: Res<PlayerInfo>,
+    chat: Res<ChatInput>,
+    mut pause: ResMut<PauseMenu>,
+    mut keys: ResMut<Input<KeyCode>>,
+    pads: Res<Input<GamepadButton>>,
+) {
+    if *state.current() != AppState::Playing {
+        *pause = PauseMenu::default();
+        return;
+    }
+    if chat.open {
+        return;
+    }
+
+    let start = pads
+        .get_just_pressed()
+        .any(|GamepadButton(_, button)| *button == GamepadButtonType::Start);
+    // nothing to pause before the server let the player in; the loading screen is up
+    if (keys.just_pressed(KeyCode::Escape) || start) && (pause.open || player.logged_in) {
+        pause.open = !pause.open;
+        pause.settings = false;
+        *keys = Input::default();
+    }
+    if pause.open {
+        *keys = Input::default();
+    }
+}
+
+pub fn pause_system(
+    mut pause: ResMut<PauseMenu>,
+    mut settings: ResMut<ClientSettings>,
+    mut net: ResMut<NetworkResource>,
+    formats: Res<WireFormats>,
+    mut stats: ResMut<NetworkStats>,
+    mut state: ResMut<State<AppState>>,
+    clicks: Query<(&Interaction, &PauseButton), Mutated<Interaction>>,
+    mut nodes: Query<(&mut Style, &PauseNode)>,
+    mut labels: Query<(&mut Text, &ToggleLabel)>,
+) {
+    if pause.leaving {
+        if state.next().is_none() {
+            if let Err(err) = state.set_next(AppState::Menu) {
+                warn!("Unable to return to the menu: {:?}", err);
+            }
+        }
+        return;
+    }
+
+    for (interaction, button) in clicks.iter() {
+        if *interaction != Interaction::Clicked || !pause.open {
+            continue;
+        }
+        match button {
+            PauseButton::Resume => pause.open = false,
+            PauseButton::Settings => pause.settings = true,
+            PauseButton::Back => pause.settings = false,
+            PauseButton::Toggle(toggle) => {
+                toggle.flip(&mut settings);
+                settings.save();
+            }
+            PauseButton::Disconnect => {
+                info!("Logging out");
+                let message = ClientMessage::Logout;
+                broadcast_wire(&mut net, &formats, &message);
+                stats.sent(&message);
+                pause.leaving = true;
+            }
+        }
+    }
+
+    for (mut style, node) in nodes.iter_mut() {
+        let shown = match node {
+            PauseNode::Root => pause.open,
+            PauseNode::Buttons => !pause.settings,
+            PauseNode::Settings => pause.settings,
+        };
+        if style.display != display(shown) {
+            style.display = display(shown);
+        }
+    }
+    if !pause.open {
+        return;
+    }
+    for (mut text, label) in labels.iter_mut() {
+        let value = label.0.label(&settings);
+        if text.value != value {
+            text.value = value;
+        }
+    }
+}
@@ -0,0 +1,145 @@
+use crate::{
+    menu::UiFont,
+    minimap::{FRAME_WIDTH, MINIMAP_MARGIN, MINIMAP_SIZE},
+    status::{ConnectionStatus, Status},
+};
+use agarlib::*;
+use bevy::prelude::*;
+
+// round trips past these turn the ping yellow and red; red is where the connection counts
+// as degraded
+const WARN_RTT: f64 = 0.15;
+const BAD_RTT: f64 = DEGRADED_RTT;
+// seconds without a snapshot before the icon flashes; the server sends several a second
+const STALL_TIME: f64 = 1.0;
+// flashes a second
+const FLASH_RATE: f64 = 2.0;
+const ICON_SIZE: f32 = 12.0;
+
+const GOOD_COLOR: Color = Color::rgb(0.6, 1.0, 0.6);
+const WARN_COLOR: Color = Color::rgb(1.0, 0.85, 0.3);
+const BAD_COLOR: Color = Color::rgb(1.0, 0.3, 0.3);
+
+// the snapshots counted for the connection in use, and when the count last moved
+#[derive(Default)]
+pub struct Ping {
+    handle: Option<ConnectionHandle>,
+    snapshots: u64,
+    since: f64,
+}
+
+struct PingText;
+struct StallIcon;
+
+pub fn ping_setup(
+    commands: &mut Commands,
+    font: Res<UiFont>,
+    mut materials: ResMut<Assets<ColorMaterial>>,
+) {
+    let font = font.0.clone();
+    let transparent = materials.add(Color::rgba(0.0, 0.0, 0.0, 0.0).into());
+    let icon = materials.add(BAD_COLOR.into());
+
+    // drawn by the hud's ui camera, left of the minimap's bottom edge
+    commands
+        .spawn(NodeBundle {
+            style: Style {
+                position_type: PositionType::Absolute,
+                position: Rect {
+                    right: Val::Px(MINIMAP_MARGIN * 2.0 + MINIMAP_SIZE + FRAME_WIDTH * 2.0),
+                    bottom: Val::Px(MINIMAP_MARGIN),
+                    ..Default::default()
+                },
+                align_items: AlignItems::Center,
+                ..Default::default()
+            },
+            material: transparent,
+            ..Default::default()
+        })
+        .with_children(|row| {
+            row.spawn(NodeBundle {
+                style: Style {
+                    size: Size::new(Val::Px(ICON_SIZE), Val::Px(ICON_SIZE)),
+                    margin: Rect::all(Val::Px(4.0)),
+                    display: Display::None,
+                    ..Default::default()
+                },
+                material: icon,
+                ..Default::default()
+            })
+            .with(StallIcon);
+            row.spawn(TextBundle {
+                text: Text {
+                    value: String::new(),
+                    font,
+                    style: TextStyle {
+                        font_size: 16.0,
+                        color: GOOD_COLOR,
+                        ..Default::default()
+                    },
+                },
+                ..Default::default()
+            })
+            .with(PingText);
+        });
+}
+
+// the smoothed round trip from the clock sync, colored by how bad it is, and a flashing icon
+// once snapshots stop coming while the connection is still up
+pub fn ping_system(
+    mut ping: Local<Ping>,
+    time: Res<Time>,
+    clock: Res<ClockSync>,
+    status: Res<ConnectionStatus>,
+    stats: Res<NetworkStats>,
+    mut texts: Query<&mut Text, With<PingText>>,
+    mut icons: Query<&mut Style, With<StallIcon>>,
+) {
+    let now = time.seconds_since_startup();
+    let up = matches!(status.status, Status::Connected | Status::Degraded);
+
+    let snapshots = status
+        .handle
+        .and_then(|handle| stats.connections.get(&handle))
+        .map_or(0, |connection| connection.snapshots_received);
+    if status.handle != ping.handle || snapshots != ping.snapshots {
+        ping.handle = status.handle;
+        ping.snapshots = snapshots;
+        ping.since = now;
+    }
+    // nothing arrived yet while logging in
+    let stalled = up && snapshots > 0 && now - ping.since > STALL_TIME;
+
+    let (value, color) = match clock.offset {
+        Some(_) if up => {
+            let color = if clock.rtt > BAD_RTT {
+                BAD_COLOR
+            } else if clock.rtt > WARN_RTT {
+                WARN_COLOR
+            } else {
+                GOOD_COLOR
+            };
+            (format!("{:.0}ms", clock.rtt * 1000.0), color)
+        }
+        _ => (String::new(), GOOD_COLOR),
+    };
+    for mut text in texts.iter_mut() {
+        if text.value != value {
+            text.value = value.clone();
+        }
+        if text.style.color != color {
+            text.style.color = color;
+        }
+    }
+
+    let display = if stalled && (now * FLASH_RATE).fract() < 0.5 {
+        Display::Flex
+    } else {
+        Display::None
+    };
+    for mut style in icons.iter_mut() {
+        if style.display != display {
+            style.display = display;
+        }
+    }
+}
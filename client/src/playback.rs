@@ -0,0 +1,315 @@
+use crate::{
+    apply_feeds, apply_snapshots,
+    chat::ChatReceived,
+    despawn::fade_out,
+    interp::INTERP_DELAY,
+    labels::Names,
+    menu::UiFont,
+    settings::{Action, Actions},
+    skins::Palette,
+    AgarQuery, FeedState, PlayerInfo,
+};
+use agarlib::*;
+use anyhow::{anyhow, Context, Result};
+use bevy::prelude::*;
+use std::{fs::File, io::BufReader};
+
+// seconds a seek moves by
+const SEEK_STEP: f64 = 10.0;
+const SPEEDS: [f64; 7] = [0.125, 0.25, 0.5, 1.0, 2.0, 4.0, 8.0];
+const NORMAL_SPEED: usize = 3;
+
+// a replay recorded by the server, played back in place of the network; see `--replay`
+pub struct Playback {
+    path: String,
+    tick_rate: f64,
+    rules: GameRules,
+    // the frames of one world, in order
+    frames: Vec<ReplayFrame>,
+    // the next frame to apply
+    next: usize,
+    // the frame on screen, between ticks while playing
+    position: f64,
+    speed: usize,
+    paused: bool,
+    // paused by the server's operator at the time
+    match_paused: bool,
+}
+
+impl Playback {
+    // the whole file is read up front so seeking back doesn't reread it; a replay of a
+    // server hosting several worlds plays the first one
+    pub fn load(path: &str) -> Result<Self> {
+        let file = File::open(path).with_context(|| format!("opening {}", path))?;
+        let mut reader = ReplayReader::new(BufReader::new(file))?;
+        let header = reader.header().clone();
+
+        let mut frames: Vec<ReplayFrame> = vec![];
+        for frame in &mut reader {
+            let frame = match frame {
+                Ok(frame) => frame,
+                // a server that crashed leaves the last record cut short
+                Err(_) if !frames.is_empty() => break,
+                Err(err) => return Err(err),
+            };
+            if frames
+                .first()
+                .map_or(true, |first| first.world == frame.world)
+            {
+                frames.push(frame);
+            }
+        }
+        let position = frames
+            .first()
+            .ok_or_else(|| anyhow!("the replay has no frames"))?
+            .frame as f64;
+
+        Ok(Self {
+            path: path.to_string(),
+            tick_rate: header.tick_rate,
+            rules: header.rules,
+            position,
+            frames,
+            next: 0,
+            speed: NORMAL_SPEED,
+            paused: false,
+            match_paused: false,
+        })
+    }
+
+    pub fn rules(&self) -> GameRules {
+        self.rules.clone()
+    }
+
+    fn first_frame(&self) -> f64 {
+        self.frames.first().map_or(0.0, |frame| frame.frame as f64)
+    }
+
+    fn last_frame(&self) -> f64 {
+        self.frames.last().map_or(0.0, |frame| frame.frame as f64)
+    }
+}
+
+fn clock_text(seconds: f64) -> String {
+    let seconds = seconds.max(0.0) as u64;
+    format!("{}:{:02}", seconds / 60, seconds % 60)
+}
+
+struct PlaybackLine;
+
+pub fn playback_setup(
+    commands: &mut Commands,
+    playback: Res<Playback>,
+    mut player: ResMut<PlayerInfo>,
+    font: Res<UiFont>,
+    mut materials: ResMut<Assets<ColorMaterial>>,
+) {
+    info!(
+        "Playing back {} frames of world {} from {}",
+        playback.frames.len(),
+        playback.frames[0].world,
+        playback.path
+    );
+    // nobody to play as; the spectator camera looks around
+    player.spectating = true;
+
+    let font = font.0.clone();
+    let transparent = materials.add(Color::rgba(0.0, 0.0, 0.0, 0.0).into());
+    commands
+        .spawn(CameraUiBundle::default())
+        .spawn(NodeBundle {
+            style: Style {
+                size: Size::new(Val::Percent(100.0), Val::Auto),
+                position_type: PositionType::Absolute,
+                position: Rect {
+                    bottom: Val::Px(10.0),
+                    ..Default::default()
+                },
+                justify_content: JustifyContent::Center,
+                ..Default::default()
+            },
+            material: transparent,
+            ..Default::default()
+        })
+        .with_children(|line| {
+            line.spawn(TextBundle {
+                text: Text {
+                    value: String::new(),
+                    font,
+                    style: TextStyle {
+                        font_size: 20.0,
+                        color: Color::WHITE,
+                        ..Default::default()
+                    },
+                },
+                ..Default::default()
+            })
+            .with(PlaybackLine);
+        });
+}
+
+// plays the frames due by the playback clock through the same paths as the network's;
+// seeking back starts over from the first frame and catches up within one run
+pub fn playback_system(
+    commands: &mut Commands,
+    time: Res<Time>,
+    actions: Res<Actions>,
+    mut playback: ResMut<Playback>,
+    mut player: ResMut<PlayerInfo>,
+    mut clock: ResMut<ClockSync>,
+    mut rules: ResMut<GameRules>,
+    mut entities: ResMut<NetworkEntities>,
+    mut feed_state: ResMut<FeedState>,
+    mut names: ResMut<Names>,
+    (mut materials, mut palette): (ResMut<Assets<ColorMaterial>>, ResMut<Palette>),
+    mut meshes: ResMut<Assets<Mesh>>,
+    mut agars: AgarQuery,
+    replicated: Query<Entity, With<UpdateContext>>,
+    mut lines: Query<&mut Text, With<PlaybackLine>>,
+) {
+    let (first, last) = (playback.first_frame(), playback.last_frame());
+    let tick_rate = playback.tick_rate;
+
+    if actions.just_pressed(Action::PlaybackPause) {
+        // at the end, playing again starts over
+        if playback.paused && playback.position >= last {
+            playback.position = first - 1.0;
+        }
+        playback.paused = !playback.paused;
+    }
+    if actions.just_pressed(Action::PlaybackSlower) {
+        playback.speed = playback.speed.saturating_sub(1);
+    }
+    if actions.just_pressed(Action::PlaybackFaster) {
+        playback.speed = (playback.speed + 1).min(SPEEDS.len() - 1);
+    }
+    let mut seek = 0.0;
+    if actions.just_pressed(Action::SeekBack) {
+        seek -= SEEK_STEP * tick_rate;
+    }
+    if actions.just_pressed(Action::SeekForward) {
+        seek += SEEK_STEP * tick_rate;
+    }
+
+    if !playback.paused {
+        playback.position += time.delta_seconds_f64() * tick_rate * SPEEDS[playback.speed];
+    }
+    playback.position = (playback.position + seek).max(first - 1.0).min(last);
+    if playback.position >= last && !playback.paused {
+        info!("End of replay");
+        playback.paused = true;
+    }
+
+    // frames a little ahead of the clock, so cells have a snapshot on either side of it
+    let due = playback.position + INTERP_DELAY * tick_rate;
+    let shown = playback
+        .next
+        .checked_sub(1)
+        .map(|index| playback.frames[index].frame as f64);
+    if shown.map_or(false, |shown| due < shown) {
+        for entity in replicated.iter() {
+            commands.despawn(entity);
+        }
+        *entities = NetworkEntities::default();
+        *feed_state = FeedState::default();
+        playback.match_paused = false;
+        commands.insert_resource(Leaderboard::default());
+        *names = Names::default();
+        playback.next = 0;
+        // the old cells are still around until the commands run
+        return;
+    }
+
+    let start = playback.next;
+    let end = playback.frames[start..]
+        .iter()
+        .position(|frame| frame.frame as f64 > due)
+        .map_or(playback.frames.len(), |count| start + count);
+
+    let mut snapshots = vec![];
+    let mut match_paused = playback.match_paused;
+    for frame in &playback.frames[start..end] {
+        for event in &frame.events {
+            match event.clone() {
+                ReplayEvent::State(state) => {
+                    match_paused = state.paused;
+                    snapshots.push((state.frame, state.agars));
+                }
+                ReplayEvent::Feeds { baseline, updates } => {
+                    apply_feeds(commands, &mut feed_state, &mut entities, baseline, updates);
+                }
+                ReplayEvent::Message(message) => match message {
+                    ClientMessage::EntityDespawned(id) => {
+                        if let Some(entity) = entities.remove(id) {
+                            fade_out(commands, entity);
+                        }
+                        // unlike the network's, the order is exact, and the server hands
+                        // the id out again later
+                        for (_, snapshot) in &mut snapshots {
+                            snapshot.remove(&id);
+                        }
+                    }
+                    ClientMessage::Map(map) => {
+                        commands.insert_resource(map);
+                    }
+                    ClientMessage::Leaderboard(leaderboard) => {
+                        commands.insert_resource(leaderboard);
+                    }
+                    ClientMessage::Names {
+                        baseline,
+                        names: received,
+                    } => names.receive(baseline, received),
+                    ClientMessage::Rules(replay_rules) => *rules = replay_rules,
+                    ClientMessage::ChatLine {
+                        from,
+                        name,
+                        text,
+                        team,
+                    } => {
+                        commands.spawn((ChatReceived {
+                            from,
+                            name,
+                            text,
+                            team,
+                        },));
+                    }
+                    _ => {}
+                },
+            }
+        }
+    }
+    playback.next = end;
+    playback.match_paused = match_paused;
+    apply_snapshots(
+        commands,
+        &mut agars,
+        &mut entities,
+        (&mut materials, &mut palette),
+        &mut meshes,
+        &rules,
+        snapshots,
+        &[],
+    );
+
+    // the playback clock stands in for the server's, so interpolation follows it
+    clock.tick_rate = tick_rate;
+    clock.frame = 0;
+    clock.frame_time = 0.0;
+    clock.offset =
+        Some(playback.position / tick_rate + INTERP_DELAY - time.seconds_since_startup());
+    player.paused = playback.paused || match_paused;
+
+    let value = format!(
+        "{} {} / {} at {}x{}",
+        playback.path,
+        clock_text((playback.position - first) / tick_rate),
+        clock_text((last - first) / tick_rate),
+        SPEEDS[playback.speed],
+        if playback.paused { ", paused" } else { "" }
+    );
+    for mut text in lines.iter_mut() {
+        if text.value != value {
+            text.value = value.clone();
+        }
+    }
+}
@@ -0,0 +1,119 @@
+use crate::PlayerInfo;
+use agarlib::*;
+use bevy::prelude::*;
+
+// seconds a feed stays hidden without the server despawning it before it comes back, on top
+// of the round trip
+const REVERT_TIME: f64 = 0.3;
+// seconds after the last prediction that the growth is kept at most, in case the server's
+// size never catches up, e.g. after a virus popped the cell
+const SETTLE_TIME: f64 = 1.0;
+
+// on a feed hidden because the player's cell ran over it, until the server agrees
+pub struct Predicted;
+
+// feeds the player's cell is taken to have eaten before the server says so, and the growth
+// they're worth
+#[derive(Default)]
+pub struct Predictions {
+    feeds: Vec<(Entity, f64)>,
+    // the server's size of the cell before the first of them
+    base: f32,
+    growth: f32,
+    since: f64,
+}
+
+impl Predictions {
+    // what the player's cell is drawn at; never less than the server's size
+    pub fn size(&self, size: f32) -> f32 {
+        size.max(self.base + self.growth)
+    }
+
+    fn clear(&mut self) {
+        self.base = 0.0;
+        self.growth = 0.0;
+    }
+}
+
+// hides feeds the drawn cell of the player covers right away and grows the cell by them,
+// instead of waiting a round trip for the server; a feed the server doesn't despawn in time
+// comes back, along with the growth
+pub fn predict_system(
+    commands: &mut Commands,
+    time: Res<Time>,
+    clock: Res<ClockSync>,
+    player: Res<PlayerInfo>,
+    mut predictions: ResMut<Predictions>,
+    agars: Query<(&Agar, &UpdateContext, &Transform)>,
+    mut feeds: Query<
+        (
+            Entity,
+            &Transform,
+            &mut Visible,
+            Option<&UpdateContext>,
+            Option<&Predicted>,
+        ),
+        With<Feed>,
+    >,
+) {
+    let now = time.seconds_since_startup();
+    let own = player.id.and_then(|id| {
+        agars
+            .iter()
+            .find(|(_, context, _)| context.id == id)
+            .map(|(agar, _, transform)| (agar.size, transform.translation))
+    });
+
+    // the server despawned it, which takes the context away, or it's gone altogether
+    let revert_after = REVERT_TIME + clock.rtt;
+    let mut reverted = 0.0;
+    predictions.feeds.retain(|&(entity, at)| {
+        let (_, _, mut visible, context, _) = match feeds.get_mut(entity) {
+            Ok(feed) => feed,
+            Err(_) => return false,
+        };
+        if context.is_none() {
+            return false;
+        }
+        if own.is_some() && now - at < revert_after {
+            return true;
+        }
+        visible.is_visible = true;
+        commands.remove_one::<Predicted>(entity);
+        reverted += FEED_GROWTH;
+        false
+    });
+    predictions.growth = (predictions.growth - reverted).max(0.0);
+
+    let (size, at) = match own {
+        Some(own) => own,
+        None => {
+            predictions.clear();
+            return;
+        }
+    };
+    // the server's snapshots have caught up
+    if size >= predictions.base + predictions.growth
+        || (predictions.feeds.is_empty() && now - predictions.since > SETTLE_TIME)
+    {
+        predictions.clear();
+    }
+
+    for (entity, transform, mut visible, context, predicted) in feeds.iter_mut() {
+        if context.is_none() || predicted.is_some() {
+            continue;
+        }
+        // the server's rule, on what's drawn
+        if transform.translation.truncate().distance(at.truncate()) >= size {
+            continue;
+        }
+        if predictions.growth <= 0.0 {
+            predictions.base = size;
+        }
+        visible.is_visible = false;
+        commands.insert_one(entity, Predicted);
+        predictions.feeds.push((entity, now));
+        predictions.growth += FEED_GROWTH;
+        predictions.since = now;
+    }
+}
@@ -0,0 +1,28 @@
+use crate::storage;
+use bevy::prelude::*;
+
+// servers the player connected to lately, newest first
+const MAX_RECENT: usize = 5;
+const KEY: &str = "servers";
+
+pub fn load() -> Vec<String> {
+    storage::read(KEY)
+        .unwrap_or_default()
+        .lines()
+        .map(str::trim)
+        .filter(|line| !line.is_empty())
+        .take(MAX_RECENT)
+        .map(String::from)
+        .collect()
+}
+
+// moves `server` to the front and saves the list
+pub fn remember(recent: &mut Vec<String>, server: &str) {
+    recent.retain(|entry| entry != server);
+    recent.insert(0, server.to_string());
+    recent.truncate(MAX_RECENT);
+
+    if let Err(err) = storage::write(KEY, &recent.join("\n")) {
+        warn!("Unable to save recent servers: {}", err);
+    }
+}
@@ -0,0 +1,150 @@
+use crate::{
+    death::Life,
+    labels::Names,
+    menu::{AppState, Menu},
+    spectate::Spectator,
+    status::{ConnectionStatus, Status},
+    FeedState, PlayerInfo,
+};
+use agarlib::*;
+use bevy::{
+    prelude::*,
+    render::{camera::Camera, render_graph::base::camera::CAMERA_UI},
+};
+use bevy_networking_turbulence::NetworkResource;
+
+// tries before giving up and going back to the menu
+pub const MAX_RECONNECTS: u32 = 5;
+// seconds before the first try, doubled for each one after up to `MAX_BACKOFF`
+const FIRST_BACKOFF: f64 = 1.0;
+const MAX_BACKOFF: f64 = 16.0;
+
+fn backoff(attempt: u32) -> f64 {
+    (FIRST_BACKOFF * 2f64.powi(attempt as i32)).min(MAX_BACKOFF)
+}
+
+// drops every connection and everything they replicated
+fn disconnect(
+    commands: &mut Commands,
+    net: &mut NetworkResource,
+    formats: &mut WireFormats,
+    entities: &mut NetworkEntities,
+    feed_state: &mut FeedState,
+    replicated: &Query<Entity, With<UpdateContext>>,
+) {
+    for handle in net.connections.keys() {
+        formats.remove(*handle);
+    }
+    net.connections.clear();
+    for entity in replicated.iter() {
+        commands.despawn(entity);
+    }
+    *entities = NetworkEntities::default();
+    *feed_state = FeedState::default();
+}
+
+// after a lost connection, connects again with the owner token from `Owner`, which gets
+// the player their cell back if the server still has it; see `RESUME_GRACE`
+pub fn reconnect_system(
+    commands: &mut Commands,
+    time: Res<Time>,
+    config: Res<NetworkConfig>,
+    mut status: ResMut<ConnectionStatus>,
+    mut net: ResMut<NetworkResource>,
+    mut formats: ResMut<WireFormats>,
+    mut entities: ResMut<NetworkEntities>,
+    mut feed_state: ResMut<FeedState>,
+    mut player: ResMut<PlayerInfo>,
+    mut spectator: ResMut<Spectator>,
+    mut menu: ResMut<Menu>,
+    mut state: ResMut<State<AppState>>,
+    replicated: Query<Entity, With<UpdateContext>>,
+) {
+    let now = time.seconds_since_startup();
+    match status.status {
+        Status::Connected | Status::Degraded => status.attempt = 0,
+        Status::Lost | Status::Unreachable if status.attempt < MAX_RECONNECTS => {
+            let delay = backoff(status.attempt);
+            info!("Reconnecting in {}s", delay);
+            status.status = Status::Reconnecting;
+            status.handle = None;
+            status.retry_at = Some(now + delay);
+            status.since = now;
+        }
+        Status::Lost | Status::Unreachable => {
+            if state.next().is_none() {
+                let addr =
+                    client_addr(&config).map_or("the server".to_string(), |addr| addr.to_string());
+                warn!("Giving up on {} after {} tries", addr, status.attempt);
+                menu.error = Some(format!("Lost the connection to {}", addr));
+                if let Err(err) = state.set_next(AppState::Menu) {
+                    warn!("Unable to return to the menu: {:?}", err);
+                }
+            }
+        }
+        Status::Reconnecting if status.retry_at.map_or(true, |at| now >= at) => {
+            status.attempt += 1;
+            status.status = Status::Connecting;
+            status.retry_at = None;
+            status.since = now;
+            disconnect(
+                commands,
+                &mut net,
+                &mut formats,
+                &mut entities,
+                &mut feed_state,
+                &replicated,
+            );
+            player.id = None;
+            player.logged_in = false;
+            spectator.resubscribe();
+            connect(&mut net, &config);
+        }
+        _ => {}
+    }
+}
+
+// back to the menu: everything from the game goes, including the ui camera
+pub fn leave_cleanup(
+    commands: &mut Commands,
+    mut net: ResMut<NetworkResource>,
+    mut formats: ResMut<WireFormats>,
+    mut entities: ResMut<NetworkEntities>,
+    mut feed_state: ResMut<FeedState>,
+    mut player: ResMut<PlayerInfo>,
+    mut life: ResMut<State<Life>>,
+    replicated: Query<Entity, With<UpdateContext>>,
+    nodes: Query<Entity, (With<Node>, Without<Parent>)>,
+    cameras: Query<(Entity, &Camera)>,
+) {
+    disconnect(
+        commands,
+        &mut net,
+        &mut formats,
+        &mut entities,
+        &mut feed_state,
+        &replicated,
+    );
+    for entity in nodes.iter() {
+        commands.despawn_recursive(entity);
+    }
+    for (entity, camera) in cameras.iter() {
+        if camera.name.as_deref() == Some(CAMERA_UI) {
+            commands.despawn(entity);
+        }
+    }
+    commands.insert_resource(Leaderboard::default());
+    commands.insert_resource(Names::default());
+
+    player.id = None;
+    player.logged_in = false;
+    player.death = None;
+    player.rejection = None;
+    player.spectating = false;
+    player.paused = false;
+    if *life.current() == Life::Dead {
+        if let Err(err) = life.set_next(Life::Alive) {
+            warn!("Unable to leave the death screen: {:?}", err);
+        }
+    }
+}
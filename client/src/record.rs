@@ -0,0 +1,285 @@
+use crate::{
+    labels::Names,
+    settings::{Action, Actions},
+    PlayerInfo,
+};
+use agarlib::*;
+use anyhow::Result;
+use bevy::{app::AppExit, prelude::*};
+use std::collections::HashMap;
+
+// the desktop streams into the file, the browser keeps the replay in memory until it's
+// downloaded
+#[cfg(not(target_arch = "wasm32"))]
+type Sink = std::io::BufWriter<std::fs::File>;
+#[cfg(target_arch = "wasm32")]
+type Sink = Vec<u8>;
+
+#[cfg(not(target_arch = "wasm32"))]
+fn file_name() -> String {
+    let now = std::time::SystemTime::now()
+        .duration_since(std::time::UNIX_EPOCH)
+        .map_or(0, |since| since.as_secs());
+    format!("agar-{}.replay", now)
+}
+
+#[cfg(target_arch = "wasm32")]
+fn file_name() -> String {
+    format!("agar-{}.replay", (js_sys::Date::now() / 1000.0) as u64)
+}
+
+// in the directory the client runs in
+#[cfg(not(target_arch = "wasm32"))]
+fn open(name: &str) -> Result<Sink> {
+    use anyhow::Context;
+    let file = std::fs::File::create(name).with_context(|| format!("creating {}", name))?;
+    Ok(std::io::BufWriter::new(file))
+}
+
+#[cfg(target_arch = "wasm32")]
+fn open(_name: &str) -> Result<Sink> {
+    Ok(vec![])
+}
+
+#[cfg(not(target_arch = "wasm32"))]
+fn save(mut sink: Sink, _name: &str) -> Result<()> {
+    use std::io::Write;
+    Ok(sink.flush()?)
+}
+
+// offered as a download
+#[cfg(target_arch = "wasm32")]
+fn save(sink: Sink, name: &str) -> Result<()> {
+    use anyhow::anyhow;
+    use wasm_bindgen::JsCast;
+
+    let parts = js_sys::Array::new();
+    parts.push(&js_sys::Uint8Array::from(&sink[..]));
+    let blob = web_sys::Blob::new_with_u8_array_sequence(&parts)
+        .map_err(|err| anyhow!("unable to make a blob: {:?}", err))?;
+    let url = web_sys::Url::create_object_url_with_blob(&blob)
+        .map_err(|err| anyhow!("unable to make an object url: {:?}", err))?;
+    let link = web_sys::window()
+        .and_then(|window| window.document())
+        .and_then(|document| document.create_element("a").ok())
+        .and_then(|element| element.dyn_into::<web_sys::HtmlAnchorElement>().ok())
+        .ok_or_else(|| anyhow!("no document to download from"))?;
+    link.set_href(&url);
+    link.set_download(name);
+    link.click();
+    let _ = web_sys::Url::revoke_object_url(&url);
+    Ok(())
+}
+
+// a replay of what this client receives, in the format the server records, so `--replay`
+// plays either; toggled by `Action::Record`
+#[derive(Default)]
+pub struct Recorder {
+    writer: Option<ReplayWriter<Sink>>,
+    name: String,
+    started: f64,
+    // received since the last frame was written
+    events: Vec<ReplayEvent>,
+    // the newest frame of the server seen; snapshots arrive out of order, frames mustn't
+    frame: u32,
+}
+
+impl Recorder {
+    // seconds since the recording started
+    pub fn elapsed(&self, now: f64) -> Option<f64> {
+        self.writer.as_ref().map(|_| now - self.started)
+    }
+
+    // the world's messages; the connection's own, e.g. pings, are left out
+    pub fn message(&mut self, message: &ClientMessage) {
+        if self.writer.is_none() {
+            return;
+        }
+        let event = match message {
+            ClientMessage::FeedResponse {
+                baseline, updates, ..
+            } => ReplayEvent::Feeds {
+                baseline: *baseline,
+                updates: updates.clone(),
+            },
+            ClientMessage::LoginAck { rules, .. } => {
+                ReplayEvent::Message(ClientMessage::Rules(rules.clone()))
+            }
+            ClientMessage::EntityDespawned(_)
+            | ClientMessage::PlayerEaten { .. }
+            | ClientMessage::Leaderboard(_)
+            | ClientMessage::Names { .. }
+            | ClientMessage::Map(_)
+            | ClientMessage::Rules(_)
+            | ClientMessage::ChatLine { .. }
+            | ClientMessage::ServerShuttingDown { .. }
+            | ClientMessage::FeedDensity(_) => ReplayEvent::Message(message.clone()),
+            _ => return,
+        };
+        self.events.push(event);
+    }
+
+    pub fn state(&mut self, state: &GameStateMessage) {
+        if self.writer.is_some() {
+            self.frame = self.frame.max(state.frame);
+            self.events.push(ReplayEvent::State(state.clone()));
+        }
+    }
+
+    // the far cells, as a partial snapshot
+    pub fn overview(&mut self, frame: u32, agars: &HashMap<EntityId, AgarUpdate>, paused: bool) {
+        if self.writer.is_none() {
+            return;
+        }
+        self.state(&GameStateMessage {
+            frame,
+            seq: frame,
+            agars: agars.clone(),
+            feeds: 0,
+            partial: true,
+            paused,
+        });
+    }
+
+    fn finish(&mut self) {
+        self.flush();
+        let writer = match self.writer.take() {
+            Some(writer) => writer,
+            None => return,
+        };
+        match writer.finish().and_then(|sink| save(sink, &self.name)) {
+            Ok(()) => info!("Replay saved as {}", self.name),
+            Err(err) => error!("Unable to save the replay: {:#}", err),
+        }
+        self.events.clear();
+    }
+
+    // writes what arrived as one frame, once there's a frame number to give it
+    fn flush(&mut self) {
+        if self.events.is_empty() || self.frame == 0 {
+            return;
+        }
+        let frame = ReplayFrame {
+            world: 0,
+            frame: self.frame,
+            events: std::mem::take(&mut self.events),
+        };
+        let written = match &mut self.writer {
+            Some(writer) => writer.write_frame(&frame),
+            None => return,
+        };
+        if let Err(err) = written {
+            error!("Unable to record the replay; recording stopped: {:#}", err);
+            self.writer = None;
+        }
+    }
+}
+
+// the hotkey starts a recording with the world as it is on screen, and stops it again; so
+// does losing the connection, since a reconnect may land on a restarted server counting
+// frames from zero
+pub fn record_system(
+    time: Res<Time>,
+    actions: Res<Actions>,
+    player: Res<PlayerInfo>,
+    clock: Res<ClockSync>,
+    rules: Res<GameRules>,
+    map: Res<GameMap>,
+    names: Res<Names>,
+    leaderboard: Res<Leaderboard>,
+    mut recorder: ResMut<Recorder>,
+    mut reader: Local<EventReader<AppExit>>,
+    exits: Res<Events<AppExit>>,
+    agars: Query<(&Agar, &UpdateContext, &Transform)>,
+    feeds: Query<(&Feed, &UpdateContext, &Transform)>,
+) {
+    let exiting = reader.iter(&exits).next().is_some();
+    let toggle = actions.just_pressed(Action::Record);
+    if recorder.writer.is_some() {
+        if toggle || exiting || !player.logged_in {
+            recorder.finish();
+        } else {
+            recorder.flush();
+        }
+        return;
+    }
+    if !toggle || !player.logged_in {
+        return;
+    }
+
+    let name = file_name();
+    let header = ReplayHeader {
+        version: REPLAY_VERSION,
+        tick_rate: clock.tick_rate,
+        // the server's isn't sent to players
+        seed: 0,
+        rules: rules.clone(),
+    };
+    let writer = match open(&name).and_then(|sink| ReplayWriter::new(sink, &header)) {
+        Ok(writer) => writer,
+        Err(err) => {
+            error!("Unable to start recording: {:#}", err);
+            return;
+        }
+    };
+    info!("Recording to {}", name);
+
+    // what's on screen already, as a full snapshot and a feed baseline
+    let states: HashMap<_, _> = agars
+        .iter()
+        .map(|(agar, context, transform)| {
+            let update = AgarUpdate {
+                agar: agar.clone(),
+                translation: transform.translation,
+            };
+            (context.id, update)
+        })
+        .collect();
+    let frame = agars
+        .iter()
+        .map(|(_, context, _)| context.frame)
+        .max()
+        .unwrap_or(0);
+    let updates = feeds
+        .iter()
+        .map(|(feed, context, transform)| {
+            FeedUpdate::Spawn(FeedUpdateSpawn {
+                id: context.id,
+                color: feed.color,
+                translation: transform.translation,
+            })
+        })
+        .collect();
+
+    *recorder = Recorder {
+        writer: Some(writer),
+        name,
+        started: time.seconds_since_startup(),
+        events: vec![
+            ReplayEvent::Message(ClientMessage::Map(map.clone())),
+            ReplayEvent::Message(ClientMessage::Names {
+                baseline: true,
+                names: names.0.clone(),
+            }),
+            ReplayEvent::Message(ClientMessage::Leaderboard(leaderboard.clone())),
+            ReplayEvent::Feeds {
+                baseline: true,
+                updates,
+            },
+        ],
+        frame,
+    };
+    recorder.state(&GameStateMessage {
+        frame,
+        seq: frame,
+        agars: states,
+        feeds: 0,
+        partial: false,
+        paused: player.paused,
+    });
+}
+
+// leaving the game ends the recording
+pub fn record_cleanup(mut recorder: ResMut<Recorder>) {
+    recorder.finish();
+}
@@ -0,0 +1,396 @@
+use crate::{chat::ChatInput, pause::PauseMenu, storage};
+use bevy::prelude::*;
+use serde::{Deserialize, Serialize};
+use std::collections::{HashMap, HashSet};
+
+// json in `storage` under this key; fields missing from an older file take their defaults
+const KEY: &str = "settings";
+
+// runs `actions_system` between `stage::PRE_UPDATE`, where the chat takes the keyboard
+// while it's open, and the systems acting on the actions
+pub const ACTIONS: &str = "actions";
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize, Deserialize)]
+pub enum Action {
+    Up,
+    Down,
+    Left,
+    Right,
+    Scoreboard,
+    Overlay,
+    Mute,
+    VolumeDown,
+    VolumeUp,
+    // spectators: the camera moves on to the next player
+    NextPlayer,
+    PlaybackPause,
+    SeekBack,
+    SeekForward,
+    PlaybackSlower,
+    PlaybackFaster,
+    // spectators: back into the game
+    Respawn,
+    // opens the chat; Enter sends the line and Escape drops it
+    Chat,
+    // opens the chat for the player's team only, in team mode
+    TeamChat,
+    // a step in or out, like a turn of the wheel
+    ZoomIn,
+    ZoomOut,
+    // starts and stops recording a replay of the game
+    Record,
+}
+
+impl Action {
+    // in the order the menu lists them
+    pub const ALL: [Action; 21] = [
+        Action::Up,
+        Action::Down,
+        Action::Left,
+        Action::Right,
+        Action::Chat,
+        Action::TeamChat,
+        Action::Scoreboard,
+        Action::ZoomIn,
+        Action::ZoomOut,
+        Action::NextPlayer,
+        Action::Respawn,
+        Action::Overlay,
+        Action::Record,
+        Action::Mute,
+        Action::VolumeDown,
+        Action::VolumeUp,
+        Action::PlaybackPause,
+        Action::SeekBack,
+        Action::SeekForward,
+        Action::PlaybackSlower,
+        Action::PlaybackFaster,
+    ];
+}
+
+// what the player set up, kept between runs
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct ClientSettings {
+    // the last name played with; `--name` or `?name` win over it
+    pub name: String,
+    // index into `AGAR_COLORS`; the server picks when `None`
+    pub color: Option<u8>,
+    // below `SKIN_COUNT`; `--skin` or `?skin` win over it
+    pub skin: Option<u8>,
+    pub volume: f32,
+    pub muted: bool,
+    pub vsync: bool,
+    // multisampling, 1 for none; read at startup
+    pub msaa: u32,
+    // names over cells
+    pub labels: bool,
+    // textured cells; off draws every cell in its plain color, e.g. on slow machines
+    pub skins: bool,
+    // bursts where things are eaten and trails behind fast cells
+    pub particles: bool,
+    // feeds in colors told apart with any kind of color blindness, and in different shapes
+    pub colorblind: bool,
+    // fewer updates and rough feed counts instead of the feeds, e.g. on mobile data
+    pub low_bandwidth: bool,
+    // keys and mouse buttons by name, as in `KEY_NAMES` and `BUTTON_NAMES`
+    pub keys: HashMap<Action, Vec<String>>,
+}
+
+impl Default for ClientSettings {
+    fn default() -> Self {
+        Self {
+            name: String::new(),
+            color: None,
+            skin: None,
+            volume: 0.5,
+            muted: false,
+            vsync: true,
+            msaa: 4,
+            labels: true,
+            skins: true,
+            particles: true,
+            colorblind: false,
+            low_bandwidth: false,
+            keys: default_keys(),
+        }
+    }
+}
+
+fn default_keys() -> HashMap<Action, Vec<String>> {
+    let keys: &[(Action, &[&str])] = &[
+        (Action::Up, &["W", "Up"]),
+        (Action::Down, &["S", "Down"]),
+        (Action::Left, &["A", "Left"]),
+        (Action::Right, &["D", "Right"]),
+        (Action::Scoreboard, &["Tab"]),
+        (Action::Overlay, &["F3"]),
+        (Action::Mute, &["M"]),
+        (Action::VolumeDown, &["Minus"]),
+        (Action::VolumeUp, &["Equals"]),
+        (Action::NextPlayer, &["Space"]),
+        (Action::PlaybackPause, &["K"]),
+        (Action::SeekBack, &["J"]),
+        (Action::SeekForward, &["L"]),
+        (Action::PlaybackSlower, &["Comma"]),
+        (Action::PlaybackFaster, &["Period"]),
+        (Action::Respawn, &["R"]),
+        (Action::Chat, &["Return"]),
+        (Action::TeamChat, &["T"]),
+        (Action::ZoomIn, &["E"]),
+        (Action::ZoomOut, &["Q"]),
+        (Action::Record, &["F9"]),
+    ];
+    keys.iter()
+        .map(|(action, names)| (*action, names.iter().map(|name| name.to_string()).collect()))
+        .collect()
+}
+
+// the keys a settings file can bind
+const KEY_NAMES: &[(&str, KeyCode)] = &[
+    ("A", KeyCode::A),
+    ("B", KeyCode::B),
+    ("C", KeyCode::C),
+    ("D", KeyCode::D),
+    ("E", KeyCode::E),
+    ("F", KeyCode::F),
+    ("G", KeyCode::G),
+    ("H", KeyCode::H),
+    ("I", KeyCode::I),
+    ("J", KeyCode::J),
+    ("K", KeyCode::K),
+    ("L", KeyCode::L),
+    ("M", KeyCode::M),
+    ("N", KeyCode::N),
+    ("O", KeyCode::O),
+    ("P", KeyCode::P),
+    ("Q", KeyCode::Q),
+    ("R", KeyCode::R),
+    ("S", KeyCode::S),
+    ("T", KeyCode::T),
+    ("U", KeyCode::U),
+    ("V", KeyCode::V),
+    ("W", KeyCode::W),
+    ("X", KeyCode::X),
+    ("Y", KeyCode::Y),
+    ("Z", KeyCode::Z),
+    ("0", KeyCode::Key0),
+    ("1", KeyCode::Key1),
+    ("2", KeyCode::Key2),
+    ("3", KeyCode::Key3),
+    ("4", KeyCode::Key4),
+    ("5", KeyCode::Key5),
+    ("6", KeyCode::Key6),
+    ("7", KeyCode::Key7),
+    ("8", KeyCode::Key8),
+    ("9", KeyCode::Key9),
+    ("F1", KeyCode::F1),
+    ("F2", KeyCode::F2),
+    ("F3", KeyCode::F3),
+    ("F4", KeyCode::F4),
+    ("F5", KeyCode::F5),
+    ("F6", KeyCode::F6),
+    ("F7", KeyCode::F7),
+    ("F8", KeyCode::F8),
+    ("F9", KeyCode::F9),
+    ("F10", KeyCode::F10),
+    ("F11", KeyCode::F11),
+    ("F12", KeyCode::F12),
+    ("Up", KeyCode::Up),
+    ("Down", KeyCode::Down),
+    ("Left", KeyCode::Left),
+    ("Right", KeyCode::Right),
+    ("Space", KeyCode::Space),
+    ("Tab", KeyCode::Tab),
+    ("Minus", KeyCode::Minus),
+    ("Equals", KeyCode::Equals),
+    ("Comma", KeyCode::Comma),
+    ("Period", KeyCode::Period),
+    ("Slash", KeyCode::Slash),
+    ("LShift", KeyCode::LShift),
+    ("RShift", KeyCode::RShift),
+    ("LControl", KeyCode::LControl),
+    ("RControl", KeyCode::RControl),
+    ("LAlt", KeyCode::LAlt),
+    ("RAlt", KeyCode::RAlt),
+    ("Return", KeyCode::Return),
+    ("Back", KeyCode::Back),
+    ("Semicolon", KeyCode::Semicolon),
+    ("Apostrophe", KeyCode::Apostrophe),
+    ("LBracket", KeyCode::LBracket),
+    ("RBracket", KeyCode::RBracket),
+    ("Backslash", KeyCode::Backslash),
+    ("Grave", KeyCode::Grave),
+    ("Insert", KeyCode::Insert),
+    ("Delete", KeyCode::Delete),
+    ("Home", KeyCode::Home),
+    ("End", KeyCode::End),
+    ("PageUp", KeyCode::PageUp),
+    ("PageDown", KeyCode::PageDown),
+];
+
+// the mouse buttons a settings file can bind
+const BUTTON_NAMES: &[(&str, MouseButton)] = &[
+    ("MouseLeft", MouseButton::Left),
+    ("MouseRight", MouseButton::Right),
+    ("MouseMiddle", MouseButton::Middle),
+];
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum Binding {
+    Key(KeyCode),
+    Button(MouseButton),
+}
+
+fn binding(name: &str) -> Option<Binding> {
+    let key = KEY_NAMES
+        .iter()
+        .find(|(known, _)| known.eq_ignore_ascii_case(name))
+        .map(|(_, key)| Binding::Key(*key));
+    key.or_else(|| {
+        BUTTON_NAMES
+            .iter()
+            .find(|(known, _)| known.eq_ignore_ascii_case(name))
+            .map(|(_, button)| Binding::Button(*button))
+    })
+}
+
+// the name a key goes by in the settings, if it can be bound
+pub fn key_name(key: KeyCode) -> Option<&'static str> {
+    KEY_NAMES
+        .iter()
+        .find(|(_, known)| *known == key)
+        .map(|(name, _)| *name)
+}
+
+pub fn button_name(button: MouseButton) -> Option<&'static str> {
+    BUTTON_NAMES
+        .iter()
+        .find(|(_, known)| *known == button)
+        .map(|(name, _)| *name)
+}
+
+impl ClientSettings {
+    pub fn load() -> Self {
+        let mut settings = match storage::read(KEY) {
+            Some(text) => serde_json::from_str(&text).unwrap_or_else(|err| {
+                warn!("Ignoring unreadable settings: {}", err);
+                Self::default()
+            }),
+            None => Self::default(),
+        };
+
+        settings.volume = if settings.volume.is_finite() {
+            settings.volume.max(0.0).min(1.0)
+        } else {
+            Self::default().volume
+        };
+        settings.msaa = settings.msaa.max(1);
+        // actions added since the file was written get their default keys
+        for (action, keys) in default_keys() {
+            settings.keys.entry(action).or_insert(keys);
+        }
+        for (action, names) in &settings.keys {
+            for name in names.iter().filter(|name| binding(name).is_none()) {
+                warn!("Unknown key {:?} bound to {:?}", name, action);
+            }
+        }
+        settings
+    }
+
+    pub fn save(&self) {
+        let saved = serde_json::to_string_pretty(self)
+            .map_err(|err| err.to_string())
+            .and_then(|text| storage::write(KEY, &text));
+        if let Err(err) = saved {
+            warn!("Unable to save settings: {}", err);
+        }
+    }
+
+    fn bound(&self, action: Action) -> impl Iterator<Item = Binding> + '_ {
+        self.keys
+            .get(&action)
+            .into_iter()
+            .flatten()
+            .filter_map(|name| binding(name))
+    }
+
+    pub fn pressed(
+        &self,
+        keys: &Input<KeyCode>,
+        buttons: &Input<MouseButton>,
+        action: Action,
+    ) -> bool {
+        self.bound(action).any(|binding| match binding {
+            Binding::Key(key) => keys.pressed(key),
+            Binding::Button(button) => buttons.pressed(button),
+        })
+    }
+
+    pub fn just_pressed(
+        &self,
+        keys: &Input<KeyCode>,
+        buttons: &Input<MouseButton>,
+        action: Action,
+    ) -> bool {
+        self.bound(action).any(|binding| match binding {
+            Binding::Key(key) => keys.just_pressed(key),
+            Binding::Button(button) => buttons.just_pressed(button),
+        })
+    }
+
+    // what `action` is bound to, for the menu
+    pub fn bindings(&self, action: Action) -> String {
+        match self.keys.get(&action) {
+            Some(names) if !names.is_empty() => names.join(" / "),
+            _ => "-".to_string(),
+        }
+    }
+
+    // `name` replaces whatever `action` was bound to
+    pub fn rebind(&mut self, action: Action, name: &str) {
+        self.keys.insert(action, vec![name.to_string()]);
+    }
+}
+
+// the actions held and started this frame, from the bindings in the settings
+#[derive(Default)]
+pub struct Actions {
+    pressed: HashSet<Action>,
+    just_pressed: HashSet<Action>,
+}
+
+impl Actions {
+    pub fn pressed(&self, action: Action) -> bool {
+        self.pressed.contains(&action)
+    }
+
+    pub fn just_pressed(&self, action: Action) -> bool {
+        self.just_pressed.contains(&action)
+    }
+}
+
+// systems ask `Actions` rather than the keyboard, so any binding works for them; none are
+// held while the chat or the escape menu is open
+pub fn actions_system(
+    settings: Res<ClientSettings>,
+    chat: Res<ChatInput>,
+    pause: Res<PauseMenu>,
+    keys: Res<Input<KeyCode>>,
+    buttons: Res<Input<MouseButton>>,
+    mut actions: ResMut<Actions>,
+) {
+    actions.pressed.clear();
+    actions.just_pressed.clear();
+    if chat.open || pause.open {
+        return;
+    }
+    for &action in Action::ALL.iter() {
+        if settings.pressed(&keys, &buttons, action) {
+            actions.pressed.insert(action);
+        }
+        if settings.just_pressed(&keys, &buttons, action) {
+            actions.just_pressed.insert(action);
+        }
+    }
+}
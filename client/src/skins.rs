@@ -0,0 +1,179 @@
+use crate::settings::ClientSettings;
+use agarlib::*;
+use bevy::{
+    asset::LoadState,
+    prelude::*,
+    render::{mesh::Indices, pipeline::PrimitiveTopology},
+};
+use std::collections::HashMap;
+
+// `SKIN_COUNT` grayscale skins in a square grid, tinted with the cell's color; the browser
+// build fetches it from `assets/` next to the page
+const ATLAS_PATH: &str = "skins.png";
+const ATLAS_COLUMNS: u8 = 4;
+// triangles around a skinned cell
+const CIRCLE_SEGMENTS: usize = 48;
+
+#[derive(Default)]
+pub struct SkinAtlas {
+    // `None` with skins turned off
+    texture: Option<Handle<Texture>>,
+    // one circle per skin, mapped onto its part of the atlas
+    meshes: HashMap<u8, Handle<Mesh>>,
+    // the atlas tinted with each color
+    materials: HashMap<u8, Handle<ColorMaterial>>,
+}
+
+// one flat material per player color, shared by every cell wearing it
+#[derive(Default)]
+pub struct Palette {
+    materials: HashMap<u8, Handle<ColorMaterial>>,
+}
+
+impl Palette {
+    pub fn material(
+        &mut self,
+        materials: &mut Assets<ColorMaterial>,
+        color: u8,
+    ) -> Handle<ColorMaterial> {
+        self.materials
+            .entry(color)
+            .or_insert_with(|| materials.add(agar_color(color).into()))
+            .clone()
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum Look {
+    Plain(u8),
+    Skin(u8, u8),
+}
+
+// what a cell is drawn as, and the flat circle it was spawned with
+struct CellLook {
+    plain: Handle<Mesh>,
+    shown: Look,
+}
+
+// a unit circle like the flat one, with the uvs of `skin`'s cell in the atlas
+fn skin_mesh(skin: u8) -> Mesh {
+    let cell = 1.0 / ATLAS_COLUMNS as f32;
+    let (column, row) = (skin % ATLAS_COLUMNS, skin / ATLAS_COLUMNS);
+    // textures run top down
+    let uv = |x: f32, y: f32| {
+        [
+            (column as f32 + 0.5 + x * 0.5) * cell,
+            (row as f32 + 0.5 - y * 0.5) * cell,
+        ]
+    };
+
+    let mut positions = vec![[0.0, 0.0, 0.0]];
+    let mut uvs = vec![uv(0.0, 0.0)];
+    for i in 0..CIRCLE_SEGMENTS {
+        let angle = i as f32 / CIRCLE_SEGMENTS as f32 * std::f32::consts::PI * 2.0;
+        let (y, x) = angle.sin_cos();
+        positions.push([x, y, 0.0]);
+        uvs.push(uv(x, y));
+    }
+    let indices = (0..CIRCLE_SEGMENTS as u32)
+        .flat_map(|i| vec![0, i + 1, (i + 1) % CIRCLE_SEGMENTS as u32 + 1])
+        .collect();
+
+    let mut mesh = Mesh::new(PrimitiveTopology::TriangleList);
+    mesh.set_attribute(
+        Mesh::ATTRIBUTE_NORMAL,
+        vec![[0.0, 0.0, 1.0]; positions.len()],
+    );
+    mesh.set_attribute(Mesh::ATTRIBUTE_POSITION, positions);
+    mesh.set_attribute(Mesh::ATTRIBUTE_UV_0, uvs);
+    mesh.set_indices(Some(Indices::U32(indices)));
+    mesh
+}
+
+pub fn skins_setup(
+    settings: Res<ClientSettings>,
+    asset_server: Res<AssetServer>,
+    mut atlas: ResMut<SkinAtlas>,
+) {
+    if settings.skins {
+        atlas.texture = Some(asset_server.load(ATLAS_PATH));
+    }
+}
+
+// draws cells with their skin once the atlas is in, and with their plain color without one;
+// also follows color changes, e.g. a player reclaiming a restored agar
+pub fn skins_system(
+    commands: &mut Commands,
+    asset_server: Res<AssetServer>,
+    mut atlas: ResMut<SkinAtlas>,
+    mut palette: ResMut<Palette>,
+    mut materials: ResMut<Assets<ColorMaterial>>,
+    mut meshes: ResMut<Assets<Mesh>>,
+    spawned: Query<(Entity, &Agar, &Handle<Mesh>), Without<CellLook>>,
+    mut cells: Query<(
+        &Agar,
+        &mut CellLook,
+        &mut Handle<Mesh>,
+        &mut Handle<ColorMaterial>,
+    )>,
+) {
+    for (entity, agar, mesh) in spawned.iter() {
+        commands.insert_one(
+            entity,
+            CellLook {
+                plain: mesh.clone(),
+                shown: Look::Plain(agar.color),
+            },
+        );
+    }
+
+    let state = atlas
+        .texture
+        .as_ref()
+        .map(|texture| asset_server.get_load_state(texture));
+    let texture = match state {
+        Some(LoadState::Loaded) => atlas.texture.clone(),
+        Some(LoadState::Failed) => {
+            warn!("Unable to load {}; cells are drawn plain", ATLAS_PATH);
+            atlas.texture = None;
+            None
+        }
+        _ => None,
+    };
+
+    let atlas = &mut *atlas;
+    for (agar, mut look, mut mesh, mut material) in cells.iter_mut() {
+        let wanted = match (agar.skin, &texture) {
+            (Some(skin), Some(_)) if skin < SKIN_COUNT => Look::Skin(skin, agar.color),
+            _ => Look::Plain(agar.color),
+        };
+        if look.shown == wanted {
+            continue;
+        }
+        look.shown = wanted;
+
+        match (wanted, &texture) {
+            (Look::Skin(skin, color), Some(texture)) => {
+                *mesh = atlas
+                    .meshes
+                    .entry(skin)
+                    .or_insert_with(|| meshes.add(skin_mesh(skin)))
+                    .clone();
+                *material = atlas
+                    .materials
+                    .entry(color)
+                    .or_insert_with(|| {
+                        materials.add(ColorMaterial {
+                            color: agar_color(color),
+                            texture: Some(texture.clone()),
+                        })
+                    })
+                    .clone();
+            }
+            _ => {
+                *mesh = look.plain.clone();
+                *material = palette.material(&mut materials, agar.color);
+            }
+        }
+    }
+}
@@ -0,0 +1,236 @@
+use crate::{
+    ease_camera,
+    settings::{Action, Actions},
+    PlayerInfo,
+};
+use agarlib::*;
+use bevy::{
+    input::mouse::{MouseScrollUnit, MouseWheel},
+    prelude::*,
+    render::{camera::Camera, render_graph::base::camera::CAMERA_2D},
+};
+use bevy_networking_turbulence::NetworkResource;
+
+// world units a second at a zoom of 1
+const PAN_SPEED: f32 = WINDOW_WIDTH;
+// how much a wheel notch changes a spectator's zoom; `GameRules` bounds it
+const ZOOM_STEP: f32 = 1.1;
+// pixels of a touchpad scroll that count as a wheel notch
+const PIXELS_PER_LINE: f32 = 50.0;
+
+// what the spectator camera is doing; reset once the player stops spectating
+pub struct Spectator {
+    // the player followed, or the biggest cell when `None`
+    target: Option<EntityId>,
+    // panned away from any player; the camera stays where it's put
+    free: bool,
+    zoom: f32,
+    cursor: Option<Vec2>,
+    // the viewport sent to the server, to get full snapshots of what's in view
+    subscribed: Option<Region>,
+}
+
+impl Default for Spectator {
+    fn default() -> Self {
+        Self {
+            target: None,
+            free: false,
+            zoom: 1.0,
+            cursor: None,
+            subscribed: None,
+        }
+    }
+}
+
+impl Spectator {
+    // a new connection starts without regions
+    pub fn resubscribe(&mut self) {
+        self.subscribed = None;
+    }
+}
+
+fn send(
+    net: &mut NetworkResource,
+    formats: &WireFormats,
+    stats: &mut NetworkStats,
+    message: ClientMessage,
+) {
+    broadcast_wire(net, formats, &message);
+    stats.sent(&message);
+}
+
+// the region around the view, snapped to `REGION_STEP`
+fn viewport(center: Vec3, zoom: f32) -> Region {
+    let snap = |value: f32| (value / REGION_STEP).round() * REGION_STEP;
+    let width = snap(WINDOW_WIDTH * zoom * REGION_MARGIN) + REGION_STEP;
+    let height = snap(WINDOW_HEIGHT * zoom * REGION_MARGIN) + REGION_STEP;
+    Region::around(Vec2::new(snap(center.x), snap(center.y)), width, height)
+}
+
+// the wheel's notches this frame, turned toward the screen, and the zoom keys' steps; each
+// zooms in by `ZOOM_STEP`, and negative ones zoom out
+pub fn zoom_steps(
+    actions: &Actions,
+    reader: &mut EventReader<MouseWheel>,
+    events: &Events<MouseWheel>,
+) -> f32 {
+    let mut steps: f32 = reader
+        .iter(events)
+        .map(|event| match event.unit {
+            MouseScrollUnit::Line => event.y,
+            MouseScrollUnit::Pixel => event.y / PIXELS_PER_LINE,
+        })
+        .sum();
+    if actions.just_pressed(Action::ZoomIn) {
+        steps += 1.0;
+    }
+    if actions.just_pressed(Action::ZoomOut) {
+        steps -= 1.0;
+    }
+    steps
+}
+
+pub fn zoom_by(zoom: f32, steps: f32) -> f32 {
+    zoom * ZOOM_STEP.powf(-steps)
+}
+
+// drag or the movement keys pan, the wheel zooms and `Action::NextPlayer` follows the next
+// biggest player, going back to the biggest after the smallest
+pub fn spectate_system(
+    time: Res<Time>,
+    actions: Res<Actions>,
+    buttons: Res<Input<MouseButton>>,
+    player: Res<PlayerInfo>,
+    rules: Res<GameRules>,
+    mut spectator: ResMut<Spectator>,
+    mut net: ResMut<NetworkResource>,
+    formats: Res<WireFormats>,
+    mut stats: ResMut<NetworkStats>,
+    mut cursor_reader: Local<EventReader<CursorMoved>>,
+    cursor_events: Res<Events<CursorMoved>>,
+    mut wheel_reader: Local<EventReader<MouseWheel>>,
+    wheel_events: Res<Events<MouseWheel>>,
+    mut cameras: Query<(&Camera, &mut Transform)>,
+    agars: Query<(&Agar, &UpdateContext, &Transform)>,
+) {
+    if !player.spectating {
+        if let Some(region) = spectator.subscribed {
+            send(
+                &mut net,
+                &formats,
+                &mut stats,
+                ClientMessage::Unsubscribe(region),
+            );
+        }
+        *spectator = Spectator::default();
+        return;
+    }
+
+    // a pixel covers `zoom` world units
+    let zoom = spectator.zoom;
+    let mut pan = Vec2::zero();
+    for event in cursor_reader.iter(&cursor_events) {
+        if let Some(last) = spectator.cursor {
+            if buttons.pressed(MouseButton::Left) {
+                pan -= (event.position - last) * zoom;
+            }
+        }
+        spectator.cursor = Some(event.position);
+    }
+    let held = |action| actions.pressed(action);
+    let mut direction = Vec2::zero();
+    if held(Action::Up) {
+        direction.y += 1.0;
+    }
+    if held(Action::Down) {
+        direction.y -= 1.0;
+    }
+    if held(Action::Left) {
+        direction.x -= 1.0;
+    }
+    if held(Action::Right) {
+        direction.x += 1.0;
+    }
+    if direction != Vec2::zero() {
+        pan += direction.normalize() * PAN_SPEED * zoom * time.delta_seconds();
+    }
+    if pan != Vec2::zero() {
+        spectator.free = true;
+    }
+
+    let steps = zoom_steps(&actions, &mut wheel_reader, &wheel_events);
+    // the same bound the server holds subscribed regions to
+    spectator.zoom = rules.clamp_spectator_zoom(zoom_by(spectator.zoom, steps));
+
+    // biggest first
+    let mut players: Vec<_> = agars.iter().collect();
+    players.sort_by(|a, b| {
+        b.0.size
+            .partial_cmp(&a.0.size)
+            .unwrap_or(std::cmp::Ordering::Equal)
+            .then(a.1.id.cmp(&b.1.id))
+    });
+    if actions.just_pressed(Action::NextPlayer) {
+        let current = spectator.target.and_then(|target| {
+            players
+                .iter()
+                .position(|(_, context, _)| context.id == target)
+        });
+        let next = match current {
+            Some(index) if !spectator.free => (index + 1) % players.len(),
+            Some(index) => index,
+            None => 0,
+        };
+        spectator.target = players.get(next).map(|(_, context, _)| context.id);
+        spectator.free = false;
+        if let Some(target) = spectator.target {
+            info!("Spectating {:?}", target);
+        }
+    }
+    // an eaten target hands over to the biggest cell
+    let followed = players
+        .iter()
+        .find(|(_, context, _)| Some(context.id) == spectator.target)
+        .or_else(|| players.first())
+        .map(|(_, _, transform)| transform.translation);
+
+    let mut center = None;
+    for (camera, mut transform) in cameras.iter_mut() {
+        if camera.name.as_deref() != Some(CAMERA_2D) {
+            continue;
+        }
+        match followed {
+            Some(target) if !spectator.free => {
+                ease_camera(&mut transform, target, spectator.zoom, time.delta_seconds())
+            }
+            _ => {
+                // panning moves the camera as it goes; only the zoom eases
+                transform.translation += pan.extend(0.0);
+                let target = transform.translation;
+                ease_camera(&mut transform, target, spectator.zoom, time.delta_seconds());
+            }
+        }
+        center = Some(transform.translation);
+    }
+
+    let region = match center {
+        Some(center) => viewport(center, spectator.zoom),
+        None => return,
+    };
+    if spectator.subscribed != Some(region) {
+        if let Some(old) = spectator.subscribed.replace(region) {
+            send(
+                &mut net,
+                &formats,
+                &mut stats,
+                ClientMessage::Unsubscribe(old),
+            );
+        }
+        send(
+            &mut net,
+            &formats,
+            &mut stats,
+            ClientMessage::Subscribe(region),
+        );
+    }
+}
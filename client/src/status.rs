@@ -0,0 +1,241 @@
+use crate::{menu::UiFont, reconnect::MAX_RECONNECTS, PlayerInfo};
+use agarlib::*;
+use bevy::prelude::*;
+
+// seconds "Connected" stays up before the line clears
+const CONNECTED_NOTICE: f64 = 2.0;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Status {
+    Connecting,
+    Connected,
+    Degraded,
+    Lost,
+    // no connection came up at all, e.g. a wrong address or a server that's down
+    Unreachable,
+    // waiting to try again after `Lost` or `Unreachable`; see `reconnect_system`
+    Reconnecting,
+    Rejected(LoginRejection),
+}
+
+// follows `ConnectionStateEvent`s for the connection in use; a redirect or a reconnect
+// opens a new one and the old one's loss doesn't count
+pub struct ConnectionStatus {
+    pub status: Status,
+    pub handle: Option<ConnectionHandle>,
+    pub since: f64,
+    // reconnects since the connection last came up
+    pub attempt: u32,
+    pub retry_at: Option<f64>,
+}
+
+impl Default for ConnectionStatus {
+    fn default() -> Self {
+        Self {
+            status: Status::Connecting,
+            handle: None,
+            since: 0.0,
+            attempt: 0,
+            retry_at: None,
+        }
+    }
+}
+
+// a line at the top while things are fine, a panel in the middle once they aren't
+struct StatusRoot;
+struct StatusLine;
+struct StatusPanel;
+struct StatusTitle;
+struct StatusDetail;
+
+fn rejection_reason(rejection: LoginRejection) -> &'static str {
+    match rejection {
+        LoginRejection::ChannelProfileMismatch => "The client and the server versions differ",
+        LoginRejection::EncryptionRequired => "The server only takes encrypted connections",
+        LoginRejection::ServerFull => "The server is full",
+        LoginRejection::Banned => "Banned from this server",
+        LoginRejection::ShuttingDown => "The server is shutting down",
+        LoginRejection::BadName => "The name isn't allowed or is taken",
+        LoginRejection::Restricted => "The server is private; check the password",
+        LoginRejection::UntrustedServer => "The server's identity doesn't match the pinned key",
+    }
+}
+
+pub fn status_setup(
+    commands: &mut Commands,
+    time: Res<Time>,
+    mut status: ResMut<ConnectionStatus>,
+    font: Res<UiFont>,
+    mut materials: ResMut<Assets<ColorMaterial>>,
+) {
+    // connecting starts along with this
+    *status = ConnectionStatus {
+        since: time.seconds_since_startup(),
+        ..Default::default()
+    };
+
+    let font = font.0.clone();
+    let text = |font_size: f32| TextBundle {
+        text: Text {
+            value: String::new(),
+            font: font.clone(),
+            style: TextStyle {
+                font_size,
+                color: Color::WHITE,
+                ..Default::default()
+            },
+        },
+        style: Style {
+            margin: Rect::all(Val::Px(6.0)),
+            ..Default::default()
+        },
+        ..Default::default()
+    };
+    let transparent = materials.add(Color::rgba(0.0, 0.0, 0.0, 0.0).into());
+    let panel = materials.add(Color::rgba(0.0, 0.0, 0.0, 0.7).into());
+
+    // drawn by the hud's ui camera
+    commands
+        .spawn(NodeBundle {
+            style: Style {
+                size: Size::new(Val::Percent(100.0), Val::Percent(100.0)),
+                position_type: PositionType::Absolute,
+                flex_direction: FlexDirection::ColumnReverse,
+                align_items: AlignItems::Center,
+                ..Default::default()
+            },
+            material: transparent,
+            ..Default::default()
+        })
+        .with(StatusRoot)
+        .with_children(|root| {
+            root.spawn(text(20.0)).with(StatusLine);
+            root.spawn(NodeBundle {
+                style: Style {
+                    display: Display::None,
+                    flex_direction: FlexDirection::ColumnReverse,
+                    align_items: AlignItems::Center,
+                    margin: Rect {
+                        top: Val::Auto,
+                        bottom: Val::Auto,
+                        ..Default::default()
+                    },
+                    padding: Rect::all(Val::Px(16.0)),
+                    ..Default::default()
+                },
+                material: panel,
+                ..Default::default()
+            })
+            .with(StatusPanel)
+            .with_children(|panel| {
+                panel.spawn(text(32.0)).with(StatusTitle);
+                panel.spawn(text(20.0)).with(StatusDetail);
+            });
+        });
+}
+
+pub fn status_system(
+    time: Res<Time>,
+    player: Res<PlayerInfo>,
+    config: Res<NetworkConfig>,
+    mut status: ResMut<ConnectionStatus>,
+    mut reader: Local<EventReader<ConnectionStateEvent>>,
+    events: Res<Events<ConnectionStateEvent>>,
+    mut texts: Query<(
+        &mut Text,
+        Option<&StatusLine>,
+        Option<&StatusTitle>,
+        Option<&StatusDetail>,
+    )>,
+    mut panels: Query<&mut Style, With<StatusPanel>>,
+) {
+    let now = time.seconds_since_startup();
+    let mut next = status.status;
+    for event in reader.iter(&events) {
+        if event.state == ConnectionState::Connecting {
+            status.handle = Some(event.handle);
+        } else if status.handle != Some(event.handle) {
+            continue;
+        }
+        next = match event.state {
+            ConnectionState::Connecting => Status::Connecting,
+            ConnectionState::Connected => Status::Connected,
+            ConnectionState::Degraded => Status::Degraded,
+            ConnectionState::Lost => Status::Lost,
+        };
+    }
+    if next == Status::Connecting && status.handle.is_none() && now - status.since > LOST_TIMEOUT {
+        next = Status::Unreachable;
+    }
+    // the server answers a rejected login and then closes the connection
+    if let Some(rejection) = player.rejection {
+        next = Status::Rejected(rejection);
+    }
+    if next != status.status {
+        info!("Connection {:?}", next);
+        status.status = next;
+        status.since = now;
+    }
+
+    let addr = client_addr(&config).map_or("the server".to_string(), |addr| addr.to_string());
+    let (line, panel) = match status.status {
+        Status::Connecting if status.attempt > 0 => (
+            String::new(),
+            Some((
+                "Reconnecting...",
+                format!(
+                    "Attempt {} of {} to reach {}",
+                    status.attempt, MAX_RECONNECTS, addr
+                ),
+            )),
+        ),
+        Status::Connecting => (format!("Connecting to {}...", addr), None),
+        Status::Connected if now - status.since < CONNECTED_NOTICE => {
+            ("Connected".to_string(), None)
+        }
+        Status::Connected => (String::new(), None),
+        Status::Degraded => ("Connection unstable".to_string(), None),
+        Status::Lost => (
+            String::new(),
+            Some(("Connection lost", format!("{} stopped answering", addr))),
+        ),
+        Status::Unreachable => (
+            String::new(),
+            Some(("Unable to connect", format!("No answer from {}", addr))),
+        ),
+        Status::Reconnecting => (
+            String::new(),
+            Some((
+                "Reconnecting...",
+                format!(
+                    "Lost {}; trying again in {:.0}s",
+                    addr,
+                    status.retry_at.map_or(0.0, |at| (at - now).max(0.0)).ceil()
+                ),
+            )),
+        ),
+        Status::Rejected(rejection) => (
+            String::new(),
+            Some(("Login rejected", rejection_reason(rejection).to_string())),
+        ),
+    };
+
+    for mut style in panels.iter_mut() {
+        style.display = if panel.is_some() {
+            Display::Flex
+        } else {
+            Display::None
+        };
+    }
+    for (mut text, status_line, title, detail) in texts.iter_mut() {
+        let value = match (status_line, title, detail, &panel) {
+            (Some(_), ..) => line.clone(),
+            (_, Some(_), _, Some((title, _))) => title.to_string(),
+            (_, _, Some(_), Some((_, detail))) => detail.clone(),
+            _ => continue,
+        };
+        if text.value != value {
+            text.value = value;
+        }
+    }
+}
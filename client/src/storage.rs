@@ -0,0 +1,36 @@
+// small settings kept between runs: `~/.agarcli_<key>` on the desktop, the `agarcli_<key>`
+// item of local storage in the browser
+
+#[cfg(not(target_arch = "wasm32"))]
+fn path(key: &str) -> std::path::PathBuf {
+    let home = std::env::var_os("HOME").unwrap_or_else(|| ".".into());
+    std::path::Path::new(&home).join(format!(".agarcli_{}", key))
+}
+
+#[cfg(not(target_arch = "wasm32"))]
+pub fn read(key: &str) -> Option<String> {
+    std::fs::read_to_string(path(key)).ok()
+}
+
+#[cfg(not(target_arch = "wasm32"))]
+pub fn write(key: &str, text: &str) -> Result<(), String> {
+    std::fs::write(path(key), text).map_err(|err| err.to_string())
+}
+
+#[cfg(target_arch = "wasm32")]
+fn storage() -> Option<web_sys::Storage> {
+    web_sys::window()?.local_storage().ok()?
+}
+
+#[cfg(target_arch = "wasm32")]
+pub fn read(key: &str) -> Option<String> {
+    storage()?.get_item(&format!("agarcli_{}", key)).ok()?
+}
+
+#[cfg(target_arch = "wasm32")]
+pub fn write(key: &str, text: &str) -> Result<(), String> {
+    storage()
+        .ok_or_else(|| "no local storage".to_string())?
+        .set_item(&format!("agarcli_{}", key), text)
+        .map_err(|err| format!("{:?}", err))
+}
@@ -0,0 +1,147 @@
+use crate::{pause::PauseMenu, view_size, PlayerInfo};
+use agarlib::*;
+use bevy::prelude::*;
+use bevy_networking_turbulence::NetworkResource;
+
+// steering with a finger: the cell heads for the touch, like it does for the cursor, and
+// stops when the finger lifts. split and eject gestures can hook in here once they exist
+
+// where the finger is, or `None` once it lifted; positions are normalized like the
+// cursor's, see `normalize_cursor`
+#[cfg(not(target_arch = "wasm32"))]
+pub struct TouchQueue;
+
+#[cfg(not(target_arch = "wasm32"))]
+impl TouchQueue {
+    pub fn install() -> Self {
+        Self
+    }
+}
+
+#[cfg(not(target_arch = "wasm32"))]
+fn touches(
+    _queue: &TouchQueue,
+    size: Vec2,
+    reader: &mut EventReader<bevy::input::touch::TouchInput>,
+    events: &Events<bevy::input::touch::TouchInput>,
+) -> Vec<Option<Vec2>> {
+    use bevy::input::touch::TouchPhase;
+
+    reader
+        .iter(events)
+        .map(|touch| match touch.phase {
+            TouchPhase::Started | TouchPhase::Moved => Some(normalize_cursor(touch.position, size)),
+            TouchPhase::Ended | TouchPhase::Cancelled => None,
+        })
+        .collect()
+}
+
+// winit doesn't pass touches through in the browser, so the page's own events fill this
+#[cfg(target_arch = "wasm32")]
+pub struct TouchQueue(std::sync::Arc<std::sync::Mutex<Vec<Option<Vec2>>>>);
+
+#[cfg(target_arch = "wasm32")]
+impl TouchQueue {
+    pub fn install() -> Self {
+        use std::sync::{Arc, Mutex};
+        use wasm_bindgen::{closure::Closure, JsCast};
+
+        let queue = Arc::new(Mutex::new(vec![]));
+        let document = match web_sys::window().and_then(|window| window.document()) {
+            Some(document) => document,
+            None => {
+                warn!("No document; touch steering is off");
+                return Self(queue);
+            }
+        };
+
+        let shared = queue.clone();
+        let listener = Closure::wrap(Box::new(move |event: web_sys::TouchEvent| {
+            // keeps the page from scrolling under the finger; taps still click the ui
+            if event.type_() == "touchmove" {
+                event.prevent_default();
+            }
+
+            let touch = event.touches().get(0);
+            let position = touch.and_then(|touch| {
+                let canvas = web_sys::window()?
+                    .document()?
+                    .query_selector("canvas")
+                    .ok()??;
+                let rect = canvas.get_bounding_client_rect();
+                if rect.width() <= 0.0 || rect.height() <= 0.0 {
+                    return None;
+                }
+                // the page counts from the top left, the game from the bottom left
+                let x = touch.client_x() as f64 - rect.left();
+                let y = rect.height() - (touch.client_y() as f64 - rect.top());
+                let size = Vec2::new(rect.width() as f32, rect.height() as f32);
+                Some(normalize_cursor(Vec2::new(x as f32, y as f32), size))
+            });
+            if let Ok(mut queue) = shared.lock() {
+                queue.push(position);
+            }
+        }) as Box<dyn FnMut(web_sys::TouchEvent)>);
+
+        let mut options = web_sys::AddEventListenerOptions::new();
+        options.passive(false);
+        for kind in &["touchstart", "touchmove", "touchend", "touchcancel"] {
+            let added = document.add_event_listener_with_callback_and_add_event_listener_options(
+                kind,
+                listener.as_ref().unchecked_ref(),
+                &options,
+            );
+            if let Err(err) = added {
+                warn!("Unable to listen for {}: {:?}", kind, err);
+            }
+        }
+        // lives as long as the page
+        listener.forget();
+
+        Self(queue)
+    }
+}
+
+#[cfg(target_arch = "wasm32")]
+fn touches(
+    queue: &TouchQueue,
+    _size: Vec2,
+    _reader: &mut EventReader<bevy::input::touch::TouchInput>,
+    _events: &Events<bevy::input::touch::TouchInput>,
+) -> Vec<Option<Vec2>> {
+    queue
+        .0
+        .lock()
+        .map(|mut queue| queue.drain(..).collect())
+        .unwrap_or_default()
+}
+
+pub fn touch_system(
+    mut net: ResMut<NetworkResource>,
+    formats: Res<WireFormats>,
+    mut stats: ResMut<NetworkStats>,
+    player: Res<PlayerInfo>,
+    rules: Res<GameRules>,
+    queue: Res<TouchQueue>,
+    windows: Res<Windows>,
+    mut reader: Local<EventReader<bevy::input::touch::TouchInput>>,
+    events: Res<Events<bevy::input::touch::TouchInput>>,
+    pause: Res<PauseMenu>,
+) {
+    let size = view_size(&windows).unwrap_or(Vec2::new(WINDOW_WIDTH, WINDOW_HEIGHT));
+    let touches = touches(&queue, size, &mut reader, &events);
+    // the keyboard steers on its own, and nothing does under the escape menu
+    if player.keyboard || pause.open {
+        return;
+    }
+
+    // a burst of moves within a frame only needs the latest
+    let position = match touches.last() {
+        Some(Some(position)) => zoom_cursor(*position, rules.clamp_zoom(player.zoom)),
+        Some(None) => neutral_cursor(),
+        None => return,
+    };
+    let message = ClientMessage::Input(position);
+    broadcast_wire(&mut net, &formats, &message);
+    stats.sent(&message);
+}
@@ -0,0 +1,311 @@
+use crate::{
+    codec::{WireFormat, WireMessage, WirePacket},
+    crypto::{Identity, IdentityKey},
+    ClientMessage, ControlMessage, GameStateMessage, NetworkPlugin, OverviewMessage,
+    MAX_MESSAGE_LEN, MAX_STATE_MESSAGE_LEN,
+};
+use bevy_networking_turbulence::{
+    ConnectionChannelsBuilder, MessageChannelMode, MessageChannelSettings, ReliableChannelSettings,
+};
+use serde::{Deserialize, Serialize};
+use std::{sync::Arc, time::Duration};
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct ChannelConfig {
+    pub reliable: bool,
+    pub bandwidth: usize,
+    pub recv_window_size: usize,
+    pub send_window_size: usize,
+    pub burst_bandwidth: usize,
+    pub init_send: usize,
+    pub max_message_len: usize,
+    pub message_buffer_size: usize,
+    pub packet_buffer_size: usize,
+}
+
+impl ChannelConfig {
+    pub fn reliable() -> Self {
+        Self {
+            reliable: true,
+            bandwidth: 4096,
+            recv_window_size: 1024,
+            send_window_size: 1024,
+            burst_bandwidth: 1024,
+            init_send: 512,
+            max_message_len: MAX_MESSAGE_LEN,
+            message_buffer_size: 8,
+            packet_buffer_size: 8,
+        }
+    }
+
+    pub fn unreliable() -> Self {
+        Self {
+            reliable: false,
+            max_message_len: MAX_STATE_MESSAGE_LEN,
+            ..Self::reliable()
+        }
+    }
+
+    pub fn overview() -> Self {
+        Self {
+            message_buffer_size: 4,
+            packet_buffer_size: 4,
+            ..Self::unreliable()
+        }
+    }
+
+    pub fn settings(&self, channel: u8) -> MessageChannelSettings {
+        let channel_mode = if self.reliable {
+            MessageChannelMode::Reliable {
+                reliability_settings: ReliableChannelSettings {
+                    bandwidth: self.bandwidth,
+                    recv_window_size: self.recv_window_size,
+                    send_window_size: self.send_window_size,
+                    burst_bandwidth: self.burst_bandwidth,
+                    init_send: self.init_send,
+                    wakeup_time: Duration::from_millis(100),
+                    initial_rtt: Duration::from_millis(200),
+                    max_rtt: Duration::from_secs(2),
+                    rtt_update_factor: 0.1,
+                    rtt_resend_factor: 1.5,
+                },
+                max_message_len: self.max_message_len,
+            }
+        } else {
+            MessageChannelMode::Unreliable
+        };
+
+        MessageChannelSettings {
+            channel,
+            channel_mode,
+            message_buffer_size: self.message_buffer_size,
+            packet_buffer_size: self.packet_buffer_size,
+        }
+    }
+}
+
+// part of the fingerprint; bump it whenever a message changes shape, as the fingerprint
+// can't see inside them
+pub const PROTOCOL_VERSION: u32 = 2;
+
+// the built-in channels in registration order, by the messages they carry
+const CHANNELS: [&str; 4] = [
+    "ClientMessage",
+    "GameStateMessage",
+    "OverviewMessage",
+    "ControlMessage",
+];
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct ChannelProfile {
+    pub reliable: ChannelConfig,
+    pub state: ChannelConfig,
+    pub overview: ChannelConfig,
+    pub control: ChannelConfig,
+    // named by `NetworkPluginBuilder::channel`
+    pub extra: Vec<(String, ChannelConfig)>,
+}
+
+impl Default for ChannelProfile {
+    fn default() -> Self {
+        Self {
+            reliable: ChannelConfig::reliable(),
+            state: ChannelConfig::unreliable(),
+            overview: ChannelConfig::overview(),
+            control: ChannelConfig::reliable(),
+            extra: vec![],
+        }
+    }
+}
+
+impl ChannelProfile {
+    // the protocol version and every channel's message and settings, in registration order;
+    // stable across builds and compilers, unlike `DefaultHasher` or type names
+    pub fn fingerprint(&self) -> u64 {
+        let builtin = [&self.reliable, &self.state, &self.overview, &self.control];
+        let channels: Vec<(&str, &ChannelConfig)> = CHANNELS
+            .iter()
+            .copied()
+            .zip(builtin.iter().copied())
+            .chain(
+                self.extra
+                    .iter()
+                    .map(|(name, config)| (name.as_str(), config)),
+            )
+            .collect();
+        let bytes = bincode::serialize(&(PROTOCOL_VERSION, channels)).unwrap_or_default();
+
+        bytes.iter().fold(0xcbf2_9ce4_8422_2325, |hash, byte| {
+            (hash ^ *byte as u64).wrapping_mul(0x0100_0000_01b3)
+        })
+    }
+}
+
+type RegisterChannel = Arc<dyn Fn(&mut ConnectionChannelsBuilder) + Send + Sync>;
+
+#[derive(Clone, Default)]
+pub struct NetworkConfig {
+    pub profile: ChannelProfile,
+    pub addresses: Vec<String>,
+    pub(crate) extra: Vec<RegisterChannel>,
+}
+
+impl NetworkConfig {
+    pub fn register(&self, builder: &mut ConnectionChannelsBuilder) {
+        builder
+            .register::<WirePacket<ClientMessage>>(self.profile.reliable.settings(0))
+            .unwrap();
+        builder
+            .register::<WirePacket<GameStateMessage>>(self.profile.state.settings(1))
+            .unwrap();
+        builder
+            .register::<WirePacket<OverviewMessage>>(self.profile.overview.settings(2))
+            .unwrap();
+        builder
+            .register::<WirePacket<ControlMessage>>(self.profile.control.settings(3))
+            .unwrap();
+
+        for register in &self.extra {
+            register(builder);
+        }
+    }
+}
+
+#[derive(Default)]
+pub struct NetworkPluginBuilder {
+    server: bool,
+    format: WireFormat,
+    encryption: bool,
+    identity: Option<Vec<u8>>,
+    pinned: Option<IdentityKey>,
+    manual_connect: bool,
+    config: NetworkConfig,
+}
+
+impl NetworkPluginBuilder {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn server(mut self, server: bool) -> Self {
+        self.server = server;
+        self
+    }
+
+    pub fn format(mut self, format: WireFormat) -> Self {
+        self.format = format;
+        self
+    }
+
+    pub fn encryption(mut self, encryption: bool) -> Self {
+        self.encryption = encryption;
+        self
+    }
+
+    // servers: signs the exchange keys; see `Identity`
+    pub fn identity(mut self, identity: Option<&Identity>) -> Self {
+        self.identity = identity.map(Identity::to_bytes);
+        self
+    }
+
+    // clients: only take exchange keys signed by this identity
+    pub fn pinned(mut self, pinned: Option<IdentityKey>) -> Self {
+        self.pinned = pinned;
+        self
+    }
+
+    // leaves connecting to the app, through `connect`
+    pub fn manual_connect(mut self) -> Self {
+        self.manual_connect = true;
+        self
+    }
+
+    pub fn address(mut self, addr: impl Into<String>) -> Self {
+        self.config.addresses.push(addr.into());
+        self
+    }
+
+    pub fn addresses(mut self, addrs: impl IntoIterator<Item = String>) -> Self {
+        self.config.addresses.extend(addrs);
+        self
+    }
+
+    pub fn profile(mut self, profile: ChannelProfile) -> Self {
+        self.config.profile = profile;
+        self
+    }
+
+    pub fn reliable(mut self, f: impl FnOnce(&mut ChannelConfig)) -> Self {
+        f(&mut self.config.profile.reliable);
+        self
+    }
+
+    pub fn state(mut self, f: impl FnOnce(&mut ChannelConfig)) -> Self {
+        f(&mut self.config.profile.state);
+        self
+    }
+
+    pub fn overview(mut self, f: impl FnOnce(&mut ChannelConfig)) -> Self {
+        f(&mut self.config.profile.overview);
+        self
+    }
+
+    // `name` goes into the fingerprint; keep it the same on both ends and across builds
+    pub fn channel<M: WireMessage>(mut self, name: &str, config: ChannelConfig) -> Self {
+        let channel = 4 + self.config.extra.len() as u8;
+        let settings = config.clone();
+
+        self.config.profile.extra.push((name.to_string(), config));
+        self.config
+            .extra
+            .push(Arc::new(move |builder: &mut ConnectionChannelsBuilder| {
+                builder
+                    .register::<WirePacket<M>>(settings.settings(channel))
+                    .unwrap();
+            }));
+        self
+    }
+
+    pub fn build(self) -> NetworkPlugin {
+        NetworkPlugin {
+            server: self.server,
+            format: self.format,
+            encryption: self.encryption,
+            identity: self.identity,
+            pinned: self.pinned,
+            autoconnect: !self.manual_connect,
+            config: self.config,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn fingerprint_follows_settings() {
+        let profile = ChannelProfile::default();
+        assert_eq!(
+            profile.fingerprint(),
+            ChannelProfile::default().fingerprint()
+        );
+
+        let mut changed = profile.clone();
+        changed.state.max_message_len += 1;
+        assert_ne!(changed.fingerprint(), profile.fingerprint());
+    }
+
+    #[test]
+    fn fingerprint_follows_extra_channels() {
+        let named = |name: &str| ChannelProfile {
+            extra: vec![(name.to_string(), ChannelConfig::reliable())],
+            ..ChannelProfile::default()
+        };
+        assert_ne!(
+            named("Chat").fingerprint(),
+            ChannelProfile::default().fingerprint()
+        );
+        assert_ne!(named("Chat").fingerprint(), named("Voice").fingerprint());
+    }
+}
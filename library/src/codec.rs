@@ -0,0 +1,902 @@
+use crate::{
+    crypto::{Identity, IdentityKey, KeyExchange, SignedKey, WireCipher, WireNonce, WirePublicKey},
+    ClientMessage,
+};
+use anyhow::{anyhow, Result};
+use bevy::prelude::*;
+use bevy_networking_turbulence::{ConnectionHandle, MessageChannels, NetworkResource};
+use bincode::Options;
+use serde::{de::DeserializeOwned, Deserialize, Serialize};
+use std::{
+    any::TypeId,
+    collections::{HashMap, HashSet},
+    convert::TryInto,
+    marker::PhantomData,
+    sync::atomic::{AtomicU64, Ordering},
+};
+
+pub trait WireMessage: Serialize + DeserializeOwned + Clone + Send + Sync + 'static {}
+
+impl<T: Serialize + DeserializeOwned + Clone + Send + Sync + 'static> WireMessage for T {}
+
+pub trait WireCodec {
+    fn encode<T: Serialize>(&self, value: &T) -> Result<Vec<u8>>;
+    fn decode<T: DeserializeOwned>(&self, bytes: &[u8]) -> Result<T>;
+}
+
+// same layout turbulence uses for its own channels
+pub struct BincodeCodec;
+
+impl WireCodec for BincodeCodec {
+    fn encode<T: Serialize>(&self, value: &T) -> Result<Vec<u8>> {
+        Ok(bincode::serialize(value)?)
+    }
+
+    fn decode<T: DeserializeOwned>(&self, bytes: &[u8]) -> Result<T> {
+        Ok(bincode::deserialize(bytes)?)
+    }
+}
+
+pub struct VarintCodec;
+
+impl WireCodec for VarintCodec {
+    fn encode<T: Serialize>(&self, value: &T) -> Result<Vec<u8>> {
+        Ok(bincode::DefaultOptions::new().serialize(value)?)
+    }
+
+    fn decode<T: DeserializeOwned>(&self, bytes: &[u8]) -> Result<T> {
+        Ok(bincode::DefaultOptions::new().deserialize(bytes)?)
+    }
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq)]
+pub enum WireFormat {
+    Bincode,
+    Varint,
+}
+
+impl Default for WireFormat {
+    fn default() -> Self {
+        WireFormat::Bincode
+    }
+}
+
+pub const WIRE_FORMATS: &[WireFormat] = &[WireFormat::Varint, WireFormat::Bincode];
+
+impl WireFormat {
+    // authenticated with a sealed payload, so the format can't be swapped under it
+    fn tag(self) -> u8 {
+        match self {
+            WireFormat::Bincode => 0,
+            WireFormat::Varint => 1,
+        }
+    }
+}
+
+impl WireCodec for WireFormat {
+    fn encode<T: Serialize>(&self, value: &T) -> Result<Vec<u8>> {
+        match self {
+            WireFormat::Bincode => BincodeCodec.encode(value),
+            WireFormat::Varint => VarintCodec.encode(value),
+        }
+    }
+
+    fn decode<T: DeserializeOwned>(&self, bytes: &[u8]) -> Result<T> {
+        match self {
+            WireFormat::Bincode => BincodeCodec.decode(bytes),
+            WireFormat::Varint => VarintCodec.decode(bytes),
+        }
+    }
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct WirePacket<M> {
+    pub format: WireFormat,
+    pub payload: Vec<u8>,
+    pub nonce: Option<WireNonce>,
+    #[serde(skip)]
+    marker: PhantomData<M>,
+}
+
+// sealed payloads start with who sealed them and a counter, both under the cipher's
+// authentication; the same key is used both ways, so a packet can't be reflected back either
+const FROM_CLIENT: u8 = 0;
+const FROM_SERVER: u8 = 1;
+const SEAL_HEADER: usize = 9;
+
+// how far behind the newest counter of a channel a packet may still arrive; older ones can't
+// be told apart from repeats anymore and are refused with them
+const REPLAY_WINDOW: u64 = 128;
+
+#[derive(Default)]
+struct ReplayWindow {
+    newest: u64,
+    // bit n stands for `newest - n`
+    seen: u128,
+}
+
+impl ReplayWindow {
+    fn accept(&mut self, counter: u64) -> bool {
+        if counter > self.newest {
+            let shift = counter - self.newest;
+            self.seen = if shift >= REPLAY_WINDOW {
+                0
+            } else {
+                self.seen << shift
+            };
+            self.seen |= 1;
+            self.newest = counter;
+            return true;
+        }
+
+        let age = self.newest - counter;
+        if age >= REPLAY_WINDOW || self.seen & (1 << age) != 0 {
+            return false;
+        }
+        self.seen |= 1 << age;
+        true
+    }
+}
+
+#[derive(Default)]
+struct WireSession {
+    format: WireFormat,
+    exchange: Option<KeyExchange>,
+    cipher: Option<WireCipher>,
+    // the peer can decrypt what we seal
+    outbound: bool,
+    // channels the peer already encrypts on
+    inbound: HashSet<TypeId>,
+    // the peer sealed a login channel message; nothing comes in the clear from then on
+    sealed: bool,
+    // counter of the last packet sealed, over all channels
+    sent: AtomicU64,
+    received: HashMap<TypeId, ReplayWindow>,
+}
+
+#[derive(Default)]
+pub struct WireFormats {
+    pub preferred: WireFormat,
+    pub encryption: bool,
+    server: bool,
+    // server side: signs the exchange keys
+    identity: Option<Identity>,
+    // client side: the identity the server's exchange key has to be signed with
+    pinned: Option<IdentityKey>,
+    sessions: HashMap<ConnectionHandle, WireSession>,
+}
+
+impl WireFormats {
+    pub fn new(preferred: WireFormat, encryption: bool, server: bool) -> Self {
+        Self {
+            preferred,
+            encryption,
+            server,
+            identity: None,
+            pinned: None,
+            sessions: HashMap::new(),
+        }
+    }
+
+    pub fn with_identity(mut self, identity: Option<Identity>) -> Self {
+        self.identity = identity;
+        self
+    }
+
+    pub fn with_pinned(mut self, pinned: Option<IdentityKey>) -> Self {
+        self.pinned = pinned;
+        self
+    }
+
+    pub fn get(&self, handle: ConnectionHandle) -> WireFormat {
+        self.sessions
+            .get(&handle)
+            .map(|session| session.format)
+            .unwrap_or_default()
+    }
+
+    pub fn set(&mut self, handle: ConnectionHandle, format: WireFormat) {
+        self.sessions.entry(handle).or_default().format = format;
+    }
+
+    pub fn remove(&mut self, handle: ConnectionHandle) {
+        self.sessions.remove(&handle);
+    }
+
+    pub fn negotiate(&self, supported: &[WireFormat]) -> WireFormat {
+        if supported.contains(&self.preferred) {
+            self.preferred
+        } else {
+            WireFormat::default()
+        }
+    }
+
+    pub fn encrypted(&self, handle: ConnectionHandle) -> bool {
+        self.sessions
+            .get(&handle)
+            .map_or(false, |session| session.outbound)
+    }
+
+    // client side: offer a key in the login request
+    pub fn begin_key_exchange(&mut self, handle: ConnectionHandle) -> Option<WirePublicKey> {
+        if !self.encryption {
+            return None;
+        }
+
+        let exchange = KeyExchange::new();
+        let public = exchange.public;
+        self.sessions.entry(handle).or_default().exchange = Some(exchange);
+        Some(public)
+    }
+
+    // server side: answer the offered key, signed with the identity; outbound encryption
+    // starts once the client proves it derived the same key
+    pub fn accept_key_exchange(
+        &mut self,
+        handle: ConnectionHandle,
+        peer: WirePublicKey,
+    ) -> SignedKey {
+        let exchange = KeyExchange::new();
+        let public = match &self.identity {
+            Some(identity) => identity.sign(exchange.public, &peer),
+            None => SignedKey {
+                key: exchange.public,
+                signature: vec![],
+            },
+        };
+        let session = self.sessions.entry(handle).or_default();
+        session.cipher = Some(exchange.finish(peer));
+        session.outbound = false;
+        public
+    }
+
+    // client side: the login ack carries the server key; with an identity pinned, a key it
+    // didn't sign is refused and the connection stays without a cipher, and so is no key at
+    // all unless an earlier one was already checked
+    pub fn complete_key_exchange(
+        &mut self,
+        handle: ConnectionHandle,
+        peer: Option<&SignedKey>,
+    ) -> Result<()> {
+        let pinned = self.pinned;
+        let session = self.sessions.entry(handle).or_default();
+
+        let peer = match peer {
+            Some(peer) => peer,
+            None if pinned.is_some() && session.cipher.is_none() => {
+                session.exchange = None;
+                return Err(anyhow!(
+                    "the server sent no key to check the pinned identity"
+                ));
+            }
+            None => return Ok(()),
+        };
+
+        if let Some(exchange) = session.exchange.take() {
+            if let Some(pinned) = pinned {
+                if !peer.verify(&pinned, &exchange.public) {
+                    return Err(anyhow!(
+                        "the server key isn't signed by the pinned identity"
+                    ));
+                }
+            }
+            session.cipher = Some(exchange.finish(peer.key));
+            session.outbound = true;
+        }
+        Ok(())
+    }
+
+    pub fn seal<M: WireMessage>(
+        &self,
+        handle: ConnectionHandle,
+        message: &M,
+    ) -> Result<WirePacket<M>> {
+        let format = self.get(handle);
+        let payload = format.encode(message)?;
+        let from = if self.server {
+            FROM_SERVER
+        } else {
+            FROM_CLIENT
+        };
+        let cipher = self
+            .sessions
+            .get(&handle)
+            .filter(|session| session.outbound)
+            .and_then(|session| Some((session, session.cipher.as_ref()?)));
+
+        let (payload, nonce) = match cipher {
+            Some((session, cipher)) => {
+                let counter = session.sent.fetch_add(1, Ordering::Relaxed) + 1;
+                let sealed = [&[from][..], &counter.to_le_bytes(), &payload].concat();
+                let (payload, nonce) = cipher.seal(&sealed, &[format.tag()])?;
+                (payload, Some(nonce))
+            }
+            None => (payload, None),
+        };
+
+        Ok(WirePacket {
+            format,
+            payload,
+            nonce,
+            marker: PhantomData,
+        })
+    }
+
+    pub fn open<M: WireMessage>(
+        &mut self,
+        handle: ConnectionHandle,
+        packet: &WirePacket<M>,
+    ) -> Result<M> {
+        let channel = TypeId::of::<M>();
+        let from_peer = if self.server {
+            FROM_CLIENT
+        } else {
+            FROM_SERVER
+        };
+        let session = self.sessions.get_mut(&handle);
+
+        let payload = match (session, &packet.nonce) {
+            (Some(session), Some(nonce)) => {
+                let cipher = session
+                    .cipher
+                    .as_ref()
+                    .ok_or_else(|| anyhow!("encrypted packet before key exchange"))?;
+                let sealed = cipher.open(&packet.payload, nonce, &[packet.format.tag()])?;
+                if sealed.len() < SEAL_HEADER || sealed[0] != from_peer {
+                    return Err(anyhow!("encrypted packet from the wrong side"));
+                }
+                let counter = u64::from_le_bytes(sealed[1..SEAL_HEADER].try_into()?);
+                if !session.received.entry(channel).or_default().accept(counter) {
+                    return Err(anyhow!("replayed packet {}", counter));
+                }
+                session.outbound = true;
+                session.inbound.insert(channel);
+                // the login channel is ordered, so its plaintext can't trail a sealed packet;
+                // unreliable packets sent in the clear before it are only dropped late
+                if channel == TypeId::of::<ClientMessage>() {
+                    session.sealed = true;
+                }
+                sealed[SEAL_HEADER..].to_vec()
+            }
+            (None, Some(_)) => return Err(anyhow!("encrypted packet before key exchange")),
+            (Some(session), None) if session.sealed || session.inbound.contains(&channel) => {
+                return Err(anyhow!("unencrypted packet after key exchange"));
+            }
+            (_, None) => packet.payload.clone(),
+        };
+
+        packet.format.decode(&payload)
+    }
+}
+
+pub fn send_wire<M: WireMessage>(
+    net: &mut NetworkResource,
+    formats: &WireFormats,
+    handle: ConnectionHandle,
+    message: &M,
+) -> Result<bool> {
+    let packet = formats.seal(handle, message)?;
+
+    match net.send_message(handle, packet) {
+        Ok(None) => Ok(true),
+        Ok(Some(_)) => Ok(false),
+        Err(err) => Err(anyhow!("{}", err)),
+    }
+}
+
+pub fn broadcast_wire<M: WireMessage>(
+    net: &mut NetworkResource,
+    formats: &WireFormats,
+    message: &M,
+) {
+    let handles: Vec<_> = net.connections.keys().copied().collect();
+
+    for handle in handles {
+        match send_wire(net, formats, handle, message) {
+            Ok(true) => {}
+            Ok(false) => debug!("channel to [{}] is full", handle),
+            Err(err) => debug!("unable to send to [{}]: {}", handle, err),
+        }
+    }
+}
+
+pub fn recv_wire<M: WireMessage>(
+    channels: &mut MessageChannels,
+    formats: &mut WireFormats,
+    handle: ConnectionHandle,
+) -> Option<Result<M>> {
+    channels
+        .recv::<WirePacket<M>>()
+        .map(|packet| formats.open(handle, &packet))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::*;
+
+    const CODECS: &[WireFormat] = &[WireFormat::Bincode, WireFormat::Varint];
+
+    fn pass() -> RedirectPass {
+        RedirectPass::issue(b"secret", "agar", "eu.example.com:14192", 1_000)
+    }
+
+    fn agar_update(id: EntityId) -> AgarUpdate {
+        AgarUpdate {
+            agar: Agar {
+                skin: Some(3),
+                color: 5,
+                ..Agar::with_size(40.0 + id as f32)
+            },
+            translation: Vec3::new(100.0 + id as f32, 200.0, 1.0),
+        }
+    }
+
+    // maps hold a single entry so encodings don't depend on iteration order
+    fn client_messages() -> Vec<ClientMessage> {
+        let login = LoginRequest {
+            formats: WIRE_FORMATS.to_vec(),
+            profile: 99,
+            public_key: Some([7; 32]),
+            token: Some(pass()),
+            name: "agar".to_string(),
+            resume: true,
+            region: Some("eu".to_string()),
+            color: Some(2),
+            skin: Some(4),
+            low_bandwidth: true,
+        };
+        let spawn = FeedUpdateSpawn {
+            id: 8,
+            color: FeedColor::Green,
+            translation: Vec3::new(1.0, 2.0, 0.0),
+        };
+
+        vec![
+            ClientMessage::Login(login),
+            ClientMessage::Login(LoginRequest::default()),
+            ClientMessage::LoginAck {
+                id: 1,
+                format: WireFormat::Varint,
+                rules: GameRules::default(),
+                public_key: Some(SignedKey {
+                    key: [9; 32],
+                    signature: vec![2; 64],
+                }),
+                tick_rate: 30.0,
+                low_bandwidth: false,
+            },
+            ClientMessage::LoginRejected(LoginRejection::Restricted),
+            ClientMessage::Input(Vec2::new(320.0, 240.0)),
+            ClientMessage::FeedRequest(4),
+            ClientMessage::FeedResponse {
+                revision: 4,
+                baseline: true,
+                complete: false,
+                updates: vec![FeedUpdate::Spawn(spawn), FeedUpdate::Despawn(9)],
+            },
+            ClientMessage::TimeSyncRequest(1.5),
+            ClientMessage::TimeSyncResponse {
+                client_time: 1.5,
+                server_time: 20.25,
+                server_frame: 600,
+            },
+            ClientMessage::Ping(3.0),
+            ClientMessage::Pong(3.0),
+            ClientMessage::Chat("hi".to_string()),
+            ClientMessage::TeamChat("go left".to_string()),
+            ClientMessage::ChatLine {
+                from: Some(1),
+                name: "agar".to_string(),
+                text: "hi".to_string(),
+                team: false,
+            },
+            ClientMessage::ChatLine {
+                from: Some(2),
+                name: "blue".to_string(),
+                text: "go left".to_string(),
+                team: true,
+            },
+            ClientMessage::ChatLine {
+                from: None,
+                name: String::new(),
+                text: "restarting".to_string(),
+                team: false,
+            },
+            ClientMessage::Rules(GameRules::default()),
+            ClientMessage::Map(GameMap::default()),
+            ClientMessage::ServerShuttingDown { seconds: 10 },
+            ClientMessage::Subscribe(Region::around(Vec2::new(500.0, 500.0), 100.0, 80.0)),
+            ClientMessage::Unsubscribe(Region::around(Vec2::zero(), 10.0, 10.0)),
+            ClientMessage::EntityDespawned(5),
+            ClientMessage::PlayerEaten {
+                victim: 5,
+                eater: 1,
+                victim_mass: 42.5,
+            },
+            ClientMessage::Leaderboard(Leaderboard {
+                entries: vec![LeaderboardEntry {
+                    id: 1,
+                    name: "agar".to_string(),
+                    size: 80.0,
+                }],
+                total: 3,
+            }),
+            ClientMessage::Respawn,
+            ClientMessage::Respawned(6),
+            ClientMessage::Names {
+                baseline: true,
+                names: vec![(1, "agar".to_string())].into_iter().collect(),
+            },
+            ClientMessage::FeedDensity(FeedDensity {
+                columns: 2,
+                rows: 1,
+                counts: vec![3, 0],
+            }),
+            ClientMessage::Logout,
+            ClientMessage::CredentialsRequired {
+                public_key: Some(SignedKey {
+                    key: [1; 32],
+                    signature: vec![],
+                }),
+            },
+            ClientMessage::CredentialsRequired { public_key: None },
+            ClientMessage::Credentials("key".to_string()),
+            ClientMessage::OwnerRequired {
+                public_key: Some(SignedKey {
+                    key: [3; 32],
+                    signature: vec![4; 64],
+                }),
+            },
+            ClientMessage::Owner(77),
+        ]
+    }
+
+    // fails to build when a variant is added, so it gets a sample above
+    fn variant(message: &ClientMessage) -> &'static str {
+        match message {
+            ClientMessage::Login(_) => "Login",
+            ClientMessage::LoginAck { .. } => "LoginAck",
+            ClientMessage::LoginRejected(_) => "LoginRejected",
+            ClientMessage::Input(_) => "Input",
+            ClientMessage::FeedRequest(_) => "FeedRequest",
+            ClientMessage::FeedResponse { .. } => "FeedResponse",
+            ClientMessage::TimeSyncRequest(_) => "TimeSyncRequest",
+            ClientMessage::TimeSyncResponse { .. } => "TimeSyncResponse",
+            ClientMessage::Ping(_) => "Ping",
+            ClientMessage::Pong(_) => "Pong",
+            ClientMessage::Chat(_) => "Chat",
+            ClientMessage::TeamChat(_) => "TeamChat",
+            ClientMessage::ChatLine { .. } => "ChatLine",
+            ClientMessage::Rules(_) => "Rules",
+            ClientMessage::Map(_) => "Map",
+            ClientMessage::ServerShuttingDown { .. } => "ServerShuttingDown",
+            ClientMessage::Subscribe(_) => "Subscribe",
+            ClientMessage::Unsubscribe(_) => "Unsubscribe",
+            ClientMessage::EntityDespawned(_) => "EntityDespawned",
+            ClientMessage::PlayerEaten { .. } => "PlayerEaten",
+            ClientMessage::Leaderboard(_) => "Leaderboard",
+            ClientMessage::Respawn => "Respawn",
+            ClientMessage::Respawned(_) => "Respawned",
+            ClientMessage::Names { .. } => "Names",
+            ClientMessage::FeedDensity(_) => "FeedDensity",
+            ClientMessage::Logout => "Logout",
+            ClientMessage::CredentialsRequired { .. } => "CredentialsRequired",
+            ClientMessage::Credentials(_) => "Credentials",
+            ClientMessage::OwnerRequired { .. } => "OwnerRequired",
+            ClientMessage::Owner(_) => "Owner",
+        }
+    }
+
+    fn state_message() -> GameStateMessage {
+        GameStateMessage {
+            frame: 1200,
+            seq: 40,
+            agars: vec![(3, agar_update(3))].into_iter().collect(),
+            feeds: 17,
+            partial: true,
+            paused: false,
+        }
+    }
+
+    fn overview_message() -> OverviewMessage {
+        let rules = GameRules::default();
+        OverviewMessage {
+            frame: 1200,
+            cells: (0..4)
+                .map(|id| {
+                    let update = agar_update(id);
+                    CellSummary::new(id, update.translation, &update.agar, &rules)
+                })
+                .collect(),
+        }
+    }
+
+    fn control_messages() -> Vec<ControlMessage> {
+        vec![ControlMessage::Redirect {
+            addr: "eu.example.com:14192".to_string(),
+            token: pass(),
+        }]
+    }
+
+    // none of the messages implement `PartialEq`; the same bytes back out is as good
+    fn assert_round_trip<C: WireCodec, M: WireMessage + std::fmt::Debug>(codec: &C, message: &M) {
+        let bytes = codec.encode(message).unwrap();
+        let decoded: M = codec.decode(&bytes).unwrap();
+        assert_eq!(
+            codec.encode(&decoded).unwrap(),
+            bytes,
+            "{:?} changed on the way",
+            message
+        );
+    }
+
+    fn assert_all_round_trip<C: WireCodec>(codec: &C) {
+        for message in client_messages() {
+            assert_round_trip(codec, &message);
+        }
+        for message in control_messages() {
+            assert_round_trip(codec, &message);
+        }
+        assert_round_trip(codec, &state_message());
+        assert_round_trip(codec, &overview_message());
+    }
+
+    #[test]
+    fn every_variant_sampled() {
+        let names: HashSet<_> = client_messages().iter().map(variant).collect();
+        assert_eq!(names.len(), 30);
+    }
+
+    #[test]
+    fn bincode_round_trip() {
+        assert_all_round_trip(&BincodeCodec);
+    }
+
+    #[test]
+    fn varint_round_trip() {
+        assert_all_round_trip(&VarintCodec);
+    }
+
+    #[test]
+    fn wire_format_round_trip() {
+        for format in CODECS {
+            assert_all_round_trip(format);
+        }
+    }
+
+    #[test]
+    fn wire_format_matches_codec() {
+        let message = state_message();
+        assert_eq!(
+            WireFormat::Bincode.encode(&message).unwrap(),
+            BincodeCodec.encode(&message).unwrap()
+        );
+        assert_eq!(
+            WireFormat::Varint.encode(&message).unwrap(),
+            VarintCodec.encode(&message).unwrap()
+        );
+    }
+
+    #[test]
+    fn sealed_packets_round_trip() {
+        for format in CODECS {
+            let mut formats = WireFormats::new(*format, false, true);
+            formats.set(1, *format);
+            for message in client_messages() {
+                let packet = formats.seal(1, &message).unwrap();
+                assert_eq!(packet.format, *format);
+                assert!(packet.nonce.is_none());
+                let opened: ClientMessage = formats.open(1, &packet).unwrap();
+                assert_eq!(variant(&opened), variant(&message));
+            }
+        }
+    }
+
+    // a client and a server keyed with each other on connection 1
+    fn keyed(identity: Identity, pinned: IdentityKey) -> (WireFormats, WireFormats, Result<()>) {
+        let mut client =
+            WireFormats::new(WireFormat::Varint, true, false).with_pinned(Some(pinned));
+        let mut server =
+            WireFormats::new(WireFormat::Varint, true, true).with_identity(Some(identity));
+        let offer = client.begin_key_exchange(1).unwrap();
+        let signed = server.accept_key_exchange(1, offer);
+        let completed = client.complete_key_exchange(1, Some(&signed));
+        (client, server, completed)
+    }
+
+    #[test]
+    fn pinned_identity_accepted() {
+        let identity = Identity::generate();
+        let public = identity.public();
+        let (mut client, mut server, completed) = keyed(identity, public);
+        completed.unwrap();
+        assert!(client.encrypted(1));
+
+        let packet = client.seal(1, &ClientMessage::Ping(1.0)).unwrap();
+        assert!(packet.nonce.is_some());
+        server.open(1, &packet).unwrap();
+        assert!(server.encrypted(1));
+
+        let packet = server.seal(1, &state_message()).unwrap();
+        assert!(packet.nonce.is_some());
+        client.open(1, &packet).unwrap();
+    }
+
+    #[test]
+    fn unpinned_identity_refused() {
+        let (client, _, completed) = keyed(Identity::generate(), Identity::generate().public());
+        assert!(completed.is_err());
+        assert!(!client.encrypted(1));
+    }
+
+    #[test]
+    fn missing_key_refused_when_pinned() {
+        let mut client = WireFormats::new(WireFormat::Varint, true, false)
+            .with_pinned(Some(Identity::generate().public()));
+        client.begin_key_exchange(1).unwrap();
+        assert!(client.complete_key_exchange(1, None).is_err());
+        assert!(!client.encrypted(1));
+
+        // the login ack after the credentials doesn't repeat the key
+        let identity = Identity::generate();
+        let public = identity.public();
+        let (mut client, _, completed) = keyed(identity, public);
+        completed.unwrap();
+        client.complete_key_exchange(1, None).unwrap();
+        assert!(client.encrypted(1));
+
+        let mut unpinned = WireFormats::new(WireFormat::Varint, true, false);
+        unpinned.begin_key_exchange(1).unwrap();
+        unpinned.complete_key_exchange(1, None).unwrap();
+        assert!(!unpinned.encrypted(1));
+    }
+
+    #[test]
+    fn sealed_packet_fits_limit() {
+        let identity = Identity::generate();
+        let public = identity.public();
+        let (mut client, _, _) = keyed(identity, public);
+
+        let header = message_size(&ClientMessage::Chat(String::new()));
+        let message = ClientMessage::Chat("x".repeat(MAX_STATE_MESSAGE_LEN - header));
+        assert_eq!(message_size(&message), MAX_STATE_MESSAGE_LEN);
+
+        for format in CODECS {
+            client.set(1, *format);
+            let packet = client.seal(1, &message).unwrap();
+            assert!(packet.nonce.is_some());
+            let size = bincode::serialized_size(&packet).unwrap() as usize;
+            assert!(
+                size <= MAX_STATE_MESSAGE_LEN,
+                "{:?} sealed to {}",
+                format,
+                size
+            );
+        }
+    }
+
+    #[test]
+    fn identity_hex_round_trip() {
+        let public = Identity::generate().public();
+        assert_eq!(parse_identity(&identity_hex(&public)), Some(public));
+        assert_eq!(parse_identity("00"), None);
+    }
+
+    #[test]
+    fn replayed_packet_refused() {
+        let identity = Identity::generate();
+        let public = identity.public();
+        let (client, mut server, _) = keyed(identity, public);
+
+        let first = client.seal(1, &ClientMessage::Ping(1.0)).unwrap();
+        let second = client.seal(1, &ClientMessage::Ping(2.0)).unwrap();
+        // out of order is fine, twice is not
+        server.open(1, &second).unwrap();
+        server.open(1, &first).unwrap();
+        assert!(server.open(1, &first).is_err());
+        assert!(server.open(1, &second).is_err());
+    }
+
+    #[test]
+    fn reflected_packet_refused() {
+        let identity = Identity::generate();
+        let public = identity.public();
+        let (client, mut server, _) = keyed(identity, public);
+
+        server
+            .open(1, &client.seal(1, &ClientMessage::Ping(1.0)).unwrap())
+            .unwrap();
+        let packet = server.seal(1, &ClientMessage::Pong(1.0)).unwrap();
+        assert!(server.open(1, &packet).is_err());
+    }
+
+    #[test]
+    fn swapped_format_refused() {
+        let identity = Identity::generate();
+        let public = identity.public();
+        let (mut client, mut server, _) = keyed(identity, public);
+        client.set(1, WireFormat::Varint);
+
+        let mut packet = client.seal(1, &ClientMessage::Ping(1.0)).unwrap();
+        packet.format = WireFormat::Bincode;
+        assert!(server.open(1, &packet).is_err());
+    }
+
+    #[test]
+    fn plaintext_refused_after_key_exchange() {
+        let identity = Identity::generate();
+        let public = identity.public();
+        let (client, mut server, _) = keyed(identity, public);
+        let plain = WireFormats::new(WireFormat::Varint, false, false);
+
+        // still in the clear until the client proves the key
+        server
+            .open(1, &plain.seal(1, &ClientMessage::Ping(1.0)).unwrap())
+            .unwrap();
+        server
+            .open(1, &client.seal(1, &ClientMessage::Ping(2.0)).unwrap())
+            .unwrap();
+        // on any channel, not only the one that went sealed
+        assert!(server
+            .open(1, &plain.seal(1, &ClientMessage::Ping(3.0)).unwrap())
+            .is_err());
+        assert!(server
+            .open(1, &plain.seal(1, &state_message()).unwrap())
+            .is_err());
+    }
+
+    // `cargo test -p agarlib state_sizes -- --nocapture` prints the table
+    #[test]
+    fn state_sizes() {
+        let with_agars = |count: u32| GameStateMessage {
+            agars: (0..count).map(|id| (id, agar_update(id))).collect(),
+            ..state_message()
+        };
+        // as many agars as one unreliable packet takes
+        let full = split_state_message(with_agars(500), MAX_STATE_MESSAGE_LEN)
+            .into_iter()
+            .next()
+            .unwrap();
+        assert!(message_size(&full) <= MAX_STATE_MESSAGE_LEN);
+        let shapes = [
+            ("empty", with_agars(0)),
+            ("typical", with_agars(20)),
+            ("full", full),
+        ];
+
+        println!(
+            "{:>8} {:>6} {:>8} {:>7}",
+            "shape", "agars", "bincode", "varint"
+        );
+        for (shape, message) in shapes.iter() {
+            let bincode = BincodeCodec.encode(message).unwrap().len();
+            let varint = VarintCodec.encode(message).unwrap().len();
+            println!(
+                "{:>8} {:>6} {:>8} {:>7}",
+                shape,
+                message.agars.len(),
+                bincode,
+                varint
+            );
+            assert!(
+                varint <= bincode,
+                "{}: varint {} > bincode {}",
+                shape,
+                varint,
+                bincode
+            );
+        }
+    }
+
+    #[test]
+    fn varint_smaller_on_state() {
+        let message = GameStateMessage {
+            agars: (0..20).map(|id| (id, agar_update(id))).collect(),
+            ..state_message()
+        };
+        let bincode = BincodeCodec.encode(&message).unwrap().len();
+        let varint = VarintCodec.encode(&message).unwrap().len();
+        assert!(varint < bincode, "varint {} >= bincode {}", varint, bincode);
+    }
+}
@@ -0,0 +1,212 @@
+use anyhow::{anyhow, Result};
+use chacha20poly1305::{
+    aead::{Aead, NewAead, Payload},
+    Key, XChaCha20Poly1305, XNonce,
+};
+use ed25519_dalek::{Keypair, PublicKey as VerifyingKey, Signature, Signer, Verifier};
+use hmac::{Hmac, Mac, NewMac};
+use rand_core::{OsRng, RngCore};
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use std::convert::TryFrom;
+use x25519_dalek::{EphemeralSecret, PublicKey};
+
+pub type WireNonce = [u8; 24];
+
+pub type WirePublicKey = [u8; 32];
+
+const KEY_CONTEXT: &[u8] = b"agar wire key v1";
+
+#[derive(Clone)]
+pub struct WireCipher(XChaCha20Poly1305);
+
+impl WireCipher {
+    pub fn new(shared: &[u8]) -> Self {
+        let mut hasher = Sha256::new();
+        hasher.update(KEY_CONTEXT);
+        hasher.update(shared);
+        let key = hasher.finalize();
+
+        Self(XChaCha20Poly1305::new(Key::from_slice(&key)))
+    }
+
+    // 24-byte nonces are large enough to pick at random per packet; `aad` is authenticated
+    // along with the payload but travels outside it
+    pub fn seal(&self, payload: &[u8], aad: &[u8]) -> Result<(Vec<u8>, WireNonce)> {
+        let mut nonce = [0; 24];
+        OsRng.fill_bytes(&mut nonce);
+
+        let sealed = self
+            .0
+            .encrypt(XNonce::from_slice(&nonce), Payload { msg: payload, aad })
+            .map_err(|_| anyhow!("unable to encrypt payload"))?;
+
+        Ok((sealed, nonce))
+    }
+
+    pub fn open(&self, payload: &[u8], nonce: &WireNonce, aad: &[u8]) -> Result<Vec<u8>> {
+        self.0
+            .decrypt(XNonce::from_slice(nonce), Payload { msg: payload, aad })
+            .map_err(|_| anyhow!("payload failed authentication"))
+    }
+}
+
+pub struct KeyExchange {
+    secret: EphemeralSecret,
+    pub public: WirePublicKey,
+}
+
+impl KeyExchange {
+    pub fn new() -> Self {
+        let secret = EphemeralSecret::new(OsRng);
+        let public = *PublicKey::from(&secret).as_bytes();
+
+        Self { secret, public }
+    }
+
+    pub fn finish(self, peer: WirePublicKey) -> WireCipher {
+        let shared = self.secret.diffie_hellman(&PublicKey::from(peer));
+
+        WireCipher::new(shared.as_bytes())
+    }
+}
+
+// the public half of a server's `Identity`; published in `/status` for clients to pin
+pub type IdentityKey = [u8; 32];
+
+const IDENTITY_CONTEXT: &[u8] = b"agar server key v1";
+
+// a server's long-lived signing key. it signs the exchange key of every connection, so a
+// client that pinned the public half knows nobody in between swapped the key
+pub struct Identity(Keypair);
+
+impl Identity {
+    pub fn generate() -> Self {
+        Self(Keypair::generate(&mut OsRng))
+    }
+
+    pub fn from_bytes(bytes: &[u8]) -> Result<Self> {
+        Keypair::from_bytes(bytes)
+            .map(Self)
+            .map_err(|_| anyhow!("invalid identity key"))
+    }
+
+    pub fn to_bytes(&self) -> Vec<u8> {
+        self.0.to_bytes().to_vec()
+    }
+
+    pub fn public(&self) -> IdentityKey {
+        self.0.public.to_bytes()
+    }
+
+    // `peer` is the key the client offered, so the signature only vouches for this exchange
+    pub fn sign(&self, key: WirePublicKey, peer: &WirePublicKey) -> SignedKey {
+        let signature = self.0.sign(&signed_bytes(&key, peer));
+        SignedKey {
+            key,
+            signature: signature.to_bytes().to_vec(),
+        }
+    }
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+pub struct SignedKey {
+    pub key: WirePublicKey,
+    // empty from a server without an identity
+    pub signature: Vec<u8>,
+}
+
+impl SignedKey {
+    pub fn verify(&self, identity: &IdentityKey, peer: &WirePublicKey) -> bool {
+        let identity = match VerifyingKey::from_bytes(identity) {
+            Ok(identity) => identity,
+            Err(_) => return false,
+        };
+        Signature::try_from(self.signature.as_slice()).map_or(false, |signature| {
+            identity
+                .verify(&signed_bytes(&self.key, peer), &signature)
+                .is_ok()
+        })
+    }
+}
+
+fn signed_bytes(key: &WirePublicKey, peer: &WirePublicKey) -> Vec<u8> {
+    [IDENTITY_CONTEXT, &key[..], &peer[..]].concat()
+}
+
+pub fn identity_hex(identity: &IdentityKey) -> String {
+    identity
+        .iter()
+        .map(|byte| format!("{:02x}", byte))
+        .collect()
+}
+
+pub fn parse_identity(hex: &str) -> Option<IdentityKey> {
+    let hex = hex.trim();
+    if hex.len() != 64 || !hex.is_ascii() {
+        return None;
+    }
+    let mut identity = [0; 32];
+    for (i, byte) in identity.iter_mut().enumerate() {
+        *byte = u8::from_str_radix(&hex[i * 2..i * 2 + 2], 16).ok()?;
+    }
+    Some(identity)
+}
+
+// for secrets compared against what a client sent; both sides are hashed first so neither
+// the length nor the first differing byte shows in how long it takes
+pub fn secret_eq(a: &[u8], b: &[u8]) -> bool {
+    let (a, b) = (Sha256::digest(a), Sha256::digest(b));
+    a.iter()
+        .zip(b.iter())
+        .fold(0, |diff, (a, b)| diff | (a ^ b))
+        == 0
+}
+
+const PASS_CONTEXT: &[u8] = b"agar redirect pass v1";
+
+// lets a redirected player past the target's `max_players`; only servers sharing the
+// `redirect_secret` can issue one, and it names the player, the target and when it runs out
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+pub struct RedirectPass {
+    pub target: String,
+    // unix seconds
+    pub expires: u64,
+    pub mac: Vec<u8>,
+}
+
+impl RedirectPass {
+    pub fn issue(secret: &[u8], name: &str, target: &str, expires: u64) -> Self {
+        let mac = pass_mac(secret, name, target, expires)
+            .finalize()
+            .into_bytes()
+            .to_vec();
+        Self {
+            target: target.to_string(),
+            expires,
+            mac,
+        }
+    }
+
+    // whether this server issued it, or one sharing its secret did, for `name` and `target`
+    pub fn verify(&self, secret: &[u8], name: &str, target: &str, now: u64) -> bool {
+        !secret.is_empty()
+            && self.target == target
+            && now <= self.expires
+            && pass_mac(secret, name, &self.target, self.expires)
+                .verify(&self.mac)
+                .is_ok()
+    }
+}
+
+// length-prefixed, so no two inputs hash alike
+fn pass_mac(secret: &[u8], name: &str, target: &str, expires: u64) -> Hmac<Sha256> {
+    let mut mac = Hmac::<Sha256>::new_varkey(secret).expect("hmac takes keys of any length");
+    mac.update(PASS_CONTEXT);
+    for field in [name.as_bytes(), target.as_bytes()].iter() {
+        mac.update(&(field.len() as u64).to_le_bytes());
+        mac.update(field);
+    }
+    mac.update(&expires.to_le_bytes());
+    mac
+}
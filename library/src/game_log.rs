@@ -0,0 +1,44 @@
+use std::sync::atomic::{AtomicU64, Ordering};
+
+#[doc(hidden)]
+pub use bevy::log::debug as __debug;
+
+// events logged in full before sampling kicks in
+const BURST: u64 = 20;
+// after the burst, one event in this many is logged
+const SAMPLE: u64 = 100;
+
+// one per `game_event!` call site
+pub struct Sampler {
+    seen: AtomicU64,
+}
+
+impl Sampler {
+    pub const fn new() -> Self {
+        Self {
+            seen: AtomicU64::new(0),
+        }
+    }
+
+    // how many events the call site has seen, if this one should be logged
+    pub fn sample(&self) -> Option<u64> {
+        let seen = self.seen.fetch_add(1, Ordering::Relaxed) + 1;
+        if seen <= BURST || seen % SAMPLE == 0 {
+            Some(seen)
+        } else {
+            None
+        }
+    }
+}
+
+// per-entity chatter from hot loops: logged at debug under the `game_event` target, in full
+// for the first few events and sampled after that; `seen` counts every event of the call site
+#[macro_export]
+macro_rules! game_event {
+    ($($arg:tt)+) => {{
+        static SAMPLER: $crate::game_log::Sampler = $crate::game_log::Sampler::new();
+        if let Some(seen) = SAMPLER.sample() {
+            $crate::game_log::__debug!(target: "game_event", seen, $($arg)+);
+        }
+    }};
+}
@@ -1,36 +1,260 @@
 use bevy::prelude::*;
 use bevy_networking_turbulence::{
-    ConnectionChannelsBuilder, MessageChannelMode, MessageChannelSettings, NetworkEvent,
-    NetworkResource, NetworkingPlugin, ReliableChannelSettings,
+    ConnectionChannelsBuilder, ConnectionHandle, NetworkEvent, NetworkResource, NetworkingPlugin,
 };
 use serde::{Deserialize, Serialize};
-use std::{collections::HashMap, net::SocketAddr, time::Duration};
+use std::{
+    collections::{HashMap, HashSet},
+    net::{IpAddr, SocketAddr},
+};
+
+mod channels;
+mod codec;
+mod crypto;
+pub mod game_log;
+mod map;
+mod replay;
+
+pub use channels::*;
+pub use codec::*;
+pub use crypto::*;
+pub use map::*;
+pub use replay::*;
 
-const SERVER_PORT: u16 = 14192;
+pub const SERVER_PORT: u16 = 14192;
 
 pub const AGAR_INIT_SIZE: f32 = 15.0;
 pub const AGAR_MAX_SIZE: f32 = 500.0;
+pub const AGAR_EAT_RATIO: f32 = 1.1;
 
 pub fn max_velocity(size: f32) -> f32 {
     500.0 / ((size - AGAR_INIT_SIZE).powf(0.8) + 1.0) + 50.0
 }
 
+// the colors players pick from, or the server picks for them
+pub const AGAR_COLORS: [(f32, f32, f32); 8] = [
+    (0.8, 0.0, 0.0),
+    (0.9, 0.5, 0.0),
+    (0.9, 0.8, 0.1),
+    (0.1, 0.7, 0.2),
+    (0.0, 0.7, 0.8),
+    (0.1, 0.3, 0.9),
+    (0.6, 0.2, 0.8),
+    (0.9, 0.4, 0.7),
+];
+
+// skins in the client's atlas; `Agar::skin` indexes it
+pub const SKIN_COUNT: u8 = 16;
+
+pub fn agar_color(color: u8) -> Color {
+    let (r, g, b) = AGAR_COLORS[color as usize % AGAR_COLORS.len()];
+    Color::rgb(r, g, b)
+}
+
+// what eating a feed adds to a cell's size
+pub const FEED_GROWTH: f32 = 1.0;
+
 pub const WINDOW_WIDTH: f32 = 1000.0;
 pub const WINDOW_HEIGHT: f32 = 1000.0;
 
 pub const WORLD_WIDTH: f32 = 2000.0;
 pub const WORLD_HEIGHT: f32 = 2000.0;
 
-pub fn input_to_velocity(pos: &Vec2, max: f32) -> Vec3 {
-    let w = 0.5;
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct GameRules {
+    pub world_width: f32,
+    pub world_height: f32,
+    pub agar_init_size: f32,
+    pub agar_max_size: f32,
+    pub eat_ratio: f32,
+    // how far players may zoom in and out with the wheel, as factors of `camera_zoom`
+    pub min_zoom: f32,
+    pub max_zoom: f32,
+}
+
+impl Default for GameRules {
+    fn default() -> Self {
+        Self {
+            world_width: WORLD_WIDTH,
+            world_height: WORLD_HEIGHT,
+            agar_init_size: AGAR_INIT_SIZE,
+            agar_max_size: AGAR_MAX_SIZE,
+            eat_ratio: AGAR_EAT_RATIO,
+            min_zoom: MIN_WHEEL_ZOOM,
+            max_zoom: MAX_WHEEL_ZOOM,
+        }
+    }
+}
+
+impl GameRules {
+    pub fn can_eat(&self, eater: f32, victim: f32) -> bool {
+        eater > victim * self.eat_ratio
+    }
+
+    pub fn clamp_zoom(&self, zoom: f32) -> f32 {
+        zoom.max(self.min_zoom).min(self.max_zoom)
+    }
+
+    // the farthest a spectator zooms out: as far as the largest player can
+    pub fn max_spectator_zoom(&self) -> f32 {
+        camera_zoom(self, self.agar_max_size) * self.max_zoom
+    }
+
+    // a spectator zooms in as far as the smallest player can and out as far as the largest
+    pub fn clamp_spectator_zoom(&self, zoom: f32) -> f32 {
+        zoom.max(self.min_zoom).min(self.max_spectator_zoom())
+    }
+
+    // the largest region a spectator's view subscribes to; the server refuses bigger ones
+    pub fn max_region(&self) -> Vec2 {
+        // snapping rounds up by less than two steps
+        let zoom = self.max_spectator_zoom() * REGION_MARGIN;
+        Vec2::new(
+            WINDOW_WIDTH * zoom + REGION_STEP * 2.0,
+            WINDOW_HEIGHT * zoom + REGION_STEP * 2.0,
+        )
+    }
+
+    pub fn clamp(&self, translation: Vec3) -> Vec3 {
+        Vec3::new(
+            translation.x.max(0.0).min(self.world_width),
+            translation.y.max(0.0).min(self.world_height),
+            translation.z,
+        )
+    }
+}
+
+pub const NEAR_DISTANCE: f32 = WINDOW_WIDTH;
+// seconds between overview messages
+pub const OVERVIEW_INTERVAL: f64 = 0.2;
+// seconds between leaderboard messages, and the most players one lists; the client's full
+// scoreboard shows that many rows
+pub const LEADERBOARD_INTERVAL: f64 = 1.0;
+pub const LEADERBOARD_LEN: usize = 40;
+// seconds between checks for names to send
+pub const NAME_SYNC_INTERVAL: f64 = 0.5;
+
+pub fn in_far_tier(distance: f32, target_size: f32) -> bool {
+    distance >= NEAR_DISTANCE + target_size
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ReplicationKind {
+    Own,
+    Agar,
+    Subscribed,
+    Feed,
+}
+
+pub fn replication_priority(
+    rules: &GameRules,
+    kind: ReplicationKind,
+    distance: f32,
+    viewer_size: f32,
+    target_size: f32,
+) -> f32 {
+    match kind {
+        ReplicationKind::Own => 1.0,
+        ReplicationKind::Agar if !in_far_tier(distance, target_size) => {
+            if rules.can_eat(target_size, viewer_size) {
+                1.0
+            } else {
+                0.5
+            }
+        }
+        ReplicationKind::Agar => 0.2,
+        ReplicationKind::Subscribed => 0.5,
+        ReplicationKind::Feed => 0.1,
+    }
+}
+
+pub const SERVER_TICK_RATE: f64 = 30.0;
+
+// whole ticks covering `seconds`; at least one
+pub fn ticks(seconds: f64, tick_rate: f64) -> u32 {
+    (seconds * tick_rate).round().max(1.0) as u32
+}
+pub const TIME_SYNC_INTERVAL: f64 = 2.0;
+pub const PING_INTERVAL: f64 = 1.0;
+
+// how far the camera zooms out for a cell of `size`: 1 at the rules' initial size up to
+// `MAX_ZOOM` at their largest
+pub const MAX_ZOOM: f32 = 2.0;
+
+pub fn camera_zoom(rules: &GameRules, size: f32) -> f32 {
+    let t = (size - rules.agar_init_size) / (rules.agar_max_size - rules.agar_init_size);
+    1.0 + t.max(0.0).min(1.0) * (MAX_ZOOM - 1.0)
+}
+
+// the default bounds of `GameRules::min_zoom` and `max_zoom`
+pub const MIN_WHEEL_ZOOM: f32 = 0.5;
+pub const MAX_WHEEL_ZOOM: f32 = 1.5;
+
+// the cursor at the window's center, which steers nowhere
+pub fn neutral_cursor() -> Vec2 {
+    Vec2::new(WINDOW_WIDTH / 2.0, WINDOW_HEIGHT / 2.0)
+}
+
+// `pos` is the cursor in window coordinates; with the camera zoomed out by `zoom`, a
+// pixel covers that many world units
+pub fn input_to_velocity(pos: &Vec2, max: f32, zoom: f32) -> Vec3 {
+    let w = 0.5 * zoom;
     let x = (pos.x - WINDOW_WIDTH / 2.0) * w;
     let y = (pos.y - WINDOW_HEIGHT / 2.0) * w;
     let l = (x.powf(2.0) + y.powf(2.0)).sqrt();
+    if l == 0.0 {
+        return Vec3::zero();
+    }
     let w = l.min(max) / l;
 
     Vec3::new(x, y, 0.0) * w
 }
 
+// `pos` in a window of `size` logical pixels as a cursor in the `WINDOW_WIDTH` by
+// `WINDOW_HEIGHT` window the protocol assumes. both axes scale alike, so the direction to
+// the center survives any aspect ratio
+pub fn normalize_cursor(pos: Vec2, size: Vec2) -> Vec2 {
+    let center = neutral_cursor();
+    let short = size.x.min(size.y);
+    if short.is_nan() || short <= 0.0 {
+        return center;
+    }
+    let scale = WINDOW_WIDTH.min(WINDOW_HEIGHT) / short;
+    clamp_cursor(center + (pos - size / 2.0) * scale)
+}
+
+// a cursor on a view zoomed by `zoom` on top of `camera_zoom`, as the cursor pointing at the
+// same spot without it, which is what the server steers by
+pub fn zoom_cursor(pos: Vec2, zoom: f32) -> Vec2 {
+    let center = neutral_cursor();
+    clamp_cursor(center + (pos - center) * zoom)
+}
+
+// pulls a cursor outside the window back in along the line to the center
+pub fn clamp_cursor(pos: Vec2) -> Vec2 {
+    let center = neutral_cursor();
+    let offset = pos - center;
+    let over = (offset.x.abs() / center.x).max(offset.y.abs() / center.y);
+    if over <= 1.0 {
+        pos
+    } else {
+        center + offset / over
+    }
+}
+
+// the cursor position a gamepad stick stands for; tilts inside `deadzone` count as
+// centered, and the rest of the range reaches out to the window's edge
+pub fn stick_to_input(stick: Vec2, deadzone: f32) -> Vec2 {
+    let center = neutral_cursor();
+    let tilt = stick.length().min(1.0);
+    if tilt <= deadzone || deadzone >= 1.0 {
+        return center;
+    }
+
+    let reach = (tilt - deadzone) / (1.0 - deadzone);
+    center + stick.normalize() * reach * (WINDOW_WIDTH.min(WINDOW_HEIGHT) / 2.0 - 1.0)
+}
+
 #[derive(Serialize, Deserialize, Debug, Clone, Copy)]
 pub enum FeedColor {
     Red,
@@ -40,6 +264,28 @@ pub enum FeedColor {
 
 pub type EntityId = u32;
 
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct NetworkId(pub EntityId);
+
+#[derive(Debug, Default)]
+pub struct NetworkEntities {
+    entities: HashMap<EntityId, Entity>,
+}
+
+impl NetworkEntities {
+    pub fn insert(&mut self, id: EntityId, entity: Entity) {
+        self.entities.insert(id, entity);
+    }
+
+    pub fn remove(&mut self, id: EntityId) -> Option<Entity> {
+        self.entities.remove(&id)
+    }
+
+    pub fn get(&self, id: EntityId) -> Option<Entity> {
+        self.entities.get(&id).copied()
+    }
+}
+
 #[derive(Default)]
 pub struct NetworkBroadcast {
     pub frame: u32,
@@ -67,17 +313,395 @@ pub struct AgarUpdate {
 #[derive(Serialize, Deserialize, Debug, Clone)]
 pub struct GameStateMessage {
     pub frame: u32,
+    pub seq: u32,
     pub agars: HashMap<EntityId, AgarUpdate>,
     pub feeds: u64,
+    // only changed agars are listed; absence doesn't mean despawned
+    pub partial: bool,
+    // the match is frozen by an operator; nothing moves until it resumes
+    pub paused: bool,
+}
+
+pub const MAX_SUBSCRIPTIONS: usize = 4;
+
+// a spectator subscribes to a margin around its view, snapped to `REGION_STEP` so panning
+// doesn't resubscribe every frame
+pub const REGION_STEP: f32 = WINDOW_WIDTH / 4.0;
+pub const REGION_MARGIN: f32 = 1.5;
+
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq)]
+pub struct Region {
+    pub x: f32,
+    pub y: f32,
+    pub width: f32,
+    pub height: f32,
+}
+
+impl Region {
+    pub fn around(center: Vec2, width: f32, height: f32) -> Self {
+        Self {
+            x: center.x - width / 2.0,
+            y: center.y - height / 2.0,
+            width,
+            height,
+        }
+    }
+
+    pub fn contains(&self, translation: Vec3) -> bool {
+        translation.x >= self.x
+            && translation.x < self.x + self.width
+            && translation.y >= self.y
+            && translation.y < self.y + self.height
+    }
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct CellSummary {
+    pub id: EntityId,
+    pub x: u16,
+    pub y: u16,
+    pub size: u16,
+    pub color: u8,
+    pub skin: Option<u8>,
+}
+
+impl CellSummary {
+    pub fn new(id: EntityId, translation: Vec3, agar: &Agar, rules: &GameRules) -> Self {
+        let quantize = |v: f32, max: f32| ((v / max).max(0.0).min(1.0) * u16::MAX as f32) as u16;
+
+        Self {
+            id,
+            x: quantize(translation.x, rules.world_width),
+            y: quantize(translation.y, rules.world_height),
+            size: agar.size.round().min(u16::MAX as f32) as u16,
+            color: agar.color,
+            skin: agar.skin,
+        }
+    }
+
+    pub fn update(&self, rules: &GameRules) -> AgarUpdate {
+        let size = self.size as f32;
+        let x = self.x as f32 / u16::MAX as f32 * rules.world_width;
+        let y = self.y as f32 / u16::MAX as f32 * rules.world_height;
+
+        AgarUpdate {
+            agar: Agar {
+                size,
+                velocity: Vec2::zero(),
+                max_velocity: max_velocity(size),
+                color: self.color,
+                skin: self.skin,
+            },
+            translation: Vec3::new(x, y, 1.0),
+        }
+    }
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct OverviewMessage {
+    pub frame: u32,
+    pub cells: Vec<CellSummary>,
+}
+
+// columns and rows of `FeedDensity`'s grid, and seconds between two of them
+pub const DENSITY_GRID: u8 = 16;
+pub const DENSITY_INTERVAL: f64 = 2.0;
+
+// feeds per cell of a grid over the world, row by row from the bottom left; sent to
+// low-bandwidth clients instead of the feeds themselves
+#[derive(Serialize, Deserialize, Debug, Clone, Default, PartialEq)]
+pub struct FeedDensity {
+    pub columns: u8,
+    pub rows: u8,
+    pub counts: Vec<u8>,
+}
+
+impl FeedDensity {
+    pub fn new(rules: &GameRules, feeds: impl Iterator<Item = Vec3>) -> Self {
+        let mut density = Self {
+            columns: DENSITY_GRID,
+            rows: DENSITY_GRID,
+            counts: vec![0; DENSITY_GRID as usize * DENSITY_GRID as usize],
+        };
+        let cell = density.cell_size(rules);
+        for feed in feeds {
+            let column = ((feed.x / cell.x) as usize).min(density.columns as usize - 1);
+            let row = ((feed.y / cell.y) as usize).min(density.rows as usize - 1);
+            let count = &mut density.counts[row * density.columns as usize + column];
+            *count = count.saturating_add(1);
+        }
+        density
+    }
+
+    pub fn cell_size(&self, rules: &GameRules) -> Vec2 {
+        Vec2::new(
+            rules.world_width / self.columns.max(1) as f32,
+            rules.world_height / self.rows.max(1) as f32,
+        )
+    }
+
+    // the bottom left corner of the cell at `index` into `counts`
+    pub fn cell_origin(&self, index: usize, rules: &GameRules) -> Vec2 {
+        let columns = self.columns.max(1) as usize;
+        let cell = self.cell_size(rules);
+        Vec2::new(
+            (index % columns) as f32 * cell.x,
+            (index / columns) as f32 * cell.y,
+        )
+    }
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct LeaderboardEntry {
+    pub id: EntityId,
+    pub name: String,
+    pub size: f32,
+}
+
+// the biggest players of a world, biggest first; `total` counts everyone in it
+#[derive(Serialize, Deserialize, Debug, Clone, Default)]
+pub struct Leaderboard {
+    pub entries: Vec<LeaderboardEntry>,
+    pub total: u32,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, Default)]
+pub struct LoginRequest {
+    pub formats: Vec<WireFormat>,
+    pub profile: u64,
+    pub public_key: Option<WirePublicKey>,
+    // from a `Redirect`; lets the player past a full server
+    pub token: Option<RedirectPass>,
+    pub name: String,
+    // the client holds a token from an earlier `Owner`; the server asks for it with
+    // `OwnerRequired`, and it reclaims that agar after a lost connection or a server restart
+    pub resume: bool,
+    // self-reported, e.g. "eu"; the server may send the player to one closer by
+    pub region: Option<String>,
+    // index into `AGAR_COLORS`; `None` lets the server pick the one least worn in the world
+    pub color: Option<u8>,
+    // below `SKIN_COUNT`; `None` for a plain cell
+    pub skin: Option<u8>,
+    // fewer snapshots, and `FeedDensity` instead of the feeds, if the server allows it
+    pub low_bandwidth: bool,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq)]
+pub enum LoginRejection {
+    ChannelProfileMismatch,
+    EncryptionRequired,
+    ServerFull,
+    Banned,
+    ShuttingDown,
+    // too long, odd characters, a banned word, or taken by another player in the world
+    BadName,
+    // the server is private: the name isn't whitelisted and the password is missing or wrong
+    Restricted,
+    // never sent; the client's own verdict on a server whose key the pinned identity didn't sign
+    UntrustedServer,
+}
+
+// handled by the network plugin itself on its own channel
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub enum ControlMessage {
+    Redirect { addr: String, token: RedirectPass },
 }
 
 #[derive(Serialize, Deserialize, Debug, Clone)]
 pub enum ClientMessage {
-    Login,
-    LoginAck(EntityId),
+    Login(LoginRequest),
+    LoginAck {
+        id: EntityId,
+        format: WireFormat,
+        rules: GameRules,
+        public_key: Option<SignedKey>,
+        tick_rate: f64,
+        // the server agreed to `LoginRequest::low_bandwidth`
+        low_bandwidth: bool,
+    },
+    LoginRejected(LoginRejection),
     Input(Vec2),
     FeedRequest(u64),
-    FeedResponse(Vec<FeedUpdate>),
+    FeedResponse {
+        revision: u64,
+        baseline: bool,
+        complete: bool,
+        updates: Vec<FeedUpdate>,
+    },
+    TimeSyncRequest(f64),
+    TimeSyncResponse {
+        client_time: f64,
+        server_time: f64,
+        server_frame: u32,
+    },
+    Ping(f64),
+    Pong(f64),
+    // from a player; relayed to their world as `ChatLine`
+    Chat(String),
+    // from a player in team mode; relayed to their team only, as a `ChatLine` with `team` set
+    TeamChat(String),
+    // `from` is `None` for lines from the server itself, e.g. announcements
+    ChatLine {
+        from: Option<EntityId>,
+        name: String,
+        text: String,
+        team: bool,
+    },
+    // the server reloaded its config; replaces the rules of `LoginAck`
+    Rules(GameRules),
+    // the static layout of the player's world, sent after `LoginAck`
+    Map(GameMap),
+    ServerShuttingDown {
+        seconds: u32,
+    },
+    Subscribe(Region),
+    Unsubscribe(Region),
+    EntityDespawned(EntityId),
+    PlayerEaten {
+        victim: EntityId,
+        eater: EntityId,
+        victim_mass: f32,
+    },
+    Leaderboard(Leaderboard),
+    // from a player whose cell was eaten; answered with `Respawned` and the new cell's id
+    Respawn,
+    Respawned(EntityId),
+    // the named players of the world by cell; sent when they change and to whoever joins,
+    // split like `FeedResponse`: the `baseline` part replaces the names, the rest add to them
+    Names {
+        baseline: bool,
+        names: HashMap<EntityId, String>,
+    },
+    // where the feeds are, every `DENSITY_INTERVAL` to low-bandwidth clients
+    FeedDensity(FeedDensity),
+    // the player is leaving for good; their cell goes at once instead of waiting for them
+    Logout,
+    // a private server answers the login with its key; the password or whitelist key goes
+    // out in `Credentials` once the connection is encrypted with it, never before
+    CredentialsRequired {
+        public_key: Option<SignedKey>,
+    },
+    Credentials(String),
+    // a login with `resume` is answered with the server key too; the token goes back in
+    // `Owner` once the connection is encrypted with it, never before
+    OwnerRequired {
+        public_key: Option<SignedKey>,
+    },
+    // the token that reclaims an agar: from the server once the connection is encrypted, and
+    // from a resuming client in answer to `OwnerRequired`
+    Owner(u64),
+}
+
+pub const MAX_MESSAGE_LEN: usize = 10240;
+
+// unreliable messages have to fit in a single packet
+pub const MAX_STATE_MESSAGE_LEN: usize = 1024;
+
+// the `WirePacket` envelope once sealed: format tag, payload length and nonce around it, the
+// seal header and the authentication tag inside it
+pub const WIRE_OVERHEAD: usize = 64;
+
+fn encoded_size<T: Serialize>(value: &T) -> usize {
+    bincode::serialized_size(value).map_or(usize::MAX, |size| size as usize)
+}
+
+pub fn message_size<T: Serialize>(message: &T) -> usize {
+    encoded_size(message).saturating_add(WIRE_OVERHEAD)
+}
+
+fn chunk_by_size<T: Serialize>(items: Vec<T>, header: usize, limit: usize) -> Vec<Vec<T>> {
+    let mut chunks = vec![];
+    let mut chunk = vec![];
+    let mut size = header;
+
+    for item in items {
+        let item_size = encoded_size(&item);
+        if !chunk.is_empty() && size + item_size > limit {
+            chunks.push(std::mem::take(&mut chunk));
+            size = header;
+        }
+        size += item_size;
+        chunk.push(item);
+    }
+
+    if !chunk.is_empty() || chunks.is_empty() {
+        chunks.push(chunk);
+    }
+
+    chunks
+}
+
+pub fn split_feed_response(
+    revision: u64,
+    baseline: bool,
+    updates: Vec<FeedUpdate>,
+    limit: usize,
+) -> Vec<ClientMessage> {
+    let header = message_size(&ClientMessage::FeedResponse {
+        revision,
+        baseline,
+        complete: true,
+        updates: vec![],
+    });
+    let chunks = chunk_by_size(updates, header, limit);
+    let last = chunks.len() - 1;
+
+    chunks
+        .into_iter()
+        .enumerate()
+        .map(|(i, updates)| ClientMessage::FeedResponse {
+            revision,
+            baseline: baseline && i == 0,
+            complete: i == last,
+            updates,
+        })
+        .collect()
+}
+
+pub fn split_names(names: HashMap<EntityId, String>, limit: usize) -> Vec<ClientMessage> {
+    let header = message_size(&ClientMessage::Names {
+        baseline: true,
+        names: HashMap::new(),
+    });
+    let names: Vec<_> = names.into_iter().collect();
+
+    chunk_by_size(names, header, limit)
+        .into_iter()
+        .enumerate()
+        .map(|(i, names)| ClientMessage::Names {
+            baseline: i == 0,
+            names: names.into_iter().collect(),
+        })
+        .collect()
+}
+
+pub fn split_state_message(mut message: GameStateMessage, limit: usize) -> Vec<GameStateMessage> {
+    let agars: Vec<_> = std::mem::take(&mut message.agars).into_iter().collect();
+    let header = message_size(&message);
+    let chunks = chunk_by_size(agars, header, limit);
+    let partial = message.partial || chunks.len() > 1;
+
+    chunks
+        .into_iter()
+        .map(|agars| GameStateMessage {
+            agars: agars.into_iter().collect(),
+            partial,
+            ..message.clone()
+        })
+        .collect()
+}
+
+pub fn split_overview_message(mut message: OverviewMessage, limit: usize) -> Vec<OverviewMessage> {
+    let cells = std::mem::take(&mut message.cells);
+    let header = message_size(&message);
+
+    chunk_by_size(cells, header, limit)
+        .into_iter()
+        .map(|cells| OverviewMessage {
+            cells,
+            ..message.clone()
+        })
+        .collect()
 }
 
 #[derive(Debug)]
@@ -97,6 +721,81 @@ pub struct UpdateContext {
     pub frame: u32,
 }
 
+#[derive(Debug, Clone)]
+pub struct ClockSync {
+    pub offset: Option<f64>,
+    pub rtt: f64,
+    pub frame: u32,
+    pub frame_time: f64,
+    pub next_sync: f64,
+    // announced by the server in `LoginAck`
+    pub tick_rate: f64,
+}
+
+impl Default for ClockSync {
+    fn default() -> Self {
+        Self::new(SERVER_TICK_RATE)
+    }
+}
+
+impl ClockSync {
+    pub fn new(tick_rate: f64) -> Self {
+        Self {
+            offset: None,
+            rtt: 0.0,
+            frame: 0,
+            frame_time: 0.0,
+            next_sync: 0.0,
+            tick_rate,
+        }
+    }
+
+    pub fn should_sync(&mut self, now: f64) -> bool {
+        if now < self.next_sync {
+            return false;
+        }
+        self.next_sync = now + TIME_SYNC_INTERVAL;
+        true
+    }
+
+    pub fn update(&mut self, client_time: f64, server_time: f64, server_frame: u32, now: f64) {
+        let rtt = (now - client_time).max(0.0);
+        let offset = server_time + rtt / 2.0 - now;
+
+        // smooth out jitter once the first sample is in
+        match self.offset {
+            Some(prev) => {
+                self.offset = Some(prev + (offset - prev) * 0.1);
+                self.rtt += (rtt - self.rtt) * 0.1;
+            }
+            None => {
+                self.offset = Some(offset);
+                self.rtt = rtt;
+            }
+        }
+
+        self.frame = server_frame;
+        self.frame_time = server_time;
+    }
+
+    pub fn server_time(&self, local_time: f64) -> Option<f64> {
+        self.offset.map(|offset| local_time + offset)
+    }
+
+    pub fn frame_to_server_time(&self, frame: u32) -> f64 {
+        self.frame_time + (frame as f64 - self.frame as f64) / self.tick_rate
+    }
+
+    pub fn server_time_to_frame(&self, server_time: f64) -> f64 {
+        self.frame as f64 + (server_time - self.frame_time) * self.tick_rate
+    }
+
+    pub fn frame_to_local_time(&self, frame: u32) -> Option<f64> {
+        self.offset
+            .map(|offset| self.frame_to_server_time(frame) - offset)
+    }
+}
+
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct Feed {
     pub color: FeedColor,
@@ -107,14 +806,24 @@ pub struct Agar {
     pub size: f32,
     pub velocity: Vec2,
     pub max_velocity: f32,
+    // index into `AGAR_COLORS`, picked by the player
+    pub color: u8,
+    // drawn over the cell instead of `color` by clients that have the skins
+    pub skin: Option<u8>,
 }
 
 impl Agar {
     pub fn new() -> Self {
+        Self::with_size(AGAR_INIT_SIZE)
+    }
+
+    pub fn with_size(size: f32) -> Self {
         Self {
-            size: AGAR_INIT_SIZE,
+            size,
             velocity: Vec2::zero(),
-            max_velocity: max_velocity(AGAR_INIT_SIZE),
+            max_velocity: max_velocity(size),
+            color: 0,
+            skin: None,
         }
     }
 
@@ -122,47 +831,223 @@ impl Agar {
         self.size += size;
         self.max_velocity = max_velocity(self.size);
     }
+
+    pub fn resize(&mut self, size: f32) {
+        self.size = size;
+        self.max_velocity = max_velocity(self.size);
+    }
+
+    // takes in cells whose sizes squared add up to `area`: eating keeps the area of both
+    // circles, not the sum of their radii
+    pub fn absorb(&mut self, area: f32) {
+        self.resize((self.size * self.size + area).sqrt());
+    }
 }
 
 pub struct NetworkPlugin {
     pub server: bool,
+    pub format: WireFormat,
+    pub encryption: bool,
+    // server side: the bytes of the `Identity` that signs exchange keys
+    pub identity: Option<Vec<u8>>,
+    // client side: exchange keys not signed by this identity are refused
+    pub pinned: Option<IdentityKey>,
+    // clients connect at startup unless this is off; see `connect`
+    pub autoconnect: bool,
+    pub config: NetworkConfig,
 }
 
-const CLIENT_STATE_MESSAGE_SETTINGS: MessageChannelSettings = MessageChannelSettings {
-    channel: 0,
-    channel_mode: MessageChannelMode::Reliable {
-        reliability_settings: ReliableChannelSettings {
-            bandwidth: 4096,
-            recv_window_size: 1024,
-            send_window_size: 1024,
-            burst_bandwidth: 1024,
-            init_send: 512,
-            wakeup_time: Duration::from_millis(100),
-            initial_rtt: Duration::from_millis(200),
-            max_rtt: Duration::from_secs(2),
-            rtt_update_factor: 0.1,
-            rtt_resend_factor: 1.5,
-        },
-        max_message_len: 10240,
-    },
-    message_buffer_size: 8,
-    packet_buffer_size: 8,
-};
-
-const GAME_STATE_MESSAGE_SETTINGS: MessageChannelSettings = MessageChannelSettings {
-    channel: 1,
-    channel_mode: MessageChannelMode::Unreliable,
-    message_buffer_size: 8,
-    packet_buffer_size: 8,
-};
-
 #[derive(Default)]
 pub struct NetworkReader {
     pub network_events: EventReader<NetworkEvent>,
 }
 
+pub const MAX_SEND_RETRIES: u32 = 30;
+pub const QUALITY_REPORT_INTERVAL: f64 = 1.0;
+
+#[derive(Debug, Clone, Default)]
+pub struct ConnectionStats {
+    pub snapshots_received: u64,
+    pub snapshots_dropped: u64,
+    pub resends: u64,
+    pub send_failures: u64,
+    // since when the reliable channel has been too full to take a message
+    pub saturated_since: Option<f64>,
+    // total since the connection opened
+    pub bytes_received: u64,
+    last_seq: Option<u32>,
+}
+
+impl ConnectionStats {
+    pub fn snapshot_received(&mut self, seq: u32) {
+        match self.last_seq {
+            // another chunk of the same snapshot, or a late one
+            Some(last) if seq <= last => return,
+            Some(last) => self.snapshots_dropped += (seq - last - 1) as u64,
+            None => {}
+        }
+        self.snapshots_received += 1;
+        self.last_seq = Some(seq);
+    }
+
+    pub fn received<M: WireMessage>(&mut self, message: &M) {
+        self.bytes_received += message_size(message) as u64;
+    }
+
+    pub fn loss(&self) -> f32 {
+        let total = self.snapshots_received + self.snapshots_dropped;
+        if total == 0 {
+            0.0
+        } else {
+            self.snapshots_dropped as f32 / total as f32
+        }
+    }
+}
+
+#[derive(Debug, Default)]
+pub struct NetworkStats {
+    pub connections: HashMap<ConnectionHandle, ConnectionStats>,
+    // totals since startup; each message type has a channel of its own
+    pub bytes_sent: HashMap<&'static str, u64>,
+    pub dropped: u64,
+    next_report: f64,
+}
+
+impl NetworkStats {
+    pub fn connection(&mut self, handle: ConnectionHandle) -> &mut ConnectionStats {
+        self.connections.entry(handle).or_default()
+    }
+
+    pub fn sent<M: WireMessage>(&mut self, message: &M) {
+        let channel = std::any::type_name::<M>()
+            .rsplit("::")
+            .next()
+            .unwrap_or_default();
+
+        *self.bytes_sent.entry(channel).or_default() += message_size(message) as u64;
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct NetworkQualityEvent {
+    pub handle: ConnectionHandle,
+    pub stats: ConnectionStats,
+    pub loss: f32,
+}
+
+#[derive(Debug, Clone)]
+pub enum NetworkWarning {
+    ChannelsUnavailable(ConnectionHandle),
+    SendFailed(ConnectionHandle, String),
+    MessageTooLarge(ConnectionHandle, usize),
+}
+
+pub const DEGRADED_TIMEOUT: f64 = 3.0;
+pub const LOST_TIMEOUT: f64 = 10.0;
+// seconds the server keeps a dropped player's agar for them to reconnect to
+pub const RESUME_GRACE: f64 = 60.0;
+pub const DEGRADED_RTT: f64 = 0.5;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ConnectionState {
+    Connecting,
+    Connected,
+    Degraded,
+    Lost,
+}
+
+#[derive(Debug, Clone)]
+pub struct ConnectionStateEvent {
+    pub handle: ConnectionHandle,
+    pub state: ConnectionState,
+}
+
+#[derive(Debug, Clone)]
+struct ConnectionTracker {
+    state: ConnectionState,
+    since: f64,
+    last_heard: Option<f64>,
+    rtt: Option<f64>,
+}
+
+#[derive(Debug, Default)]
+pub struct ConnectionStates {
+    connections: HashMap<ConnectionHandle, ConnectionTracker>,
+}
+
+impl ConnectionStates {
+    pub fn heard(&mut self, handle: ConnectionHandle, now: f64) {
+        if let Some(tracker) = self.connections.get_mut(&handle) {
+            tracker.last_heard = Some(now);
+        }
+    }
+
+    pub fn rtt(&mut self, handle: ConnectionHandle, rtt: f64) {
+        if let Some(tracker) = self.connections.get_mut(&handle) {
+            tracker.rtt = Some(rtt);
+        }
+    }
+
+    pub fn state(&self, handle: ConnectionHandle) -> Option<ConnectionState> {
+        self.connections.get(&handle).map(|tracker| tracker.state)
+    }
+}
+
+struct Pending {
+    handle: ConnectionHandle,
+    message: ClientMessage,
+    attempts: u32,
+    // never given up on while the connection is open
+    critical: bool,
+}
+
+#[derive(Default)]
+pub struct SendQueue {
+    pending: Vec<Pending>,
+}
+
+impl SendQueue {
+    pub fn push(&mut self, handle: ConnectionHandle, message: ClientMessage) {
+        self.pending.push(Pending {
+            handle,
+            message,
+            attempts: 0,
+            critical: false,
+        });
+    }
+
+    // for messages the client can't do without, like login acks and despawns
+    pub fn push_critical(&mut self, handle: ConnectionHandle, message: ClientMessage) {
+        self.pending.push(Pending {
+            handle,
+            message,
+            attempts: 0,
+            critical: true,
+        });
+    }
+
+    pub fn pending(&self, handle: ConnectionHandle) -> usize {
+        self.pending.iter().filter(|p| p.handle == handle).count()
+    }
+
+    pub fn forget(&mut self, handle: ConnectionHandle) {
+        self.pending.retain(|p| p.handle != handle);
+    }
+}
+
 impl Plugin for NetworkPlugin {
     fn build(&self, app: &mut AppBuilder) {
+        let identity = self
+            .identity
+            .as_ref()
+            .and_then(|bytes| match Identity::from_bytes(bytes) {
+                Ok(identity) => Some(identity),
+                Err(err) => {
+                    error!("Unable to use the identity: {}", err);
+                    None
+                }
+            });
+
         if self.server {
             #[cfg(not(target_arch = "wasm32"))]
             {
@@ -173,40 +1058,522 @@ impl Plugin for NetworkPlugin {
                 app
             }
         } else {
-            app.add_startup_system(client_setup.system())
+            if self.autoconnect {
+                app.add_startup_system(client_setup.system());
+            }
+            app.add_resource(RedirectToken::default())
+                .add_system_to_stage(stage::PRE_UPDATE, redirect_system.system())
         }
         .add_plugin(NetworkingPlugin)
+        .add_event::<NetworkWarning>()
+        .add_event::<NetworkQualityEvent>()
+        .add_event::<ConnectionStateEvent>()
+        .add_resource(ConnectionStates::default())
+        .add_system(connection_state_system.system())
+        .add_resource(NetworkStats::default())
+        .add_system(network_quality_system.system())
         .add_startup_system(network_setup.system())
-        .add_resource(NetworkReader::default());
+        .add_resource(NetworkReader::default())
+        .add_resource(SendQueue::default())
+        .add_resource(
+            WireFormats::new(self.format, self.encryption, self.server)
+                .with_identity(identity)
+                .with_pinned(self.pinned),
+        )
+        .add_resource(self.config.clone())
+        .add_system_to_stage(stage::LAST, send_queue_system.system());
     }
 }
 
-fn network_setup(mut net: ResMut<NetworkResource>) {
-    net.set_channels_builder(|builder: &mut ConnectionChannelsBuilder| {
-        builder
-            .register::<ClientMessage>(CLIENT_STATE_MESSAGE_SETTINGS)
-            .unwrap();
-        builder
-            .register::<GameStateMessage>(GAME_STATE_MESSAGE_SETTINGS)
-            .unwrap();
+// presented in the login after a redirect
+#[derive(Default)]
+pub struct RedirectToken(pub Option<RedirectPass>);
+
+fn redirect_system(
+    mut net: ResMut<NetworkResource>,
+    mut formats: ResMut<WireFormats>,
+    mut token: ResMut<RedirectToken>,
+) {
+    let mut redirects = vec![];
+
+    for (handle, connection) in net.connections.iter_mut() {
+        let channels = match connection.channels() {
+            Some(channels) => channels,
+            None => continue,
+        };
+
+        while let Some(message) = recv_wire::<ControlMessage>(channels, &mut formats, *handle) {
+            match message {
+                Ok(ControlMessage::Redirect { addr, token }) => {
+                    redirects.push((*handle, addr, token))
+                }
+                Err(err) => warn!("Malformed ControlMessage on [{}]: {}", handle, err),
+            }
+        }
+    }
+
+    for (handle, addr, new_token) in redirects {
+        let socket_address = match resolve(&addr).first() {
+            Some(socket_address) => *socket_address,
+            None => {
+                error!("Unable to follow redirect to {}", addr);
+                continue;
+            }
+        };
+
+        info!("Redirected to {}", socket_address);
+        net.connections.remove(&handle);
+        formats.remove(handle);
+        token.0 = Some(new_token);
+        net.connect(socket_address);
+    }
+}
+
+fn network_setup(mut net: ResMut<NetworkResource>, config: Res<NetworkConfig>) {
+    let config = config.clone();
+    net.set_channels_builder(move |builder: &mut ConnectionChannelsBuilder| {
+        config.register(builder);
     });
 }
 
+fn send_queue_system(
+    mut net: ResMut<NetworkResource>,
+    mut queue: ResMut<SendQueue>,
+    mut warnings: ResMut<Events<NetworkWarning>>,
+    formats: Res<WireFormats>,
+    mut stats: ResMut<NetworkStats>,
+    config: Res<NetworkConfig>,
+    time: Res<Time>,
+) {
+    let pending = std::mem::take(&mut queue.pending);
+    // once a channel is full the rest of its messages wait, so they stay in order
+    let mut full = HashSet::new();
+
+    for mut pending in pending {
+        let handle = pending.handle;
+        if full.contains(&handle) {
+            queue.pending.push(pending);
+            continue;
+        }
+
+        let message = &pending.message;
+        let size = message_size(&message);
+        if size > config.profile.reliable.max_message_len {
+            warn!(
+                "dropping {} byte message to [{}]: {:?}",
+                size, handle, message
+            );
+            warnings.send(NetworkWarning::MessageTooLarge(handle, size));
+            stats.dropped += 1;
+            continue;
+        }
+
+        let reason = match send_wire(&mut net, &formats, handle, message) {
+            Ok(true) => {
+                stats.sent(message);
+                continue;
+            }
+            Ok(false) => {
+                full.insert(handle);
+                "channel is full".to_string()
+            }
+            Err(err) => err.to_string(),
+        };
+
+        let open = net.connections.contains_key(&handle);
+        if open && (pending.critical || pending.attempts < MAX_SEND_RETRIES) {
+            stats.connection(handle).resends += 1;
+            pending.attempts += 1;
+            queue.pending.push(pending);
+        } else {
+            stats.connection(handle).send_failures += 1;
+            stats.dropped += 1;
+            warn!(
+                "giving up sending {:?} to [{}]: {}",
+                pending.message, handle, reason
+            );
+            warnings.send(NetworkWarning::SendFailed(handle, reason));
+        }
+    }
+
+    let now = time.seconds_since_startup();
+    for handle in &full {
+        stats.connection(*handle).saturated_since.get_or_insert(now);
+    }
+    for (handle, connection) in stats.connections.iter_mut() {
+        if !full.contains(handle) {
+            connection.saturated_since = None;
+        }
+    }
+}
+
+fn connection_state_system(
+    time: Res<Time>,
+    mut reader: Local<EventReader<NetworkEvent>>,
+    network_events: Res<Events<NetworkEvent>>,
+    mut states: ResMut<ConnectionStates>,
+    mut events: ResMut<Events<ConnectionStateEvent>>,
+) {
+    let now = time.seconds_since_startup();
+
+    for event in reader.iter(&network_events) {
+        match event {
+            NetworkEvent::Connected(handle) => {
+                let tracker = ConnectionTracker {
+                    state: ConnectionState::Connecting,
+                    since: now,
+                    last_heard: None,
+                    rtt: None,
+                };
+                states.connections.insert(*handle, tracker);
+                events.send(ConnectionStateEvent {
+                    handle: *handle,
+                    state: ConnectionState::Connecting,
+                });
+            }
+            NetworkEvent::Disconnected(handle) => {
+                if let Some(tracker) = states.connections.remove(handle) {
+                    if tracker.state != ConnectionState::Lost {
+                        events.send(ConnectionStateEvent {
+                            handle: *handle,
+                            state: ConnectionState::Lost,
+                        });
+                    }
+                }
+            }
+            _ => {}
+        }
+    }
+
+    for (handle, tracker) in states.connections.iter_mut() {
+        let slow = tracker.rtt.map_or(false, |rtt| rtt > DEGRADED_RTT);
+        let state = match tracker.last_heard {
+            None if now - tracker.since > LOST_TIMEOUT => ConnectionState::Lost,
+            None => ConnectionState::Connecting,
+            Some(heard) if now - heard > LOST_TIMEOUT => ConnectionState::Lost,
+            Some(heard) if now - heard > DEGRADED_TIMEOUT || slow => ConnectionState::Degraded,
+            Some(_) => ConnectionState::Connected,
+        };
+
+        if state != tracker.state {
+            tracker.state = state;
+            events.send(ConnectionStateEvent {
+                handle: *handle,
+                state,
+            });
+        }
+    }
+}
+
+fn network_quality_system(
+    time: Res<Time>,
+    net: Res<NetworkResource>,
+    mut stats: ResMut<NetworkStats>,
+    mut events: ResMut<Events<NetworkQualityEvent>>,
+) {
+    let now = time.seconds_since_startup();
+    if now < stats.next_report {
+        return;
+    }
+    stats.next_report = now + QUALITY_REPORT_INTERVAL;
+
+    stats
+        .connections
+        .retain(|handle, _| net.connections.contains_key(handle));
+
+    for (handle, connection) in &stats.connections {
+        events.send(NetworkQualityEvent {
+            handle: *handle,
+            stats: connection.clone(),
+            loss: connection.loss(),
+        });
+    }
+}
+
 pub const ADDR: Option<&'static str> = option_env!("SERVER_ADDR");
 
-pub fn addr() -> &'static str {
-    ADDR.unwrap_or("172.23.76.35")
+// default when neither the command line nor the config names an address;
+// comma separated `host[:port]` entries, ipv6 literals need brackets with a port
+pub fn addrs() -> Vec<String> {
+    ADDR.unwrap_or("127.0.0.1")
+        .split(',')
+        .map(|addr| addr.trim().to_string())
+        .filter(|addr| !addr.is_empty())
+        .collect()
 }
 
-fn client_setup(mut net: ResMut<NetworkResource>) {
-    let socket_address = SocketAddr::new(addr().parse().unwrap(), SERVER_PORT);
-    info!("Starting client");
-    net.connect(socket_address);
+pub fn resolve(addr: &str) -> Vec<SocketAddr> {
+    if let Ok(socket_address) = addr.parse::<SocketAddr>() {
+        return vec![socket_address];
+    }
+
+    let literal = addr.trim_start_matches('[').trim_end_matches(']');
+    if let Ok(ip) = literal.parse::<IpAddr>() {
+        return vec![SocketAddr::new(ip, SERVER_PORT)];
+    }
+
+    resolve_host(addr)
 }
 
 #[cfg(not(target_arch = "wasm32"))]
-fn server_setup(mut net: ResMut<NetworkResource>) {
-    let socket_address = SocketAddr::new(addr().parse().unwrap(), SERVER_PORT);
-    info!("Starting server: {}", socket_address);
-    net.listen(socket_address);
+fn resolve_host(host: &str) -> Vec<SocketAddr> {
+    use std::net::ToSocketAddrs;
+
+    let host = if host.contains(':') {
+        host.to_string()
+    } else {
+        format!("{}:{}", host, SERVER_PORT)
+    };
+
+    match host.to_socket_addrs() {
+        Ok(addrs) => addrs.collect(),
+        Err(err) => {
+            warn!("Unable to resolve {}: {}", host, err);
+            vec![]
+        }
+    }
+}
+
+// no resolver in the browser
+#[cfg(target_arch = "wasm32")]
+fn resolve_host(host: &str) -> Vec<SocketAddr> {
+    warn!("Unable to resolve {}: hostnames are not supported", host);
+    vec![]
+}
+
+fn config_addrs(config: &NetworkConfig) -> Vec<SocketAddr> {
+    let addrs = if config.addresses.is_empty() {
+        addrs()
+    } else {
+        config.addresses.clone()
+    };
+
+    addrs.iter().flat_map(|addr| resolve(addr)).collect()
+}
+
+// the server a client connects to; ipv6 is preferred when the host has both
+pub fn client_addr(config: &NetworkConfig) -> Option<SocketAddr> {
+    let mut addrs = config_addrs(config);
+    addrs.sort_by_key(|addr| !addr.is_ipv6());
+    addrs.first().copied()
+}
+
+fn client_setup(mut net: ResMut<NetworkResource>, config: Res<NetworkConfig>) {
+    connect(&mut net, &config);
+}
+
+// for clients built without `autoconnect`, e.g. once the player is done with a menu
+pub fn connect(net: &mut NetworkResource, config: &NetworkConfig) {
+    match client_addr(config) {
+        Some(socket_address) => {
+            info!("Starting client: {}", socket_address);
+            net.connect(socket_address);
+        }
+        None => error!("No server address to connect to"),
+    }
+}
+
+#[cfg(not(target_arch = "wasm32"))]
+fn server_setup(mut net: ResMut<NetworkResource>, config: Res<NetworkConfig>) {
+    let addrs = config_addrs(&config);
+
+    if addrs.is_empty() {
+        error!("No address to listen on");
+    }
+
+    for socket_address in addrs {
+        info!("Starting server: {}", socket_address);
+        net.listen(socket_address);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn despawns(count: u32) -> Vec<FeedUpdate> {
+        (0..count).map(FeedUpdate::Despawn).collect()
+    }
+
+    fn despawned_ids(messages: &[ClientMessage]) -> Vec<EntityId> {
+        messages
+            .iter()
+            .flat_map(|message| match message {
+                ClientMessage::FeedResponse { updates, .. } => updates.clone(),
+                _ => panic!("not a feed response: {:?}", message),
+            })
+            .map(|update| match update {
+                FeedUpdate::Despawn(id) => id,
+                FeedUpdate::Spawn(spawn) => spawn.id,
+            })
+            .collect()
+    }
+
+    fn state_message(count: u32) -> GameStateMessage {
+        GameStateMessage {
+            frame: 7,
+            seq: 3,
+            agars: (0..count)
+                .map(|id| {
+                    let update = AgarUpdate {
+                        agar: Agar::new(),
+                        translation: Vec3::new(id as f32, 0.0, 0.0),
+                    };
+                    (id, update)
+                })
+                .collect(),
+            feeds: 42,
+            partial: false,
+            paused: false,
+        }
+    }
+
+    #[test]
+    fn chunk_empty_input() {
+        let chunks = chunk_by_size(Vec::<u64>::new(), 16, MAX_MESSAGE_LEN);
+        assert_eq!(chunks, vec![Vec::<u64>::new()]);
+    }
+
+    #[test]
+    fn chunk_exactly_at_limit() {
+        // a u64 is 8 bytes in bincode
+        let items: Vec<u64> = (0..4).collect();
+        assert_eq!(chunk_by_size(items.clone(), 16, 16 + 32).len(), 1);
+        assert_eq!(chunk_by_size(items, 16, 16 + 31).len(), 2);
+    }
+
+    #[test]
+    fn chunk_item_over_limit() {
+        // an item that can't fit anywhere still goes out, alone
+        let chunks = chunk_by_size(vec![1u64, 2, 3], 16, 20);
+        assert_eq!(chunks, vec![vec![1], vec![2], vec![3]]);
+    }
+
+    #[test]
+    fn feed_response_empty() {
+        let messages = split_feed_response(5, true, vec![], MAX_MESSAGE_LEN);
+        match messages.as_slice() {
+            [ClientMessage::FeedResponse {
+                revision: 5,
+                baseline: true,
+                complete: true,
+                updates,
+            }] => assert!(updates.is_empty()),
+            messages => panic!("unexpected split: {:?}", messages),
+        }
+    }
+
+    #[test]
+    fn feed_response_exactly_at_limit() {
+        let updates = despawns(100);
+        let limit = message_size(&ClientMessage::FeedResponse {
+            revision: 5,
+            baseline: true,
+            complete: true,
+            updates: updates.clone(),
+        });
+        assert!(limit <= MAX_MESSAGE_LEN);
+        assert_eq!(
+            split_feed_response(5, true, updates.clone(), limit).len(),
+            1
+        );
+        assert_eq!(split_feed_response(5, true, updates, limit - 1).len(), 2);
+    }
+
+    #[test]
+    fn feed_response_reassembles() {
+        let messages = split_feed_response(5, true, despawns(5000), MAX_MESSAGE_LEN);
+        assert!(messages.len() > 1);
+        assert_eq!(despawned_ids(&messages), (0..5000).collect::<Vec<_>>());
+
+        for (i, message) in messages.iter().enumerate() {
+            assert!(message_size(message) <= MAX_MESSAGE_LEN);
+            match message {
+                ClientMessage::FeedResponse {
+                    revision,
+                    baseline,
+                    complete,
+                    ..
+                } => {
+                    assert_eq!(*revision, 5);
+                    // only the first chunk clears the client's feeds, only the last ends it
+                    assert_eq!(*baseline, i == 0);
+                    assert_eq!(*complete, i == messages.len() - 1);
+                }
+                _ => unreachable!(),
+            }
+        }
+    }
+
+    #[test]
+    fn eating_keeps_area() {
+        let mut eater = Agar::with_size(30.0);
+        eater.absorb(40.0 * 40.0);
+        assert!((eater.size - 50.0).abs() < 1e-4);
+        assert_eq!(eater.max_velocity, max_velocity(eater.size));
+    }
+
+    #[test]
+    fn names_reassemble() {
+        let names: HashMap<_, _> = (0..2000).map(|id| (id, format!("player {}", id))).collect();
+        let messages = split_names(names.clone(), MAX_MESSAGE_LEN);
+        assert!(messages.len() > 1);
+
+        let mut received = HashMap::new();
+        for (i, message) in messages.iter().enumerate() {
+            assert!(message_size(message) <= MAX_MESSAGE_LEN);
+            match message {
+                ClientMessage::Names {
+                    baseline,
+                    names: part,
+                } => {
+                    assert_eq!(*baseline, i == 0);
+                    received.extend(part.clone());
+                }
+                _ => unreachable!(),
+            }
+        }
+        assert_eq!(received, names);
+    }
+
+    #[test]
+    fn state_message_empty() {
+        let messages = split_state_message(state_message(0), MAX_STATE_MESSAGE_LEN);
+        assert_eq!(messages.len(), 1);
+        assert!(messages[0].agars.is_empty());
+        assert!(!messages[0].partial);
+    }
+
+    #[test]
+    fn state_message_exactly_at_limit() {
+        let message = state_message(10);
+        let limit = message_size(&message);
+        let messages = split_state_message(message.clone(), limit);
+        assert_eq!(messages.len(), 1);
+        assert!(!messages[0].partial);
+        assert_eq!(split_state_message(message, limit - 1).len(), 2);
+    }
+
+    #[test]
+    fn state_message_agar_over_limit() {
+        let messages = split_state_message(state_message(3), 1);
+        assert_eq!(messages.len(), 3);
+        assert!(messages.iter().all(|message| message.agars.len() == 1));
+    }
+
+    #[test]
+    fn state_message_reassembles() {
+        let messages = split_state_message(state_message(200), MAX_STATE_MESSAGE_LEN);
+        assert!(messages.len() > 1);
+
+        let mut ids = vec![];
+        for message in &messages {
+            assert!(message_size(message) <= MAX_STATE_MESSAGE_LEN);
+            assert!(message.partial);
+            assert_eq!((message.frame, message.seq, message.feeds), (7, 3, 42));
+            ids.extend(message.agars.keys().copied());
+        }
+        ids.sort_unstable();
+        assert_eq!(ids, (0..200).collect::<Vec<_>>());
+    }
 }
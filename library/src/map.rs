@@ -0,0 +1,235 @@
+use crate::{encoded_size, WORLD_HEIGHT, WORLD_WIDTH};
+use bevy::prelude::*;
+use rand::Rng;
+use serde::{Deserialize, Serialize};
+
+// in world coordinates; a rect is anchored at its lower left corner
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq)]
+#[serde(rename_all = "snake_case")]
+pub enum Shape {
+    Circle {
+        x: f32,
+        y: f32,
+        radius: f32,
+    },
+    Rect {
+        x: f32,
+        y: f32,
+        width: f32,
+        height: f32,
+    },
+}
+
+impl Shape {
+    // whether a cell of `radius` at `p` overlaps the shape
+    pub fn overlaps(&self, p: Vec3, radius: f32) -> bool {
+        match *self {
+            Shape::Circle { x, y, radius: r } => Vec2::new(p.x - x, p.y - y).length() < r + radius,
+            Shape::Rect { .. } => {
+                let closest = self.closest(p);
+                Vec2::new(p.x - closest.x, p.y - closest.y).length() < radius || self.contains(p)
+            }
+        }
+    }
+
+    pub fn contains(&self, p: Vec3) -> bool {
+        match *self {
+            Shape::Circle { x, y, radius } => Vec2::new(p.x - x, p.y - y).length() < radius,
+            Shape::Rect {
+                x,
+                y,
+                width,
+                height,
+            } => p.x >= x && p.x <= x + width && p.y >= y && p.y <= y + height,
+        }
+    }
+
+    // the point of the shape closest to `p`; `p` itself when inside a rect
+    fn closest(&self, p: Vec3) -> Vec2 {
+        match *self {
+            Shape::Circle { x, y, radius } => {
+                let offset = Vec2::new(p.x - x, p.y - y);
+                let length = offset.length();
+                if length <= radius {
+                    Vec2::new(p.x, p.y)
+                } else {
+                    Vec2::new(x, y) + offset * (radius / length)
+                }
+            }
+            Shape::Rect {
+                x,
+                y,
+                width,
+                height,
+            } => Vec2::new(p.x.max(x).min(x + width), p.y.max(y).min(y + height)),
+        }
+    }
+
+    // moves a cell of `radius` at `p` to the nearest spot outside the shape
+    pub fn push_out(&self, p: Vec3, radius: f32) -> Vec3 {
+        if !self.overlaps(p, radius) {
+            return p;
+        }
+
+        match *self {
+            Shape::Circle { x, y, radius: r } => {
+                let offset = Vec2::new(p.x - x, p.y - y);
+                let direction = if offset.length() > f32::EPSILON {
+                    offset.normalize()
+                } else {
+                    Vec2::unit_x()
+                };
+                let out = Vec2::new(x, y) + direction * (r + radius);
+                Vec3::new(out.x, out.y, p.z)
+            }
+            Shape::Rect {
+                x,
+                y,
+                width,
+                height,
+            } => {
+                if self.contains(p) {
+                    // out through the nearest edge
+                    let exits = [
+                        (p.x - x, Vec3::new(x - radius, p.y, p.z)),
+                        (x + width - p.x, Vec3::new(x + width + radius, p.y, p.z)),
+                        (p.y - y, Vec3::new(p.x, y - radius, p.z)),
+                        (y + height - p.y, Vec3::new(p.x, y + height + radius, p.z)),
+                    ];
+                    let mut nearest = exits[0];
+                    for exit in &exits[1..] {
+                        if exit.0 < nearest.0 {
+                            nearest = *exit;
+                        }
+                    }
+                    nearest.1
+                } else {
+                    let closest = self.closest(p);
+                    let out = closest + (Vec2::new(p.x, p.y) - closest).normalize() * radius;
+                    Vec3::new(out.x, out.y, p.z)
+                }
+            }
+        }
+    }
+
+    pub fn random_point(&self, rng: &mut impl Rng) -> Vec2 {
+        match *self {
+            Shape::Circle { x, y, radius } => {
+                // uniform over the area, not bunched up in the middle
+                let distance = radius * rng.gen::<f32>().sqrt();
+                let angle = rng.gen_range(0.0..std::f32::consts::TAU);
+                Vec2::new(x + distance * angle.cos(), y + distance * angle.sin())
+            }
+            Shape::Rect {
+                x,
+                y,
+                width,
+                height,
+            } => Vec2::new(x + rng.gen::<f32>() * width, y + rng.gen::<f32>() * height),
+        }
+    }
+
+    fn valid(&self) -> bool {
+        match *self {
+            Shape::Circle { radius, .. } => radius > 0.0,
+            Shape::Rect { width, height, .. } => width > 0.0 && height > 0.0,
+        }
+    }
+}
+
+// cells inside move `speed` times as fast
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq)]
+pub struct BoostZone {
+    pub area: Shape,
+    pub speed: f32,
+}
+
+// cells big enough to eat it lose half their size on contact; smaller ones can hide under it
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq)]
+pub struct Virus {
+    pub x: f32,
+    pub y: f32,
+    pub size: f32,
+}
+
+// the static layout of a world; the server loads it from a map file and sends it to
+// players after login
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+#[serde(default)]
+pub struct GameMap {
+    pub name: String,
+    pub width: f32,
+    pub height: f32,
+    // cells can't enter these
+    pub obstacles: Vec<Shape>,
+    // players spawn inside one of these; anywhere when empty
+    pub spawns: Vec<Shape>,
+    pub boosts: Vec<BoostZone>,
+    pub viruses: Vec<Virus>,
+}
+
+impl Default for GameMap {
+    fn default() -> Self {
+        Self {
+            name: String::new(),
+            width: WORLD_WIDTH,
+            height: WORLD_HEIGHT,
+            obstacles: vec![],
+            spawns: vec![],
+            boosts: vec![],
+            viruses: vec![],
+        }
+    }
+}
+
+impl GameMap {
+    pub fn blocked(&self, p: Vec3, radius: f32) -> bool {
+        self.obstacles
+            .iter()
+            .any(|obstacle| obstacle.overlaps(p, radius))
+    }
+
+    pub fn push_out(&self, p: Vec3, radius: f32) -> Vec3 {
+        self.obstacles
+            .iter()
+            .fold(p, |p, obstacle| obstacle.push_out(p, radius))
+    }
+
+    // speed factor at `p`; the fastest zone wins where they overlap
+    pub fn boost(&self, p: Vec3) -> f32 {
+        self.boosts
+            .iter()
+            .filter(|boost| boost.area.contains(p))
+            .map(|boost| boost.speed)
+            .fold(1.0, f32::max)
+    }
+
+    // what's wrong with the map, if anything
+    pub fn check(&self, max_len: usize) -> Result<(), String> {
+        if self.width <= 0.0 || self.height <= 0.0 {
+            return Err("the world size must be positive".to_string());
+        }
+        let mut shapes = self
+            .obstacles
+            .iter()
+            .chain(&self.spawns)
+            .chain(self.boosts.iter().map(|boost| &boost.area));
+        if let Some(shape) = shapes.find(|shape| !shape.valid()) {
+            return Err(format!("{:?} has no area", shape));
+        }
+        if let Some(boost) = self.boosts.iter().find(|boost| boost.speed <= 0.0) {
+            return Err(format!("{:?} needs a positive speed", boost));
+        }
+        if let Some(virus) = self.viruses.iter().find(|virus| virus.size <= 0.0) {
+            return Err(format!("{:?} needs a positive size", virus));
+        }
+        let len = encoded_size(self);
+        if len > max_len {
+            return Err(format!(
+                "the map takes {} bytes on the wire, over the {} byte limit",
+                len, max_len
+            ));
+        }
+        Ok(())
+    }
+}
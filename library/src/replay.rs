@@ -0,0 +1,185 @@
+use crate::{ClientMessage, FeedUpdate, GameRules, GameStateMessage, WireCodec, WireFormat};
+use anyhow::{anyhow, Result};
+use flate2::{read::GzDecoder, write::GzEncoder, Compression};
+use serde::{Deserialize, Serialize};
+use std::io::{ErrorKind, Read, Write};
+
+const REPLAY_MAGIC: &[u8; 4] = b"AGRP";
+// bump when the layout of the records changes
+pub const REPLAY_VERSION: u32 = 5;
+
+// records use the same encoding as the wire
+const REPLAY_FORMAT: WireFormat = WireFormat::Varint;
+
+// far above any frame, but keeps a corrupt length prefix from allocating gigabytes
+const MAX_RECORD_LEN: usize = 16 << 20;
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct ReplayHeader {
+    pub version: u32,
+    pub tick_rate: f64,
+    // seed of the server's simulation rng; 0 in replays recorded by a client
+    pub seed: u64,
+    pub rules: GameRules,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub enum ReplayEvent {
+    State(GameStateMessage),
+    // `baseline` replaces every feed, like in `FeedResponse`
+    Feeds {
+        baseline: bool,
+        updates: Vec<FeedUpdate>,
+    },
+    // reliable messages sent to the players of the world
+    Message(ClientMessage),
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct ReplayFrame {
+    // one server process may host several worlds
+    pub world: u32,
+    pub frame: u32,
+    pub events: Vec<ReplayEvent>,
+}
+
+// gzip stream of length-prefixed records: the header, then frames in order
+pub struct ReplayWriter<W: Write> {
+    encoder: GzEncoder<W>,
+}
+
+impl<W: Write> ReplayWriter<W> {
+    pub fn new(writer: W, header: &ReplayHeader) -> Result<Self> {
+        let mut encoder = GzEncoder::new(writer, Compression::default());
+        encoder.write_all(REPLAY_MAGIC)?;
+
+        let mut writer = Self { encoder };
+        writer.write_record(header)?;
+        Ok(writer)
+    }
+
+    fn write_record<T: Serialize>(&mut self, record: &T) -> Result<()> {
+        let bytes = REPLAY_FORMAT.encode(record)?;
+        if bytes.len() > MAX_RECORD_LEN {
+            return Err(anyhow!("record of {} bytes is too large", bytes.len()));
+        }
+        self.encoder
+            .write_all(&(bytes.len() as u32).to_le_bytes())?;
+        self.encoder.write_all(&bytes)?;
+        Ok(())
+    }
+
+    pub fn write_frame(&mut self, frame: &ReplayFrame) -> Result<()> {
+        self.write_record(frame)
+    }
+
+    pub fn finish(self) -> Result<W> {
+        Ok(self.encoder.finish()?)
+    }
+}
+
+pub struct ReplayReader<R: Read> {
+    decoder: GzDecoder<R>,
+    header: ReplayHeader,
+}
+
+impl<R: Read> ReplayReader<R> {
+    pub fn new(reader: R) -> Result<Self> {
+        let mut decoder = GzDecoder::new(reader);
+
+        let mut magic = [0; 4];
+        decoder.read_exact(&mut magic)?;
+        if &magic != REPLAY_MAGIC {
+            return Err(anyhow!("not a replay file"));
+        }
+
+        let header: ReplayHeader =
+            read_record(&mut decoder)?.ok_or_else(|| anyhow!("replay has no header"))?;
+        if header.version != REPLAY_VERSION {
+            return Err(anyhow!(
+                "replay version {} is not supported (expected {})",
+                header.version,
+                REPLAY_VERSION
+            ));
+        }
+
+        Ok(Self { decoder, header })
+    }
+
+    pub fn header(&self) -> &ReplayHeader {
+        &self.header
+    }
+
+    pub fn next_frame(&mut self) -> Result<Option<ReplayFrame>> {
+        read_record(&mut self.decoder)
+    }
+}
+
+impl<R: Read> Iterator for ReplayReader<R> {
+    type Item = Result<ReplayFrame>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        self.next_frame().transpose()
+    }
+}
+
+// `None` at a clean end of stream; a record cut short (e.g. by a crash) is an error
+fn read_record<T: serde::de::DeserializeOwned>(reader: &mut impl Read) -> Result<Option<T>> {
+    let mut len = [0; 4];
+    match reader.read_exact(&mut len) {
+        Ok(()) => {}
+        Err(err) if err.kind() == ErrorKind::UnexpectedEof => return Ok(None),
+        Err(err) => return Err(err.into()),
+    }
+
+    let len = u32::from_le_bytes(len) as usize;
+    if len > MAX_RECORD_LEN {
+        return Err(anyhow!("record of {} bytes is too large", len));
+    }
+    let mut bytes = vec![0; len];
+    reader.read_exact(&mut bytes)?;
+    Ok(Some(REPLAY_FORMAT.decode(&bytes)?))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn header() -> ReplayHeader {
+        ReplayHeader {
+            version: REPLAY_VERSION,
+            tick_rate: 30.0,
+            seed: 0,
+            rules: GameRules::default(),
+        }
+    }
+
+    #[test]
+    fn frames_round_trip() {
+        let mut writer = ReplayWriter::new(vec![], &header()).unwrap();
+        for frame in 0..3 {
+            writer
+                .write_frame(&ReplayFrame {
+                    world: 0,
+                    frame,
+                    events: vec![ReplayEvent::Message(ClientMessage::Respawn)],
+                })
+                .unwrap();
+        }
+        let bytes = writer.finish().unwrap();
+
+        let reader = ReplayReader::new(bytes.as_slice()).unwrap();
+        let frames: Vec<_> = reader.map(|frame| frame.unwrap().frame).collect();
+        assert_eq!(frames, vec![0, 1, 2]);
+    }
+
+    #[test]
+    fn oversized_record_refused() {
+        let mut writer = ReplayWriter::new(vec![], &header()).unwrap();
+        writer.encoder.write_all(&u32::MAX.to_le_bytes()).unwrap();
+        let bytes = writer.finish().unwrap();
+
+        let mut reader = ReplayReader::new(bytes.as_slice()).unwrap();
+        assert!(reader.next_frame().is_err());
+    }
+}
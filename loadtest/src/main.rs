@@ -0,0 +1,370 @@
+use agarlib::*;
+use bevy::{app::ScheduleRunnerSettings, prelude::*};
+use bevy_networking_turbulence::{NetworkEvent, NetworkResource};
+use clap::{App as Cli, Arg};
+use rand::Rng;
+use std::{collections::HashMap, time::Duration};
+
+const TICK_RATE: f64 = 30.0;
+const INPUT_INTERVAL: f64 = 0.1;
+const TIME_SYNC_INTERVAL: f64 = 1.0;
+
+struct LoadTestArgs {
+    server: Option<String>,
+    bots: usize,
+    ramp: f64,
+    report: f64,
+}
+
+impl LoadTestArgs {
+    fn parse() -> Self {
+        let matches = Cli::new("agarload")
+            .about("Headless bots for load testing the agar server")
+            .arg(
+                Arg::with_name("server")
+                    .long("server")
+                    .value_name("ADDR")
+                    .help("Server to connect to, as host[:port]"),
+            )
+            .arg(
+                Arg::with_name("bots")
+                    .long("bots")
+                    .value_name("N")
+                    .default_value("10")
+                    .help("Number of concurrent connections"),
+            )
+            .arg(
+                Arg::with_name("ramp")
+                    .long("ramp")
+                    .value_name("SECONDS")
+                    .default_value("0.1")
+                    .help("Delay between opening connections"),
+            )
+            .arg(
+                Arg::with_name("report")
+                    .long("report")
+                    .value_name("SECONDS")
+                    .default_value("5")
+                    .help("Interval between stats reports"),
+            )
+            .get_matches();
+
+        let number = |name| {
+            matches
+                .value_of(name)
+                .and_then(|value| value.parse::<f64>().ok())
+                .unwrap_or_else(|| {
+                    error!("invalid --{}", name);
+                    std::process::exit(1);
+                })
+        };
+
+        Self {
+            server: matches.value_of("server").map(String::from),
+            bots: number("bots") as usize,
+            ramp: number("ramp"),
+            report: number("report"),
+        }
+    }
+}
+
+fn main() {
+    tracing_subscriber::fmt().init();
+
+    let args = LoadTestArgs::parse();
+
+    App::build()
+        .add_resource(ScheduleRunnerSettings::run_loop(Duration::from_secs_f64(
+            1.0 / TICK_RATE,
+        )))
+        .add_plugins(MinimalPlugins)
+        .add_resource(Bots::new(&args))
+        .add_system(connect_system.system())
+        .add_system(login_system.system())
+        .add_system_to_stage(stage::PRE_UPDATE, message_system.system())
+        .add_system(input_system.system())
+        .add_system(report_system.system())
+        // the plugin opens the first connection; `connect_system` ramps up the rest.
+        // redirects aren't followed per bot, so point it at a game server directly
+        .add_plugin(
+            NetworkPluginBuilder::new()
+                .encryption(true)
+                .addresses(args.server)
+                .build(),
+        )
+        .run();
+}
+
+#[derive(Default)]
+struct Bot {
+    name: String,
+    id: Option<EntityId>,
+    heading: f32,
+    rtt: Option<f64>,
+    snapshots: u64,
+    overviews: u64,
+    deaths: u64,
+}
+
+struct Bots {
+    target: usize,
+    ramp: f64,
+    report: f64,
+    opened: usize,
+    next_connect: f64,
+    next_input: f64,
+    next_time_sync: f64,
+    next_report: f64,
+    bots: HashMap<u32, Bot>,
+}
+
+impl Bots {
+    fn new(args: &LoadTestArgs) -> Self {
+        Self {
+            target: args.bots,
+            ramp: args.ramp,
+            report: args.report,
+            // opened by the network plugin at startup
+            opened: 1,
+            next_connect: args.ramp,
+            next_input: 0.0,
+            next_time_sync: 0.0,
+            next_report: args.report,
+            bots: HashMap::new(),
+        }
+    }
+}
+
+fn connect_system(
+    time: Res<Time>,
+    mut bots: ResMut<Bots>,
+    mut net: ResMut<NetworkResource>,
+    config: Res<NetworkConfig>,
+) {
+    let now = time.seconds_since_startup();
+    if bots.opened >= bots.target || now < bots.next_connect {
+        return;
+    }
+    bots.next_connect = now + bots.ramp;
+
+    if let Some(addr) = client_addr(&config) {
+        net.connect(addr);
+        bots.opened += 1;
+    }
+}
+
+fn login(
+    handle: u32,
+    bot: &Bot,
+    formats: &mut WireFormats,
+    config: &NetworkConfig,
+) -> ClientMessage {
+    ClientMessage::Login(LoginRequest {
+        formats: WIRE_FORMATS.to_vec(),
+        profile: config.profile.fingerprint(),
+        public_key: formats.begin_key_exchange(handle),
+        name: bot.name.clone(),
+        ..Default::default()
+    })
+}
+
+fn login_system(
+    mut bots: ResMut<Bots>,
+    mut queue: ResMut<SendQueue>,
+    mut formats: ResMut<WireFormats>,
+    config: Res<NetworkConfig>,
+    mut state: ResMut<NetworkReader>,
+    network_events: Res<Events<NetworkEvent>>,
+) {
+    for event in state.network_events.iter(&network_events) {
+        match event {
+            NetworkEvent::Connected(handle) => {
+                let bot = Bot {
+                    name: format!("bot-{}", handle),
+                    heading: rand::thread_rng().gen_range(0.0..std::f32::consts::TAU),
+                    ..Default::default()
+                };
+                let message = login(*handle, &bot, &mut formats, &config);
+                queue.push(*handle, message);
+                bots.bots.insert(*handle, bot);
+            }
+            NetworkEvent::Disconnected(handle) => {
+                warn!("Bot [{}] disconnected", handle);
+                bots.bots.remove(handle);
+                formats.remove(*handle);
+            }
+            _ => {}
+        }
+    }
+}
+
+fn message_system(
+    time: Res<Time>,
+    mut bots: ResMut<Bots>,
+    mut net: ResMut<NetworkResource>,
+    mut queue: ResMut<SendQueue>,
+    mut formats: ResMut<WireFormats>,
+) {
+    let now = time.seconds_since_startup();
+    let mut replies = vec![];
+
+    for (handle, connection) in net.connections.iter_mut() {
+        let bot = match bots.bots.get_mut(handle) {
+            Some(bot) => bot,
+            None => continue,
+        };
+        let channels = match connection.channels() {
+            Some(channels) => channels,
+            None => continue,
+        };
+
+        while let Some(message) = recv_wire::<ClientMessage>(channels, &mut formats, *handle) {
+            match message {
+                Ok(ClientMessage::LoginAck {
+                    id,
+                    format,
+                    public_key,
+                    ..
+                }) => {
+                    formats.set(*handle, format);
+                    if let Err(err) = formats.complete_key_exchange(*handle, public_key.as_ref()) {
+                        error!("Bot [{}] refused the server: {}", handle, err);
+                    }
+                    bot.id = Some(id);
+                }
+                Ok(ClientMessage::Respawned(id)) => {
+                    bot.id = Some(id);
+                }
+                Ok(ClientMessage::LoginRejected(rejection)) => {
+                    error!("Bot [{}] rejected: {:?}", handle, rejection);
+                }
+                Ok(ClientMessage::TimeSyncResponse { client_time, .. }) => {
+                    let rtt = now - client_time;
+                    bot.rtt = Some(bot.rtt.map_or(rtt, |old| old + (rtt - old) * 0.2));
+                }
+                Ok(ClientMessage::Ping(sent)) => {
+                    replies.push((*handle, ClientMessage::Pong(sent)));
+                }
+                // dead bots respawn to keep the load up
+                Ok(ClientMessage::PlayerEaten { victim, .. }) if Some(victim) == bot.id => {
+                    bot.id = None;
+                    bot.deaths += 1;
+                    replies.push((*handle, ClientMessage::Respawn));
+                }
+                Ok(_) => {}
+                Err(err) => warn!("Malformed ClientMessage on [{}]: {}", handle, err),
+            }
+        }
+
+        while let Some(state) = recv_wire::<GameStateMessage>(channels, &mut formats, *handle) {
+            if state.is_ok() {
+                bot.snapshots += 1;
+            }
+        }
+        while let Some(overview) = recv_wire::<OverviewMessage>(channels, &mut formats, *handle) {
+            if overview.is_ok() {
+                bot.overviews += 1;
+            }
+        }
+    }
+
+    for (handle, message) in replies {
+        queue.push(handle, message);
+    }
+}
+
+// a random walk, steered like a cursor around the middle of the window
+fn input_system(
+    time: Res<Time>,
+    mut bots: ResMut<Bots>,
+    mut net: ResMut<NetworkResource>,
+    formats: Res<WireFormats>,
+) {
+    let now = time.seconds_since_startup();
+    let bots = &mut *bots;
+    let inputs_due = now >= bots.next_input;
+    let time_sync_due = now >= bots.next_time_sync;
+    if inputs_due {
+        bots.next_input = now + INPUT_INTERVAL;
+    }
+    if time_sync_due {
+        bots.next_time_sync = now + TIME_SYNC_INTERVAL;
+    }
+
+    let mut rng = rand::thread_rng();
+    for (handle, bot) in bots.bots.iter_mut().filter(|(_, bot)| bot.id.is_some()) {
+        if inputs_due {
+            bot.heading += rng.gen_range(-0.5..0.5);
+            let distance = rng.gen_range(50.0..300.0);
+            let cursor = Vec2::new(
+                WINDOW_WIDTH / 2.0 + bot.heading.cos() * distance,
+                WINDOW_HEIGHT / 2.0 + bot.heading.sin() * distance,
+            );
+
+            if let Err(err) = send_wire(&mut net, &formats, *handle, &ClientMessage::Input(cursor))
+            {
+                debug!("unable to send input from [{}]: {}", handle, err);
+            }
+        }
+
+        if time_sync_due {
+            let message = ClientMessage::TimeSyncRequest(now);
+            if let Err(err) = send_wire(&mut net, &formats, *handle, &message) {
+                debug!("unable to send time sync from [{}]: {}", handle, err);
+            }
+        }
+    }
+}
+
+fn report_system(time: Res<Time>, mut bots: ResMut<Bots>) {
+    let now = time.seconds_since_startup();
+    if now < bots.next_report {
+        return;
+    }
+    let elapsed = bots.report;
+    bots.next_report = now + elapsed;
+
+    let mut rtts = vec![];
+    let mut snapshots = 0;
+    let mut logged_in = 0;
+
+    for (handle, bot) in bots.bots.iter_mut() {
+        let rate = bot.snapshots as f64 / elapsed;
+        debug!(
+            "[{}] {}: rtt {:?}, {:.1} snapshots/s, {:.1} overviews/s, {} deaths",
+            handle,
+            bot.name,
+            bot.rtt,
+            rate,
+            bot.overviews as f64 / elapsed,
+            bot.deaths
+        );
+
+        if bot.id.is_some() {
+            logged_in += 1;
+        }
+        if let Some(rtt) = bot.rtt {
+            rtts.push(rtt);
+        }
+        snapshots += bot.snapshots;
+        bot.snapshots = 0;
+        bot.overviews = 0;
+    }
+
+    rtts.sort_by(|a, b| a.partial_cmp(b).unwrap_or(std::cmp::Ordering::Equal));
+    let percentile = |p: f64| {
+        rtts.get(((rtts.len() as f64 - 1.0) * p).round() as usize)
+            .map_or(0.0, |rtt| rtt * 1000.0)
+    };
+
+    info!(
+        "{}/{} bots connected, {} playing; rtt p50 {:.0}ms p95 {:.0}ms max {:.0}ms; {:.1} snapshots/s per bot",
+        bots.bots.len(),
+        bots.target,
+        logged_in,
+        percentile(0.5),
+        percentile(0.95),
+        percentile(1.0),
+        snapshots as f64 / elapsed / bots.bots.len().max(1) as f64,
+    );
+}
@@ -0,0 +1,407 @@
+use crate::{
+    config::{ServerConfig, MAX_FEED_TARGET},
+    grid::SpatialIndex,
+    instance::{Instance, PerInstance},
+    leave::Leaving,
+    names,
+    region::ConnectionTags,
+    reload::Reload,
+    FeedUpdates, Inbox, NetworkIdAllocator, OwnerToken, PlayerName, Simulation, TransformHistory,
+};
+use agarlib::*;
+use bevy::prelude::*;
+use bevy_networking_turbulence::NetworkResource;
+use rand::Rng;
+use serde::Serialize;
+use std::{
+    collections::HashSet,
+    fmt,
+    net::IpAddr,
+    sync::{
+        mpsc::{channel, Receiver, Sender},
+        Mutex,
+    },
+    time::Duration,
+};
+
+const REPLY_TIMEOUT: Duration = Duration::from_secs(2);
+const MAX_WORLD_SIZE: f32 = 100_000.0;
+
+// the http api names players by id; the console also takes names
+#[derive(Debug)]
+pub enum Target {
+    Id(EntityId),
+    Name(String),
+}
+
+impl fmt::Display for Target {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Target::Id(id) => write!(f, "{}", id),
+            Target::Name(name) => write!(f, "{}", name),
+        }
+    }
+}
+
+#[derive(Debug)]
+pub enum AdminCommand {
+    ListPlayers,
+    Kick(Target),
+    Ban(Target),
+    Announce(String),
+    SetFeedTarget(usize),
+    ResetWorld,
+    Pause,
+    Resume,
+    Reload,
+    ResizeWorld(f32, f32),
+    ListWhitelist,
+    Whitelist(String),
+    Unwhitelist(String),
+    CheatReports,
+}
+
+#[derive(Debug)]
+pub struct AdminReply {
+    pub status: u16,
+    pub body: String,
+}
+
+impl AdminReply {
+    fn ok(body: impl Into<String>) -> Self {
+        Self {
+            status: 200,
+            body: body.into(),
+        }
+    }
+
+    fn not_found(target: &Target) -> Self {
+        Self {
+            status: 404,
+            body: format!("no player {}", target),
+        }
+    }
+}
+
+pub struct AdminRequest {
+    pub command: AdminCommand,
+    pub reply: Sender<AdminReply>,
+}
+
+// filled by the http and console threads, drained by `admin_system` every tick
+pub struct AdminQueue {
+    requests: Mutex<Receiver<AdminRequest>>,
+}
+
+impl AdminQueue {
+    pub fn new() -> (Self, Sender<AdminRequest>) {
+        let (sender, receiver) = channel();
+        let queue = Self {
+            requests: Mutex::new(receiver),
+        };
+        (queue, sender)
+    }
+}
+
+// hands a command to the game loop and waits for its answer
+pub fn request(requests: &Sender<AdminRequest>, command: AdminCommand) -> AdminReply {
+    let (reply, replies) = channel();
+    if requests.send(AdminRequest { command, reply }).is_err() {
+        return AdminReply {
+            status: 503,
+            body: "server is shutting down".to_string(),
+        };
+    }
+
+    replies
+        .recv_timeout(REPLY_TIMEOUT)
+        .unwrap_or_else(|_| AdminReply {
+            status: 504,
+            body: "no answer from the game loop".to_string(),
+        })
+}
+
+// at least a cell across, and small enough for f32 positions to stay precise
+fn valid_size(size: f32) -> bool {
+    (AGAR_MAX_SIZE * 2.0..=MAX_WORLD_SIZE).contains(&size)
+}
+
+#[derive(Default)]
+pub struct Bans {
+    pub addrs: HashSet<IpAddr>,
+    // owner tokens of banned players, so a parked agar can't be reclaimed from elsewhere;
+    // they don't outlive the server, and neither do the agars they name
+    pub owners: HashSet<u64>,
+}
+
+#[derive(Serialize)]
+struct PlayerEntry<'a> {
+    id: EntityId,
+    handle: u32,
+    instance: usize,
+    name: &'a str,
+    size: f32,
+    x: f32,
+    y: f32,
+    addr: Option<String>,
+    region: Option<&'a str>,
+    rtt: Option<f64>,
+}
+
+pub fn admin_system(
+    commands: &mut Commands,
+    net: Res<NetworkResource>,
+    admin: Res<AdminQueue>,
+    mut inbox: ResMut<Inbox>,
+    mut config: ResMut<ServerConfig>,
+    mut bans: ResMut<Bans>,
+    mut ids: ResMut<NetworkIdAllocator>,
+    mut leaving: ResMut<Leaving>,
+    mut feed_updates: ResMut<PerInstance<FeedUpdates>>,
+    mut index: ResMut<PerInstance<SpatialIndex>>,
+    mut simulation: ResMut<Simulation>,
+    mut reload: ResMut<Reload>,
+    rules: Res<GameRules>,
+    tags: Res<ConnectionTags>,
+    mut agars: Query<(
+        Entity,
+        &NetworkId,
+        &mut Agar,
+        &mut Transform,
+        Option<&NetworkHandle>,
+        &OwnerToken,
+        &PlayerName,
+        &Instance,
+    )>,
+    feeds: Query<(Entity, &NetworkId, &Feed, &Instance)>,
+) {
+    let requests = match admin.requests.lock() {
+        Ok(requests) => requests,
+        Err(_) => return,
+    };
+
+    for AdminRequest { command, reply } in requests.try_iter() {
+        info!("Admin command: {:?}", command);
+        let ban = matches!(command, AdminCommand::Ban(_));
+
+        let response = match command {
+            AdminCommand::ListPlayers => {
+                let players: Vec<_> = agars
+                    .iter_mut()
+                    .filter_map(|(_, id, agar, transform, handle, _, name, instance)| {
+                        let handle = handle?;
+                        let tag = tags.get(handle.id);
+                        Some(PlayerEntry {
+                            id: id.0,
+                            handle: handle.id,
+                            instance: instance.0,
+                            name: &name.0,
+                            size: agar.size,
+                            x: transform.translation.x,
+                            y: transform.translation.y,
+                            addr: tag.and_then(|tag| tag.addr).map(|addr| addr.to_string()),
+                            region: tag.and_then(|tag| tag.region.as_deref()),
+                            rtt: tag.and_then(|tag| tag.rtt),
+                        })
+                    })
+                    .collect();
+
+                match serde_json::to_string(&players) {
+                    Ok(body) => AdminReply::ok(body),
+                    Err(err) => AdminReply {
+                        status: 500,
+                        body: err.to_string(),
+                    },
+                }
+            }
+            // parked agars too, or their owner could take them back after the kick
+            AdminCommand::Kick(target) | AdminCommand::Ban(target) => {
+                let player = agars
+                    .iter_mut()
+                    .find(|(_, id, _, _, _, _, name, _)| match &target {
+                        Target::Id(target) => id.0 == *target,
+                        Target::Name(target) => {
+                            names::normalize(&name.0) == names::normalize(target)
+                        }
+                    })
+                    .map(|(entity, _, _, _, handle, owner, _, _)| {
+                        (entity, handle.map(|handle| handle.id), owner.0)
+                    });
+
+                match player {
+                    Some((entity, handle, owner)) => {
+                        if ban {
+                            let addr = handle
+                                .and_then(|handle| net.connections.get(&handle))
+                                .and_then(|connection| connection.remote_address());
+                            if let Some(addr) = addr {
+                                bans.addrs.insert(addr.ip());
+                            }
+                            bans.owners.insert(owner);
+                        }
+
+                        match handle {
+                            Some(handle) => leaving.drop_player(handle),
+                            None => leaving.drop_parked(entity),
+                        }
+                        AdminReply::ok(format!("removed player {}", target))
+                    }
+                    None => AdminReply::not_found(&target),
+                }
+            }
+            AdminCommand::Announce(text) => {
+                inbox.announcements.push(text);
+                AdminReply::ok("announced")
+            }
+            AdminCommand::SetFeedTarget(target) => {
+                if target > MAX_FEED_TARGET {
+                    AdminReply {
+                        status: 400,
+                        body: format!("feed target {} is above {}", target, MAX_FEED_TARGET),
+                    }
+                } else {
+                    config.feed_target = target;
+                    AdminReply::ok(format!("feed target is {}", target))
+                }
+            }
+            AdminCommand::ResetWorld => {
+                for (entity, id, _feed, instance) in feeds.iter() {
+                    if ids.free(id.0).is_some() {
+                        feed_updates[*instance].despawn(id.0);
+                        commands.despawn(entity);
+                    }
+                }
+                for (_, index) in index.iter_mut() {
+                    index.feeds.clear();
+                }
+
+                for (entity, _, mut agar, mut transform, handle, ..) in agars.iter_mut() {
+                    // parked agars are left as they were, for their owners to come back to
+                    if handle.is_none() {
+                        continue;
+                    }
+                    *agar = Agar {
+                        color: agar.color,
+                        skin: agar.skin,
+                        ..Agar::with_size(rules.agar_init_size)
+                    };
+                    transform.translation = simulation
+                        .random_point(&rules, rules.agar_init_size, true)
+                        .extend(1.0);
+                    // don't let lag compensation see the old positions
+                    commands.insert_one(entity, TransformHistory::default());
+                }
+
+                AdminReply::ok("world reset")
+            }
+            AdminCommand::Pause => {
+                simulation.paused = true;
+                AdminReply::ok("paused")
+            }
+            AdminCommand::Resume => {
+                simulation.paused = false;
+                AdminReply::ok("resumed")
+            }
+            AdminCommand::ListWhitelist => {
+                // the names only; a key is only handed out by `whitelist add`
+                let names: Vec<_> = config
+                    .whitelist
+                    .keys()
+                    .chain(config.granted.keys())
+                    .collect();
+                match serde_json::to_string(&names) {
+                    Ok(body) => AdminReply::ok(body),
+                    Err(err) => AdminReply {
+                        status: 500,
+                        body: err.to_string(),
+                    },
+                }
+            }
+            AdminCommand::Whitelist(name) => {
+                let key = match names::whitelist_key(&name, &config) {
+                    Some(key) => key.to_string(),
+                    None => {
+                        let key = format!("{:032x}", rand::thread_rng().gen::<u128>());
+                        config.granted.insert(name.clone(), key.clone());
+                        key
+                    }
+                };
+                match config.save_granted() {
+                    Ok(()) => AdminReply::ok(format!("whitelisted {} with key {}", name, key)),
+                    Err(err) => AdminReply {
+                        status: 500,
+                        body: format!(
+                            "whitelisted {} with key {} until restart: {:#}",
+                            name, key, err
+                        ),
+                    },
+                }
+            }
+            AdminCommand::Unwhitelist(name) => {
+                let normalized = names::normalize(&name);
+                let before = config.granted.len();
+                config
+                    .granted
+                    .retain(|entry, _| names::normalize(entry) != normalized);
+                let in_file = config
+                    .whitelist
+                    .keys()
+                    .any(|entry| names::normalize(entry) == normalized);
+                if config.granted.len() < before {
+                    match config.save_granted() {
+                        Ok(()) => AdminReply::ok(format!("removed {} from the whitelist", name)),
+                        Err(err) => AdminReply {
+                            status: 500,
+                            body: format!("removed {} until restart: {:#}", name, err),
+                        },
+                    }
+                } else if in_file {
+                    AdminReply {
+                        status: 409,
+                        body: format!("{} is whitelisted in {}", name, config.path),
+                    }
+                } else {
+                    AdminReply {
+                        status: 404,
+                        body: format!("{} is not whitelisted", name),
+                    }
+                }
+            }
+            AdminCommand::Reload => {
+                reload.requested = true;
+                AdminReply::ok(format!("reloading {}", config.path))
+            }
+            AdminCommand::ResizeWorld(width, height) => {
+                if !config.map.is_empty() {
+                    AdminReply {
+                        status: 409,
+                        body: format!("the world size comes from {}", config.map),
+                    }
+                } else if !valid_size(width) || !valid_size(height) {
+                    AdminReply {
+                        status: 400,
+                        body: format!("bad world size {}x{}", width, height),
+                    }
+                } else {
+                    // the same density of feeds over the new area
+                    let scale = (width * height) / (config.world_width * config.world_height);
+                    config.feed_target =
+                        ((config.feed_target as f32 * scale).round() as usize).min(MAX_FEED_TARGET);
+                    config.world_width = width;
+                    config.world_height = height;
+                    AdminReply::ok(format!(
+                        "world is {}x{} with {} feeds",
+                        width, height, config.feed_target
+                    ))
+                }
+            }
+            AdminCommand::CheatReports => {
+                inbox.cheat_reports.push(reply);
+                continue;
+            }
+        };
+
+        // the http side may have timed out already
+        let _ = reply.send(response);
+    }
+}
@@ -0,0 +1,48 @@
+use crate::{config::ServerConfig, leave::Leaving};
+use agarlib::*;
+use bevy::prelude::*;
+use bevy_networking_turbulence::NetworkResource;
+
+// messages waiting for a connection before it's dropped, whatever `saturation_timeout` says
+const MAX_BACKLOG: usize = 2048;
+
+// drops connections whose reliable channel stayed full for `saturation_timeout` seconds, or
+// whose backlog grew past `MAX_BACKLOG`; their critical messages would otherwise pile up in
+// the send queue for good
+pub fn backpressure_system(
+    time: Res<Time>,
+    stats: Res<NetworkStats>,
+    queue: Res<SendQueue>,
+    net: Res<NetworkResource>,
+    mut leaving: ResMut<Leaving>,
+    config: Res<ServerConfig>,
+) {
+    let now = time.seconds_since_startup();
+    let timeout = config.saturation_timeout;
+    let saturated = stats.connections.iter().filter(|(_, connection)| {
+        timeout > 0.0
+            && connection
+                .saturated_since
+                .map_or(false, |since| now - since >= timeout)
+    });
+    for (handle, _) in saturated.filter(|(handle, _)| net.connections.contains_key(handle)) {
+        warn!(
+            "Disconnecting [{}]: channel saturated for {}s with {} messages waiting",
+            handle,
+            timeout,
+            queue.pending(*handle)
+        );
+        leaving.drop_player(*handle);
+    }
+
+    for handle in net.connections.keys() {
+        let pending = queue.pending(*handle);
+        if pending > MAX_BACKLOG {
+            warn!(
+                "Disconnecting [{}]: {} messages waiting to be sent",
+                handle, pending
+            );
+            leaving.drop_player(*handle);
+        }
+    }
+}
@@ -0,0 +1,79 @@
+use crate::{
+    config::ServerConfig,
+    instance::{Instance, Instances, PerInstance},
+    FeedUpdates,
+};
+use agarlib::*;
+use bevy::prelude::*;
+use bevy_networking_turbulence::NetworkResource;
+use std::collections::{HashMap, HashSet};
+
+// snapshots to low-bandwidth clients are this many times further apart
+pub const LOW_BANDWIDTH_SLOWDOWN: u32 = 3;
+
+// on the cell of a player whose client asked for low bandwidth, when the server allows it
+pub struct LowBandwidth;
+
+// connections on low bandwidth, kept past their cell, e.g. while they spectate
+#[derive(Default)]
+pub struct LowBandwidthClients {
+    handles: HashSet<u32>,
+}
+
+impl LowBandwidthClients {
+    pub fn contains(&self, handle: u32) -> bool {
+        self.handles.contains(&handle)
+    }
+}
+
+pub fn granted(login: &LoginRequest, config: &ServerConfig) -> bool {
+    login.low_bandwidth && config.low_bandwidth
+}
+
+// low-bandwidth clients don't ask for the feeds; they get how many there are where, right
+// after logging in and every `DENSITY_INTERVAL`
+pub fn density_system(
+    time: Res<Time>,
+    mut next: Local<f64>,
+    net: Res<NetworkResource>,
+    instances: Res<Instances>,
+    rules: Res<GameRules>,
+    feed_updates: Res<PerInstance<FeedUpdates>>,
+    mut clients: ResMut<LowBandwidthClients>,
+    mut queue: ResMut<SendQueue>,
+    players: Query<&NetworkHandle, With<LowBandwidth>>,
+) {
+    clients
+        .handles
+        .retain(|handle| net.connections.contains_key(handle));
+    let joined: Vec<_> = players
+        .iter()
+        .map(|handle| handle.id)
+        .filter(|&handle| clients.handles.insert(handle))
+        .collect();
+
+    let now = time.seconds_since_startup();
+    let due = now >= *next;
+    if due {
+        *next = now + DENSITY_INTERVAL;
+    }
+
+    let mut densities: HashMap<Instance, FeedDensity> = HashMap::new();
+    for &handle in &clients.handles {
+        if !due && !joined.contains(&handle) {
+            continue;
+        }
+        let instance = match instances.get(handle) {
+            Some(instance) => instance,
+            None => continue,
+        };
+        let density = densities.entry(instance).or_insert_with(|| {
+            let feeds = feed_updates[instance]
+                .snapshot
+                .values()
+                .map(|feed| feed.translation);
+            FeedDensity::new(&rules, feeds)
+        });
+        queue.push(handle, ClientMessage::FeedDensity(density.clone()));
+    }
+}
@@ -0,0 +1,60 @@
+use crate::{
+    grid::SpatialIndex,
+    instance::{Instance, PerInstance},
+    FeedUpdates, NetworkIdAllocator, TransformHistory,
+};
+use agarlib::*;
+use bevy::prelude::*;
+
+// follows the world size in `GameRules`: cells outside new bounds are moved inside, and
+// feeds outside are removed for `feed_spawn_system` to replace within them
+pub fn bounds_system(
+    commands: &mut Commands,
+    mut bounds: Local<Option<(f32, f32)>>,
+    rules: Res<GameRules>,
+    mut feed_updates: ResMut<PerInstance<FeedUpdates>>,
+    mut ids: ResMut<NetworkIdAllocator>,
+    mut index: ResMut<PerInstance<SpatialIndex>>,
+    mut agars: Query<(Entity, &mut Transform), With<Agar>>,
+    feeds: Query<(Entity, &NetworkId, &Feed, &Instance)>,
+) {
+    let size = (rules.world_width, rules.world_height);
+    let old = match bounds.replace(size) {
+        Some(old) if old != size => old,
+        _ => return,
+    };
+    info!(
+        "World resized from {}x{} to {}x{}",
+        old.0, old.1, size.0, size.1
+    );
+
+    for (entity, mut transform) in agars.iter_mut() {
+        let inside = rules.clamp(transform.translation);
+        if inside != transform.translation {
+            transform.translation = inside;
+            // lag compensation must not see the cell outside the world
+            commands.insert_one(entity, TransformHistory::default());
+        }
+    }
+
+    let mut removed = 0;
+    // feeds never move, so their spawn updates still say where they are
+    for (entity, id, _feed, instance) in feeds.iter() {
+        let translation = match feed_updates[*instance].snapshot.get(&id.0) {
+            Some(spawn) => spawn.translation,
+            None => continue,
+        };
+        if rules.clamp(translation) == translation {
+            continue;
+        }
+        if ids.free(id.0).is_some() {
+            feed_updates[*instance].despawn(id.0);
+            index[*instance].feeds.remove(entity, translation);
+            commands.despawn(entity);
+            removed += 1;
+        }
+    }
+    if removed > 0 {
+        info!("Removed {} feeds outside the world", removed);
+    }
+}
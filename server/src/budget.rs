@@ -0,0 +1,158 @@
+use crate::{
+    metrics::{tick_start_system, Metrics},
+    spans::take_timings,
+    Simulation, SIMULATION,
+};
+use agarlib::*;
+use bevy::prelude::*;
+use std::{
+    collections::VecDeque,
+    time::{Duration, Instant},
+};
+
+const TICK_START: &str = "tick_start";
+pub const TICK_END: &str = "tick_end";
+// a serial stage holding only its timer runs exactly between the stages around it
+const STAGE_TIMERS: &[(&str, &str)] = &[
+    (stage::FIRST, "first_timer"),
+    (stage::PRE_UPDATE, "pre_update_timer"),
+    (stage::UPDATE, "update_timer"),
+    (SIMULATION, "simulation_timer"),
+    (stage::POST_UPDATE, "post_update_timer"),
+];
+
+// seconds of ticks kept for the histogram
+const HISTORY: f64 = 10.0;
+const REPORT_INTERVAL: Duration = Duration::from_secs(60);
+// upper bounds of the histogram buckets; a last bucket takes the rest
+const BUCKETS_MS: &[f64] = &[1.0, 2.0, 5.0, 10.0, 20.0, 33.0, 50.0, 100.0];
+
+pub struct TickBudget {
+    budget: Duration,
+    // when the last timed stage ended; the tick's start before the first
+    stage_start: Option<Instant>,
+    stages: Vec<(&'static str, Duration)>,
+    history: VecDeque<Duration>,
+    capacity: usize,
+    next_report: Instant,
+}
+
+impl TickBudget {
+    pub fn new(tick_rate: f64) -> Self {
+        let capacity = ticks(HISTORY, tick_rate) as usize;
+        Self {
+            budget: Duration::from_secs_f64(1.0 / tick_rate),
+            stage_start: None,
+            stages: vec![],
+            history: VecDeque::with_capacity(capacity),
+            capacity,
+            next_report: Instant::now() + REPORT_INTERVAL,
+        }
+    }
+
+    fn stage_done(&mut self, stage: &'static str, tick_start: Option<Instant>) {
+        let now = Instant::now();
+        if let Some(start) = self.stage_start.replace(now).or(tick_start) {
+            self.stages.push((stage, now - start));
+        }
+    }
+
+    // recent ticks per bucket of `BUCKETS_MS`
+    pub fn histogram(&self) -> Vec<usize> {
+        let mut counts = vec![0; BUCKETS_MS.len() + 1];
+        for tick in &self.history {
+            let ms = tick.as_secs_f64() * 1000.0;
+            let bucket = BUCKETS_MS
+                .iter()
+                .position(|bound| ms <= *bound)
+                .unwrap_or(BUCKETS_MS.len());
+            counts[bucket] += 1;
+        }
+        counts
+    }
+}
+
+pub fn add_stage_timers(app: &mut AppBuilder) {
+    app.add_stage_before(
+        stage::FIRST,
+        TICK_START,
+        SystemStage::serial().with_system(tick_start_system.system()),
+    )
+    .add_stage_after(
+        stage::LAST,
+        TICK_END,
+        SystemStage::serial().with_system(tick_end_system.system()),
+    );
+
+    for &(stage, timer) in STAGE_TIMERS {
+        app.add_stage_after(
+            stage,
+            timer,
+            SystemStage::serial().with_system(
+                (move |mut budget: ResMut<TickBudget>, metrics: Res<Metrics>| {
+                    budget.stage_done(stage, metrics.tick_started())
+                })
+                .system(),
+            ),
+        );
+    }
+}
+
+// times the tick from the metrics' `tick_start_system`, and starts over for the next one
+fn tick_end_system(
+    mut budget: ResMut<TickBudget>,
+    mut simulation: ResMut<Simulation>,
+    mut metrics: ResMut<Metrics>,
+) {
+    budget.stage_done(stage::LAST, metrics.tick_started());
+    let budget = &mut *budget;
+    let stages = std::mem::take(&mut budget.stages);
+    budget.stage_start = None;
+    // only this tick's systems count, also when it isn't slow
+    let timings = take_timings();
+    let elapsed = match metrics.end_tick() {
+        Some(elapsed) => elapsed,
+        None => return,
+    };
+
+    if budget.history.len() >= budget.capacity {
+        budget.history.pop_front();
+    }
+    budget.history.push_back(elapsed);
+
+    let slow = elapsed > budget.budget;
+    simulation.overload.tick(slow);
+    if slow {
+        metrics.slow_tick();
+
+        let ms = |duration: &Duration| duration.as_secs_f64() * 1000.0;
+        let slowest_stage = stages.iter().max_by_key(|(_, elapsed)| *elapsed);
+        let slowest_system = timings.into_iter().max_by_key(|(_, elapsed)| *elapsed);
+        warn!(
+            "Slow tick: {:.1}ms over the {:.1}ms budget; slowest stage {}, slowest system {}",
+            ms(&elapsed),
+            ms(&budget.budget),
+            slowest_stage.map_or("-".to_string(), |(stage, elapsed)| format!(
+                "{} ({:.1}ms)",
+                stage,
+                ms(elapsed)
+            )),
+            slowest_system.map_or("-".to_string(), |(system, elapsed)| format!(
+                "{} ({:.1}ms)",
+                system,
+                ms(&elapsed)
+            )),
+        );
+    }
+
+    let now = Instant::now();
+    if now >= budget.next_report {
+        budget.next_report = now + REPORT_INTERVAL;
+        info!(
+            "Tick times of the last {} ticks per bucket of {:?}ms (and over): {:?}",
+            budget.history.len(),
+            BUCKETS_MS,
+            budget.histogram()
+        );
+    }
+}
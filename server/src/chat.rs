@@ -0,0 +1,140 @@
+use crate::{
+    config::ServerConfig,
+    instance::{Instances, PerInstance},
+    replay::Recorder,
+    team::Teams,
+    Inbox, PlayerName,
+};
+use agarlib::*;
+use bevy::prelude::*;
+use std::collections::HashMap;
+
+// token bucket per connection: `chat_burst` lines at once, refilled at `chat_rate` per second
+struct Allowance {
+    tokens: f64,
+    last: f64,
+}
+
+#[derive(Default)]
+pub struct ChatLimits {
+    allowances: HashMap<u32, Allowance>,
+}
+
+impl ChatLimits {
+    fn allow(&mut self, handle: u32, now: f64, config: &ServerConfig) -> bool {
+        let burst = config.chat_burst as f64;
+        let allowance = self.allowances.entry(handle).or_insert(Allowance {
+            tokens: burst,
+            last: now,
+        });
+        allowance.tokens =
+            (allowance.tokens + (now - allowance.last) * config.chat_rate).min(burst);
+        allowance.last = now;
+
+        if allowance.tokens < 1.0 {
+            return false;
+        }
+        allowance.tokens -= 1.0;
+        true
+    }
+}
+
+// drops control characters and caps the length; `None` if nothing is left
+fn sanitize(text: &str, max_len: usize) -> Option<String> {
+    let text: String = text
+        .chars()
+        .filter(|c| !c.is_control())
+        .take(max_len)
+        .collect();
+    let text = text.trim();
+    if text.is_empty() {
+        None
+    } else {
+        Some(text.to_string())
+    }
+}
+
+pub fn system_line(text: impl Into<String>) -> ClientMessage {
+    ClientMessage::ChatLine {
+        from: None,
+        name: String::new(),
+        text: text.into(),
+        team: false,
+    }
+}
+
+// relays chat to everyone in the sender's world, team chat to their team in it, and
+// announcements to every world
+pub fn chat_system(
+    time: Res<Time>,
+    mut inbox: ResMut<Inbox>,
+    mut limits: ResMut<ChatLimits>,
+    config: Res<ServerConfig>,
+    instances: Res<Instances>,
+    teams: Res<Teams>,
+    broadcasts: Res<PerInstance<NetworkBroadcast>>,
+    mut queue: ResMut<SendQueue>,
+    mut recorder: ResMut<Recorder>,
+    senders: Query<(&NetworkHandle, &NetworkId, &PlayerName)>,
+) {
+    let now = time.seconds_since_startup();
+    let inbox = &mut *inbox;
+
+    for text in inbox.announcements.drain(..) {
+        let line = system_line(text);
+        for (instance, _) in broadcasts.iter() {
+            for handle in instances.handles(instance) {
+                queue.push(handle, line.clone());
+            }
+            recorder.record(instance, line.clone());
+        }
+    }
+
+    for (handle, text, team) in inbox.chats.drain(..) {
+        // not playing yet; nobody to talk to
+        let instance = match instances.get(handle) {
+            Some(instance) => instance,
+            None => continue,
+        };
+        let text = match sanitize(&text, config.chat_max_len) {
+            Some(text) => text,
+            None => continue,
+        };
+        if !limits.allow(handle, now, &config) {
+            debug!("Chat from [{}] is over the rate limit", handle);
+            queue.push(handle, system_line("You are sending messages too fast"));
+            continue;
+        }
+
+        let recipients = if team {
+            teams.teammates(handle, &instances)
+        } else {
+            instances.handles(instance)
+        };
+        if recipients.is_empty() {
+            queue.push(handle, system_line("There are no teams in this game mode"));
+            continue;
+        }
+
+        let sender = senders.iter().find(|(h, ..)| h.id == handle);
+        let line = ClientMessage::ChatLine {
+            from: sender.map(|(_, id, _)| id.0),
+            name: sender.map_or_else(String::new, |(.., name)| name.0.clone()),
+            text,
+            team,
+        };
+        info!("Chat in {:?} from [{}]: {:?}", instance, handle, line);
+
+        for handle in recipients {
+            queue.push(handle, line.clone());
+        }
+        // replays show what every player saw, so team lines stay out of them
+        if !team {
+            recorder.record(instance, line);
+        }
+    }
+
+    limits
+        .allowances
+        .retain(|handle, _| instances.get(*handle).is_some());
+}
@@ -0,0 +1,257 @@
+use crate::{
+    admin::AdminReply, config::ServerConfig, leave::Leaving, Inbox, PlayerName, Simulation,
+};
+use agarlib::*;
+use bevy::prelude::*;
+use bevy_networking_turbulence::NetworkResource;
+use serde::Serialize;
+use std::collections::{HashMap, HashSet, VecDeque};
+
+// reports kept for the admin api
+const MAX_REPORTS: usize = 100;
+// teleports per second tolerated; resets and resizes move a cell once
+const MAX_TELEPORTS: u32 = 5;
+// seconds a flag counts towards `cheat_kick_after`
+const FLAG_WINDOW: f64 = 60.0;
+
+#[derive(Serialize, Debug, Clone, Copy, PartialEq, Eq, Hash)]
+#[serde(rename_all = "snake_case")]
+enum Cheat {
+    InputRate,
+    CursorOutside,
+    Teleport,
+    Growth,
+}
+
+#[derive(Serialize, Debug, Clone)]
+struct CheatReport {
+    // seconds since startup
+    at: f64,
+    handle: u32,
+    player: String,
+    cheat: Cheat,
+    detail: String,
+    // flags against the connection within `FLAG_WINDOW`, this one included
+    flags: usize,
+}
+
+// counts over one-second windows, like the flood guard
+#[derive(Default)]
+struct Suspect {
+    window: f64,
+    inputs: u32,
+    teleports: u32,
+    size: Option<f32>,
+    // one flag per heuristic and window
+    flagged: HashSet<Cheat>,
+    // when the connection was flagged, for as long as it counts
+    flags: VecDeque<f64>,
+}
+
+pub struct CheatGuard {
+    max_inputs: u32,
+    max_growth: f32,
+    kick_after: u32,
+    suspects: HashMap<u32, Suspect>,
+    // where each cell was, in simulated seconds
+    cells: HashMap<EntityId, (f64, Vec3)>,
+    reports: VecDeque<CheatReport>,
+}
+
+impl CheatGuard {
+    pub fn new(config: &ServerConfig) -> Self {
+        Self {
+            max_inputs: config.cheat_input_rate,
+            max_growth: config.cheat_growth,
+            kick_after: config.cheat_kick_after,
+            suspects: HashMap::new(),
+            cells: HashMap::new(),
+            reports: VecDeque::new(),
+        }
+    }
+
+    fn suspect(&mut self, handle: u32, now: f64) -> &mut Suspect {
+        let suspect = self.suspects.entry(handle).or_default();
+        if now - suspect.window >= 1.0 {
+            suspect.window = now;
+            suspect.inputs = 0;
+            suspect.teleports = 0;
+            suspect.size = None;
+            suspect.flagged.clear();
+        }
+        suspect
+    }
+
+    fn flag(&mut self, handle: u32, player: &str, now: f64, cheat: Cheat, detail: String) {
+        let suspect = match self.suspects.get_mut(&handle) {
+            Some(suspect) => suspect,
+            None => return,
+        };
+        if !suspect.flagged.insert(cheat) {
+            return;
+        }
+        suspect.flags.push_back(now);
+
+        warn!("[{}] flagged for {:?}: {}", handle, cheat, detail);
+        if self.reports.len() >= MAX_REPORTS {
+            self.reports.pop_front();
+        }
+        self.reports.push_back(CheatReport {
+            at: now,
+            handle,
+            player: player.to_string(),
+            cheat,
+            detail,
+            flags: suspect.flags.len(),
+        });
+    }
+
+    fn input(&mut self, handle: u32, player: &str, now: f64, cursor: Vec2) {
+        let max_inputs = self.max_inputs;
+        let suspect = self.suspect(handle, now);
+        suspect.inputs += 1;
+        let inputs = suspect.inputs;
+
+        if inputs > max_inputs {
+            let detail = format!("more than {} inputs a second", max_inputs);
+            self.flag(handle, player, now, Cheat::InputRate, detail);
+        }
+        let inside =
+            (0.0..=WINDOW_WIDTH).contains(&cursor.x) && (0.0..=WINDOW_HEIGHT).contains(&cursor.y);
+        if !inside {
+            let detail = format!("cursor at {}, {}", cursor.x, cursor.y);
+            self.flag(handle, player, now, Cheat::CursorOutside, detail);
+        }
+    }
+
+    // the cursor says nothing here: keys, touch and sticks jump it across the window. what
+    // counts is a cell covering more ground between two ticks than its speed allows, pushed
+    // out of an obstacle by its radius at most
+    fn jumped(
+        &mut self,
+        id: EntityId,
+        time: f64,
+        translation: Vec3,
+        speed: f32,
+        radius: f32,
+    ) -> bool {
+        match self.cells.insert(id, (time, translation)) {
+            Some((since, from)) => {
+                let reach = speed * (time - since) as f32 + radius;
+                from.distance(translation) > reach * 1.1
+            }
+            None => false,
+        }
+    }
+
+    fn teleport(&mut self, handle: u32, player: &str, now: f64) {
+        let suspect = self.suspect(handle, now);
+        suspect.teleports += 1;
+        let teleports = suspect.teleports;
+        if teleports > MAX_TELEPORTS {
+            let detail = format!("{} moves faster than the cell can in a second", teleports);
+            self.flag(handle, player, now, Cheat::Teleport, detail);
+        }
+    }
+
+    fn size(&mut self, handle: u32, player: &str, now: f64, size: f32) {
+        let max_growth = self.max_growth;
+        let suspect = self.suspect(handle, now);
+        let start = *suspect.size.get_or_insert(size);
+
+        // small cells double in size from a single meal
+        if size > start * max_growth && size - start > AGAR_INIT_SIZE * max_growth {
+            let detail = format!("grew from {:.1} to {:.1} within a second", start, size);
+            self.flag(handle, player, now, Cheat::Growth, detail);
+        }
+    }
+
+    // flags older than `FLAG_WINDOW` are forgotten, so a player isn't kicked for an hour of
+    // borderline play
+    fn offenders(&mut self, now: f64) -> Vec<u32> {
+        for suspect in self.suspects.values_mut() {
+            while suspect
+                .flags
+                .front()
+                .map_or(false, |at| now - at > FLAG_WINDOW)
+            {
+                suspect.flags.pop_front();
+            }
+        }
+        if self.kick_after == 0 {
+            return vec![];
+        }
+        let kick_after = self.kick_after as usize;
+        self.suspects
+            .iter()
+            .filter(|(_, suspect)| suspect.flags.len() >= kick_after)
+            .map(|(handle, _)| *handle)
+            .collect()
+    }
+}
+
+// runs the heuristics over this tick's inputs and sizes, answers report requests and,
+// with `cheat_kick_after` set, disconnects connections flagged that often
+pub fn cheat_system(
+    time: Res<Time>,
+    simulation: Res<Simulation>,
+    mut guard: ResMut<CheatGuard>,
+    mut inbox: ResMut<Inbox>,
+    net: Res<NetworkResource>,
+    mut leaving: ResMut<Leaving>,
+    agars: Query<(&NetworkId, &NetworkHandle, &Agar, &Transform, &PlayerName)>,
+) {
+    let now = time.seconds_since_startup();
+    let guard = &mut *guard;
+    let names: HashMap<_, _> = agars
+        .iter()
+        .map(|(_, handle, _, _, name)| (handle.id, name.0.as_str()))
+        .collect();
+
+    for (handle, cursor) in inbox.inputs.drain(..) {
+        let player = names.get(&handle).copied().unwrap_or_default();
+        guard.input(handle, player, now, cursor);
+    }
+    let boost = simulation
+        .map
+        .boosts
+        .iter()
+        .map(|boost| boost.speed)
+        .fold(1.0, f32::max);
+    for (id, handle, agar, transform, name) in agars.iter() {
+        guard.size(handle.id, &name.0, now, agar.size);
+        let speed = agar.max_velocity * boost;
+        if guard.jumped(
+            id.0,
+            simulation.time,
+            transform.translation,
+            speed,
+            agar.size,
+        ) {
+            guard.teleport(handle.id, &name.0, now);
+        }
+    }
+
+    let handles: Vec<_> = net.connections.keys().copied().collect();
+    guard.suspects.retain(|handle, _| handles.contains(handle));
+    let cells: HashSet<_> = agars.iter().map(|(id, ..)| id.0).collect();
+    guard.cells.retain(|id, _| cells.contains(id));
+
+    for reply in inbox.cheat_reports.drain(..) {
+        let reports: Vec<_> = guard.reports.iter().collect();
+        let response = match serde_json::to_string(&reports) {
+            Ok(body) => AdminReply { status: 200, body },
+            Err(err) => AdminReply {
+                status: 500,
+                body: err.to_string(),
+            },
+        };
+        let _ = reply.send(response);
+    }
+
+    for handle in guard.offenders(now) {
+        warn!("Disconnecting [{}] for cheating", handle);
+        guard.suspects.remove(&handle);
+        leaving.drop_player(handle);
+    }
+}
@@ -0,0 +1,706 @@
+use agarlib::*;
+use anyhow::{anyhow, Context, Result};
+use clap::{App, Arg};
+use serde::{Deserialize, Serialize};
+use std::{
+    collections::BTreeMap,
+    fmt::Display,
+    net::{IpAddr, SocketAddr},
+    path::Path,
+    str::FromStr,
+};
+
+const CONFIG_PATH: &str = "config.toml";
+
+// feeds per world; far more than any world needs, low enough to keep a tick short
+pub const MAX_FEED_TARGET: usize = 100_000;
+
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum GameMode {
+    FreeForAll,
+    // players can't eat each other
+    Sandbox,
+    // players are split into teams that can't eat each other and have a chat of their own
+    Teams,
+}
+
+impl FromStr for GameMode {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        match s {
+            "free_for_all" => Ok(GameMode::FreeForAll),
+            "sandbox" => Ok(GameMode::Sandbox),
+            "teams" => Ok(GameMode::Teams),
+            _ => Err(anyhow!("unknown game mode {}", s)),
+        }
+    }
+}
+
+#[derive(Deserialize, Debug, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum LogRotation {
+    Hourly,
+    Daily,
+    Never,
+}
+
+impl FromStr for LogRotation {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        match s {
+            "hourly" => Ok(LogRotation::Hourly),
+            "daily" => Ok(LogRotation::Daily),
+            "never" => Ok(LogRotation::Never),
+            _ => Err(anyhow!("unknown log rotation {}", s)),
+        }
+    }
+}
+
+#[derive(Deserialize, Debug, Clone)]
+#[serde(default)]
+pub struct ServerConfig {
+    // empty falls back to the build-time `SERVER_ADDR`
+    pub bind: Vec<String>,
+    pub port: u16,
+    // the address players reach this server on, e.g. behind nat or in a container;
+    // published in /status, empty if the bind address is reachable as is
+    pub public_addr: String,
+    pub tick_rate: f64,
+    // simulation steps a frame may run to make up for a slow one; the rest is skipped
+    pub max_catch_up: u32,
+    pub world_width: f32,
+    pub world_height: f32,
+    // how far players may zoom in and out with the wheel, as factors of the usual zoom
+    pub min_zoom: f32,
+    pub max_zoom: f32,
+    // toml file with the world layout; its size wins over `world_width` and `world_height`
+    pub map: String,
+    pub feed_target: usize,
+    pub max_players: usize,
+    // independent worlds hosted by this process; players are spread across them
+    pub instances: usize,
+    pub mode: GameMode,
+    pub encryption: bool,
+    // the key that signs the key exchange, created on the first start; clients pin its
+    // public half. empty makes a new one every start
+    pub identity_file: String,
+    // clients may ask for fewer snapshots and feed densities instead of the feeds
+    pub low_bandwidth: bool,
+    pub redirect_to: Vec<String>,
+    pub redirect_above: usize,
+    // shared by the servers redirecting to each other; signs the passes that let redirected
+    // players past `max_players`. empty issues none and honours none
+    pub redirect_secret: String,
+    // this server's region, e.g. "eu"; published in /status
+    pub region: String,
+    // `region=host:port` servers that players reporting that region are sent to
+    pub region_servers: Vec<String>,
+    // players whose rtt is below this many seconds stay, whatever region they report
+    pub region_rtt: f64,
+    // serves /metrics, /status and /admin/*; empty disables it
+    pub http: String,
+    // bearer token for /admin/*; empty disables the admin api
+    pub admin_token: String,
+    // admin commands typed on stdin; see `help` there
+    pub console: bool,
+    // seconds between the shutdown signal and exit
+    pub shutdown_grace: f64,
+    // hours between scheduled restarts; 0 disables them
+    pub restart_every: f64,
+    // daily restarts at these "HH:MM" utc times
+    pub restart_at: Vec<String>,
+    // world snapshots are written here; empty disables them
+    pub snapshot_path: String,
+    pub snapshot_interval: f64,
+    // seconds without connections before the simulation stops and the loop slows down;
+    // 0 keeps it running
+    pub hibernate_after: f64,
+    // start from the snapshot at `snapshot_path` instead of an empty world
+    pub restore: bool,
+    // replay file to record every frame to; empty disables recording
+    pub record: String,
+    // json lines file every finished game is appended to; empty disables the export
+    pub match_stats: String,
+    // seeds the simulation rng; random when unset
+    pub seed: Option<u64>,
+    // messages per second a connection may send before it is throttled
+    pub flood_messages: u32,
+    // malformed messages per second tolerated before it is throttled
+    pub flood_malformed: u32,
+    // throttled seconds before the connection is dropped
+    pub flood_strikes: u32,
+    // also ban the address of a dropped connection
+    pub flood_ban: bool,
+    // seconds a client's reliable channel may stay full before it is dropped; 0 only
+    // drops it once its backlog hits the hard limit
+    pub saturation_timeout: f64,
+    // inputs per second a human can't plausibly send
+    pub cheat_input_rate: u32,
+    // factor a cell may grow by within a second
+    pub cheat_growth: f32,
+    // cheat flags within a minute before the connection is dropped; 0 only reports them
+    pub cheat_kick_after: u32,
+    // longest player name, in characters
+    pub name_max_len: usize,
+    // names containing any of these, ignoring case and punctuation, are rejected
+    pub banned_words: Vec<String>,
+    // only whitelisted players (or holders of `password`) may log in
+    pub whitelist_only: bool,
+    // player name to the key that player logs in with; the name alone gets nobody in
+    pub whitelist: BTreeMap<String, String>,
+    // entries added through the admin api, kept on top of `whitelist`; empty keeps them
+    // until the server stops
+    pub whitelist_file: String,
+    // when set, logins without it are rejected unless whitelisted; empty disables it
+    pub password: String,
+    // longest chat line, in characters; longer ones are cut
+    pub chat_max_len: usize,
+    // chat lines a player may send per second, after a burst of `chat_burst`
+    pub chat_rate: f64,
+    pub chat_burst: u32,
+    // `RUST_LOG`-style filter, e.g. "info,agarsrv=debug"; `RUST_LOG` itself wins over it
+    pub log_level: String,
+    // log to this file instead of stdout; empty keeps stdout
+    pub log_file: String,
+    pub log_rotation: LogRotation,
+    // one json object per line, for log pipelines
+    pub log_json: bool,
+    // panic reports (last snapshot and received messages) go here; empty disables them
+    pub crash_dir: String,
+    // run the server as a child process and restart it from the last snapshot when it dies
+    pub supervise: bool,
+    // sqlite file for scores, bans and profiles; needs the `storage` feature, empty disables it
+    pub database: String,
+    // the file this was loaded from, watched for changes
+    #[serde(skip)]
+    pub path: String,
+    // read from `whitelist_file`
+    #[serde(skip)]
+    pub granted: BTreeMap<String, String>,
+}
+
+impl Default for ServerConfig {
+    fn default() -> Self {
+        Self {
+            bind: vec![],
+            port: SERVER_PORT,
+            public_addr: String::new(),
+            tick_rate: SERVER_TICK_RATE,
+            max_catch_up: 4,
+            world_width: WORLD_WIDTH,
+            world_height: WORLD_HEIGHT,
+            min_zoom: MIN_WHEEL_ZOOM,
+            max_zoom: MAX_WHEEL_ZOOM,
+            map: String::new(),
+            feed_target: 100,
+            max_players: 100,
+            instances: 1,
+            mode: GameMode::FreeForAll,
+            encryption: false,
+            identity_file: "identity.key".to_string(),
+            low_bandwidth: true,
+            redirect_to: vec![],
+            redirect_above: 50,
+            redirect_secret: String::new(),
+            region: String::new(),
+            region_servers: vec![],
+            region_rtt: 0.1,
+            http: "127.0.0.1:9100".to_string(),
+            admin_token: String::new(),
+            console: true,
+            shutdown_grace: 5.0,
+            restart_every: 0.0,
+            restart_at: vec![],
+            snapshot_path: "world.snapshot".to_string(),
+            snapshot_interval: 30.0,
+            hibernate_after: 10.0,
+            restore: false,
+            record: String::new(),
+            match_stats: String::new(),
+            seed: None,
+            flood_messages: 200,
+            flood_malformed: 5,
+            flood_strikes: 3,
+            flood_ban: true,
+            saturation_timeout: 10.0,
+            cheat_input_rate: 150,
+            cheat_growth: 3.0,
+            cheat_kick_after: 0,
+            name_max_len: 16,
+            banned_words: vec![],
+            whitelist_only: false,
+            whitelist: BTreeMap::new(),
+            whitelist_file: "whitelist.toml".to_string(),
+            password: String::new(),
+            chat_max_len: 200,
+            chat_rate: 0.5,
+            chat_burst: 5,
+            log_level: "info".to_string(),
+            log_file: String::new(),
+            log_rotation: LogRotation::Daily,
+            log_json: false,
+            crash_dir: "crashes".to_string(),
+            supervise: false,
+            database: String::new(),
+            path: CONFIG_PATH.to_string(),
+        }
+    }
+}
+
+impl ServerConfig {
+    // command line flags win over `AGAR_*` variables, which win over the file
+    pub fn from_args() -> Result<Self> {
+        let matches = App::new("agarsrv")
+            .about("Agar game server")
+            .arg(
+                Arg::with_name("config")
+                    .long("config")
+                    .value_name("FILE")
+                    .help("Config file [default: config.toml]"),
+            )
+            .arg(
+                Arg::with_name("bind")
+                    .long("bind")
+                    .value_name("ADDR")
+                    .multiple(true)
+                    .number_of_values(1)
+                    .use_delimiter(true)
+                    .help("Address to listen on; repeat or comma separate for more"),
+            )
+            .arg(
+                Arg::with_name("port")
+                    .long("port")
+                    .value_name("PORT")
+                    .help("Port for bind addresses without one"),
+            )
+            .arg(
+                Arg::with_name("public-addr")
+                    .long("public-addr")
+                    .value_name("ADDR")
+                    .help("Address players reach the server on, if not the bind address"),
+            )
+            .arg(
+                Arg::with_name("mode")
+                    .long("mode")
+                    .value_name("MODE")
+                    .possible_values(&["free_for_all", "sandbox", "teams"])
+                    .help("Game mode"),
+            )
+            .arg(
+                Arg::with_name("map")
+                    .long("map")
+                    .value_name("FILE")
+                    .help("Map file with the world layout"),
+            )
+            .arg(
+                Arg::with_name("restore")
+                    .long("restore")
+                    .help("Resume the world saved in the snapshot file"),
+            )
+            .arg(
+                Arg::with_name("supervise")
+                    .long("supervise")
+                    .help("Restart the server from the last snapshot when it crashes"),
+            )
+            .arg(
+                Arg::with_name("record")
+                    .long("record")
+                    .value_name("FILE")
+                    .help("Record a replay of the match to FILE"),
+            )
+            .arg(
+                Arg::with_name("seed")
+                    .long("seed")
+                    .value_name("SEED")
+                    .help("Seed for the simulation rng"),
+            )
+            .get_matches();
+
+        let mut config = Self::load(matches.value_of("config"))?;
+
+        if let Some(bind) = matches.values_of("bind") {
+            config.bind = bind.map(String::from).collect();
+        }
+        if let Some(port) = matches.value_of("port") {
+            config.port = port.parse().context("invalid --port")?;
+        }
+        if let Some(addr) = matches.value_of("public-addr") {
+            config.public_addr = addr.to_string();
+        }
+        if let Some(mode) = matches.value_of("mode") {
+            config.mode = mode.parse()?;
+        }
+        if let Some(map) = matches.value_of("map") {
+            config.map = map.to_string();
+        }
+        if matches.is_present("restore") {
+            config.restore = true;
+        }
+        if matches.is_present("supervise") {
+            config.supervise = true;
+        }
+        if let Some(record) = matches.value_of("record") {
+            config.record = record.to_string();
+        }
+        if let Some(seed) = matches.value_of("seed") {
+            config.seed = Some(seed.parse().context("invalid --seed")?);
+        }
+
+        Ok(config)
+    }
+
+    // `path` or `AGAR_CONFIG` points at the file; `AGAR_*` variables override its entries
+    pub fn load(path: Option<&str>) -> Result<Self> {
+        let explicit = path
+            .map(String::from)
+            .or_else(|| std::env::var("AGAR_CONFIG").ok());
+        let path = explicit.clone().unwrap_or_else(|| CONFIG_PATH.to_string());
+
+        if explicit.is_some() && !Path::new(&path).exists() {
+            return Err(anyhow!("{} not found", path));
+        }
+
+        let mut config = if Path::new(&path).exists() {
+            let text =
+                std::fs::read_to_string(&path).with_context(|| format!("reading {}", path))?;
+            toml::from_str(&text).with_context(|| format!("parsing {}", path))?
+        } else {
+            Self::default()
+        };
+        config.path = path;
+
+        override_list("AGAR_BIND", &mut config.bind);
+        override_value("AGAR_PORT", &mut config.port)?;
+        override_value("AGAR_PUBLIC_ADDR", &mut config.public_addr)?;
+        override_value("AGAR_TICK_RATE", &mut config.tick_rate)?;
+        override_value("AGAR_MAX_CATCH_UP", &mut config.max_catch_up)?;
+        override_value("AGAR_WORLD_WIDTH", &mut config.world_width)?;
+        override_value("AGAR_WORLD_HEIGHT", &mut config.world_height)?;
+        override_value("AGAR_MIN_ZOOM", &mut config.min_zoom)?;
+        override_value("AGAR_MAX_ZOOM", &mut config.max_zoom)?;
+        override_value("AGAR_MAP", &mut config.map)?;
+        override_value("AGAR_FEED_TARGET", &mut config.feed_target)?;
+        override_value("AGAR_MAX_PLAYERS", &mut config.max_players)?;
+        override_value("AGAR_INSTANCES", &mut config.instances)?;
+        override_value("AGAR_MODE", &mut config.mode)?;
+        override_value("AGAR_ENCRYPTION", &mut config.encryption)?;
+        override_value("AGAR_IDENTITY_FILE", &mut config.identity_file)?;
+        override_value("AGAR_LOW_BANDWIDTH", &mut config.low_bandwidth)?;
+        override_list("AGAR_REDIRECT_TO", &mut config.redirect_to);
+        override_value("AGAR_REDIRECT_ABOVE", &mut config.redirect_above)?;
+        override_value("AGAR_REDIRECT_SECRET", &mut config.redirect_secret)?;
+        override_value("AGAR_REGION", &mut config.region)?;
+        override_list("AGAR_REGION_SERVERS", &mut config.region_servers);
+        override_value("AGAR_REGION_RTT", &mut config.region_rtt)?;
+        override_value("AGAR_HTTP", &mut config.http)?;
+        override_value("AGAR_ADMIN_TOKEN", &mut config.admin_token)?;
+        override_value("AGAR_CONSOLE", &mut config.console)?;
+        override_value("AGAR_SHUTDOWN_GRACE", &mut config.shutdown_grace)?;
+        override_value("AGAR_RESTART_EVERY", &mut config.restart_every)?;
+        override_list("AGAR_RESTART_AT", &mut config.restart_at);
+        override_value("AGAR_SNAPSHOT_PATH", &mut config.snapshot_path)?;
+        override_value("AGAR_SNAPSHOT_INTERVAL", &mut config.snapshot_interval)?;
+        override_value("AGAR_HIBERNATE_AFTER", &mut config.hibernate_after)?;
+        override_value("AGAR_RESTORE", &mut config.restore)?;
+        override_value("AGAR_RECORD", &mut config.record)?;
+        override_value("AGAR_MATCH_STATS", &mut config.match_stats)?;
+        if let Ok(seed) = std::env::var("AGAR_SEED") {
+            config.seed = Some(seed.parse().context("invalid AGAR_SEED")?);
+        }
+        override_value("AGAR_FLOOD_MESSAGES", &mut config.flood_messages)?;
+        override_value("AGAR_FLOOD_MALFORMED", &mut config.flood_malformed)?;
+        override_value("AGAR_FLOOD_STRIKES", &mut config.flood_strikes)?;
+        override_value("AGAR_FLOOD_BAN", &mut config.flood_ban)?;
+        override_value("AGAR_SATURATION_TIMEOUT", &mut config.saturation_timeout)?;
+        override_value("AGAR_CHEAT_INPUT_RATE", &mut config.cheat_input_rate)?;
+        override_value("AGAR_CHEAT_GROWTH", &mut config.cheat_growth)?;
+        override_value("AGAR_CHEAT_KICK_AFTER", &mut config.cheat_kick_after)?;
+        override_value("AGAR_NAME_MAX_LEN", &mut config.name_max_len)?;
+        override_list("AGAR_BANNED_WORDS", &mut config.banned_words);
+        override_value("AGAR_WHITELIST_ONLY", &mut config.whitelist_only)?;
+        if let Ok(var) = std::env::var("AGAR_WHITELIST") {
+            config.whitelist = parse_whitelist(&var)?;
+        }
+        override_value("AGAR_WHITELIST_FILE", &mut config.whitelist_file)?;
+        override_value("AGAR_PASSWORD", &mut config.password)?;
+        override_value("AGAR_CHAT_MAX_LEN", &mut config.chat_max_len)?;
+        override_value("AGAR_CHAT_RATE", &mut config.chat_rate)?;
+        override_value("AGAR_CHAT_BURST", &mut config.chat_burst)?;
+        override_value("AGAR_LOG_LEVEL", &mut config.log_level)?;
+        override_value("AGAR_LOG_FILE", &mut config.log_file)?;
+        override_value("AGAR_LOG_ROTATION", &mut config.log_rotation)?;
+        override_value("AGAR_LOG_JSON", &mut config.log_json)?;
+        override_value("AGAR_CRASH_DIR", &mut config.crash_dir)?;
+        override_value("AGAR_SUPERVISE", &mut config.supervise)?;
+        override_value("AGAR_DATABASE", &mut config.database)?;
+
+        if config.tick_rate <= 0.0 {
+            return Err(anyhow!("tick_rate must be positive"));
+        }
+        for time in &config.restart_at {
+            crate::restart::parse_time(time)?;
+        }
+        crate::region::parse_servers(&config.region_servers)?;
+        if config.max_catch_up == 0 {
+            return Err(anyhow!("max_catch_up must be at least 1"));
+        }
+        if config.instances == 0 {
+            return Err(anyhow!("instances must be at least 1"));
+        }
+        if config.cheat_growth <= 1.0 {
+            return Err(anyhow!("cheat_growth must be above 1"));
+        }
+        if config.min_zoom <= 0.0 || config.min_zoom > 1.0 || config.max_zoom < 1.0 {
+            return Err(anyhow!(
+                "min_zoom must be in (0, 1] and max_zoom at least 1"
+            ));
+        }
+        if config.feed_target > MAX_FEED_TARGET {
+            return Err(anyhow!("feed_target must be at most {}", MAX_FEED_TARGET));
+        }
+        if config.flood_strikes == 0 {
+            return Err(anyhow!("flood_strikes must be at least 1"));
+        }
+        config.granted = config.read_granted()?;
+
+        Ok(config)
+    }
+
+    fn read_granted(&self) -> Result<BTreeMap<String, String>> {
+        let path = &self.whitelist_file;
+        if path.is_empty() || !Path::new(path).exists() {
+            return Ok(BTreeMap::new());
+        }
+        let text = std::fs::read_to_string(path).with_context(|| format!("reading {}", path))?;
+        toml::from_str(&text).with_context(|| format!("parsing {}", path))
+    }
+
+    // after an admin edit; the file is rewritten whole
+    pub fn save_granted(&self) -> Result<()> {
+        let path = &self.whitelist_file;
+        if path.is_empty() {
+            return Ok(());
+        }
+        let text = toml::to_string(&self.granted)?;
+        std::fs::write(path, text).with_context(|| format!("writing {}", path))
+    }
+
+    pub fn load_identity(&self) -> Result<Identity> {
+        let path = &self.identity_file;
+        if path.is_empty() {
+            return Ok(Identity::generate());
+        }
+        if Path::new(path).exists() {
+            let bytes = std::fs::read(path).with_context(|| format!("reading {}", path))?;
+            return Identity::from_bytes(&bytes).with_context(|| format!("parsing {}", path));
+        }
+
+        let identity = Identity::generate();
+        write_private(path, &identity.to_bytes()).with_context(|| format!("writing {}", path))?;
+        Ok(identity)
+    }
+
+    // the private servers' credentials only ever travel encrypted
+    pub fn private(&self) -> bool {
+        self.whitelist_only || !self.password.is_empty()
+    }
+
+    // the built-in default addresses when none are configured, on `port` all the same
+    pub fn addresses(&self) -> Vec<String> {
+        let bind = if self.bind.is_empty() {
+            addrs()
+        } else {
+            self.bind.clone()
+        };
+        bind.iter().map(|addr| with_port(addr, self.port)).collect()
+    }
+
+    // `0.0.0.0` or `[::]`, as usual in containers
+    pub fn binds_wildcard(&self) -> bool {
+        self.addresses().iter().any(|addr| {
+            addr.parse::<SocketAddr>()
+                .map_or(false, |addr| addr.ip().is_unspecified())
+        })
+    }
+
+    // takes over the entries that can change while running and differ between the `old` and
+    // `new` file, so what the admin api changed meanwhile stays unless the file changes it too;
+    // returns the names of those that did
+    pub fn apply(&mut self, old: &ServerConfig, new: &ServerConfig) -> Vec<&'static str> {
+        let mut changed = vec![];
+        if old.feed_target != new.feed_target {
+            self.feed_target = new.feed_target;
+            changed.push("feed_target");
+        }
+        if old.name_max_len != new.name_max_len {
+            self.name_max_len = new.name_max_len;
+            changed.push("name_max_len");
+        }
+        if old.banned_words != new.banned_words {
+            self.banned_words = new.banned_words.clone();
+            changed.push("banned_words");
+        }
+        if old.whitelist_only != new.whitelist_only {
+            self.whitelist_only = new.whitelist_only;
+            changed.push("whitelist_only");
+        }
+        if old.whitelist != new.whitelist {
+            self.whitelist = new.whitelist.clone();
+            changed.push("whitelist");
+        }
+        if old.granted != new.granted {
+            self.granted = new.granted.clone();
+            changed.push("granted");
+        }
+        if old.password != new.password {
+            self.password = new.password.clone();
+            changed.push("password");
+        }
+        if old.chat_max_len != new.chat_max_len {
+            self.chat_max_len = new.chat_max_len;
+            changed.push("chat_max_len");
+        }
+        if old.chat_rate != new.chat_rate {
+            self.chat_rate = new.chat_rate;
+            changed.push("chat_rate");
+        }
+        if old.chat_burst != new.chat_burst {
+            self.chat_burst = new.chat_burst;
+            changed.push("chat_burst");
+        }
+        if old.max_players != new.max_players {
+            self.max_players = new.max_players;
+            changed.push("max_players");
+        }
+        if old.saturation_timeout != new.saturation_timeout {
+            self.saturation_timeout = new.saturation_timeout;
+            changed.push("saturation_timeout");
+        }
+        if old.hibernate_after != new.hibernate_after {
+            self.hibernate_after = new.hibernate_after;
+            changed.push("hibernate_after");
+        }
+        if old.min_zoom != new.min_zoom {
+            self.min_zoom = new.min_zoom;
+            changed.push("min_zoom");
+        }
+        if old.max_zoom != new.max_zoom {
+            self.max_zoom = new.max_zoom;
+            changed.push("max_zoom");
+        }
+        // with a map, its size stays until a restart
+        if self.map.is_empty() && old.world_width != new.world_width {
+            self.world_width = new.world_width;
+            changed.push("world_width");
+        }
+        if self.map.is_empty() && old.world_height != new.world_height {
+            self.world_height = new.world_height;
+            changed.push("world_height");
+        }
+        changed
+    }
+
+    // for logging; hides the secrets
+    pub fn redacted(&self) -> Self {
+        let hide = |secret: &str| {
+            if secret.is_empty() {
+                String::new()
+            } else {
+                "<redacted>".to_string()
+            }
+        };
+        Self {
+            admin_token: hide(&self.admin_token),
+            password: hide(&self.password),
+            whitelist: self
+                .whitelist
+                .iter()
+                .map(|(name, key)| (name.clone(), hide(key)))
+                .collect(),
+            granted: BTreeMap::new(),
+            redirect_secret: hide(&self.redirect_secret),
+            ..self.clone()
+        }
+    }
+
+    pub fn rules(&self) -> GameRules {
+        GameRules {
+            world_width: self.world_width,
+            world_height: self.world_height,
+            min_zoom: self.min_zoom,
+            max_zoom: self.max_zoom,
+            ..GameRules::default()
+        }
+    }
+}
+
+fn with_port(addr: &str, port: u16) -> String {
+    if addr.parse::<SocketAddr>().is_ok() {
+        addr.to_string()
+    } else if let Ok(ip) = addr.parse::<IpAddr>() {
+        SocketAddr::new(ip, port).to_string()
+    } else if addr.ends_with(']') || !addr.contains(':') {
+        format!("{}:{}", addr, port)
+    } else {
+        addr.to_string()
+    }
+}
+
+// readable by the server's user alone, where the platform can say so
+fn write_private(path: &str, bytes: &[u8]) -> std::io::Result<()> {
+    use std::io::Write;
+
+    let mut options = std::fs::OpenOptions::new();
+    options.write(true).create_new(true);
+    #[cfg(unix)]
+    {
+        use std::os::unix::fs::OpenOptionsExt;
+        options.mode(0o600);
+    }
+    options.open(path)?.write_all(bytes)
+}
+
+fn split_list(list: &str) -> Vec<String> {
+    list.split(',')
+        .map(|item| item.trim().to_string())
+        .filter(|item| !item.is_empty())
+        .collect()
+}
+
+// `name=key` entries
+fn parse_whitelist(var: &str) -> Result<BTreeMap<String, String>> {
+    split_list(var)
+        .iter()
+        .map(|entry| match entry.split_once('=') {
+            Some((name, key)) if !name.trim().is_empty() && !key.trim().is_empty() => {
+                Ok((name.trim().to_string(), key.trim().to_string()))
+            }
+            _ => Err(anyhow!(
+                "invalid AGAR_WHITELIST entry {:?}, expected name=key",
+                entry
+            )),
+        })
+        .collect()
+}
+
+fn override_list(name: &str, list: &mut Vec<String>) {
+    if let Ok(var) = std::env::var(name) {
+        *list = split_list(&var);
+    }
+}
+
+fn override_value<T>(name: &str, value: &mut T) -> Result<()>
+where
+    T: FromStr,
+    T::Err: Display,
+{
+    if let Ok(var) = std::env::var(name) {
+        *value = var
+            .parse()
+            .map_err(|err| anyhow!("invalid {}: {}", name, err))?;
+    }
+    Ok(())
+}
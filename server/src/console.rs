@@ -0,0 +1,101 @@
+use crate::{
+    admin::{self, AdminCommand, AdminReply, AdminRequest, Target},
+    http,
+};
+use bevy::prelude::*;
+use std::{
+    io::{stdin, BufRead},
+    sync::mpsc::Sender,
+    thread,
+};
+
+const HELP: &str = "\
+players                 list the players
+kick <id|name>          disconnect a player
+ban <id|name>           disconnect a player and ban their address
+say <message>           announce to every world
+feeds <n>               set the feed target
+reset                   reset the world
+pause | resume          freeze or unfreeze the simulation
+reload                  reload the config file
+resize <w>x<h>          resize the world
+whitelist [add|remove <name>]
+reports                 list cheat reports
+help";
+
+fn target(arg: &str) -> Target {
+    match arg.parse() {
+        Ok(id) => Target::Id(id),
+        Err(_) => Target::Name(arg.to_string()),
+    }
+}
+
+fn parse(line: &str) -> Result<AdminCommand, String> {
+    let (command, arg) = match line.find(char::is_whitespace) {
+        Some(i) => (&line[..i], line[i..].trim()),
+        None => (line, ""),
+    };
+
+    match (command, arg) {
+        ("players", "") => Ok(AdminCommand::ListPlayers),
+        ("kick", arg) if !arg.is_empty() => Ok(AdminCommand::Kick(target(arg))),
+        ("ban", arg) if !arg.is_empty() => Ok(AdminCommand::Ban(target(arg))),
+        ("say", arg) if !arg.is_empty() => Ok(AdminCommand::Announce(arg.to_string())),
+        ("feeds", arg) => arg
+            .parse()
+            .map(AdminCommand::SetFeedTarget)
+            .map_err(|_| "usage: feeds <n>".to_string()),
+        ("reset", "") => Ok(AdminCommand::ResetWorld),
+        ("pause", "") => Ok(AdminCommand::Pause),
+        ("resume", "") => Ok(AdminCommand::Resume),
+        ("reload", "") => Ok(AdminCommand::Reload),
+        ("resize", arg) => http::parse_size(arg)
+            .map(|(width, height)| AdminCommand::ResizeWorld(width, height))
+            .ok_or_else(|| "usage: resize <width>x<height>".to_string()),
+        ("whitelist", "") => Ok(AdminCommand::ListWhitelist),
+        ("reports", "") => Ok(AdminCommand::CheatReports),
+        ("whitelist", arg) => match arg.split_once(char::is_whitespace) {
+            Some(("add", name)) => Ok(AdminCommand::Whitelist(name.trim().to_string())),
+            Some(("remove", name)) => Ok(AdminCommand::Unwhitelist(name.trim().to_string())),
+            _ => Err("usage: whitelist [add|remove <name>]".to_string()),
+        },
+        _ => Err(format!("unknown command {:?}; try help", line)),
+    }
+}
+
+// reads commands from stdin, one per line, into the same queue the admin api uses
+pub fn spawn(requests: Sender<AdminRequest>) {
+    thread::spawn(move || {
+        for line in stdin().lock().lines() {
+            let line = match line {
+                Ok(line) => line,
+                Err(err) => {
+                    warn!("Console closed: {}", err);
+                    return;
+                }
+            };
+            let line = line.trim();
+            if line.is_empty() {
+                continue;
+            }
+            if line == "help" {
+                println!("{}", HELP);
+                continue;
+            }
+
+            match parse(line) {
+                Ok(command) => {
+                    let AdminReply { status, body } = admin::request(&requests, command);
+                    if status == 200 {
+                        println!("{}", body);
+                    } else {
+                        println!("error {}: {}", status, body);
+                    }
+                }
+                Err(err) => println!("{}", err),
+            }
+        }
+        // stdin is closed when the server runs detached; nothing to do about it
+        debug!("Console input ended");
+    });
+}
@@ -0,0 +1,112 @@
+use crate::persistence::WorldSnapshot;
+use agarlib::*;
+use once_cell::sync::Lazy;
+use serde::Serialize;
+use std::{
+    collections::VecDeque,
+    path::Path,
+    sync::Mutex,
+    time::{SystemTime, UNIX_EPOCH},
+};
+
+// received messages kept for the crash report
+const RECENT_MESSAGES: usize = 256;
+
+// filled by the game loop; the panic hook can't reach into the ecs world
+static RECENT: Lazy<Mutex<VecDeque<(u32, ClientMessage)>>> =
+    Lazy::new(|| Mutex::new(VecDeque::with_capacity(RECENT_MESSAGES)));
+static WORLD: Lazy<Mutex<Option<WorldSnapshot>>> = Lazy::new(|| Mutex::new(None));
+
+pub fn remember(handle: u32, message: &ClientMessage) {
+    let message = redacted(message);
+    if let Ok(mut recent) = RECENT.lock() {
+        if recent.len() >= RECENT_MESSAGES {
+            recent.pop_front();
+        }
+        recent.push_back((handle, message));
+    }
+}
+
+// reports and logs get passed around; keep passwords, whitelist keys, owner tokens and
+// redirect passes out of them
+pub fn redacted(message: &ClientMessage) -> ClientMessage {
+    match message {
+        ClientMessage::Credentials(_) => ClientMessage::Credentials(String::new()),
+        ClientMessage::Owner(_) => ClientMessage::Owner(0),
+        ClientMessage::Login(login) => ClientMessage::Login(LoginRequest {
+            token: None,
+            ..login.clone()
+        }),
+        message => message.clone(),
+    }
+}
+
+// the latest periodic snapshot, written out again if the server crashes
+// without the owner tokens, which would let anyone reading the report take over the agars
+pub fn remember_world(snapshot: &WorldSnapshot) {
+    let mut snapshot = snapshot.clone();
+    for agar in &mut snapshot.agars {
+        agar.owner = 0;
+    }
+    if let Ok(mut world) = WORLD.lock() {
+        *world = Some(snapshot);
+    }
+}
+
+#[derive(Serialize)]
+struct CrashReport<'a> {
+    panic: String,
+    location: Option<String>,
+    world: Option<&'a WorldSnapshot>,
+    // oldest first, with the connection handle
+    messages: Vec<&'a (u32, ClientMessage)>,
+}
+
+// on panic, writes a report into `dir` and aborts, so a half-broken world doesn't keep running
+pub fn install_panic_hook(dir: String) {
+    let default_hook = std::panic::take_hook();
+
+    std::panic::set_hook(Box::new(move |info| {
+        default_hook(info);
+        if !dir.is_empty() {
+            let panic = info
+                .payload()
+                .downcast_ref::<&str>()
+                .map(|message| message.to_string())
+                .or_else(|| info.payload().downcast_ref::<String>().cloned())
+                .unwrap_or_else(|| "unknown panic".to_string());
+            let location = info.location().map(|location| location.to_string());
+
+            match write_report(&dir, panic, location) {
+                Ok(path) => eprintln!("Crash report written to {}", path),
+                Err(err) => eprintln!("Unable to write crash report: {}", err),
+            }
+        }
+        std::process::abort();
+    }));
+}
+
+fn write_report(dir: &str, panic: String, location: Option<String>) -> std::io::Result<String> {
+    // the panicking thread may be the one holding a lock; don't wait on it
+    let recent = RECENT.try_lock().ok();
+    let world = WORLD.try_lock().ok();
+
+    let report = CrashReport {
+        panic,
+        location,
+        world: world.as_ref().and_then(|world| world.as_ref()),
+        messages: recent
+            .as_ref()
+            .map_or_else(Vec::new, |recent| recent.iter().collect()),
+    };
+
+    std::fs::create_dir_all(dir)?;
+    let time = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map_or(0, |elapsed| elapsed.as_secs());
+    let path = Path::new(dir).join(format!("crash-{}.json", time));
+    let json = serde_json::to_vec_pretty(&report)?;
+    std::fs::write(&path, json)?;
+
+    Ok(path.display().to_string())
+}
@@ -0,0 +1,124 @@
+use crate::{admin::Bans, config::ServerConfig, leave::Leaving};
+use agarlib::*;
+use bevy::prelude::*;
+use bevy_networking_turbulence::NetworkResource;
+use std::collections::HashMap;
+
+// counts over one-second windows
+#[derive(Default)]
+struct Counters {
+    window: f64,
+    messages: u32,
+    malformed: u32,
+    // windows in which a limit was exceeded
+    strikes: u32,
+    throttled: bool,
+}
+
+pub struct FloodGuard {
+    max_messages: u32,
+    max_malformed: u32,
+    max_strikes: u32,
+    counters: HashMap<u32, Counters>,
+    // handles to drop at the end of the tick
+    offenders: Vec<u32>,
+}
+
+impl FloodGuard {
+    pub fn new(config: &ServerConfig) -> Self {
+        Self {
+            max_messages: config.flood_messages,
+            max_malformed: config.flood_malformed,
+            max_strikes: config.flood_strikes,
+            counters: HashMap::new(),
+            offenders: vec![],
+        }
+    }
+
+    fn counters(&mut self, handle: u32, now: f64) -> &mut Counters {
+        let counters = self.counters.entry(handle).or_default();
+        if now - counters.window >= 1.0 {
+            counters.window = now;
+            counters.messages = 0;
+            counters.malformed = 0;
+            counters.throttled = false;
+        }
+        counters
+    }
+
+    fn strike(&mut self, handle: u32, reason: &str) {
+        let max_strikes = self.max_strikes;
+        let counters = match self.counters.get_mut(&handle) {
+            Some(counters) => counters,
+            None => return,
+        };
+        // one strike per window, whatever the limit
+        if counters.throttled {
+            return;
+        }
+        counters.throttled = true;
+        counters.strikes += 1;
+
+        warn!(
+            "[{}] exceeded the {} limit (strike {}/{})",
+            handle, reason, counters.strikes, max_strikes
+        );
+        if counters.strikes >= max_strikes && !self.offenders.contains(&handle) {
+            self.offenders.push(handle);
+        }
+    }
+
+    // false while the connection is throttled; its messages are dropped unread
+    pub fn admit(&mut self, handle: u32, now: f64) -> bool {
+        let max_messages = self.max_messages;
+        let counters = self.counters(handle, now);
+        counters.messages += 1;
+        if counters.throttled {
+            return false;
+        }
+        if counters.messages > max_messages {
+            self.strike(handle, "message rate");
+            return false;
+        }
+        true
+    }
+
+    pub fn malformed(&mut self, handle: u32, now: f64) {
+        let max_malformed = self.max_malformed;
+        let counters = self.counters(handle, now);
+        counters.malformed += 1;
+        if counters.malformed > max_malformed {
+            self.strike(handle, "malformed message");
+        }
+    }
+}
+
+// disconnects (and optionally bans) connections that kept flooding after being throttled
+pub fn flood_system(
+    mut guard: ResMut<FloodGuard>,
+    net: Res<NetworkResource>,
+    mut bans: ResMut<Bans>,
+    mut leaving: ResMut<Leaving>,
+    config: Res<ServerConfig>,
+) {
+    let guard = &mut *guard;
+    let handles: Vec<_> = net.connections.keys().copied().collect();
+    guard.counters.retain(|handle, _| handles.contains(handle));
+
+    for handle in guard.offenders.drain(..) {
+        let addr = net
+            .connections
+            .get(&handle)
+            .and_then(|connection| connection.remote_address());
+        match addr {
+            Some(addr) if config.flood_ban => {
+                warn!("Banning {} for flooding on [{}]", addr.ip(), handle);
+                bans.addrs.insert(addr.ip());
+            }
+            _ => warn!("Disconnecting [{}] for flooding", handle),
+        }
+
+        guard.counters.remove(&handle);
+        leaving.drop_player(handle);
+    }
+}
@@ -0,0 +1,211 @@
+use crate::{
+    instance::{Instance, PerInstance},
+    spans::SystemTimer,
+};
+use agarlib::*;
+use bevy::prelude::*;
+use std::collections::HashMap;
+
+// uniform grid; candidates it returns still need an exact distance check
+pub struct SpatialGrid {
+    cell_size: f32,
+    cells: HashMap<(i32, i32), Vec<(Entity, Vec3)>>,
+    // the corners of every key inserted since the last clear; queries walk no further
+    min_key: (i32, i32),
+    max_key: (i32, i32),
+}
+
+impl SpatialGrid {
+    pub fn new(cell_size: f32) -> Self {
+        Self {
+            cell_size,
+            cells: HashMap::new(),
+            min_key: (i32::MAX, i32::MAX),
+            max_key: (i32::MIN, i32::MIN),
+        }
+    }
+
+    fn key(&self, x: f32, y: f32) -> (i32, i32) {
+        (
+            (x / self.cell_size).floor() as i32,
+            (y / self.cell_size).floor() as i32,
+        )
+    }
+
+    pub fn clear(&mut self) {
+        self.cells.clear();
+        self.min_key = (i32::MAX, i32::MAX);
+        self.max_key = (i32::MIN, i32::MIN);
+    }
+
+    pub fn insert(&mut self, entity: Entity, translation: Vec3) {
+        let key = self.key(translation.x, translation.y);
+        self.min_key = (self.min_key.0.min(key.0), self.min_key.1.min(key.1));
+        self.max_key = (self.max_key.0.max(key.0), self.max_key.1.max(key.1));
+        self.cells
+            .entry(key)
+            .or_default()
+            .push((entity, translation));
+    }
+
+    pub fn remove(&mut self, entity: Entity, translation: Vec3) {
+        let key = self.key(translation.x, translation.y);
+        if let Some(cell) = self.cells.get_mut(&key) {
+            cell.retain(|(e, _)| *e != entity);
+            if cell.is_empty() {
+                self.cells.remove(&key);
+            }
+        }
+    }
+
+    fn rect(
+        &self,
+        min_x: f32,
+        min_y: f32,
+        max_x: f32,
+        max_y: f32,
+    ) -> impl Iterator<Item = (Entity, Vec3)> + '_ {
+        // a huge region would otherwise walk billions of empty keys
+        let (min_x, min_y) = self.key(min_x, min_y);
+        let (max_x, max_y) = self.key(max_x, max_y);
+        let (min_x, min_y) = (min_x.max(self.min_key.0), min_y.max(self.min_key.1));
+        let (max_x, max_y) = (max_x.min(self.max_key.0), max_y.min(self.max_key.1));
+
+        (min_x..=max_x)
+            .flat_map(move |x| (min_y..=max_y).map(move |y| (x, y)))
+            .filter_map(move |key| self.cells.get(&key))
+            .flatten()
+            .copied()
+    }
+
+    pub fn query(&self, center: Vec3, radius: f32) -> impl Iterator<Item = (Entity, Vec3)> + '_ {
+        self.rect(
+            center.x - radius,
+            center.y - radius,
+            center.x + radius,
+            center.y + radius,
+        )
+        .filter(move |(_, translation)| {
+            translation.truncate().distance(center.truncate()) <= radius
+        })
+    }
+
+    pub fn query_region(&self, region: &Region) -> impl Iterator<Item = (Entity, Vec3)> + '_ {
+        let region = *region;
+
+        self.rect(
+            region.x,
+            region.y,
+            region.x + region.width,
+            region.y + region.height,
+        )
+        .filter(move |(_, translation)| region.contains(*translation))
+    }
+}
+
+// feeds never move and are kept in sync on spawn/despawn; agars are rebuilt every tick
+pub struct SpatialIndex {
+    pub feeds: SpatialGrid,
+    pub agars: SpatialGrid,
+}
+
+impl SpatialIndex {
+    pub fn new(cell_size: f32) -> Self {
+        Self {
+            feeds: SpatialGrid::new(cell_size),
+            agars: SpatialGrid::new(cell_size),
+        }
+    }
+}
+
+pub fn spatial_index_system(
+    mut index: ResMut<PerInstance<SpatialIndex>>,
+    agars: Query<(Entity, &Agar, &Transform, &Instance)>,
+) {
+    let _timer = SystemTimer::start("spatial_index");
+    for (_, index) in index.iter_mut() {
+        index.agars.clear();
+    }
+
+    for (entity, _agar, transform, instance) in agars.iter() {
+        index[*instance].agars.insert(entity, transform.translation);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn grid(points: &[(f32, f32)]) -> SpatialGrid {
+        let mut grid = SpatialGrid::new(100.0);
+        for (i, (x, y)) in points.iter().enumerate() {
+            grid.insert(Entity::new(i as u32), Vec3::new(*x, *y, 0.0));
+        }
+        grid
+    }
+
+    fn ids(found: impl Iterator<Item = (Entity, Vec3)>) -> Vec<u32> {
+        let mut ids: Vec<_> = found.map(|(entity, _)| entity.id()).collect();
+        ids.sort_unstable();
+        ids
+    }
+
+    #[test]
+    fn query_across_cell_edges() {
+        let grid = grid(&[(99.0, 99.0), (101.0, 101.0), (160.0, 100.0), (300.0, 300.0)]);
+        let found = ids(grid.query(Vec3::new(100.0, 100.0, 0.0), 5.0));
+        assert_eq!(found, vec![0, 1]);
+        let found = ids(grid.query(Vec3::new(100.0, 100.0, 0.0), 60.0));
+        assert_eq!(found, vec![0, 1, 2]);
+    }
+
+    #[test]
+    fn query_negative_coordinates() {
+        let grid = grid(&[(-1.0, -1.0), (-99.0, 50.0), (1.0, 1.0), (-250.0, -250.0)]);
+        let found = ids(grid.query(Vec3::zero(), 2.0));
+        assert_eq!(found, vec![0, 2]);
+        let found = ids(grid.query(Vec3::new(-100.0, 50.0, 0.0), 5.0));
+        assert_eq!(found, vec![1]);
+    }
+
+    #[test]
+    fn region_across_cell_edges() {
+        let grid = grid(&[(99.0, 0.0), (100.0, 0.0), (-0.5, 0.0), (250.0, 50.0)]);
+        let region = Region {
+            x: -1.0,
+            y: -10.0,
+            width: 101.0,
+            height: 20.0,
+        };
+        // the far edge is outside
+        assert_eq!(ids(grid.query_region(&region)), vec![0, 2]);
+    }
+
+    #[test]
+    fn huge_region() {
+        let grid = grid(&[(0.0, 0.0), (1e6, -1e6), (-3e4, 2e4)]);
+        let region = Region {
+            x: -1e30,
+            y: -1e30,
+            width: 2e30,
+            height: 2e30,
+        };
+        assert_eq!(ids(grid.query_region(&region)), vec![0, 1, 2]);
+        let region = Region {
+            x: f32::NEG_INFINITY,
+            y: f32::NEG_INFINITY,
+            width: f32::INFINITY,
+            height: f32::INFINITY,
+        };
+        // -inf + inf is nan, so nothing is inside, but it has to come back at all
+        assert!(ids(grid.query_region(&region)).is_empty());
+        assert_eq!(ids(grid.query(Vec3::zero(), f32::INFINITY)), vec![0, 1, 2]);
+    }
+
+    #[test]
+    fn empty_after_clear() {
+        let mut grid = grid(&[(0.0, 0.0)]);
+        grid.clear();
+        assert!(ids(grid.query(Vec3::zero(), 1e30)).is_empty());
+    }
+}
@@ -0,0 +1,157 @@
+use crate::{
+    admin::{self, AdminCommand, AdminReply, AdminRequest, Target},
+    status::Health,
+};
+use agarlib::secret_eq;
+use anyhow::{anyhow, Result};
+use bevy::prelude::*;
+use prometheus::{Encoder, Registry, TextEncoder};
+use std::{
+    io::{Cursor, Read},
+    sync::{mpsc::Sender, Arc, Mutex},
+};
+use tiny_http::{Header, Method, Request, Response};
+
+pub struct Admin {
+    pub token: String,
+    pub requests: Sender<AdminRequest>,
+}
+
+type HttpResponse = Response<Cursor<Vec<u8>>>;
+
+fn respond(status: u16, body: impl Into<Vec<u8>>) -> HttpResponse {
+    Response::from_data(body.into()).with_status_code(status)
+}
+
+// requests are answered on a thread of their own, off the game loop
+pub fn serve(
+    addr: &str,
+    registry: Registry,
+    status: Arc<Mutex<String>>,
+    health: Arc<Health>,
+    admin: Option<Admin>,
+) -> Result<()> {
+    let server = tiny_http::Server::http(addr).map_err(|err| anyhow!("{}", err))?;
+
+    std::thread::spawn(move || {
+        for mut request in server.incoming_requests() {
+            let response = if request.url() == "/metrics" {
+                metrics(&registry)
+            } else if request.url() == "/status" {
+                self::status(&status)
+            } else if request.url() == "/livez" {
+                respond(200, "ok")
+            } else if request.url() == "/readyz" || request.url() == "/healthz" {
+                match health.ready() {
+                    Ok(()) => respond(200, "ok"),
+                    Err(reason) => respond(503, reason),
+                }
+            } else if request.url().starts_with("/admin/") {
+                match &admin {
+                    Some(admin) => handle_admin(admin, &mut request),
+                    None => respond(404, "admin api is disabled"),
+                }
+            } else {
+                respond(404, "not found")
+            };
+
+            if let Err(err) = request.respond(response) {
+                debug!("unable to answer http request: {}", err);
+            }
+        }
+    });
+
+    Ok(())
+}
+
+fn metrics(registry: &Registry) -> HttpResponse {
+    let encoder = TextEncoder::new();
+    let mut buffer = vec![];
+    if let Err(err) = encoder.encode(&registry.gather(), &mut buffer) {
+        error!("unable to encode metrics: {}", err);
+    }
+
+    let header = Header::from_bytes(&b"Content-Type"[..], encoder.format_type()).unwrap();
+    Response::from_data(buffer).with_header(header)
+}
+
+// public, so pages on other origins may poll it too
+fn status(status: &Mutex<String>) -> HttpResponse {
+    let body = match status.lock() {
+        Ok(status) => status.clone(),
+        Err(_) => return respond(500, "status unavailable"),
+    };
+
+    Response::from_data(body)
+        .with_header(Header::from_bytes(&b"Content-Type"[..], &b"application/json"[..]).unwrap())
+        .with_header(Header::from_bytes(&b"Access-Control-Allow-Origin"[..], &b"*"[..]).unwrap())
+}
+
+fn authorized(admin: &Admin, request: &Request) -> bool {
+    let expected = format!("Bearer {}", admin.token);
+
+    request.headers().iter().any(|header| {
+        header.field.equiv("Authorization")
+            && secret_eq(header.value.as_str().as_bytes(), expected.as_bytes())
+    })
+}
+
+fn handle_admin(admin: &Admin, request: &mut Request) -> HttpResponse {
+    if !authorized(admin, request) {
+        return respond(401, "unauthorized");
+    }
+
+    let mut body = String::new();
+    if let Err(err) = request.as_reader().read_to_string(&mut body) {
+        return respond(400, err.to_string());
+    }
+    let body = body.trim();
+
+    let path: Vec<_> = request.url()["/admin/".len()..].split('/').collect();
+    let command = match (request.method(), path.as_slice()) {
+        (Method::Get, ["players"]) => AdminCommand::ListPlayers,
+        (Method::Post, ["players", id, "kick"]) => match id.parse() {
+            Ok(id) => AdminCommand::Kick(Target::Id(id)),
+            Err(_) => return respond(400, "bad player id"),
+        },
+        (Method::Post, ["players", id, "ban"]) => match id.parse() {
+            Ok(id) => AdminCommand::Ban(Target::Id(id)),
+            Err(_) => return respond(400, "bad player id"),
+        },
+        (Method::Post, ["announce"]) if !body.is_empty() => {
+            AdminCommand::Announce(body.to_string())
+        }
+        (Method::Post, ["feed-target"]) => match body.parse() {
+            Ok(target) => AdminCommand::SetFeedTarget(target),
+            Err(_) => return respond(400, "bad feed target"),
+        },
+        (Method::Post, ["reset"]) => AdminCommand::ResetWorld,
+        (Method::Post, ["pause"]) => AdminCommand::Pause,
+        (Method::Post, ["resume"]) => AdminCommand::Resume,
+        (Method::Post, ["reload"]) => AdminCommand::Reload,
+        (Method::Post, ["world-size"]) => match parse_size(body) {
+            Some((width, height)) => AdminCommand::ResizeWorld(width, height),
+            None => return respond(400, "expected <width>x<height>"),
+        },
+        (Method::Get, ["whitelist"]) => AdminCommand::ListWhitelist,
+        (Method::Get, ["reports"]) => AdminCommand::CheatReports,
+        (Method::Post, ["whitelist"]) if !body.is_empty() => {
+            AdminCommand::Whitelist(body.to_string())
+        }
+        (Method::Delete, ["whitelist"]) if !body.is_empty() => {
+            AdminCommand::Unwhitelist(body.to_string())
+        }
+        _ => return respond(404, "unknown admin command"),
+    };
+
+    let AdminReply { status, body } = admin::request(&admin.requests, command);
+    respond(status, body)
+}
+
+// `<width>x<height>`, e.g. `3000x2000`
+pub fn parse_size(text: &str) -> Option<(f32, f32)> {
+    let mut parts = text.splitn(2, 'x');
+    let width = parts.next()?.trim().parse().ok()?;
+    let height = parts.next()?.trim().parse().ok()?;
+    Some((width, height))
+}
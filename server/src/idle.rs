@@ -0,0 +1,47 @@
+use crate::{config::ServerConfig, Simulation};
+use bevy::prelude::*;
+use bevy_networking_turbulence::NetworkResource;
+use std::{thread, time::Duration};
+
+pub const IDLE: &str = "idle";
+// frame length while hibernating; bounds how long a new connection waits to be noticed
+const HIBERNATE_WAIT: Duration = Duration::from_millis(100);
+
+#[derive(Default)]
+pub struct Idle {
+    // when the last connection went away
+    since: Option<f64>,
+}
+
+// stops simulating and slows the loop down while nobody is connected; a new connection
+// is picked up on the next frame, handshakes go on in the background meanwhile
+pub fn idle_system(
+    time: Res<Time>,
+    net: Res<NetworkResource>,
+    config: Res<ServerConfig>,
+    mut idle: ResMut<Idle>,
+    mut simulation: ResMut<Simulation>,
+) {
+    let now = time.seconds_since_startup();
+
+    if !net.connections.is_empty() || config.hibernate_after <= 0.0 {
+        idle.since = None;
+        if simulation.hibernating {
+            info!("Waking up");
+            simulation.hibernating = false;
+        }
+        return;
+    }
+
+    let since = *idle.since.get_or_insert(now);
+    if !simulation.hibernating && now - since >= config.hibernate_after {
+        info!(
+            "No players for {} seconds; hibernating",
+            config.hibernate_after
+        );
+        simulation.hibernating = true;
+    }
+    if simulation.hibernating {
+        thread::sleep(HIBERNATE_WAIT);
+    }
+}
@@ -0,0 +1,100 @@
+use std::{
+    collections::HashMap,
+    ops::{Index, IndexMut},
+};
+
+// the world an agar or feed lives in; entities only ever meet others of the same instance
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct Instance(pub usize);
+
+// one `T` per world instance
+pub struct PerInstance<T> {
+    worlds: Vec<T>,
+}
+
+impl<T> PerInstance<T> {
+    pub fn new(count: usize, f: impl FnMut() -> T) -> Self {
+        Self {
+            worlds: std::iter::repeat_with(f).take(count).collect(),
+        }
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = (Instance, &T)> {
+        self.worlds
+            .iter()
+            .enumerate()
+            .map(|(i, world)| (Instance(i), world))
+    }
+
+    pub fn iter_mut(&mut self) -> impl Iterator<Item = (Instance, &mut T)> {
+        self.worlds
+            .iter_mut()
+            .enumerate()
+            .map(|(i, world)| (Instance(i), world))
+    }
+}
+
+impl<T> Index<Instance> for PerInstance<T> {
+    type Output = T;
+
+    fn index(&self, instance: Instance) -> &T {
+        &self.worlds[instance.0]
+    }
+}
+
+impl<T> IndexMut<Instance> for PerInstance<T> {
+    fn index_mut(&mut self, instance: Instance) -> &mut T {
+        &mut self.worlds[instance.0]
+    }
+}
+
+// connections are pinned to the instance they logged into
+pub struct Instances {
+    count: usize,
+    assigned: HashMap<u32, Instance>,
+}
+
+impl Instances {
+    pub fn new(count: usize) -> Self {
+        Self {
+            count,
+            assigned: HashMap::new(),
+        }
+    }
+
+    pub fn count(&self) -> usize {
+        self.count
+    }
+
+    // where the next new player goes
+    pub fn least_crowded(&self) -> Instance {
+        let mut players = vec![0; self.count];
+        for instance in self.assigned.values() {
+            players[instance.0] += 1;
+        }
+
+        (0..self.count)
+            .min_by_key(|i| players[*i])
+            .map_or(Instance(0), Instance)
+    }
+
+    pub fn pin(&mut self, handle: u32, instance: Instance) {
+        self.assigned.insert(handle, instance);
+    }
+
+    pub fn get(&self, handle: u32) -> Option<Instance> {
+        self.assigned.get(&handle).copied()
+    }
+
+    pub fn handles(&self, instance: Instance) -> Vec<u32> {
+        self.assigned
+            .iter()
+            .filter(|(_, i)| **i == instance)
+            .map(|(handle, _)| *handle)
+            .collect()
+    }
+
+    pub fn retain(&mut self, handles: &[u32]) {
+        self.assigned.retain(|handle, _| handles.contains(handle));
+    }
+}
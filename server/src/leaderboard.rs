@@ -0,0 +1,53 @@
+use crate::{
+    instance::{Instance, Instances, PerInstance},
+    replay::Recorder,
+    PlayerName,
+};
+use agarlib::*;
+use bevy::prelude::*;
+use std::collections::HashMap;
+
+// every world's biggest players, to everyone in it once every `LEADERBOARD_INTERVAL`
+pub fn leaderboard_system(
+    time: Res<Time>,
+    mut next: Local<f64>,
+    instances: Res<Instances>,
+    broadcasts: Res<PerInstance<NetworkBroadcast>>,
+    mut queue: ResMut<SendQueue>,
+    mut recorder: ResMut<Recorder>,
+    players: Query<(&NetworkHandle, &NetworkId, &PlayerName, &Agar)>,
+) {
+    let now = time.seconds_since_startup();
+    if now < *next {
+        return;
+    }
+    *next = now + LEADERBOARD_INTERVAL;
+
+    let mut boards: HashMap<Instance, Vec<LeaderboardEntry>> = HashMap::new();
+    for (handle, id, name, agar) in players.iter() {
+        if let Some(instance) = instances.get(handle.id) {
+            boards.entry(instance).or_default().push(LeaderboardEntry {
+                id: id.0,
+                name: name.0.clone(),
+                size: agar.size,
+            });
+        }
+    }
+
+    for (instance, _) in broadcasts.iter() {
+        let mut entries = boards.remove(&instance).unwrap_or_default();
+        let total = entries.len() as u32;
+        entries.sort_by(|a, b| {
+            b.size
+                .partial_cmp(&a.size)
+                .unwrap_or(std::cmp::Ordering::Equal)
+        });
+        entries.truncate(LEADERBOARD_LEN);
+
+        let message = ClientMessage::Leaderboard(Leaderboard { entries, total });
+        for handle in instances.handles(instance) {
+            queue.push(handle, message.clone());
+        }
+        recorder.record(instance, message);
+    }
+}
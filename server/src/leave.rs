@@ -0,0 +1,65 @@
+use crate::{instance::Instance, resume::Parked, ClientLatency, Despawns, NetworkIdAllocator};
+use agarlib::*;
+use bevy::prelude::*;
+use bevy_networking_turbulence::NetworkResource;
+
+// connections on their way out. kicks, bans, floods, cheats, logouts, full channels and
+// dropped connections all leave through `leave_system`, so none of them leaves anything
+// behind
+#[derive(Default)]
+pub struct Leaving {
+    handles: Vec<u32>,
+    parked: Vec<Entity>,
+}
+
+impl Leaving {
+    // the player's cells go along with the connection; a parked cell has no handle anymore
+    // and stays for `RESUME_GRACE`
+    pub fn drop_player(&mut self, handle: u32) {
+        if !self.handles.contains(&handle) {
+            self.handles.push(handle);
+        }
+    }
+
+    // a parked cell kicked before its grace ran out; it goes without waiting for the rest
+    pub fn drop_parked(&mut self, entity: Entity) {
+        if !self.parked.contains(&entity) {
+            self.parked.push(entity);
+        }
+    }
+}
+
+pub fn leave_system(
+    commands: &mut Commands,
+    mut leaving: ResMut<Leaving>,
+    mut net: ResMut<NetworkResource>,
+    mut queue: ResMut<SendQueue>,
+    mut ids: ResMut<NetworkIdAllocator>,
+    mut despawns: ResMut<Despawns>,
+    mut latency: ResMut<ClientLatency>,
+    mut formats: ResMut<WireFormats>,
+    agars: Query<(Entity, &NetworkId, &NetworkHandle, &Instance)>,
+    parked: Query<(&NetworkId, &Instance), With<Parked>>,
+) {
+    for entity in leaving.parked.drain(..) {
+        if let Ok((id, instance)) = parked.get(entity) {
+            info!("Removing parked agar {}", id.0);
+            ids.free(id.0);
+            despawns.ids.push((*instance, id.0));
+            commands.despawn(entity);
+        }
+    }
+
+    for handle in leaving.handles.drain(..) {
+        for (entity, id, _, instance) in agars.iter().filter(|(_, _, h, _)| h.id == handle) {
+            info!("Removing agar {} of [{}]", id.0, handle);
+            ids.free(id.0);
+            despawns.ids.push((*instance, id.0));
+            commands.despawn(entity);
+        }
+        queue.forget(handle);
+        net.connections.remove(&handle);
+        latency.remove(handle);
+        formats.remove(handle);
+    }
+}
@@ -0,0 +1,46 @@
+use crate::config::{LogRotation, ServerConfig};
+use anyhow::{anyhow, Context, Result};
+use std::path::Path;
+use tracing_appender::{non_blocking::WorkerGuard, rolling};
+use tracing_subscriber::EnvFilter;
+
+// sets up the global subscriber; keep the guard until exit or the last lines are lost
+pub fn init(config: &ServerConfig) -> Result<WorkerGuard> {
+    let filter = match EnvFilter::try_from_default_env() {
+        Ok(filter) => filter,
+        Err(_) => EnvFilter::try_new(&config.log_level)
+            .with_context(|| format!("invalid log_level {:?}", config.log_level))?,
+    };
+
+    let (writer, guard) = if config.log_file.is_empty() {
+        tracing_appender::non_blocking(std::io::stdout())
+    } else {
+        let path = Path::new(&config.log_file);
+        let dir = path
+            .parent()
+            .filter(|dir| !dir.as_os_str().is_empty())
+            .unwrap_or_else(|| Path::new("."));
+        let file = path
+            .file_name()
+            .ok_or_else(|| anyhow!("log_file {:?} is not a file", config.log_file))?;
+
+        let appender = match config.log_rotation {
+            LogRotation::Hourly => rolling::hourly(dir, file),
+            LogRotation::Daily => rolling::daily(dir, file),
+            LogRotation::Never => rolling::never(dir, file),
+        };
+        tracing_appender::non_blocking(appender)
+    };
+
+    let builder = tracing_subscriber::fmt()
+        .with_env_filter(filter)
+        .with_writer(writer)
+        .with_ansi(config.log_file.is_empty());
+    if config.log_json {
+        builder.json().init();
+    } else {
+        builder.init();
+    }
+
+    Ok(guard)
+}
@@ -1,29 +1,247 @@
+// bevy systems take their resources and queries as parameters
+#![allow(clippy::too_many_arguments, clippy::type_complexity)]
+
+mod admin;
+mod backpressure;
+mod bandwidth;
+mod bounds;
+mod budget;
+mod chat;
+mod cheat;
+mod config;
+mod console;
+mod crash;
+mod flood;
+mod grid;
+mod http;
+mod idle;
+mod instance;
+mod leaderboard;
+mod leave;
+mod logging;
+mod map;
+mod match_stats;
+mod metrics;
+mod names;
+mod overload;
+mod parallel;
+mod persistence;
+mod region;
+mod reload;
+mod replay;
+mod respawn;
+mod restart;
+mod resume;
+mod shutdown;
+mod spans;
+mod status;
+#[cfg(feature = "storage")]
+mod storage;
+mod subscriptions;
+mod supervisor;
+mod team;
+
+use admin::{admin_system, AdminQueue, AdminReply, Bans};
 use agarlib::*;
-use bevy::{app::ScheduleRunnerSettings, prelude::*};
-use bevy_networking_turbulence::NetworkResource;
-use rand::Rng;
-use std::{collections::HashMap, time::Duration};
+use backpressure::backpressure_system;
+use bandwidth::{density_system, LowBandwidth, LowBandwidthClients, LOW_BANDWIDTH_SLOWDOWN};
+use bevy::{app::ScheduleRunnerSettings, ecs::ShouldRun, prelude::*, tasks::ComputeTaskPool};
+use bevy_networking_turbulence::{NetworkEvent, NetworkResource};
+use bounds::bounds_system;
+use budget::TickBudget;
+use chat::{chat_system, ChatLimits};
+use cheat::{cheat_system, CheatGuard};
+use config::{GameMode, ServerConfig};
+use flood::{flood_system, FloodGuard};
+use grid::{spatial_index_system, SpatialIndex};
+use idle::{idle_system, Idle, IDLE};
+use instance::{Instance, Instances, PerInstance};
+use leaderboard::leaderboard_system;
+use leave::{leave_system, Leaving};
+use map::{map_sync_system, virus_system};
+use match_stats::{match_stats_system, MatchStats};
+use metrics::{metrics_system, Metrics};
+use names::name_sync_system;
+use overload::Overload;
+use parallel::{par_map, BATCH_SIZE};
+use persistence::{persistence_system, restore_system, Persistence, WorldSnapshot};
+use rand::{Rng, SeedableRng};
+use rand_chacha::ChaCha8Rng;
+use region::{region_system, ConnectionTags};
+use reload::{reload_system, Reload};
+use replay::{record_system, Recorder};
+use respawn::respawn_system;
+use restart::{restart_system, RestartSchedule};
+use resume::{logout_system, owner_token_system, parked_system, Parked};
+use shutdown::{shutdown_system, ServerStopping, Shutdown};
+use spans::{connection_span, SystemTimer};
+use status::{status_system, StatusBoard};
+use std::{
+    collections::{HashMap, HashSet, VecDeque},
+    sync::mpsc::Sender,
+    time::Duration,
+};
+#[cfg(feature = "storage")]
+use storage::{storage_system, HighScores, Storage};
+use subscriptions::{subscription_system, Subscriptions};
+use team::{team_system, Teams};
+
+const FEED_LOG_LIMIT: usize = 4096;
+// a connection's feed requests are answered at most this often, in seconds; the ones in
+// between wait, and only the latest is answered
+const FEED_REQUEST_INTERVAL: f64 = 0.1;
+// seconds a redirected player has to log in at the other server
+const REDIRECT_PASS_TTL: u64 = 30;
+
+const MAX_REWIND: f64 = 0.25;
+// seconds of positions kept for lag compensation
+const HISTORY_SPAN: f64 = 0.5;
+
+const UPDATE_INTERVALS: [u32; 3] = [1, 2, 3];
+// round trip time over the connection's best that counts as congestion; a far but steady
+// client isn't congested
+const CONGESTED_RTT: f64 = 0.3;
+// seconds without resends or failed sends before a step back to a shorter interval
+const RECOVER_TIME: f64 = 3.0;
+const FULL_SNAPSHOT_INTERVAL: f64 = 1.0;
+const GRID_CELL_SIZE: f32 = 200.0;
+// tries at finding a spot clear of obstacles before taking a blocked one
+const SPAWN_ATTEMPTS: usize = 16;
+// feeds spawned per world and step; a raised feed target fills up over a few steps
+const FEED_SPAWNS_PER_STEP: usize = 256;
+
+// movement, collisions and feed spawning; runs at the tick rate however long a frame takes
+const SIMULATION: &str = "simulation";
 
 fn main() {
-    App::build().add_plugin(AgarSrv).run();
+    // nothing to log to before the config is read
+    let mut config = match ServerConfig::from_args() {
+        Ok(config) => config,
+        Err(err) => {
+            eprintln!("Invalid server config: {:#}", err);
+            std::process::exit(1);
+        }
+    };
+    let log_guard = match logging::init(&config) {
+        Ok(guard) => guard,
+        Err(err) => {
+            eprintln!("Unable to set up logging: {:#}", err);
+            std::process::exit(1);
+        }
+    };
+    info!("{:?}", config.redacted());
+    if config.binds_wildcard() {
+        // the pinned network stack puts the listen address into its webrtc offer
+        warn!("Browsers get the wildcard bind address in the webrtc offer and may not connect");
+    }
+    if config.private() && !config.encryption {
+        // the credentials are only taken over an encrypted connection
+        warn!("The server is private but `encryption` is off; nobody can log in");
+    }
+
+    if config.supervise {
+        let code = supervisor::run(&config);
+        drop(log_guard);
+        std::process::exit(code);
+    }
+    crash::install_panic_hook(config.crash_dir.clone());
+
+    // signs the exchange keys, so clients that pinned it know they reached this server
+    let identity = if config.encryption {
+        match config.load_identity() {
+            Ok(identity) => {
+                info!("Server identity: {}", identity_hex(&identity.public()));
+                Some(identity)
+            }
+            Err(err) => {
+                error!("Unable to load the server identity: {:#}", err);
+                std::process::exit(1);
+            }
+        }
+    } else {
+        None
+    };
+
+    let map = if config.map.is_empty() {
+        GameMap {
+            width: config.world_width,
+            height: config.world_height,
+            ..GameMap::default()
+        }
+    } else {
+        match map::load(&config.map) {
+            Ok(map) => {
+                info!("Loaded map {:?} from {}", map.name, config.map);
+                // the map decides the world size
+                config.world_width = map.width;
+                config.world_height = map.height;
+                map
+            }
+            Err(err) => {
+                error!("Unable to load map: {:#}", err);
+                drop(log_guard);
+                std::process::exit(1);
+            }
+        }
+    };
+
+    let snapshot = if config.restore {
+        match persistence::load(&config.snapshot_path) {
+            Ok(snapshot) => Some(snapshot),
+            Err(err) => {
+                error!("Unable to restore world: {:#}", err);
+                drop(log_guard);
+                std::process::exit(1);
+            }
+        }
+    } else {
+        None
+    };
+
+    App::build()
+        .add_plugin(AgarSrv {
+            config,
+            snapshot,
+            map,
+            identity,
+        })
+        .run();
+    drop(log_guard);
+    if restart::requested() {
+        std::process::exit(restart::RESTART_EXIT_CODE);
+    }
 }
 
 #[derive(Default)]
 struct FeedUpdates {
-    updates: Vec<FeedUpdate>,
+    revision: u64,
+    log: VecDeque<(u64, FeedUpdate)>,
     snapshot: HashMap<EntityId, FeedUpdateSpawn>,
+    acked: HashMap<u32, u64>,
+    // when each connection was last answered, and the request it's waiting on
+    answered: HashMap<u32, f64>,
+    waiting: HashMap<u32, u64>,
     total_feeds: usize,
 }
 
 impl FeedUpdates {
+    fn push(&mut self, update: FeedUpdate) {
+        self.revision += 1;
+        self.log.push_back((self.revision, update));
+
+        if self.log.len() > FEED_LOG_LIMIT {
+            self.log.pop_front();
+        }
+    }
+
     fn spawn(&mut self, update: FeedUpdateSpawn) {
-        self.updates.push(FeedUpdate::Spawn(update.clone()));
+        self.push(FeedUpdate::Spawn(update.clone()));
         self.snapshot.insert(update.id, update);
         self.total_feeds += 1;
     }
 
     fn despawn(&mut self, id: EntityId) {
-        self.updates.push(FeedUpdate::Despawn(id));
+        self.push(FeedUpdate::Despawn(id));
         self.snapshot.remove(&id);
         self.total_feeds -= 1;
     }
@@ -35,11 +253,10 @@ impl FeedUpdates {
             .collect()
     }
 
-    fn updates(&self, from: usize) -> Vec<FeedUpdate> {
-        let from = from.min(self.updates.len());
+    fn diff(&self, from: u64) -> Vec<FeedUpdate> {
         let mut updates = HashMap::new();
 
-        for update in &self.updates[from..] {
+        for (_, update) in self.log.iter().filter(|(revision, _)| *revision > from) {
             match update {
                 FeedUpdate::Spawn(s) => {
                     updates.insert(s.id, update.clone());
@@ -54,188 +271,2115 @@ impl FeedUpdates {
 
         updates.into_iter().map(|(_, update)| update).collect()
     }
+
+    fn ack(&mut self, handle: u32, revision: u64) {
+        self.acked.insert(handle, revision.min(self.revision));
+    }
+
+    fn request(&mut self, handle: u32, revision: u64) {
+        self.ack(handle, revision);
+        self.waiting.insert(handle, revision);
+    }
+
+    // the requests to answer now
+    fn due(&mut self, now: f64) -> Vec<(u32, u64)> {
+        let answered = &mut self.answered;
+        let mut due = vec![];
+        self.waiting.retain(|handle, revision| {
+            let last = answered.get(handle).copied().unwrap_or(f64::MIN);
+            if now - last < FEED_REQUEST_INTERVAL {
+                return true;
+            }
+            answered.insert(*handle, now);
+            due.push((*handle, *revision));
+            false
+        });
+        due
+    }
+
+    // updates past `from`, or every feed when the log doesn't reach back that far
+    fn since(&self, from: u64) -> (bool, Vec<FeedUpdate>) {
+        let oldest = self
+            .log
+            .front()
+            .map_or(self.revision + 1, |(revision, _)| *revision);
+
+        let baseline = from == 0 || from + 1 < oldest;
+        if baseline {
+            (true, self.snapshot())
+        } else {
+            (false, self.diff(from))
+        }
+    }
+
+    fn response(&self, from: u64, limit: usize) -> Vec<ClientMessage> {
+        let (baseline, updates) = self.since(from);
+        split_feed_response(self.revision, baseline, updates, limit)
+    }
+
+    fn trim(&mut self, handles: &[u32]) {
+        self.acked.retain(|handle, _| handles.contains(handle));
+        self.answered.retain(|handle, _| handles.contains(handle));
+        self.waiting.retain(|handle, _| handles.contains(handle));
+
+        let acked = self.acked.values().min().copied().unwrap_or(self.revision);
+        while let Some((revision, _)) = self.log.front() {
+            if *revision > acked {
+                break;
+            }
+            self.log.pop_front();
+        }
+    }
+}
+
+#[derive(Default)]
+struct NetworkIdAllocator {
+    next: EntityId,
+    entities: NetworkEntities,
+}
+
+impl NetworkIdAllocator {
+    fn allocate(&mut self, entity: Entity) -> EntityId {
+        // 0 is never handed out
+        self.next += 1;
+        self.entities.insert(self.next, entity);
+        self.next
+    }
+
+    fn free(&mut self, id: EntityId) -> Option<Entity> {
+        self.entities.remove(id)
+    }
+
+    // keeps ids from a restored snapshot; fresh ones are handed out above them
+    fn restore(&mut self, id: EntityId, entity: Entity) {
+        self.next = self.next.max(id);
+        self.entities.insert(id, entity);
+    }
+}
+
+#[derive(Default)]
+struct Despawns {
+    ids: Vec<(Instance, EntityId)>,
+}
+
+// pings a pong may still answer; older ones tell nothing a newer one doesn't
+const PINGS_KEPT: usize = 4;
+
+#[derive(Default)]
+struct ClientLatency {
+    rtts: HashMap<u32, f64>,
+    // the lowest smoothed round trip time seen per connection
+    floors: HashMap<u32, f64>,
+    next_ping: f64,
+    // timestamps of the latest pings sent; a pong has to echo one of them
+    pings: VecDeque<f64>,
+    // the newest ping each connection answered, so none counts twice
+    answered: HashMap<u32, f64>,
+    // pings sent to a single connection to place it in a region
+    probes: HashMap<u32, f64>,
+}
+
+impl ClientLatency {
+    fn ping(&mut self, now: f64) {
+        if self.pings.len() == PINGS_KEPT {
+            self.pings.pop_front();
+        }
+        self.pings.push_back(now);
+    }
+
+    fn probe(&mut self, handle: u32, now: f64) {
+        self.probes.insert(handle, now);
+    }
+
+    // the echoed timestamp comes from the client, so only one the server sent is believed
+    fn pong(&mut self, handle: u32, sent: f64, now: f64) -> bool {
+        let probed = self.probes.get(&handle) == Some(&sent);
+        let stale = self
+            .answered
+            .get(&handle)
+            .map_or(false, |last| sent <= *last);
+        if !(probed || self.pings.contains(&sent)) || stale {
+            return false;
+        }
+        if probed {
+            self.probes.remove(&handle);
+        }
+        self.answered.insert(handle, sent);
+        self.update(handle, now - sent);
+        true
+    }
+
+    fn update(&mut self, handle: u32, rtt: f64) {
+        let entry = self.rtts.entry(handle).or_insert(rtt);
+        *entry += (rtt - *entry) * 0.2;
+        let floor = self.floors.entry(handle).or_insert(*entry);
+        *floor = floor.min(*entry);
+    }
+
+    fn rtt(&self, handle: u32) -> Option<f64> {
+        self.rtts.get(&handle).copied()
+    }
+
+    // how far the round trip time has grown over the connection's best, i.e. queueing
+    fn excess(&self, handle: u32) -> Option<f64> {
+        Some(self.rtt(handle)? - self.floors.get(&handle)?)
+    }
+
+    fn remove(&mut self, handle: u32) {
+        self.rtts.remove(&handle);
+        self.floors.remove(&handle);
+        self.answered.remove(&handle);
+        self.probes.remove(&handle);
+    }
+
+    fn rewind(&self, handle: u32) -> f64 {
+        self.rtts
+            .get(&handle)
+            .copied()
+            .unwrap_or(0.0)
+            .min(MAX_REWIND)
+    }
+}
+
+#[derive(Default)]
+struct SendSchedule {
+    seq: u32,
+    level: usize,
+    next_frame: u32,
+    next_full: u32,
+    // quality reports in a row without resends or failed sends
+    clean_intervals: u32,
+    // resends and failed sends as of the last quality report
+    losses: u64,
+    // the client is on low bandwidth
+    slow: bool,
+}
+
+impl SendSchedule {
+    fn due(&self, frame: u32) -> bool {
+        frame >= self.next_frame
+    }
+
+    // a full snapshot now and then repairs changes lost on the unreliable channel
+    fn full_due(&self, frame: u32) -> bool {
+        frame >= self.next_full
+    }
+
+    // `shed` doubles the interval while the server is overloaded
+    fn sent(&mut self, frame: u32, full: bool, full_interval: u32, shed: bool) {
+        let mut interval = UPDATE_INTERVALS[self.level];
+        if self.slow {
+            interval *= LOW_BANDWIDTH_SLOWDOWN;
+        }
+        self.next_frame = frame + if shed { interval * 2 } else { interval };
+        if full {
+            self.next_full = frame + full_interval;
+        }
+    }
+
+    fn congested(&mut self) {
+        self.level = (self.level + 1).min(UPDATE_INTERVALS.len() - 1);
+        self.clean_intervals = 0;
+    }
+
+    // once per quality report with the connection's running total of resends and failed
+    // sends; steps back after `recover_intervals` reports without new ones
+    fn report(&mut self, losses: u64, recover_intervals: u32) {
+        if losses > self.losses {
+            self.clean_intervals = 0;
+        } else {
+            self.clean_intervals += 1;
+        }
+        self.losses = losses;
+        if self.clean_intervals >= recover_intervals && self.level > 0 {
+            self.level -= 1;
+            self.clean_intervals = 0;
+        }
+    }
+}
+
+struct SendBudgets {
+    schedules: HashMap<u32, SendSchedule>,
+    full_interval: u32,
+    recover_intervals: u32,
+    overview_interval: u32,
+}
+
+impl SendBudgets {
+    fn new(tick_rate: f64) -> Self {
+        Self {
+            schedules: HashMap::new(),
+            full_interval: ticks(FULL_SNAPSHOT_INTERVAL, tick_rate),
+            recover_intervals: (RECOVER_TIME / QUALITY_REPORT_INTERVAL).ceil() as u32,
+            overview_interval: ticks(OVERVIEW_INTERVAL, tick_rate),
+        }
+    }
 }
 
-struct AgarSrv;
+struct PlayerName(String);
+
+// handed to the client on login so it can reclaim its agar after a restart
+struct OwnerToken(u64);
+
+// the biggest the agar has been; kept in world snapshots along with it
+struct Score(f32);
+
+// messages taken off the wire by `handle_messages` for other systems to act on
+#[derive(Default)]
+struct Inbox {
+    logins: Vec<(u32, LoginRequest)>,
+    // the line and whether it's for the sender's team only
+    chats: Vec<(u32, String, bool)>,
+    // system lines for every player, e.g. from the admin api
+    announcements: Vec<String>,
+    // raw cursor positions, for the cheat heuristics
+    inputs: Vec<(u32, Vec2)>,
+    // admin requests for the cheat reports, answered by `cheat_system`
+    cheat_reports: Vec<Sender<AdminReply>>,
+    // eaten players who want a new cell
+    respawns: Vec<u32>,
+    // players leaving for good, removed without parking their cell
+    logouts: Vec<u32>,
+    // passwords and whitelist keys, and owner tokens of resuming players, for the logins
+    // waiting on them in `pending`, along with whether their redirect pass checked out and the
+    // credential that came already
+    credentials: Vec<(u32, String)>,
+    owners: Vec<(u32, u64)>,
+    pending: HashMap<u32, (LoginRequest, bool, Option<String>)>,
+    // owner tokens of new agars, handed out by `owner_token_system` once the connection is
+    // encrypted
+    owner_tokens: Vec<(u32, u64)>,
+    // regions spectators asked for, or dropped when false; checked by `subscription_system`
+    subscriptions: Vec<(u32, Region, bool)>,
+}
+
+// a front door instance bounces logins to `targets` once it holds `capacity` players. the
+// passes it hands out, and the ones it honours, are signed with `redirect_secret`
+#[derive(Default)]
+struct Redirector {
+    targets: Vec<String>,
+    capacity: usize,
+    next: usize,
+    secret: Vec<u8>,
+    // what the passes for this server name it
+    addr: String,
+    // macs of the passes used, until they expire; each lets one login in
+    used: HashMap<Vec<u8>, u64>,
+}
+
+impl Redirector {
+    fn new(config: &ServerConfig) -> Self {
+        Self {
+            targets: config.redirect_to.clone(),
+            capacity: config.redirect_above,
+            next: 0,
+            secret: config.redirect_secret.as_bytes().to_vec(),
+            addr: config.public_addr.clone(),
+            used: HashMap::new(),
+        }
+    }
+
+    fn redirect(&mut self, players: usize, name: &str) -> Option<(String, RedirectPass)> {
+        if self.targets.is_empty() || players < self.capacity {
+            return None;
+        }
+
+        let addr = self.targets[self.next % self.targets.len()].clone();
+        self.next += 1;
+        let pass = self.pass(name, &addr);
+        Some((addr, pass))
+    }
+
+    fn pass(&self, name: &str, target: &str) -> RedirectPass {
+        let expires = unix_now() + REDIRECT_PASS_TTL;
+        RedirectPass::issue(&self.secret, &names::normalize(name), target, expires)
+    }
+
+    // whether `pass` was issued by a server sharing the secret, for this player and this
+    // server, and isn't expired or used up
+    fn admit(&mut self, pass: &RedirectPass, name: &str) -> bool {
+        let now = unix_now();
+        self.used.retain(|_, expires| *expires >= now);
+        if self.addr.is_empty()
+            || self.used.contains_key(&pass.mac)
+            || !pass.verify(&self.secret, &names::normalize(name), &self.addr, now)
+        {
+            return false;
+        }
+        self.used.insert(pass.mac.clone(), pass.expires);
+        true
+    }
+}
+
+fn unix_now() -> u64 {
+    std::time::SystemTime::now()
+        .duration_since(std::time::UNIX_EPOCH)
+        .map_or(0, |since| since.as_secs())
+}
+
+#[derive(Default)]
+struct ReplicationPriorities {
+    accumulators: HashMap<u32, HashMap<EntityId, f32>>,
+    // frame each entity was last sent to each client
+    sent: HashMap<u32, HashMap<EntityId, u32>>,
+}
+
+#[derive(Default)]
+struct ReplicationState {
+    translation: Vec3,
+    size: f32,
+    changed: u32,
+}
+
+#[derive(Default)]
+struct TransformHistory {
+    samples: VecDeque<(f64, Vec3)>,
+}
+
+impl TransformHistory {
+    fn record(&mut self, time: f64, translation: Vec3) {
+        while let Some((t, _)) = self.samples.front() {
+            if *t >= time - HISTORY_SPAN {
+                break;
+            }
+            self.samples.pop_front();
+        }
+        self.samples.push_back((time, translation));
+    }
+
+    fn at(&self, time: f64) -> Option<Vec3> {
+        let (newest_time, newest) = *self.samples.back()?;
+        if time >= newest_time {
+            return Some(newest);
+        }
+
+        let mut next = (newest_time, newest);
+        for &(t, p) in self.samples.iter().rev().skip(1) {
+            if t <= time {
+                let w = ((time - t) / (next.0 - t)) as f32;
+                return Some(p + (next.1 - p) * w);
+            }
+            next = (t, p);
+        }
+
+        // older than the buffer; use the oldest sample we have
+        Some(next.1)
+    }
+}
+
+struct AgarSrv {
+    config: ServerConfig,
+    snapshot: Option<WorldSnapshot>,
+    map: GameMap,
+    identity: Option<Identity>,
+}
 
 impl Plugin for AgarSrv {
     fn build(&self, app: &mut AppBuilder) {
-        tracing_subscriber::fmt().init();
+        let config = self.config.clone();
+
+        let metrics = Metrics::new();
+        let (admin_queue, admin_requests) = AdminQueue::new();
+        let mut status = StatusBoard::new();
+        status.identity = self
+            .identity
+            .as_ref()
+            .map_or_else(String::new, |identity| identity_hex(&identity.public()));
+        if config.console {
+            console::spawn(admin_requests.clone());
+        }
+        if !config.http.is_empty() {
+            let admin = if config.admin_token.is_empty() {
+                None
+            } else {
+                Some(http::Admin {
+                    token: config.admin_token.clone(),
+                    requests: admin_requests,
+                })
+            };
+
+            let served = http::serve(
+                &config.http,
+                metrics.registry(),
+                status.shared(),
+                status.health(),
+                admin,
+            );
+            match served {
+                Ok(()) => info!("Serving http on {}", config.http),
+                Err(err) => error!("Unable to serve http on {}: {}", config.http, err),
+            }
+        }
+
+        let seed = config.seed.unwrap_or_else(|| rand::thread_rng().gen());
+        info!("Simulation seed {}", seed);
+
+        let recorder = if config.record.is_empty() {
+            Recorder::disabled()
+        } else {
+            match Recorder::open(&config.record, config.tick_rate, seed, &config.rules()) {
+                Ok(recorder) => {
+                    info!("Recording replay to {}", config.record);
+                    recorder
+                }
+                Err(err) => {
+                    error!("Unable to record replay: {:#}", err);
+                    std::process::exit(1);
+                }
+            }
+        };
+
+        let match_stats = if config.match_stats.is_empty() {
+            MatchStats::disabled()
+        } else {
+            match MatchStats::open(&config.match_stats) {
+                Ok(stats) => {
+                    info!("Exporting match stats to {}", config.match_stats);
+                    stats
+                }
+                Err(err) => {
+                    error!("Unable to export match stats: {:#}", err);
+                    std::process::exit(1);
+                }
+            }
+        };
+
+        let mut bans = Bans::default();
+        #[cfg(feature = "storage")]
+        let storage = if config.database.is_empty() {
+            None
+        } else {
+            match Storage::open(&config.database, &mut bans) {
+                Ok(storage) => Some(storage),
+                Err(err) => {
+                    error!("Unable to open the database: {:#}", err);
+                    std::process::exit(1);
+                }
+            }
+        };
+        #[cfg(not(feature = "storage"))]
+        if !config.database.is_empty() {
+            warn!("Ignoring `database`: built without the `storage` feature");
+        }
 
         app.add_resource(ScheduleRunnerSettings::run_loop(Duration::from_secs_f64(
-            1.0 / 30.0,
+            1.0 / config.tick_rate,
         )))
-        .add_resource(FeedUpdates::default())
+        .add_resource(Instances::new(config.instances))
+        .add_resource(PerInstance::new(config.instances, FeedUpdates::default))
+        .add_resource(ClientLatency::default())
+        .add_resource(Despawns::default())
+        .add_resource(NetworkIdAllocator::default())
+        .add_resource(SendBudgets::new(config.tick_rate))
+        .add_resource(ReplicationPriorities::default())
+        .add_resource(Subscriptions::default())
+        .add_system(subscription_system.system())
+        .add_resource(Inbox::default())
+        .add_resource(ChatLimits::default())
+        .add_resource(Teams::default())
+        .add_system(team_system.system())
+        .add_system(chat_system.system())
+        .add_system(leaderboard_system.system())
+        .add_system(name_sync_system.system())
+        .add_resource(LowBandwidthClients::default())
+        .add_system(density_system.system())
+        .add_resource(Redirector::new(&config))
+        .add_resource(config.rules())
         .add_plugins(MinimalPlugins)
-        .add_system(movement_system.system())
-        .add_resource(NetworkBroadcast { frame: 0 })
+        .add_resource(PerInstance::new(config.instances, || {
+            SpatialIndex::new(GRID_CELL_SIZE)
+        }))
+        .add_resource(metrics)
+        .add_resource(status)
+        .add_resource(TickBudget::new(config.tick_rate))
+        .add_system(status_system.system())
+        .add_system_to_stage(stage::POST_UPDATE, map_sync_system.system())
+        .add_resource(admin_queue)
+        .add_resource(bans)
+        .add_system(admin_system.system())
+        .add_resource(FloodGuard::new(&config))
+        .add_system(flood_system.system())
+        .add_system(backpressure_system.system())
+        .add_resource(CheatGuard::new(&config))
+        .add_system(cheat_system.system())
+        .add_resource(Reload::new(&config))
+        .add_system(reload_system.system())
+        .add_system(bounds_system.system())
+        .add_resource(Shutdown::install(config.shutdown_grace))
+        .add_event::<ServerStopping>()
+        .add_system(shutdown_system.system())
+        .add_resource(RestartSchedule::new(&config))
+        .add_system(restart_system.system())
+        .add_resource(Persistence::new(
+            self.snapshot.clone(),
+            config.snapshot_interval,
+        ))
+        .add_startup_system(restore_system.system())
+        .add_system_to_stage(stage::LAST, persistence_system.system())
+        .add_resource(recorder)
+        .add_system_to_stage(stage::LAST, record_system.system())
+        .add_resource(match_stats)
+        .add_system_to_stage(stage::LAST, match_stats_system.system())
+        .add_system_to_stage(stage::LAST, metrics_system.system())
+        .add_resource(PerInstance::new(
+            config.instances,
+            NetworkBroadcast::default,
+        ))
         .add_system_to_stage(stage::PRE_UPDATE, handle_messages.system())
-        .add_system(feed_collision_system.system())
-        .add_system(feed_spawn_system.system())
+        .add_system_to_stage(stage::PRE_UPDATE, disconnect_system.system())
+        .add_resource(ConnectionTags::new(&config))
+        .add_system_to_stage(stage::PRE_UPDATE, region_system.system())
+        .add_system(login_system.system())
+        .add_system(respawn_system.system())
+        .add_system(parked_system.system())
+        .add_system(score_system.system())
+        .add_system(logout_system.system())
+        .add_system(owner_token_system.system())
+        .add_system(ping_system.system())
+        .add_system(network_quality_system.system())
+        .add_resource(Simulation::new(
+            config.tick_rate,
+            config.max_catch_up,
+            seed,
+            self.map.clone(),
+        ))
+        .add_stage_after(
+            stage::UPDATE,
+            SIMULATION,
+            SystemStage::parallel()
+                .with_run_criteria(simulation_step.system())
+                .with_system(simulation_clock_system.system())
+                .with_system(movement_system.system())
+                .with_system(virus_system.system())
+                .with_system(spatial_index_system.system())
+                .with_system(feed_collision_system.system())
+                .with_system(agar_collision_system.system())
+                .with_system(history_system.system())
+                .with_system(feed_spawn_system.system()),
+        )
+        .add_resource(Leaving::default())
+        .add_system_to_stage(stage::POST_UPDATE, leave_system.system())
+        .add_resource(Snapshots::default())
+        .add_system_to_stage(stage::POST_UPDATE, snapshot_system.system())
         .add_system_to_stage(stage::POST_UPDATE, network_broadcast_system.system())
-        .add_plugin(NetworkPlugin { server: true });
+        .add_system_to_stage(stage::POST_UPDATE, despawn_broadcast_system.system())
+        .add_plugin(
+            NetworkPluginBuilder::new()
+                .server(true)
+                .format(WireFormat::Varint)
+                .encryption(config.encryption)
+                .identity(self.identity.as_ref())
+                .addresses(config.addresses())
+                .build(),
+        )
+        .add_resource(config);
+
+        budget::add_stage_timers(app);
+        // outside the tick, so the time spent asleep doesn't count against the budget
+        app.add_resource(Idle::default()).add_stage_after(
+            budget::TICK_END,
+            IDLE,
+            SystemStage::serial().with_system(idle_system.system()),
+        );
+
+        #[cfg(feature = "storage")]
+        if let Some(storage) = storage {
+            app.add_resource(storage)
+                .add_resource(HighScores::default())
+                .add_system_to_stage(stage::LAST, storage_system.system());
+        }
     }
 }
 
-fn feed_spawn_system(commands: &mut Commands, mut feed_updates: ResMut<FeedUpdates>) {
-    while feed_updates.total_feeds < 100 {
-        info!("Spawn feed {}", feed_updates.total_feeds);
+fn feed_spawn_system(
+    commands: &mut Commands,
+    mut simulation: ResMut<Simulation>,
+    mut feed_updates: ResMut<PerInstance<FeedUpdates>>,
+    mut ids: ResMut<NetworkIdAllocator>,
+    mut index: ResMut<PerInstance<SpatialIndex>>,
+    rules: Res<GameRules>,
+    config: Res<ServerConfig>,
+) {
+    if simulation.overload.active() {
+        return;
+    }
 
-        let mut rng = rand::thread_rng();
-        let pos_x = rng.gen_range(0.0..WORLD_WIDTH);
-        let pos_y = rng.gen_range(0.0..WORLD_HEIGHT);
+    for (instance, feed_updates) in feed_updates.iter_mut() {
+        let missing = config.feed_target.saturating_sub(feed_updates.total_feeds);
+        for _ in 0..missing.min(FEED_SPAWNS_PER_STEP) {
+            game_event!("Spawn feed {} in {:?}", feed_updates.total_feeds, instance);
 
-        let color = FeedColor::Blue;
-        let transform = Transform::from_translation(Vec3::new(pos_x, pos_y, 0.0));
+            let position = simulation.random_point(&rules, 0.0, false);
 
-        let entity = commands
-            .spawn((Feed { color }, transform.clone()))
-            .current_entity()
-            .unwrap();
+            let color = FeedColor::Blue;
+            let transform = Transform::from_translation(position.extend(0.0));
+
+            let entity = commands
+                .spawn((Feed { color }, transform.clone(), instance))
+                .current_entity()
+                .unwrap();
+            let id = ids.allocate(entity);
+            commands.insert_one(entity, NetworkId(id));
+            index[instance].feeds.insert(entity, transform.translation);
+
+            feed_updates.spawn(FeedUpdateSpawn {
+                id,
+                color,
+                translation: transform.translation.clone(),
+            });
+        }
+    }
+}
+
+// all the simulation reads besides the world and player input. logins, admin commands and
+// round trip times aren't recorded, so a seed alone doesn't replay a run
+struct Simulation {
+    // seconds per step of the `SIMULATION` stage
+    step: f32,
+    // simulated seconds; stands in for the wall clock inside the simulation
+    time: f64,
+    rng: ChaCha8Rng,
+    // frozen by an operator; the network keeps running
+    paused: bool,
+    // nobody is connected; nothing to simulate for
+    hibernating: bool,
+    // wall clock seconds not simulated yet
+    accumulator: f64,
+    stepping: bool,
+    // steps run this frame, at most `max_catch_up`
+    catch_up: u32,
+    max_catch_up: u32,
+    overload: Overload,
+    map: GameMap,
+}
+
+impl Simulation {
+    fn new(tick_rate: f64, max_catch_up: u32, seed: u64, map: GameMap) -> Self {
+        Self {
+            step: (1.0 / tick_rate) as f32,
+            time: 0.0,
+            rng: ChaCha8Rng::seed_from_u64(seed),
+            paused: false,
+            hibernating: false,
+            accumulator: 0.0,
+            stepping: false,
+            catch_up: 0,
+            max_catch_up,
+            overload: Overload::new(tick_rate),
+            map,
+        }
+    }
 
-        feed_updates.spawn(FeedUpdateSpawn {
-            id: entity.id(),
-            color,
-            translation: transform.translation.clone(),
+    // a random spot a cell of `radius` fits in, clear of obstacles as far as a few tries
+    // find one; inside the map's spawn regions if it has any and `spawn` is set
+    fn random_point(&mut self, rules: &GameRules, radius: f32, spawn: bool) -> Vec2 {
+        let world = Shape::Rect {
+            x: 0.0,
+            y: 0.0,
+            width: rules.world_width,
+            height: rules.world_height,
+        };
+        let mut point = Vec2::zero();
+        for _ in 0..SPAWN_ATTEMPTS {
+            let area = if spawn && !self.map.spawns.is_empty() {
+                self.map.spawns[self.rng.gen_range(0..self.map.spawns.len())]
+            } else {
+                world
+            };
+            point = rules
+                .clamp(area.random_point(&mut self.rng).extend(0.0))
+                .truncate();
+            if !self.map.blocked(point.extend(0.0), radius) {
+                break;
+            }
+        }
+        point
+    }
+}
+
+// `FixedTimestep`, except that no time is owed for the span of a pause, and a frame
+// runs at most `max_catch_up` steps; time past that is dropped instead of owed
+fn simulation_step(time: Res<Time>, mut simulation: ResMut<Simulation>) -> ShouldRun {
+    if simulation.paused || simulation.hibernating {
+        simulation.accumulator = 0.0;
+        simulation.stepping = false;
+        return ShouldRun::No;
+    }
+
+    if !simulation.stepping {
+        simulation.accumulator += time.delta_seconds_f64();
+        simulation.catch_up = 0;
+    }
+    let step = simulation.step as f64;
+    if simulation.accumulator < step {
+        simulation.stepping = false;
+        return ShouldRun::No;
+    }
+
+    if simulation.catch_up >= simulation.max_catch_up {
+        // each step would make the next frame later still
+        let behind = (simulation.accumulator / step) as u32;
+        simulation.accumulator -= behind as f64 * step;
+        simulation.overload.behind(behind);
+        simulation.stepping = false;
+        return ShouldRun::No;
+    }
+
+    simulation.catch_up += 1;
+    simulation.accumulator -= step;
+    simulation.stepping = true;
+    ShouldRun::YesAndLoop
+}
+
+fn simulation_clock_system(mut simulation: ResMut<Simulation>) {
+    simulation.time += simulation.step as f64;
+}
+
+fn movement_system(
+    pool: Res<ComputeTaskPool>,
+    simulation: Res<Simulation>,
+    rules: Res<GameRules>,
+    mut agars: Query<(&Agar, &mut Transform)>,
+) {
+    let _timer = SystemTimer::start("movement");
+    let (map, step, rules) = (&simulation.map, simulation.step, &*rules);
+    agars
+        .par_iter_mut(BATCH_SIZE)
+        .for_each(&pool, |(agar, mut transform)| {
+            let boost = map.boost(transform.translation);
+            let vel = input_to_velocity(
+                &agar.velocity,
+                agar.max_velocity,
+                camera_zoom(rules, agar.size),
+            );
+            let vel = vel * boost;
+            transform.translation = transform.translation + (vel * step);
+            transform.translation = map.push_out(rules.clamp(transform.translation), agar.size);
+            transform.translation = rules.clamp(transform.translation);
         });
+}
+
+fn score_system(mut agars: Query<(&Agar, &mut Score)>) {
+    for (agar, mut score) in agars.iter_mut() {
+        if agar.size > score.0 {
+            score.0 = agar.size;
+        }
     }
 }
 
-fn movement_system(time: Res<Time>, mut agars: Query<(&Agar, &mut Transform)>) {
-    for (agar, mut transform) in agars.iter_mut() {
-        let vel = input_to_velocity(&agar.velocity, agar.max_velocity);
-        transform.translation = transform.translation + (vel * time.delta_seconds());
-        transform.translation.x = transform.translation.x.max(0.0).min(WORLD_WIDTH);
-        transform.translation.y = transform.translation.y.max(0.0).min(WORLD_HEIGHT);
+fn history_system(
+    simulation: Res<Simulation>,
+    mut agars: Query<(&Transform, &mut TransformHistory)>,
+) {
+    let now = simulation.time;
+    for (transform, mut history) in agars.iter_mut() {
+        history.record(now, transform.translation);
     }
 }
 
-fn network_broadcast_system(
-    mut state: ResMut<NetworkBroadcast>,
+fn ping_system(
+    time: Res<Time>,
+    mut latency: ResMut<ClientLatency>,
     mut net: ResMut<NetworkResource>,
-    agars: Query<(Entity, &Agar, &Transform)>,
-    feed_updates: Res<FeedUpdates>,
+    formats: Res<WireFormats>,
+) {
+    let now = time.seconds_since_startup();
+    if now < latency.next_ping {
+        return;
+    }
+    latency.next_ping = now + PING_INTERVAL;
+    latency.ping(now);
+
+    broadcast_wire(&mut net, &formats, &ClientMessage::Ping(now));
+}
+
+// steps connections back to shorter update intervals after loss-free reports
+fn network_quality_system(
+    mut reader: Local<EventReader<NetworkQualityEvent>>,
+    events: Res<Events<NetworkQualityEvent>>,
+    mut budgets: ResMut<SendBudgets>,
 ) {
+    let recover_intervals = budgets.recover_intervals;
+    for event in reader.iter(&events) {
+        debug!(
+            "Connection [{}]: {} resends, {} failed sends",
+            event.handle, event.stats.resends, event.stats.send_failures
+        );
+        if let Some(schedule) = budgets.schedules.get_mut(&event.handle) {
+            let losses = event.stats.resends + event.stats.send_failures;
+            schedule.report(losses, recover_intervals);
+        }
+    }
+}
+
+struct CellView<'a> {
+    entity: Entity,
+    id: EntityId,
+    // `None` while parked
+    owner: Option<u32>,
+    name: &'a str,
+    instance: Instance,
+    agar: &'a Agar,
+    translation: Vec3,
+    changed: u32,
+}
+
+// what one connection gets this frame; built on the task pool, sent on the main thread
+struct Outgoing {
+    handle: u32,
+    frame: u32,
+    overview: Vec<OverviewMessage>,
+    // `Some(full)` when a state update is due
+    full: Option<bool>,
+    state: Vec<GameStateMessage>,
+    accumulators: HashMap<EntityId, f32>,
+    sent: HashMap<EntityId, u32>,
+}
+
+#[derive(Default)]
+struct Snapshots {
+    outgoing: Vec<Outgoing>,
+}
+
+// a connection's share of the snapshot work, with the state only it touches
+struct SnapshotJob {
+    handle: u32,
+    instance: Instance,
+    due: bool,
+    full: bool,
+    // low bandwidth; the overview goes out half as often
+    slow: bool,
+    seq: u32,
+    regions: Vec<Region>,
+    accumulators: HashMap<EntityId, f32>,
+    sent: HashMap<EntityId, u32>,
+}
+
+// read by every job
+struct SnapshotWorld<'a> {
+    cells: &'a [CellView<'a>],
+    by_entity: HashMap<Entity, &'a CellView<'a>>,
+    live: HashSet<EntityId>,
+    frames: HashMap<Instance, u32>,
+    overview_interval: u32,
+    index: &'a PerInstance<SpatialIndex>,
+    feed_updates: &'a PerInstance<FeedUpdates>,
+    rules: &'a GameRules,
+    config: &'a NetworkConfig,
+    paused: bool,
+}
+
+fn build_snapshot(world: &SnapshotWorld, job: SnapshotJob) -> Outgoing {
+    let SnapshotJob {
+        handle,
+        instance,
+        due,
+        full,
+        slow,
+        seq,
+        regions,
+        mut accumulators,
+        mut sent,
+    } = job;
+    let rules = world.rules;
+    let frame = world.frames[&instance];
+    let overview_interval = if slow {
+        world.overview_interval * 2
+    } else {
+        world.overview_interval
+    };
+    let overview_due = frame % overview_interval == 0;
+
+    let viewer = world.cells.iter().find(|cell| cell.owner == Some(handle));
+    let span = connection_span(handle, viewer.map(|cell| cell.name));
+    let _enter = span.enter();
+
+    // spectators and far-away cells ride the low-rate overview channel
+    let far = |cell: &CellView| match viewer {
+        Some(viewer) => in_far_tier(
+            viewer.translation.distance(cell.translation),
+            cell.agar.size,
+        ),
+        None => true,
+    };
+    let subscribed = |cell: &CellView| regions.iter().any(|r| r.contains(cell.translation));
+
+    let overview = if overview_due {
+        let message = OverviewMessage {
+            frame,
+            cells: world
+                .cells
+                .iter()
+                .filter(|&cell| cell.instance == instance && far(cell) && !subscribed(cell))
+                .map(|cell| CellSummary::new(cell.id, cell.translation, &cell.agar, rules))
+                .collect(),
+        };
+        split_overview_message(message, world.config.profile.overview.max_message_len)
+    } else {
+        vec![]
+    };
+
+    if !due {
+        return Outgoing {
+            handle,
+            frame,
+            overview,
+            full: None,
+            state: vec![],
+            accumulators,
+            sent,
+        };
+    }
+
+    accumulators.retain(|id, _| world.live.contains(id));
+    sent.retain(|id, _| world.live.contains(id));
+
+    // only cells around the viewer or inside its regions can make the cut
+    let index = &world.index[instance];
+    let mut candidates: Vec<_> = viewer
+        .map(|viewer| {
+            index
+                .agars
+                .query(viewer.translation, NEAR_DISTANCE + rules.agar_max_size)
+                .collect()
+        })
+        .unwrap_or_default();
+    for region in &regions {
+        candidates.extend(index.agars.query_region(region));
+    }
+    let mut seen = HashSet::new();
+
+    let agars = candidates
+        .into_iter()
+        .filter_map(|(entity, _)| world.by_entity.get(&entity).copied())
+        .filter(|cell| seen.insert(cell.id))
+        .filter(|&cell| {
+            let kind = if cell.owner == Some(handle) {
+                ReplicationKind::Own
+            } else if !far(cell) {
+                ReplicationKind::Agar
+            } else if subscribed(cell) {
+                ReplicationKind::Subscribed
+            } else {
+                return false;
+            };
+            let priority = match viewer {
+                Some(viewer) => replication_priority(
+                    rules,
+                    kind,
+                    viewer.translation.distance(cell.translation),
+                    viewer.agar.size,
+                    cell.agar.size,
+                ),
+                None => replication_priority(rules, kind, 0.0, 0.0, cell.agar.size),
+            };
+
+            if !full {
+                // unchanged since the client last saw it
+                if sent
+                    .get(&cell.id)
+                    .map_or(false, |last| *last >= cell.changed)
+                {
+                    return false;
+                }
+
+                let accumulator = accumulators.entry(cell.id).or_insert(0.0);
+                *accumulator += priority;
+                if *accumulator < 1.0 {
+                    return false;
+                }
+                *accumulator = 0.0;
+            }
+
+            sent.insert(cell.id, frame);
+            true
+        })
+        .map(|cell| {
+            (
+                cell.id,
+                AgarUpdate {
+                    agar: cell.agar.clone(),
+                    translation: cell.translation,
+                },
+            )
+        })
+        .collect();
+
     let message = GameStateMessage {
-        frame: state.frame,
-        agars: agars
+        frame,
+        seq,
+        agars,
+        feeds: world.feed_updates[instance].revision,
+        partial: !full,
+        paused: world.paused,
+    };
+
+    Outgoing {
+        handle,
+        frame,
+        overview,
+        full: Some(full),
+        state: split_state_message(message, world.config.profile.state.max_message_len),
+        accumulators,
+        sent,
+    }
+}
+
+// builds every connection's overview and state messages, spread across the task pool
+fn snapshot_system(
+    pool: Res<ComputeTaskPool>,
+    net: Res<NetworkResource>,
+    broadcasts: Res<PerInstance<NetworkBroadcast>>,
+    mut budgets: ResMut<SendBudgets>,
+    mut priorities: ResMut<ReplicationPriorities>,
+    mut subscriptions: ResMut<Subscriptions>,
+    mut instances: ResMut<Instances>,
+    mut snapshots: ResMut<Snapshots>,
+    config: Res<NetworkConfig>,
+    rules: Res<GameRules>,
+    index: Res<PerInstance<SpatialIndex>>,
+    feed_updates: Res<PerInstance<FeedUpdates>>,
+    simulation: Res<Simulation>,
+    low_bandwidth: Res<LowBandwidthClients>,
+    mut agars: Query<(
+        Entity,
+        &NetworkId,
+        &Agar,
+        &Transform,
+        Option<&NetworkHandle>,
+        &Instance,
+        &PlayerName,
+        &mut ReplicationState,
+    )>,
+) {
+    let _timer = SystemTimer::start("snapshot");
+    let cells: Vec<_> = agars
+        .iter_mut()
+        .map(
+            |(entity, id, agar, transform, handle, instance, name, mut state)| {
+                let frame = broadcasts[*instance].frame;
+                if state.translation != transform.translation || state.size != agar.size {
+                    state.translation = transform.translation;
+                    state.size = agar.size;
+                    state.changed = frame;
+                }
+                CellView {
+                    entity,
+                    id: id.0,
+                    owner: handle.map(|handle| handle.id),
+                    name: &name.0,
+                    instance: *instance,
+                    agar,
+                    translation: transform.translation,
+                    changed: state.changed,
+                }
+            },
+        )
+        .collect();
+
+    let handles: Vec<_> = net.connections.keys().copied().collect();
+    budgets
+        .schedules
+        .retain(|handle, _| handles.contains(handle));
+    priorities
+        .accumulators
+        .retain(|handle, _| handles.contains(handle));
+    priorities.sent.retain(|handle, _| handles.contains(handle));
+    subscriptions
+        .regions
+        .retain(|handle, _| handles.contains(handle));
+    instances.retain(&handles);
+
+    let jobs: Vec<_> = handles
+        .into_iter()
+        // not logged in yet
+        .filter_map(|handle| Some((handle, instances.get(handle)?)))
+        .map(|(handle, instance)| {
+            let frame = broadcasts[instance].frame;
+            let schedule = budgets.schedules.entry(handle).or_default();
+            schedule.slow = low_bandwidth.contains(handle);
+            SnapshotJob {
+                handle,
+                instance,
+                due: schedule.due(frame),
+                full: schedule.full_due(frame),
+                slow: schedule.slow,
+                seq: schedule.seq,
+                regions: subscriptions.get(handle).to_vec(),
+                accumulators: priorities.accumulators.remove(&handle).unwrap_or_default(),
+                sent: priorities.sent.remove(&handle).unwrap_or_default(),
+            }
+        })
+        .collect();
+
+    let world = SnapshotWorld {
+        cells: &cells,
+        by_entity: cells.iter().map(|cell| (cell.entity, cell)).collect(),
+        live: cells.iter().map(|cell| cell.id).collect(),
+        frames: broadcasts
             .iter()
-            .map(|(entity, agar, transform)| {
-                (
-                    entity.id(),
-                    AgarUpdate {
-                        agar: agar.clone(),
-                        translation: transform.translation,
-                    },
-                )
-            })
+            .map(|(instance, broadcast)| (instance, broadcast.frame))
             .collect(),
-        feeds: feed_updates.updates.len() as u64,
+        overview_interval: budgets.overview_interval,
+        index: &index,
+        feed_updates: &feed_updates,
+        rules: &rules,
+        config: &config,
+        paused: simulation.paused,
     };
-    state.frame += 1;
+    let mut outgoing = par_map(&pool, jobs, |job| build_snapshot(&world, job));
 
-    net.broadcast_message(message);
+    for out in &mut outgoing {
+        priorities
+            .accumulators
+            .insert(out.handle, std::mem::take(&mut out.accumulators));
+        priorities
+            .sent
+            .insert(out.handle, std::mem::take(&mut out.sent));
+    }
+    snapshots.outgoing = outgoing;
+}
+
+fn network_broadcast_system(
+    mut broadcasts: ResMut<PerInstance<NetworkBroadcast>>,
+    mut net: ResMut<NetworkResource>,
+    mut budgets: ResMut<SendBudgets>,
+    mut snapshots: ResMut<Snapshots>,
+    latency: Res<ClientLatency>,
+    formats: Res<WireFormats>,
+    mut stats: ResMut<NetworkStats>,
+    simulation: Res<Simulation>,
+) {
+    let _timer = SystemTimer::start("network_broadcast");
+    for out in snapshots.outgoing.drain(..) {
+        let handle = out.handle;
+        let frame = out.frame;
+
+        let total = out.overview.len();
+        for (i, message) in out.overview.into_iter().enumerate() {
+            match send_wire(&mut net, &formats, handle, &message) {
+                Ok(true) => {
+                    stats.sent(&message);
+                    continue;
+                }
+                Ok(false) => debug!("overview channel to {} is backed up", handle),
+                Err(err) => error!("unable to send overview to client: {}", err),
+            }
+            stats.dropped += (total - i) as u64;
+            break;
+        }
+
+        let full = match out.full {
+            Some(full) => full,
+            None => continue,
+        };
+
+        let mut congested = latency
+            .excess(handle)
+            .map_or(false, |excess| excess > CONGESTED_RTT);
+
+        let total = out.state.len();
+        for (i, message) in out.state.into_iter().enumerate() {
+            match send_wire(&mut net, &formats, handle, &message) {
+                Ok(true) => {
+                    stats.sent(&message);
+                    continue;
+                }
+                Ok(false) => warn!("state channel to {} is backed up", handle),
+                Err(err) => error!("unable to send state to client: {}", err),
+            }
+            stats.dropped += (total - i) as u64;
+            congested = true;
+            break;
+        }
+
+        let SendBudgets {
+            schedules,
+            full_interval,
+            ..
+        } = &mut *budgets;
+        let schedule = schedules.entry(handle).or_default();
+        if congested {
+            schedule.congested();
+        }
+
+        schedule.seq = schedule.seq.wrapping_add(1);
+        schedule.sent(frame, full, *full_interval, simulation.overload.active());
+    }
+
+    for (_, broadcast) in broadcasts.iter_mut() {
+        broadcast.frame += 1;
+    }
+}
+
+fn despawn_broadcast_system(
+    mut despawns: ResMut<Despawns>,
+    mut queue: ResMut<SendQueue>,
+    instances: Res<Instances>,
+    mut recorder: ResMut<Recorder>,
+) {
+    for (instance, id) in despawns.ids.drain(..) {
+        for handle in instances.handles(instance) {
+            queue.push_critical(handle, ClientMessage::EntityDespawned(id));
+        }
+        recorder.record(instance, ClientMessage::EntityDespawned(id));
+    }
 }
 
 fn feed_collision_system(
     commands: &mut Commands,
-    mut feed_updates: ResMut<FeedUpdates>,
-    mut agars: Query<(Entity, &mut Agar, &Transform)>,
-    feeds: Query<(Entity, &Feed, &Transform)>,
+    pool: Res<ComputeTaskPool>,
+    mut feed_updates: ResMut<PerInstance<FeedUpdates>>,
+    mut ids: ResMut<NetworkIdAllocator>,
+    mut index: ResMut<PerInstance<SpatialIndex>>,
+    mut stats: ResMut<MatchStats>,
+    // parked cells wait for their owner as they are
+    mut agars: Query<(Entity, &mut Agar, &Transform, &Instance), Without<Parked>>,
+    feeds: Query<(&NetworkId, &Feed)>,
 ) {
-    for (_entity, mut agar, agar_transform) in agars.iter_mut() {
-        for (entity, _feed, feed_transform) in feeds.iter() {
-            let p = agar_transform.translation;
-            let q = feed_transform.translation;
-            if p.distance(q) < agar.size {
-                info!("Despawn feed");
-                feed_updates.despawn(entity.id());
+    let _timer = SystemTimer::start("feed_collision");
+    let cells: Vec<_> = agars
+        .iter_mut()
+        .map(|(entity, agar, transform, instance)| {
+            (entity, agar.size, transform.translation, *instance)
+        })
+        .collect();
+
+    // the lookups only read the index; feeds are eaten in order below, first come first served
+    let grid = &*index;
+    let hits = par_map(&pool, cells, |(entity, size, p, instance)| {
+        let hits: Vec<_> = grid[instance]
+            .feeds
+            .query(p, size)
+            .filter(|(_, q)| p.distance(*q) < size)
+            .collect();
+        (entity, instance, hits)
+    });
+
+    for (eater, instance, hits) in hits {
+        if hits.is_empty() {
+            continue;
+        }
+        let mut agar = match agars.get_mut(eater) {
+            Ok((_, agar, ..)) => agar,
+            Err(_) => continue,
+        };
+        let index = &mut index[instance];
+
+        for (entity, q) in hits {
+            let id = match feeds.get(entity) {
+                Ok((id, _feed)) => id.0,
+                Err(_) => continue,
+            };
+            if ids.free(id).is_some() {
+                game_event!("Despawn feed {} in {:?}", id, instance);
+                feed_updates[instance].despawn(id);
                 commands.despawn(entity);
-                agar.grow(1.0);
+                index.feeds.remove(entity, q);
+                agar.grow(FEED_GROWTH);
+                stats.pellet(eater);
             }
         }
     }
 }
 
-fn handle_messages(
+fn agar_collision_system(
+    commands: &mut Commands,
+    pool: Res<ComputeTaskPool>,
+    simulation: Res<Simulation>,
+    latency: Res<ClientLatency>,
+    rules: Res<GameRules>,
+    mut despawns: ResMut<Despawns>,
+    mut ids: ResMut<NetworkIdAllocator>,
+    mut queue: ResMut<SendQueue>,
+    mut agars: Query<(
+        Entity,
+        &NetworkId,
+        &mut Agar,
+        &Transform,
+        Option<&NetworkHandle>,
+        &Instance,
+    )>,
+    histories: Query<(Entity, &TransformHistory)>,
+    index: Res<PerInstance<SpatialIndex>>,
+    config: Res<ServerConfig>,
+    mut recorder: ResMut<Recorder>,
+    mut stats: ResMut<MatchStats>,
+    teams: Res<Teams>,
+) {
+    let _timer = SystemTimer::start("agar_collision");
+    if config.mode == GameMode::Sandbox {
+        return;
+    }
+
+    let now = simulation.time;
+    let cells: Vec<_> = agars
+        .iter_mut()
+        .map(|(entity, id, agar, transform, handle, instance)| {
+            (
+                entity,
+                id.0,
+                agar.size,
+                transform.translation,
+                handle.map(|handle| handle.id),
+                *instance,
+            )
+        })
+        .collect();
+    let by_entity: HashMap<_, _> = cells.iter().map(|cell| (cell.0, *cell)).collect();
+    let histories: HashMap<_, _> = histories.iter().collect();
+
+    // victims may have moved this far since the rewound moment
+    let slack = max_velocity(rules.agar_init_size) * MAX_REWIND as f32;
+
+    // who each cell could eat, looked up in parallel; nothing is eaten yet
+    let (index, latency, rules, teams) = (&*index, &*latency, &*rules, &*teams);
+    let reachable = par_map(&pool, cells.clone(), |eater| {
+        let (eater, _, eater_size, eater_pos, eater_handle, instance) = eater;
+        // a parked cell is only food until its owner is back
+        let eater_handle = match eater_handle {
+            Some(handle) => handle,
+            None => return vec![],
+        };
+        // judge against what the eater saw, not where the victim is now
+        let rewind = latency.rewind(eater_handle);
+
+        index[instance]
+            .agars
+            .query(eater_pos, eater_size + slack)
+            .filter_map(|(entity, _)| by_entity.get(&entity).copied())
+            .filter(|&(victim, _, victim_size, victim_pos, victim_handle, _)| {
+                if victim == eater || !rules.can_eat(eater_size, victim_size) {
+                    return false;
+                }
+                // teammates are safe from each other; a parked cell has no team
+                if victim_handle.map_or(false, |victim| teams.allied(eater_handle, victim)) {
+                    return false;
+                }
+                let victim_pos = histories
+                    .get(&victim)
+                    .and_then(|history| history.at(now - rewind))
+                    .unwrap_or(victim_pos);
+                eater_pos.distance(victim_pos) < eater_size
+            })
+            .collect::<Vec<_>>()
+    });
+
+    let mut eaten = HashMap::new();
+    let mut growth = HashMap::new();
+
+    // in order, so the same cell wins a contested meal every time
+    for (&(eater, eater_id, _, _, eater_handle, instance), victims) in cells.iter().zip(reachable) {
+        if eaten.contains_key(&eater) {
+            continue;
+        }
+
+        for (victim, victim_id, victim_size, _, victim_handle, _) in victims {
+            if eaten.contains_key(&victim) {
+                continue;
+            }
+            eaten.insert(victim, (instance, victim_id));
+
+            let message = ClientMessage::PlayerEaten {
+                victim: victim_id,
+                eater: eater_id,
+                victim_mass: victim_size,
+            };
+            if let Some(victim_handle) = victim_handle {
+                queue.push(victim_handle, message.clone());
+            }
+            if let Some(eater_handle) = eater_handle {
+                queue.push(eater_handle, message.clone());
+            }
+            recorder.record(instance, message);
+            stats.kill(eater, victim);
+            *growth.entry(eater).or_insert(0.0) += victim_size * victim_size;
+        }
+    }
+
+    for (entity, _, mut agar, ..) in agars.iter_mut() {
+        if let Some(area) = growth.get(&entity) {
+            agar.absorb(*area);
+        }
+    }
+
+    for (entity, (instance, id)) in eaten {
+        info!("Agar {} eaten", id);
+        ids.free(id);
+        despawns.ids.push((instance, id));
+        commands.despawn(entity);
+    }
+}
+
+fn login_system(
     commands: &mut Commands,
+    mut net: ResMut<NetworkResource>,
+    mut inbox: ResMut<Inbox>,
+    mut ids: ResMut<NetworkIdAllocator>,
+    mut queue: ResMut<SendQueue>,
+    mut formats: ResMut<WireFormats>,
+    mut redirector: ResMut<Redirector>,
+    mut instances: ResMut<Instances>,
+    mut simulation: ResMut<Simulation>,
+    config: Res<NetworkConfig>,
+    rules: Res<GameRules>,
+    server_config: Res<ServerConfig>,
+    bans: Res<Bans>,
+    shutdown: Res<Shutdown>,
+    players: Query<(&PlayerName, &Agar, &Instance), With<NetworkHandle>>,
+    parked: Query<
+        (
+            Entity,
+            &OwnerToken,
+            &NetworkId,
+            &Instance,
+            &PlayerName,
+            Option<&Parked>,
+            Option<&LowBandwidth>,
+        ),
+        Without<NetworkHandle>,
+    >,
+) {
+    let _timer = SystemTimer::start("login");
+    // names in use per world, including the ones parked or restored agars hold for their
+    // owners and the ones taken by this batch of logins
+    let mut taken: HashSet<_> = players
+        .iter()
+        .map(|(name, _, instance)| (*instance, names::normalize(&name.0)))
+        .chain(
+            parked
+                .iter()
+                .map(|(_, _, _, instance, name, ..)| (*instance, names::normalize(&name.0))),
+        )
+        .collect();
+    // and how many players wear each color
+    let mut worn: HashMap<(Instance, u8), usize> = HashMap::new();
+    for (_, agar, instance) in players.iter() {
+        *worn.entry((*instance, agar.color)).or_default() += 1;
+    }
+    let mut players = players.iter().count();
+    let mut claimed = HashSet::new();
+
+    // logins that were waiting on their credential go through again with it, provided it
+    // came encrypted
+    inbox
+        .pending
+        .retain(|handle, _| net.connections.contains_key(handle));
+    let mut logins: Vec<_> = inbox
+        .logins
+        .drain(..)
+        .map(|(handle, login)| (handle, login, None, None, None))
+        .collect();
+    for (handle, owner) in std::mem::take(&mut inbox.owners) {
+        let (login, redirected, credential) = match inbox.pending.remove(&handle) {
+            Some(pending) => pending,
+            None => continue,
+        };
+        // a server without encryption has nothing better to offer
+        if formats.encrypted(handle) || !formats.encryption {
+            logins.push((handle, login, credential, Some(owner), Some(redirected)));
+        } else {
+            warn!("Unencrypted owner token on [{}]", handle);
+            queue.push(
+                handle,
+                ClientMessage::LoginRejected(LoginRejection::EncryptionRequired),
+            );
+        }
+    }
+    for (handle, credential) in std::mem::take(&mut inbox.credentials) {
+        let (login, redirected, _) = match inbox.pending.remove(&handle) {
+            Some(pending) => pending,
+            None => continue,
+        };
+        if formats.encrypted(handle) {
+            logins.push((handle, login, Some(credential), None, Some(redirected)));
+        } else {
+            warn!("Unencrypted credential on [{}]", handle);
+            queue.push(
+                handle,
+                ClientMessage::LoginRejected(LoginRejection::Restricted),
+            );
+        }
+    }
+
+    for (handle, login, credential, owner, passed) in logins {
+        let span = connection_span(handle, Some(&login.name));
+        let _enter = span.enter();
+
+        if !shutdown.accepting_logins() {
+            queue.push(
+                handle,
+                ClientMessage::LoginRejected(LoginRejection::ShuttingDown),
+            );
+            continue;
+        }
+
+        let format = formats.negotiate(&login.formats);
+        formats.set(handle, format);
+
+        if login.profile != config.profile.fingerprint() {
+            warn!("Channel profile mismatch on [{}]", handle);
+            queue.push(
+                handle,
+                ClientMessage::LoginRejected(LoginRejection::ChannelProfileMismatch),
+            );
+            continue;
+        }
+
+        let addr = net
+            .connections
+            .get(&handle)
+            .and_then(|connection| connection.remote_address());
+        if addr.map_or(false, |addr| bans.addrs.contains(&addr.ip())) {
+            warn!("Banned address on [{}]", handle);
+            queue.push(handle, ClientMessage::LoginRejected(LoginRejection::Banned));
+            continue;
+        }
+        if owner.map_or(false, |owner| bans.owners.contains(&owner)) {
+            warn!("Banned owner token on [{}]", handle);
+            queue.push(handle, ClientMessage::LoginRejected(LoginRejection::Banned));
+            continue;
+        }
+
+        // clients redirected here are let in regardless of load so they don't bounce around;
+        // a pass that doesn't check out counts for nothing
+        // a pass is used up by the first go, so a login back with its credential keeps the
+        // verdict
+        let redirected = match (&login.token, passed) {
+            (_, Some(passed)) => passed,
+            (Some(pass), None) if redirector.admit(pass, login.name.trim()) => {
+                info!("Redirected login on [{}]", handle);
+                true
+            }
+            (Some(_), None) => {
+                warn!("Invalid or expired redirect pass on [{}]", handle);
+                false
+            }
+            (None, None) => false,
+        };
+        if login.token.is_none() && passed.is_none() {
+            if let Some((addr, token)) = redirector.redirect(players, login.name.trim()) {
+                info!("Redirecting [{}] to {}", handle, addr);
+                let message = ControlMessage::Redirect { addr, token };
+                if let Err(err) = send_wire(&mut net, &formats, handle, &message) {
+                    error!("unable to redirect client: {}", err);
+                }
+                continue;
+            }
+        }
+
+        if !redirected && players >= server_config.max_players {
+            warn!("Server full; rejecting [{}]", handle);
+            queue.push(
+                handle,
+                ClientMessage::LoginRejected(LoginRejection::ServerFull),
+            );
+            continue;
+        }
+
+        let name = login.name.trim().to_string();
+        if let Err(reason) = names::check(&name, &server_config) {
+            warn!("Rejecting name {:?} on [{}]: {}", name, handle, reason);
+            queue.push(
+                handle,
+                ClientMessage::LoginRejected(LoginRejection::BadName),
+            );
+            continue;
+        }
+        if !names::admitted(&name, credential.as_deref(), &server_config) {
+            // asked for once the key exchange is done, so it never travels in the clear
+            match login.public_key {
+                Some(key) if credential.is_none() && formats.encryption => {
+                    let public_key = Some(formats.accept_key_exchange(handle, key));
+                    queue.push_critical(handle, ClientMessage::CredentialsRequired { public_key });
+                    let login = LoginRequest {
+                        public_key: None,
+                        ..login
+                    };
+                    inbox.pending.insert(handle, (login, redirected, None));
+                }
+                _ => {
+                    warn!("Rejecting {:?} on [{}]: not admitted", name, handle);
+                    queue.push(
+                        handle,
+                        ClientMessage::LoginRejected(LoginRejection::Restricted),
+                    );
+                }
+            }
+            continue;
+        }
+
+        let public_key = match login.public_key {
+            Some(key) if formats.encryption => Some(formats.accept_key_exchange(handle, key)),
+            // keyed already, before the credential came
+            None if formats.encrypted(handle) => None,
+            None if formats.encryption => {
+                warn!("Unencrypted login on [{}]", handle);
+                queue.push(
+                    handle,
+                    ClientMessage::LoginRejected(LoginRejection::EncryptionRequired),
+                );
+                continue;
+            }
+            _ => None,
+        };
+
+        // the owner token never travels in the clear either; the login waits for it
+        if login.resume && owner.is_none() {
+            queue.push_critical(handle, ClientMessage::OwnerRequired { public_key });
+            let login = LoginRequest {
+                public_key: None,
+                ..login
+            };
+            inbox
+                .pending
+                .insert(handle, (login, redirected, credential));
+            continue;
+        }
+
+        // the owner of an agar restored from a snapshot, or of one whose connection dropped,
+        // is back
+        let reclaim = owner.and_then(|owner| {
+            parked
+                .iter()
+                .find(|(entity, token, ..)| token.0 == owner && !claimed.contains(entity))
+        });
+        let low_bandwidth = bandwidth::granted(&login, &server_config);
+        if let Some((entity, _, id, instance, _, dropped, was_low)) = reclaim {
+            // a parked agar doesn't hold a seat, not even for a redirected owner
+            if players >= server_config.max_players {
+                warn!("Server full; not reclaiming agar {} for [{}]", id.0, handle);
+                queue.push(
+                    handle,
+                    ClientMessage::LoginRejected(LoginRejection::ServerFull),
+                );
+                continue;
+            }
+            info!("Reclaiming agar {} for [{}]", id.0, handle);
+            claimed.insert(entity);
+            instances.pin(handle, *instance);
+            commands.insert_one(entity, NetworkHandle::new(handle));
+            if dropped.is_some() {
+                commands.remove_one::<Parked>(entity);
+            }
+            // the client may have changed its mind since the agar was parked
+            if low_bandwidth && was_low.is_none() {
+                commands.insert_one(entity, LowBandwidth);
+            } else if !low_bandwidth && was_low.is_some() {
+                commands.remove_one::<LowBandwidth>(entity);
+            }
+            players += 1;
+
+            queue.push_critical(
+                handle,
+                ClientMessage::LoginAck {
+                    id: id.0,
+                    format,
+                    rules: rules.clone(),
+                    public_key,
+                    tick_rate: server_config.tick_rate,
+                    low_bandwidth,
+                },
+            );
+            continue;
+        }
+
+        let instance = instances.least_crowded();
+        let normalized = names::normalize(&name);
+        if !normalized.is_empty() && !taken.insert((instance, normalized)) {
+            warn!(
+                "Name {:?} is taken in {:?}; rejecting [{}]",
+                name, instance, handle
+            );
+            queue.push(
+                handle,
+                ClientMessage::LoginRejected(LoginRejection::BadName),
+            );
+            continue;
+        }
+        instances.pin(handle, instance);
+
+        let color = match login.color {
+            Some(color) => color % AGAR_COLORS.len() as u8,
+            None => least_worn(&worn, instance),
+        };
+        *worn.entry((instance, color)).or_default() += 1;
+        let skin = login.skin.filter(|&skin| skin < SKIN_COUNT);
+
+        // owner tokens must not be predictable from the seed
+        let owner = rand::thread_rng().gen();
+        let rng = &mut simulation.rng;
+        let vel_x = rng.gen_range(-0.5..=0.5);
+        let vel_y = rng.gen_range(-0.5..=0.5);
+        let position = simulation.random_point(&rules, rules.agar_init_size, true);
+        info!(
+            "Spawning {:?} {}x{} {}/{} in {:?}",
+            name, position.x, position.y, vel_x, vel_y, instance
+        );
+
+        let entity = commands
+            .spawn((
+                Agar {
+                    color,
+                    skin,
+                    ..Agar::with_size(rules.agar_init_size)
+                },
+                PlayerName(name),
+                OwnerToken(owner),
+                Score(rules.agar_init_size),
+                NetworkHandle::new(handle),
+                instance,
+                Transform::from_translation(position.extend(1.0)),
+                TransformHistory::default(),
+                ReplicationState::default(),
+            ))
+            .current_entity()
+            .unwrap();
+        let id = ids.allocate(entity);
+        commands.insert_one(entity, NetworkId(id));
+        if low_bandwidth {
+            commands.insert_one(entity, LowBandwidth);
+        }
+        players += 1;
+
+        info!("Send ack to {} ({:?})", id, format);
+        queue.push_critical(
+            handle,
+            ClientMessage::LoginAck {
+                id,
+                format,
+                rules: rules.clone(),
+                public_key,
+                tick_rate: server_config.tick_rate,
+                low_bandwidth,
+            },
+        );
+        inbox.owner_tokens.push((handle, owner));
+    }
+}
+
+// the first of the colors fewest players in the world wear
+fn least_worn(worn: &HashMap<(Instance, u8), usize>, instance: Instance) -> u8 {
+    (0..AGAR_COLORS.len() as u8)
+        .min_by_key(|&color| worn.get(&(instance, color)).copied().unwrap_or(0))
+        .unwrap_or(0)
+}
+
+fn disconnect_system(
+    commands: &mut Commands,
+    time: Res<Time>,
+    mut reader: Local<EventReader<NetworkEvent>>,
+    network_events: Res<Events<NetworkEvent>>,
+    mut leaving: ResMut<Leaving>,
+    mut agars: Query<(Entity, &NetworkId, &NetworkHandle, &mut Agar)>,
+) {
+    let now = time.seconds_since_startup();
+    for event in reader.iter(&network_events) {
+        let handle = match event {
+            NetworkEvent::Disconnected(handle) => *handle,
+            _ => continue,
+        };
+        let span = connection_span(handle, None);
+        let _enter = span.enter();
+        info!("Client [{}] disconnected", handle);
+
+        // kept for the player to reconnect to; `parked_system` removes it otherwise
+        for (entity, id, owner, mut agar) in agars.iter_mut() {
+            if owner.id != handle {
+                continue;
+            }
+            info!("Parking agar {} of [{}]", id.0, handle);
+            agar.velocity = neutral_cursor();
+            commands.remove_one::<NetworkHandle>(entity);
+            commands.insert_one(entity, Parked(now));
+        }
+
+        // parked above, so only the connection's entries are left to drop
+        leaving.drop_player(handle);
+    }
+}
+
+fn handle_messages(
     mut net: ResMut<NetworkResource>,
     mut balls: Query<(&mut Agar, &NetworkHandle)>,
-    feed_updates: Res<FeedUpdates>,
+    mut feed_updates: ResMut<PerInstance<FeedUpdates>>,
+    time: Res<Time>,
+    broadcasts: Res<PerInstance<NetworkBroadcast>>,
+    instances: Res<Instances>,
+    mut latency: ResMut<ClientLatency>,
+    mut inbox: ResMut<Inbox>,
+    mut queue: ResMut<SendQueue>,
+    mut warnings: ResMut<Events<NetworkWarning>>,
+    mut formats: ResMut<WireFormats>,
+    mut states: ResMut<ConnectionStates>,
+    config: Res<NetworkConfig>,
+    names: Query<(&NetworkHandle, &PlayerName)>,
+    mut flood: ResMut<FloodGuard>,
 ) {
-    let mut acks = vec![];
+    let _timer = SystemTimer::start("handle_messages");
+    let now = time.seconds_since_startup();
+    let names: HashMap<_, _> = names
+        .iter()
+        .map(|(handle, name)| (handle.id, name.0.as_str()))
+        .collect();
     let mut feeds = vec![];
+    let mut time_syncs = vec![];
 
     for (handle, connection) in net.connections.iter_mut() {
-        let channels = connection.channels().unwrap();
+        let span = connection_span(*handle, names.get(handle).copied());
+        let _enter = span.enter();
 
-        while let Some(client_message) = channels.recv::<ClientMessage>() {
+        let channels = match connection.channels() {
+            Some(channels) => channels,
+            None => {
+                debug!("Channels for [{}] are not ready", handle);
+                warnings.send(NetworkWarning::ChannelsUnavailable(*handle));
+                continue;
+            }
+        };
+
+        while let Some(client_message) = recv_wire::<ClientMessage>(channels, &mut formats, *handle)
+        {
+            if !flood.admit(*handle, now) {
+                continue;
+            }
+            let client_message = match client_message {
+                Ok(client_message) => client_message,
+                Err(err) => {
+                    warn!("Malformed ClientMessage on [{}]: {}", handle, err);
+                    flood.malformed(*handle, now);
+                    continue;
+                }
+            };
+            crash::remember(*handle, &client_message);
+            states.heard(*handle, now);
             debug!(
                 "ClientMessage received on [{}]: {:?}",
-                handle, client_message
+                handle,
+                crash::redacted(&client_message)
             );
             match client_message {
-                ClientMessage::Login => {
-                    let mut rng = rand::thread_rng();
-                    let vel_x = rng.gen_range(-0.5..=0.5);
-                    let vel_y = rng.gen_range(-0.5..=0.5);
-                    let pos_x = rng.gen_range(0.0..WORLD_WIDTH);
-                    let pos_y = rng.gen_range(0.0..WORLD_HEIGHT);
-                    info!("Spawning {}x{} {}/{}", pos_x, pos_y, vel_x, vel_y);
-
-                    let entity = commands
-                        .spawn((
-                            Agar::new(),
-                            NetworkHandle::new(*handle),
-                            Transform::from_translation(Vec3::new(pos_x, pos_y, 1.0)),
-                        ))
-                        .current_entity()
-                        .unwrap();
-
-                    acks.push((*handle, entity.id()));
+                ClientMessage::Login(login) => {
+                    inbox.logins.push((*handle, login));
+                }
+                ClientMessage::Chat(text) => {
+                    inbox.chats.push((*handle, text, false));
+                }
+                ClientMessage::TeamChat(text) => {
+                    inbox.chats.push((*handle, text, true));
+                }
+                ClientMessage::Respawn => {
+                    inbox.respawns.push(*handle);
+                }
+                ClientMessage::Logout => {
+                    inbox.logouts.push(*handle);
+                }
+                ClientMessage::Credentials(credential) => {
+                    inbox.credentials.push((*handle, credential));
+                }
+                ClientMessage::Owner(owner) => {
+                    inbox.owners.push((*handle, owner));
                 }
                 ClientMessage::Input(vel) => {
+                    inbox.inputs.push((*handle, vel));
+                    if !vel.x.is_finite() || !vel.y.is_finite() {
+                        continue;
+                    }
+                    // a client on a bigger window than the protocol's must not steer faster
+                    let vel = clamp_cursor(vel);
                     for (mut agar, hd) in balls.iter_mut() {
                         if hd.id == *handle {
                             agar.velocity = vel;
                         }
                     }
                 }
-                ClientMessage::FeedRequest(update_id) => {
-                    let updates = if update_id == 0 {
-                        feed_updates.snapshot()
-                    } else {
-                        feed_updates.updates(update_id as usize)
-                    };
-
-                    feeds.push((*handle, updates.to_vec()));
+                ClientMessage::FeedRequest(revision) => {
+                    if let Some(instance) = instances.get(*handle) {
+                        feed_updates[instance].request(*handle, revision);
+                    }
+                }
+                ClientMessage::TimeSyncRequest(client_time) => {
+                    time_syncs.push((*handle, client_time));
+                }
+                ClientMessage::Subscribe(region) => {
+                    inbox.subscriptions.push((*handle, region, true));
+                }
+                ClientMessage::Unsubscribe(region) => {
+                    inbox.subscriptions.push((*handle, region, false));
+                }
+                ClientMessage::Pong(sent) => {
+                    if !latency.pong(*handle, sent, now) {
+                        debug!("Pong for no ping from [{}]: {}", handle, sent);
+                        continue;
+                    }
+                    if let Some(rtt) = latency.rtt(*handle) {
+                        states.rtt(*handle, rtt);
+                    }
                 }
                 _ => {}
             }
         }
 
-        while let Some(_state_message) = channels.recv::<GameStateMessage>() {
+        while let Some(_state_message) = channels.recv::<WirePacket<GameStateMessage>>() {
             error!("GameStateMessage received on [{}]", handle);
         }
     }
 
-    for (handle, id) in acks {
-        info!("Send ack to {}", id);
+    let limit = config.profile.reliable.max_message_len;
+    for (instance, feed_updates) in feed_updates.iter_mut() {
+        feed_updates.trim(&instances.handles(instance));
+        for (handle, revision) in feed_updates.due(now) {
+            feeds.push((handle, feed_updates.response(revision, limit)));
+        }
+    }
+
+    for (handle, response) in feeds {
+        game_event!("Send feeds to client {}", handle);
 
-        match net.send_message(handle, ClientMessage::LoginAck(id)) {
-            Ok(Some(msg)) => error!("unable to send login message: {:?}", msg),
-            Err(err) => error!("unable to send login message: {}", err),
-            _ => {}
+        for message in response {
+            queue.push(handle, message);
         }
     }
 
-    for (handle, feeds) in feeds {
-        info!("Send feeds to client {}", handle);
+    for (handle, client_time) in time_syncs {
+        let message = ClientMessage::TimeSyncResponse {
+            client_time,
+            server_time: time.seconds_since_startup(),
+            server_frame: instances
+                .get(handle)
+                .map_or(0, |instance| broadcasts[instance].frame),
+        };
+
+        queue.push(handle, message);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn spawn(updates: &mut FeedUpdates, id: EntityId) {
+        updates.spawn(FeedUpdateSpawn {
+            id,
+            color: FeedColor::Red,
+            translation: Vec3::zero(),
+        });
+    }
 
-        match net.send_message(handle, ClientMessage::FeedResponse(feeds)) {
-            Ok(Some(msg)) => error!("unable to send feeds to client: {:?}", msg),
-            Err(err) => error!("unable to send feeds to client: {}", err),
-            _ => {}
+    // spawned ids, then despawned ones
+    fn ids(updates: &[FeedUpdate]) -> (Vec<EntityId>, Vec<EntityId>) {
+        let mut spawned = vec![];
+        let mut despawned = vec![];
+        for update in updates {
+            match update {
+                FeedUpdate::Spawn(spawn) => spawned.push(spawn.id),
+                FeedUpdate::Despawn(id) => despawned.push(*id),
+            }
         }
+        spawned.sort_unstable();
+        despawned.sort_unstable();
+        (spawned, despawned)
+    }
+
+    #[test]
+    fn first_request_gets_baseline() {
+        let mut updates = FeedUpdates::default();
+        spawn(&mut updates, 1);
+        spawn(&mut updates, 2);
+        updates.despawn(1);
+        let (baseline, since) = updates.since(0);
+        assert!(baseline);
+        assert_eq!(ids(&since), (vec![2], vec![]));
+    }
+
+    #[test]
+    fn delta_after_ack() {
+        let mut updates = FeedUpdates::default();
+        spawn(&mut updates, 1);
+        spawn(&mut updates, 2);
+        updates.ack(7, 2);
+        spawn(&mut updates, 3);
+        updates.despawn(1);
+        let (baseline, since) = updates.since(2);
+        assert!(!baseline);
+        assert_eq!(ids(&since), (vec![3], vec![1]));
+    }
+
+    #[test]
+    fn delta_drops_feeds_spawned_and_eaten() {
+        let mut updates = FeedUpdates::default();
+        spawn(&mut updates, 1);
+        spawn(&mut updates, 2);
+        updates.despawn(2);
+        let (baseline, since) = updates.since(1);
+        assert!(!baseline);
+        assert_eq!(ids(&since), (vec![], vec![]));
+    }
+
+    #[test]
+    fn up_to_date_gets_nothing() {
+        let mut updates = FeedUpdates::default();
+        spawn(&mut updates, 1);
+        updates.ack(7, 1);
+        updates.trim(&[7]);
+        let (baseline, since) = updates.since(1);
+        assert!(!baseline);
+        assert!(since.is_empty());
+    }
+
+    #[test]
+    fn delta_after_trim_falls_back_to_baseline() {
+        let mut updates = FeedUpdates::default();
+        spawn(&mut updates, 1);
+        spawn(&mut updates, 2);
+        spawn(&mut updates, 3);
+        updates.ack(7, 1);
+        updates.ack(8, 3);
+        // 7 is gone, so nobody holds the log back any more
+        updates.trim(&[8]);
+        spawn(&mut updates, 4);
+
+        let (baseline, since) = updates.since(1);
+        assert!(baseline);
+        assert_eq!(ids(&since), (vec![1, 2, 3, 4], vec![]));
+        let (baseline, since) = updates.since(3);
+        assert!(!baseline);
+        assert_eq!(ids(&since), (vec![4], vec![]));
+    }
+
+    #[test]
+    fn trim_keeps_what_the_slowest_needs() {
+        let mut updates = FeedUpdates::default();
+        spawn(&mut updates, 1);
+        spawn(&mut updates, 2);
+        spawn(&mut updates, 3);
+        updates.ack(7, 1);
+        updates.ack(8, 3);
+        updates.trim(&[7, 8]);
+        let (baseline, since) = updates.since(1);
+        assert!(!baseline);
+        assert_eq!(ids(&since), (vec![2, 3], vec![]));
+    }
+
+    #[test]
+    fn ack_capped_at_revision() {
+        let mut updates = FeedUpdates::default();
+        spawn(&mut updates, 1);
+        updates.ack(7, 100);
+        assert_eq!(updates.acked.get(&7), Some(&1));
+    }
+
+    #[test]
+    fn requests_answered_at_interval() {
+        let mut updates = FeedUpdates::default();
+        updates.request(7, 0);
+        assert_eq!(updates.due(10.0), vec![(7, 0)]);
+        updates.request(7, 0);
+        assert!(updates.due(10.0 + FEED_REQUEST_INTERVAL / 2.0).is_empty());
+        assert_eq!(
+            updates.due(10.0 + FEED_REQUEST_INTERVAL * 2.0),
+            vec![(7, 0)]
+        );
+        assert!(updates.due(20.0).is_empty());
     }
 }
@@ -0,0 +1,61 @@
+use crate::{instance::PerInstance, replay::Recorder, Simulation};
+use agarlib::*;
+use anyhow::{anyhow, Context, Result};
+use bevy::prelude::*;
+
+pub fn load(path: &str) -> Result<GameMap> {
+    let text = std::fs::read_to_string(path).with_context(|| format!("reading {}", path))?;
+    let map: GameMap = toml::from_str(&text).with_context(|| format!("parsing {}", path))?;
+    map.check(MAX_MESSAGE_LEN - WIRE_OVERHEAD)
+        .map_err(|err| anyhow!("{}: {}", path, err))?;
+    Ok(map)
+}
+
+// players get the layout right after their `LoginAck`, resumed sessions too
+pub fn map_sync_system(
+    mut recorded: Local<bool>,
+    simulation: Res<Simulation>,
+    broadcasts: Res<PerInstance<NetworkBroadcast>>,
+    mut queue: ResMut<SendQueue>,
+    mut recorder: ResMut<Recorder>,
+    joined: Query<&NetworkHandle, Added<NetworkHandle>>,
+) {
+    if !*recorded {
+        *recorded = true;
+        for (instance, _) in broadcasts.iter() {
+            recorder.record(instance, ClientMessage::Map(simulation.map.clone()));
+        }
+    }
+
+    for handle in joined.iter() {
+        queue.push_critical(handle.id, ClientMessage::Map(simulation.map.clone()));
+    }
+}
+
+pub fn virus_system(
+    simulation: Res<Simulation>,
+    rules: Res<GameRules>,
+    mut agars: Query<(&mut Agar, &mut Transform)>,
+) {
+    for (mut agar, mut transform) in agars.iter_mut() {
+        for virus in &simulation.map.viruses {
+            let center = Vec3::new(virus.x, virus.y, transform.translation.z);
+            if transform.translation.distance(center) >= agar.size
+                || !rules.can_eat(agar.size, virus.size)
+            {
+                continue;
+            }
+
+            game_event!("Agar of size {} popped by a virus", agar.size);
+            let size = (agar.size / 2.0).max(rules.agar_init_size);
+            agar.resize(size);
+            // out of reach, so the next step doesn't pop it again
+            let area = Shape::Circle {
+                x: virus.x,
+                y: virus.y,
+                radius: virus.size,
+            };
+            transform.translation = rules.clamp(area.push_out(transform.translation, agar.size));
+        }
+    }
+}
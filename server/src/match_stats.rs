@@ -0,0 +1,176 @@
+use crate::{instance::Instance, shutdown::ServerStopping, PlayerName};
+use agarlib::*;
+use anyhow::{Context, Result};
+use bevy::prelude::*;
+use serde::Serialize;
+use std::{
+    collections::{HashMap, HashSet},
+    fs::{File, OpenOptions},
+    io::{BufWriter, Write},
+    time::{SystemTime, UNIX_EPOCH},
+};
+
+#[derive(Serialize, Debug, Clone, Copy)]
+#[serde(rename_all = "snake_case")]
+enum Ending {
+    Eaten,
+    // disconnected, kicked or removed by a reset
+    Left,
+    Shutdown,
+}
+
+// one json line per finished game
+#[derive(Serialize)]
+struct MatchRecord<'a> {
+    player: &'a str,
+    world: usize,
+    // unix seconds
+    ended_at: u64,
+    duration: f64,
+    peak_mass: f32,
+    kills: u32,
+    pellets: u32,
+    ending: Ending,
+}
+
+struct Playing {
+    name: String,
+    world: usize,
+    started: f64,
+    peak: f32,
+    kills: u32,
+    pellets: u32,
+    eaten: bool,
+}
+
+pub struct MatchStats {
+    writer: Option<BufWriter<File>>,
+    playing: HashMap<Entity, Playing>,
+}
+
+impl MatchStats {
+    pub fn disabled() -> Self {
+        Self {
+            writer: None,
+            playing: HashMap::new(),
+        }
+    }
+
+    // appends, so restarts keep adding to the same file
+    pub fn open(path: &str) -> Result<Self> {
+        let file = OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(path)
+            .with_context(|| format!("opening {}", path))?;
+
+        Ok(Self {
+            writer: Some(BufWriter::new(file)),
+            ..Self::disabled()
+        })
+    }
+
+    pub fn pellet(&mut self, agar: Entity) {
+        if let Some(playing) = self.playing.get_mut(&agar) {
+            playing.pellets += 1;
+        }
+    }
+
+    pub fn kill(&mut self, eater: Entity, victim: Entity) {
+        if let Some(playing) = self.playing.get_mut(&eater) {
+            playing.kills += 1;
+        }
+        if let Some(playing) = self.playing.get_mut(&victim) {
+            playing.eaten = true;
+        }
+    }
+
+    fn write(&mut self, playing: Playing, now: f64, ending: Ending) -> Result<()> {
+        let writer = match &mut self.writer {
+            Some(writer) => writer,
+            None => return Ok(()),
+        };
+        let record = MatchRecord {
+            player: &playing.name,
+            world: playing.world,
+            ended_at: SystemTime::now()
+                .duration_since(UNIX_EPOCH)
+                .map_or(0, |elapsed| elapsed.as_secs()),
+            duration: now - playing.started,
+            peak_mass: playing.peak,
+            kills: playing.kills,
+            pellets: playing.pellets,
+            ending,
+        };
+        serde_json::to_writer(&mut *writer, &record)?;
+        writer.write_all(b"\n")?;
+        Ok(())
+    }
+}
+
+// follows players from join to death or disconnect, like the storage does for scores
+pub fn match_stats_system(
+    time: Res<Time>,
+    mut stats: ResMut<MatchStats>,
+    mut reader: Local<EventReader<ServerStopping>>,
+    stopping: Res<Events<ServerStopping>>,
+    players: Query<(Entity, &PlayerName, &Agar, &Instance), With<NetworkHandle>>,
+) {
+    let stopping = reader.iter(&stopping).next().is_some();
+    if stats.writer.is_none() {
+        return;
+    }
+    let now = time.seconds_since_startup();
+    let stats = &mut *stats;
+
+    let mut live = HashSet::new();
+    for (entity, name, agar, instance) in players.iter() {
+        live.insert(entity);
+        let playing = stats.playing.entry(entity).or_insert_with(|| Playing {
+            name: name.0.clone(),
+            world: instance.0,
+            started: now,
+            peak: agar.size,
+            kills: 0,
+            pellets: 0,
+            eaten: false,
+        });
+        playing.peak = playing.peak.max(agar.size);
+    }
+
+    let over: Vec<_> = stats
+        .playing
+        .keys()
+        .filter(|entity| stopping || !live.contains(*entity))
+        .copied()
+        .collect();
+    if over.is_empty() {
+        return;
+    }
+
+    for entity in over {
+        let playing = match stats.playing.remove(&entity) {
+            Some(playing) => playing,
+            None => continue,
+        };
+        let ending = match (playing.eaten, live.contains(&entity)) {
+            (true, _) => Ending::Eaten,
+            (false, true) => Ending::Shutdown,
+            (false, false) => Ending::Left,
+        };
+        if let Err(err) = stats.write(playing, now, ending) {
+            error!("Unable to write match stats; export stopped: {:#}", err);
+            stats.writer = None;
+            return;
+        }
+    }
+
+    let flushed = match &mut stats.writer {
+        Some(writer) => writer.flush(),
+        None => return,
+    };
+    if let Err(err) = flushed {
+        error!("Unable to write match stats; export stopped: {}", err);
+        stats.writer = None;
+    }
+}
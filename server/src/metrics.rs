@@ -0,0 +1,144 @@
+use agarlib::*;
+use anyhow::Result;
+use bevy::prelude::*;
+use prometheus::{Histogram, HistogramOpts, IntCounter, IntCounterVec, IntGauge, Opts, Registry};
+use std::{
+    collections::HashMap,
+    time::{Duration, Instant},
+};
+
+pub struct Metrics {
+    registry: Registry,
+    // `None` if they couldn't be registered; the server runs on without them
+    counters: Option<Counters>,
+    // `NetworkStats` totals already added to the counters
+    reported_bytes: HashMap<&'static str, u64>,
+    reported_dropped: u64,
+    tick_start: Option<Instant>,
+}
+
+struct Counters {
+    players: IntGauge,
+    entities: IntGauge,
+    tick_duration: Histogram,
+    bytes_sent: IntCounterVec,
+    messages_dropped: IntCounter,
+    slow_ticks: IntCounter,
+}
+
+impl Counters {
+    fn register(registry: &Registry) -> Result<Self> {
+        let players = IntGauge::new("players", "Connected players")?;
+        let entities = IntGauge::new("entities", "Live entities")?;
+        let tick_duration = Histogram::with_opts(
+            HistogramOpts::new("tick_duration_seconds", "Time spent in a server tick")
+                .buckets(vec![0.001, 0.002, 0.005, 0.01, 0.02, 0.033, 0.05, 0.1]),
+        )?;
+        let bytes_sent = IntCounterVec::new(
+            Opts::new("bytes_sent_total", "Bytes sent per message type"),
+            &["message"],
+        )?;
+        let messages_dropped = IntCounter::new(
+            "messages_dropped_total",
+            "Messages given up on or discarded",
+        )?;
+        let slow_ticks = IntCounter::new("slow_ticks_total", "Ticks over the time budget")?;
+
+        registry.register(Box::new(players.clone()))?;
+        registry.register(Box::new(entities.clone()))?;
+        registry.register(Box::new(tick_duration.clone()))?;
+        registry.register(Box::new(bytes_sent.clone()))?;
+        registry.register(Box::new(messages_dropped.clone()))?;
+        registry.register(Box::new(slow_ticks.clone()))?;
+
+        Ok(Self {
+            players,
+            entities,
+            tick_duration,
+            bytes_sent,
+            messages_dropped,
+            slow_ticks,
+        })
+    }
+}
+
+impl Metrics {
+    // logs and carries on without metrics when they can't be registered
+    pub fn new() -> Self {
+        let registry = Registry::new_custom(Some("agar".to_string()), None)
+            .unwrap_or_else(|_| Registry::new());
+        let counters = match Counters::register(&registry) {
+            Ok(counters) => Some(counters),
+            Err(err) => {
+                error!("Unable to register metrics: {:#}", err);
+                None
+            }
+        };
+
+        Self {
+            registry,
+            counters,
+            reported_bytes: HashMap::new(),
+            reported_dropped: 0,
+            tick_start: None,
+        }
+    }
+
+    pub fn registry(&self) -> Registry {
+        self.registry.clone()
+    }
+
+    pub fn slow_tick(&self) {
+        if let Some(counters) = &self.counters {
+            counters.slow_ticks.inc();
+        }
+    }
+
+    pub fn tick_started(&self) -> Option<Instant> {
+        self.tick_start
+    }
+
+    // records the tick that `tick_start_system` began; `None` if it didn't
+    pub fn end_tick(&mut self) -> Option<Duration> {
+        let elapsed = self.tick_start.take()?.elapsed();
+        if let Some(counters) = &self.counters {
+            counters.tick_duration.observe(elapsed.as_secs_f64());
+        }
+        Some(elapsed)
+    }
+}
+
+// first thing in a tick; the tick budget ends it after everything else
+pub fn tick_start_system(mut metrics: ResMut<Metrics>) {
+    metrics.tick_start = Some(Instant::now());
+}
+
+pub fn metrics_system(
+    mut metrics: ResMut<Metrics>,
+    stats: Res<NetworkStats>,
+    players: Query<&NetworkHandle>,
+    entities: Query<Entity>,
+) {
+    let metrics = &mut *metrics;
+    let counters = match &metrics.counters {
+        Some(counters) => counters,
+        None => return,
+    };
+
+    counters.players.set(players.iter().count() as i64);
+    counters.entities.set(entities.iter().count() as i64);
+
+    for (message, bytes) in &stats.bytes_sent {
+        let reported = metrics.reported_bytes.entry(*message).or_default();
+        counters
+            .bytes_sent
+            .with_label_values(&[*message])
+            .inc_by(bytes - *reported);
+        *reported = *bytes;
+    }
+
+    counters
+        .messages_dropped
+        .inc_by(stats.dropped - metrics.reported_dropped);
+    metrics.reported_dropped = stats.dropped;
+}
@@ -0,0 +1,140 @@
+use crate::{
+    config::ServerConfig,
+    instance::{Instance, Instances, PerInstance},
+    replay::Recorder,
+    PlayerName,
+};
+use agarlib::*;
+use bevy::prelude::*;
+use std::collections::{HashMap, HashSet};
+
+// spaces and a little punctuation are fine inside a name; the login trims those around it
+fn allowed(c: char) -> bool {
+    c.is_alphanumeric() || matches!(c, ' ' | '_' | '-' | '.')
+}
+
+// folds case and drops separators, so "B.a-d" still matches "bad"
+pub fn normalize(name: &str) -> String {
+    name.chars()
+        .filter(|c| c.is_alphanumeric())
+        .flat_map(char::to_lowercase)
+        .collect()
+}
+
+// an empty name plays anonymously; anything else has to pass the filter. takes the name
+// trimmed
+pub fn check(name: &str, config: &ServerConfig) -> Result<(), &'static str> {
+    if name.is_empty() {
+        return Ok(());
+    }
+    if name.chars().count() > config.name_max_len {
+        return Err("too long");
+    }
+    if !name.chars().all(allowed) {
+        return Err("disallowed character");
+    }
+
+    let normalized = normalize(name);
+    if normalized.is_empty() {
+        return Err("no letters or digits");
+    }
+    if config
+        .banned_words
+        .iter()
+        .map(|word| normalize(word))
+        .any(|word| !word.is_empty() && normalized.contains(&word))
+    {
+        return Err("banned word");
+    }
+
+    Ok(())
+}
+
+// the key of a whitelisted name, from the config or the admin's additions
+pub fn whitelist_key<'a>(name: &str, config: &'a ServerConfig) -> Option<&'a str> {
+    let name = normalize(name);
+    if name.is_empty() {
+        return None;
+    }
+    config
+        .granted
+        .iter()
+        .chain(config.whitelist.iter())
+        .find(|(entry, _)| normalize(entry) == name)
+        .map(|(_, key)| key.as_str())
+}
+
+// open servers admit everyone; private ones only holders of the password, or of the key
+// whitelisted with their name
+pub fn admitted(name: &str, credential: Option<&str>, config: &ServerConfig) -> bool {
+    if !config.private() {
+        return true;
+    }
+    let credential = match credential {
+        Some(credential) if !credential.is_empty() => credential,
+        _ => return false,
+    };
+
+    // both compared, so the time taken doesn't tell which one came close
+    let password = secret_eq(credential.as_bytes(), config.password.as_bytes());
+    let key = whitelist_key(name, config).map_or(false, |key| {
+        secret_eq(credential.as_bytes(), key.as_bytes())
+    });
+    password || key
+}
+
+// every world's names for the labels over cells, parked agars' included; anonymous players
+// aren't listed
+pub fn name_sync_system(
+    time: Res<Time>,
+    mut next: Local<f64>,
+    mut sent: Local<HashMap<Instance, HashMap<EntityId, String>>>,
+    mut served: Local<HashSet<u32>>,
+    instances: Res<Instances>,
+    broadcasts: Res<PerInstance<NetworkBroadcast>>,
+    mut queue: ResMut<SendQueue>,
+    mut recorder: ResMut<Recorder>,
+    config: Res<ServerConfig>,
+    players: Query<(&NetworkId, &PlayerName, &Instance)>,
+) {
+    let now = time.seconds_since_startup();
+    if now < *next {
+        return;
+    }
+    *next = now + NAME_SYNC_INTERVAL;
+
+    let mut worlds: HashMap<Instance, HashMap<EntityId, String>> = HashMap::new();
+    for (id, name, instance) in players.iter() {
+        if name.0.is_empty() {
+            continue;
+        }
+        worlds
+            .entry(*instance)
+            .or_default()
+            .insert(id.0, name.0.clone());
+    }
+
+    // the whole list goes out on any change, in as many messages as it takes
+    let limit = config.profile.reliable.max_message_len;
+    let mut connected = HashSet::new();
+    for (instance, _) in broadcasts.iter() {
+        let names = worlds.remove(&instance).unwrap_or_default();
+        let changed = sent.get(&instance) != Some(&names);
+        let messages = split_names(names.clone(), limit);
+        for handle in instances.handles(instance) {
+            if changed || !served.contains(&handle) {
+                for message in &messages {
+                    queue.push_critical(handle, message.clone());
+                }
+            }
+            connected.insert(handle);
+        }
+        if changed {
+            for message in messages {
+                recorder.record(instance, message);
+            }
+            sent.insert(instance, names);
+        }
+    }
+    *served = connected;
+}
@@ -0,0 +1,63 @@
+use agarlib::*;
+use bevy::prelude::*;
+
+// consecutive slow ticks before work is shed
+const ENTER_AFTER: u32 = 3;
+// seconds of ticks within budget before shedding stops
+const CALM_TIME: f64 = 5.0;
+
+// load shedding for a server that can't keep up: feed spawning stops and broadcasts go
+// out half as often, instead of every tick taking longer than the one before
+pub struct Overload {
+    active: bool,
+    slow_streak: u32,
+    calm_streak: u32,
+    calm_ticks: u32,
+}
+
+impl Overload {
+    pub fn new(tick_rate: f64) -> Self {
+        Self {
+            active: false,
+            slow_streak: 0,
+            calm_streak: 0,
+            calm_ticks: ticks(CALM_TIME, tick_rate),
+        }
+    }
+
+    pub fn active(&self) -> bool {
+        self.active
+    }
+
+    // after every tick, whether it went over the budget
+    pub fn tick(&mut self, slow: bool) {
+        if slow {
+            self.slow_streak += 1;
+            self.calm_streak = 0;
+            if self.slow_streak >= ENTER_AFTER {
+                self.enter();
+            }
+        } else {
+            self.slow_streak = 0;
+            self.calm_streak += 1;
+            if self.active && self.calm_streak >= self.calm_ticks {
+                info!("Load is back to normal; no longer shedding work");
+                self.active = false;
+            }
+        }
+    }
+
+    // simulation steps were given up to catch up; shed work right away
+    pub fn behind(&mut self, steps: u32) {
+        warn!("Simulation fell behind; skipped {} steps", steps);
+        self.calm_streak = 0;
+        self.enter();
+    }
+
+    fn enter(&mut self) {
+        if !self.active {
+            warn!("Overloaded; pausing feed spawns and halving the broadcast rate");
+            self.active = true;
+        }
+    }
+}
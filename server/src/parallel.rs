@@ -0,0 +1,33 @@
+use bevy::tasks::TaskPool;
+
+// items per task; small batches cost more in scheduling than they win
+pub const BATCH_SIZE: usize = 32;
+
+// maps `items` in batches on the pool; the results keep the order of `items`, so a
+// sequential pass over them decides the same way a plain loop would
+pub fn par_map<I, T, F>(pool: &TaskPool, items: Vec<I>, f: F) -> Vec<T>
+where
+    I: Send,
+    T: Send + 'static,
+    F: Fn(I) -> T + Send + Sync,
+{
+    if items.len() <= BATCH_SIZE {
+        return items.into_iter().map(f).collect();
+    }
+
+    let mut batches = vec![];
+    let mut items = items.into_iter().peekable();
+    while items.peek().is_some() {
+        batches.push(items.by_ref().take(BATCH_SIZE).collect::<Vec<_>>());
+    }
+
+    let f = &f;
+    pool.scope(|scope| {
+        for batch in batches {
+            scope.spawn(async move { batch.into_iter().map(f).collect::<Vec<_>>() });
+        }
+    })
+    .into_iter()
+    .flatten()
+    .collect()
+}
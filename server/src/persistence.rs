@@ -0,0 +1,211 @@
+use crate::{
+    config::ServerConfig,
+    crash,
+    grid::SpatialIndex,
+    instance::{Instance, Instances, PerInstance},
+    resume::Parked,
+    shutdown::ServerStopping,
+    FeedUpdates, NetworkIdAllocator, OwnerToken, PlayerName, ReplicationState, Score,
+    TransformHistory,
+};
+use agarlib::*;
+use anyhow::{anyhow, Context, Result};
+use bevy::prelude::*;
+use serde::{Deserialize, Serialize};
+use std::fs;
+
+// bump when the layout below changes
+const SNAPSHOT_VERSION: u32 = 5;
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct AgarSnapshot {
+    pub id: EntityId,
+    pub owner: u64,
+    pub instance: usize,
+    pub name: String,
+    // the biggest it has been
+    pub score: f32,
+    pub state: AgarUpdate,
+}
+
+// encoded like the wire messages so the same types can be reused
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct WorldSnapshot {
+    pub version: u32,
+    pub agars: Vec<AgarSnapshot>,
+    // one list per instance
+    pub feeds: Vec<Vec<FeedUpdateSpawn>>,
+}
+
+pub fn load(path: &str) -> Result<WorldSnapshot> {
+    let bytes = fs::read(path).with_context(|| format!("reading {}", path))?;
+    let snapshot: WorldSnapshot = WireFormat::Varint
+        .decode(&bytes)
+        .with_context(|| format!("decoding {}", path))?;
+
+    if snapshot.version != SNAPSHOT_VERSION {
+        return Err(anyhow!(
+            "{} has snapshot version {}, expected {}",
+            path,
+            snapshot.version,
+            SNAPSHOT_VERSION
+        ));
+    }
+    Ok(snapshot)
+}
+
+// written next to the target first so a crash mid-write keeps the old snapshot
+fn save(path: &str, snapshot: &WorldSnapshot) -> Result<()> {
+    let bytes = WireFormat::Varint.encode(snapshot)?;
+    let tmp = format!("{}.tmp", path);
+    fs::write(&tmp, bytes).with_context(|| format!("writing {}", tmp))?;
+    fs::rename(&tmp, path).with_context(|| format!("replacing {}", path))?;
+    Ok(())
+}
+
+pub struct Persistence {
+    restore: Option<WorldSnapshot>,
+    next_save: f64,
+}
+
+impl Persistence {
+    pub fn new(restore: Option<WorldSnapshot>, interval: f64) -> Self {
+        Self {
+            restore,
+            next_save: interval,
+        }
+    }
+}
+
+// restored agars have no `NetworkHandle`; they are parked as if their owners had just
+// dropped, so they wait out of play for `RESUME_GRACE` and go unless reclaimed
+pub fn restore_system(
+    commands: &mut Commands,
+    mut persistence: ResMut<Persistence>,
+    mut ids: ResMut<NetworkIdAllocator>,
+    mut feed_updates: ResMut<PerInstance<FeedUpdates>>,
+    mut index: ResMut<PerInstance<SpatialIndex>>,
+    instances: Res<Instances>,
+    rules: Res<GameRules>,
+) {
+    let snapshot = match persistence.restore.take() {
+        Some(snapshot) => snapshot,
+        None => return,
+    };
+    info!(
+        "Restoring {} agars and {} instances",
+        snapshot.agars.len(),
+        snapshot.feeds.len()
+    );
+
+    // a snapshot from a server with more instances is folded into the ones we have
+    let instance = |i: usize| Instance(i % instances.count());
+
+    for (i, feeds) in snapshot.feeds.into_iter().enumerate() {
+        let instance = instance(i);
+        for feed in feeds {
+            let translation = rules.clamp(feed.translation);
+            let entity = commands
+                .spawn((
+                    Feed { color: feed.color },
+                    Transform::from_translation(translation),
+                    NetworkId(feed.id),
+                    instance,
+                ))
+                .current_entity()
+                .unwrap();
+            ids.restore(feed.id, entity);
+            index[instance].feeds.insert(entity, translation);
+            feed_updates[instance].spawn(FeedUpdateSpawn {
+                translation,
+                ..feed
+            });
+        }
+    }
+
+    for agar in snapshot.agars {
+        let mut state = agar.state.agar;
+        state.velocity = Vec2::zero();
+
+        let entity = commands
+            .spawn((
+                state,
+                PlayerName(agar.name),
+                OwnerToken(agar.owner),
+                Score(agar.score),
+                Parked(0.0),
+                instance(agar.instance),
+                Transform::from_translation(rules.clamp(agar.state.translation)),
+                TransformHistory::default(),
+                ReplicationState::default(),
+                NetworkId(agar.id),
+            ))
+            .current_entity()
+            .unwrap();
+        ids.restore(agar.id, entity);
+    }
+}
+
+pub fn persistence_system(
+    time: Res<Time>,
+    config: Res<ServerConfig>,
+    mut persistence: ResMut<Persistence>,
+    mut reader: Local<EventReader<ServerStopping>>,
+    stopping: Res<Events<ServerStopping>>,
+    feed_updates: Res<PerInstance<FeedUpdates>>,
+    agars: Query<(
+        &NetworkId,
+        &OwnerToken,
+        &PlayerName,
+        &Score,
+        &Agar,
+        &Transform,
+        &Instance,
+    )>,
+) {
+    let stopping = reader.iter(&stopping).next().is_some();
+    if config.snapshot_path.is_empty() {
+        return;
+    }
+
+    let now = time.seconds_since_startup();
+    let due = config.snapshot_interval > 0.0 && now >= persistence.next_save;
+    if !due && !stopping {
+        return;
+    }
+    persistence.next_save = now + config.snapshot_interval;
+
+    let snapshot = WorldSnapshot {
+        version: SNAPSHOT_VERSION,
+        agars: agars
+            .iter()
+            .map(
+                |(id, owner, name, score, agar, transform, instance)| AgarSnapshot {
+                    id: id.0,
+                    owner: owner.0,
+                    instance: instance.0,
+                    name: name.0.clone(),
+                    score: score.0,
+                    state: AgarUpdate {
+                        agar: agar.clone(),
+                        translation: transform.translation,
+                    },
+                },
+            )
+            .collect(),
+        feeds: feed_updates
+            .iter()
+            .map(|(_, feed_updates)| feed_updates.snapshot.values().cloned().collect())
+            .collect(),
+    };
+
+    crash::remember_world(&snapshot);
+    match save(&config.snapshot_path, &snapshot) {
+        Ok(()) => debug!(
+            "Saved {} agars to {}",
+            snapshot.agars.len(),
+            config.snapshot_path
+        ),
+        Err(err) => error!("Unable to save world snapshot: {:#}", err),
+    }
+}
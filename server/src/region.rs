@@ -0,0 +1,218 @@
+use crate::{config::ServerConfig, ClientLatency, Inbox, Redirector};
+use agarlib::*;
+use anyhow::{anyhow, Result};
+use bevy::prelude::*;
+use bevy_networking_turbulence::NetworkResource;
+use std::{collections::HashMap, net::SocketAddr};
+
+const MAX_REGION_LEN: usize = 16;
+// how long a login waits for a round trip sample before it is let in without one
+const PROBE_TIMEOUT: f64 = 2.0;
+
+// what is known about a connection, for the admin api and matchmaking
+#[derive(Default, Debug, Clone)]
+pub struct ConnectionTag {
+    pub addr: Option<SocketAddr>,
+    // self-reported at login
+    pub region: Option<String>,
+    pub rtt: Option<f64>,
+}
+
+pub struct ConnectionTags {
+    tags: HashMap<u32, ConnectionTag>,
+    region: String,
+    // region to the address of a server there
+    servers: HashMap<String, String>,
+    max_rtt: f64,
+    // logins from another region waiting for their first round trip, with when they came
+    held: Vec<(u32, LoginRequest, f64)>,
+}
+
+impl ConnectionTags {
+    pub fn new(config: &ServerConfig) -> Self {
+        Self {
+            tags: HashMap::new(),
+            region: config.region.clone(),
+            servers: parse_servers(&config.region_servers).unwrap_or_default(),
+            max_rtt: config.region_rtt,
+            held: vec![],
+        }
+    }
+
+    pub fn get(&self, handle: u32) -> Option<&ConnectionTag> {
+        self.tags.get(&handle)
+    }
+
+    // a server in the player's region, unless this one is it or is already close enough
+    fn nearby(&self, region: &str, rtt: f64) -> Option<&str> {
+        if region == self.region || rtt <= self.max_rtt {
+            return None;
+        }
+        self.servers.get(region).map(String::as_str)
+    }
+
+    // there is somewhere to send the player, but no round trip to tell whether to
+    fn undecided(&self, region: &str, rtt: Option<f64>) -> bool {
+        region != self.region && rtt.is_none() && self.servers.contains_key(region)
+    }
+}
+
+// `region=host:port` entries
+pub fn parse_servers(entries: &[String]) -> Result<HashMap<String, String>> {
+    entries
+        .iter()
+        .map(|entry| {
+            let mut parts = entry.splitn(2, '=');
+            let region = parts.next().and_then(normalize);
+            let addr = parts.next().map(str::trim).unwrap_or_default();
+            match region {
+                Some(region) if !addr.is_empty() => Ok((region, addr.to_string())),
+                _ => Err(anyhow!(
+                    "invalid region server {:?}, expected region=addr",
+                    entry
+                )),
+            }
+        })
+        .collect()
+}
+
+fn normalize(region: &str) -> Option<String> {
+    let region = region.trim().to_lowercase();
+    let valid = !region.is_empty()
+        && region.len() <= MAX_REGION_LEN
+        && region
+            .chars()
+            .all(|c| c.is_ascii_alphanumeric() || c == '-' || c == '_');
+    if valid {
+        Some(region)
+    } else {
+        None
+    }
+}
+
+// tags connections and, ahead of the login, steers players to a server in their region
+pub fn region_system(
+    time: Res<Time>,
+    mut net: ResMut<NetworkResource>,
+    mut inbox: ResMut<Inbox>,
+    mut tags: ResMut<ConnectionTags>,
+    mut latency: ResMut<ClientLatency>,
+    formats: Res<WireFormats>,
+    redirector: Res<Redirector>,
+) {
+    let now = time.seconds_since_startup();
+    let tags = &mut *tags;
+    tags.tags
+        .retain(|handle, _| net.connections.contains_key(handle));
+    tags.held
+        .retain(|(handle, _, _)| net.connections.contains_key(handle));
+    for (handle, connection) in net.connections.iter() {
+        let tag = tags.tags.entry(*handle).or_default();
+        tag.addr = connection.remote_address();
+        tag.rtt = latency.rtt(*handle);
+    }
+
+    let held = std::mem::take(&mut tags.held);
+    let logins = std::mem::take(&mut inbox.logins)
+        .into_iter()
+        .map(|(handle, login)| (handle, login, now));
+    for (handle, login, since) in held.into_iter().chain(logins) {
+        let region = login.region.as_deref().and_then(normalize);
+        let rtt = match tags.tags.get_mut(&handle) {
+            Some(tag) => {
+                tag.region = region.clone();
+                tag.rtt
+            }
+            None => None,
+        };
+
+        // redirected clients stay where they were sent
+        let region = region.filter(|_| login.token.is_none());
+        if let Some(region) = &region {
+            if tags.undecided(region, rtt) && now - since < PROBE_TIMEOUT {
+                // probe rather than wait for the next round of pings
+                if since == now {
+                    latency.probe(handle, now);
+                    if let Err(err) =
+                        send_wire(&mut net, &formats, handle, &ClientMessage::Ping(now))
+                    {
+                        warn!("Unable to probe [{}]: {}", handle, err);
+                    }
+                }
+                tags.held.push((handle, login, since));
+                continue;
+            }
+        }
+        // no answer to the probe; let them play here rather than guess
+        let target = match (&region, rtt) {
+            (Some(region), Some(rtt)) => tags.nearby(region, rtt).map(String::from),
+            _ => None,
+        };
+        if let Some(addr) = target {
+            let token = redirector.pass(login.name.trim(), &addr);
+            let message = ControlMessage::Redirect {
+                addr: addr.clone(),
+                token,
+            };
+            match send_wire(&mut net, &formats, handle, &message) {
+                Ok(true) => {
+                    info!(
+                        "Redirecting [{}] to {} in region {}",
+                        handle,
+                        addr,
+                        region.unwrap_or_default()
+                    );
+                    continue;
+                }
+                // let them play here rather than not at all
+                Ok(false) => warn!("Unable to redirect [{}]: channel is full", handle),
+                Err(err) => warn!("Unable to redirect [{}]: {}", handle, err),
+            }
+        }
+
+        inbox.logins.push((handle, login));
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn entries(entries: &[&str]) -> Vec<String> {
+        entries.iter().map(|entry| entry.to_string()).collect()
+    }
+
+    #[test]
+    fn servers_by_region() {
+        let servers = parse_servers(&entries(&[
+            " EU = eu.example.com:7777 ",
+            "us-west=10.0.0.1:7777",
+        ]))
+        .unwrap();
+        assert_eq!(servers.len(), 2);
+        assert_eq!(servers["eu"], "eu.example.com:7777");
+        assert_eq!(servers["us-west"], "10.0.0.1:7777");
+    }
+
+    #[test]
+    fn address_may_hold_equals() {
+        let servers = parse_servers(&entries(&["eu=host?a=b"])).unwrap();
+        assert_eq!(servers["eu"], "host?a=b");
+    }
+
+    #[test]
+    fn malformed_refused() {
+        let long = format!("{}=host:7777", "a".repeat(MAX_REGION_LEN + 1));
+        for entry in &[
+            "eu",
+            "eu=",
+            "eu= ",
+            "=host:7777",
+            "e u=host:7777",
+            "eu!=host:7777",
+        ] {
+            assert!(parse_servers(&entries(&[*entry])).is_err(), "{:?}", entry);
+        }
+        assert!(parse_servers(&[long]).is_err());
+    }
+}
@@ -0,0 +1,93 @@
+use crate::{config::ServerConfig, instance::PerInstance, replay::Recorder};
+use agarlib::*;
+use bevy::prelude::*;
+use bevy_networking_turbulence::NetworkResource;
+use std::time::SystemTime;
+
+// seconds between checks of the config file's modification time
+const WATCH_INTERVAL: f64 = 2.0;
+
+pub struct Reload {
+    modified: Option<SystemTime>,
+    // the file as last read, without the command line; reloads apply what changed since
+    on_disk: ServerConfig,
+    next_check: f64,
+    // set by `POST /admin/reload`
+    pub requested: bool,
+}
+
+impl Reload {
+    pub fn new(config: &ServerConfig) -> Self {
+        let on_disk =
+            ServerConfig::load(Some(config.path.as_str())).unwrap_or_else(|_| config.clone());
+        Self {
+            modified: modified(&config.path),
+            on_disk,
+            next_check: WATCH_INTERVAL,
+            requested: false,
+        }
+    }
+}
+
+fn modified(path: &str) -> Option<SystemTime> {
+    std::fs::metadata(path)
+        .and_then(|metadata| metadata.modified())
+        .ok()
+}
+
+// reloads the config file when it changes, and pushes new rules to every player, also after
+// the admin api resized the world
+pub fn reload_system(
+    time: Res<Time>,
+    mut reload: ResMut<Reload>,
+    mut config: ResMut<ServerConfig>,
+    mut rules: ResMut<GameRules>,
+    net: Res<NetworkResource>,
+    broadcasts: Res<PerInstance<NetworkBroadcast>>,
+    mut queue: ResMut<SendQueue>,
+    mut recorder: ResMut<Recorder>,
+) {
+    let now = time.seconds_since_startup();
+    if now >= reload.next_check {
+        reload.next_check = now + WATCH_INTERVAL;
+        let modified = modified(&config.path);
+        if modified != reload.modified {
+            reload.modified = modified;
+            reload.requested = true;
+        }
+    }
+    if reload.requested {
+        reload.requested = false;
+        apply(&mut config, &mut reload.on_disk);
+    }
+
+    let new_rules = config.rules();
+    if new_rules == *rules {
+        return;
+    }
+    *rules = new_rules;
+
+    for handle in net.connections.keys() {
+        queue.push(*handle, ClientMessage::Rules(rules.clone()));
+    }
+    for (instance, _) in broadcasts.iter() {
+        recorder.record(instance, ClientMessage::Rules(rules.clone()));
+    }
+}
+
+fn apply(config: &mut ServerConfig, on_disk: &mut ServerConfig) {
+    let new = match ServerConfig::load(Some(config.path.as_str())) {
+        Ok(new) => new,
+        Err(err) => {
+            error!("Unable to reload the config: {:#}", err);
+            return;
+        }
+    };
+    let changed = config.apply(on_disk, &new);
+    *on_disk = new;
+    if changed.is_empty() {
+        info!("Reloaded {}; nothing to apply", config.path);
+    } else {
+        info!("Reloaded {}: {}", config.path, changed.join(", "));
+    }
+}
@@ -0,0 +1,138 @@
+use crate::{
+    instance::{Instance, PerInstance},
+    shutdown::ServerStopping,
+    FeedUpdates, ReplicationState, Simulation, FULL_SNAPSHOT_INTERVAL,
+};
+use agarlib::*;
+use anyhow::{Context, Result};
+use bevy::prelude::*;
+use std::{
+    collections::HashMap,
+    fs::File,
+    io::{BufWriter, Write},
+};
+
+pub struct Recorder {
+    writer: Option<ReplayWriter<BufWriter<File>>>,
+    pending: HashMap<Instance, Vec<ReplayEvent>>,
+    feed_revisions: HashMap<Instance, u64>,
+    full_interval: u32,
+}
+
+impl Recorder {
+    pub fn disabled() -> Self {
+        Self {
+            writer: None,
+            pending: HashMap::new(),
+            feed_revisions: HashMap::new(),
+            full_interval: 1,
+        }
+    }
+
+    pub fn open(path: &str, tick_rate: f64, seed: u64, rules: &GameRules) -> Result<Self> {
+        let file = File::create(path).with_context(|| format!("creating {}", path))?;
+        let header = ReplayHeader {
+            version: REPLAY_VERSION,
+            tick_rate,
+            seed,
+            rules: rules.clone(),
+        };
+
+        Ok(Self {
+            writer: Some(ReplayWriter::new(BufWriter::new(file), &header)?),
+            full_interval: ticks(FULL_SNAPSHOT_INTERVAL, tick_rate),
+            ..Self::disabled()
+        })
+    }
+
+    // queued until the end of the tick, after the state of the frame
+    pub fn record(&mut self, instance: Instance, message: ClientMessage) {
+        if self.writer.is_some() {
+            self.pending
+                .entry(instance)
+                .or_default()
+                .push(ReplayEvent::Message(message));
+        }
+    }
+
+    fn finish(&mut self) {
+        if let Some(writer) = self.writer.take() {
+            match writer.finish().and_then(|mut file| Ok(file.flush()?)) {
+                Ok(()) => info!("Replay saved"),
+                Err(err) => error!("Unable to finish replay: {:#}", err),
+            }
+        }
+    }
+}
+
+pub fn record_system(
+    mut recorder: ResMut<Recorder>,
+    broadcasts: Res<PerInstance<NetworkBroadcast>>,
+    feed_updates: Res<PerInstance<FeedUpdates>>,
+    simulation: Res<Simulation>,
+    mut reader: Local<EventReader<ServerStopping>>,
+    stopping: Res<Events<ServerStopping>>,
+    agars: Query<(&NetworkId, &Agar, &Transform, &Instance, &ReplicationState)>,
+) {
+    let stopping = reader.iter(&stopping).next().is_some();
+    if recorder.writer.is_none() {
+        return;
+    }
+    let recorder = &mut *recorder;
+
+    for (instance, broadcast) in broadcasts.iter() {
+        // the broadcast of this tick has already moved on to the next frame
+        let frame = broadcast.frame.saturating_sub(1);
+        let full = frame % recorder.full_interval == 0;
+
+        let state = GameStateMessage {
+            frame,
+            seq: frame,
+            agars: agars
+                .iter()
+                .filter(|(_, _, _, i, state)| **i == instance && (full || state.changed == frame))
+                .map(|(id, agar, transform, ..)| {
+                    (
+                        id.0,
+                        AgarUpdate {
+                            agar: agar.clone(),
+                            translation: transform.translation,
+                        },
+                    )
+                })
+                .collect(),
+            feeds: feed_updates[instance].revision,
+            partial: !full,
+            paused: simulation.paused,
+        };
+        let mut events = vec![ReplayEvent::State(state)];
+
+        let revision = recorder.feed_revisions.entry(instance).or_insert(0);
+        if *revision != feed_updates[instance].revision {
+            let (baseline, updates) = feed_updates[instance].since(*revision);
+            events.push(ReplayEvent::Feeds { baseline, updates });
+            *revision = feed_updates[instance].revision;
+        }
+
+        events.extend(recorder.pending.remove(&instance).unwrap_or_default());
+
+        let frame = ReplayFrame {
+            world: instance.0 as u32,
+            frame,
+            events,
+        };
+        let written = match &mut recorder.writer {
+            Some(writer) => writer.write_frame(&frame),
+            None => return,
+        };
+        if let Err(err) = written {
+            error!("Unable to record replay; recording stopped: {:#}", err);
+            recorder.writer = None;
+            return;
+        }
+    }
+
+    if stopping {
+        recorder.finish();
+    }
+}
@@ -0,0 +1,104 @@
+use crate::{
+    config::ServerConfig, instance::Instances, shutdown::Shutdown, Inbox, NetworkIdAllocator,
+    OwnerToken, PlayerName, ReplicationState, Score, Simulation, TransformHistory,
+};
+use agarlib::*;
+use bevy::prelude::*;
+use bevy_networking_turbulence::NetworkResource;
+use std::collections::HashMap;
+
+// who played on a connection, so they come back as themselves after being eaten
+pub struct Identity {
+    name: String,
+    color: u8,
+    skin: Option<u8>,
+    owner: u64,
+}
+
+// a new cell for players who were eaten and asked to respawn; they keep their name, color, skin
+// and world
+pub fn respawn_system(
+    commands: &mut Commands,
+    mut identities: Local<HashMap<u32, Identity>>,
+    net: Res<NetworkResource>,
+    mut inbox: ResMut<Inbox>,
+    mut ids: ResMut<NetworkIdAllocator>,
+    mut queue: ResMut<SendQueue>,
+    instances: Res<Instances>,
+    mut simulation: ResMut<Simulation>,
+    rules: Res<GameRules>,
+    config: Res<ServerConfig>,
+    shutdown: Res<Shutdown>,
+    joined: Query<(&NetworkHandle, &PlayerName, &Agar, &OwnerToken), Added<NetworkHandle>>,
+    players: Query<&NetworkHandle>,
+) {
+    for (handle, name, agar, owner) in joined.iter() {
+        let identity = Identity {
+            name: name.0.clone(),
+            color: agar.color,
+            skin: agar.skin,
+            owner: owner.0,
+        };
+        identities.insert(handle.id, identity);
+    }
+    identities.retain(|handle, _| net.connections.contains_key(handle));
+
+    let mut count = players.iter().count();
+    for handle in inbox.respawns.drain(..) {
+        if players.iter().any(|player| player.id == handle) {
+            debug!("[{}] asked to respawn while alive", handle);
+            continue;
+        }
+        let (identity, instance) = match (identities.get(&handle), instances.get(handle)) {
+            (Some(identity), Some(instance)) => (identity, instance),
+            _ => {
+                debug!("[{}] asked to respawn before logging in", handle);
+                continue;
+            }
+        };
+        if !shutdown.accepting_logins() {
+            queue.push(
+                handle,
+                ClientMessage::LoginRejected(LoginRejection::ShuttingDown),
+            );
+            continue;
+        }
+        if count >= config.max_players {
+            queue.push(
+                handle,
+                ClientMessage::LoginRejected(LoginRejection::ServerFull),
+            );
+            continue;
+        }
+
+        let position = simulation.random_point(&rules, rules.agar_init_size, true);
+        info!(
+            "Respawning {:?} at {}x{} in {:?}",
+            identity.name, position.x, position.y, instance
+        );
+
+        let entity = commands
+            .spawn((
+                Agar {
+                    color: identity.color,
+                    skin: identity.skin,
+                    ..Agar::with_size(rules.agar_init_size)
+                },
+                PlayerName(identity.name.clone()),
+                OwnerToken(identity.owner),
+                Score(rules.agar_init_size),
+                NetworkHandle::new(handle),
+                instance,
+                Transform::from_translation(position.extend(1.0)),
+                TransformHistory::default(),
+                ReplicationState::default(),
+            ))
+            .current_entity()
+            .unwrap();
+        let id = ids.allocate(entity);
+        commands.insert_one(entity, NetworkId(id));
+        count += 1;
+
+        queue.push_critical(handle, ClientMessage::Respawned(id));
+    }
+}
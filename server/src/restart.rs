@@ -0,0 +1,175 @@
+use crate::{config::ServerConfig, shutdown::Shutdown, Inbox};
+use anyhow::{anyhow, Result};
+use bevy::prelude::*;
+use std::{
+    sync::atomic::{AtomicBool, Ordering},
+    time::{SystemTime, UNIX_EPOCH},
+};
+
+// exit code of a scheduled restart, for the supervisor or any other wrapper to relaunch on
+pub const RESTART_EXIT_CODE: i32 = 75;
+
+// seconds before the restart at which players are warned
+const COUNTDOWN: &[f64] = &[600.0, 300.0, 120.0, 60.0, 30.0, 10.0];
+// seconds before the restart from which logins are rejected
+const LOGIN_CUTOFF: f64 = 60.0;
+// a daily restart closer than this to startup moves to the next day
+const MIN_UPTIME: f64 = 600.0;
+const DAY: f64 = 86400.0;
+
+// read by `main` once the app has exited
+static RESTARTING: AtomicBool = AtomicBool::new(false);
+
+pub fn requested() -> bool {
+    RESTARTING.load(Ordering::SeqCst)
+}
+
+// "HH:MM" in utc, as seconds into the day
+pub fn parse_time(time: &str) -> Result<f64> {
+    let invalid = || anyhow!("invalid restart time {:?}; expected HH:MM", time);
+    let mut parts = time.split(':');
+    let hours: u32 = parts
+        .next()
+        .ok_or_else(invalid)?
+        .parse()
+        .map_err(|_| invalid())?;
+    let minutes: u32 = parts
+        .next()
+        .ok_or_else(invalid)?
+        .parse()
+        .map_err(|_| invalid())?;
+    if parts.next().is_some() || hours > 23 || minutes > 59 {
+        return Err(invalid());
+    }
+    Ok((hours * 3600 + minutes * 60) as f64)
+}
+
+pub struct RestartSchedule {
+    // in `Time::seconds_since_startup`; `None` without a schedule
+    at: Option<f64>,
+    // the next entry of `COUNTDOWN` to announce
+    next_warning: usize,
+}
+
+impl RestartSchedule {
+    pub fn new(config: &ServerConfig) -> Self {
+        let every = Some(config.restart_every * 3600.0).filter(|every| *every > 0.0);
+
+        let now = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map_or(0.0, |elapsed| elapsed.as_secs_f64());
+        let today = now % DAY;
+        let daily = config
+            .restart_at
+            .iter()
+            .filter_map(|time| parse_time(time).ok())
+            .map(|time| {
+                let until = (time - today).rem_euclid(DAY);
+                if until < MIN_UPTIME {
+                    until + DAY
+                } else {
+                    until
+                }
+            })
+            .fold(None, |soonest: Option<f64>, until| {
+                Some(soonest.map_or(until, |soonest| soonest.min(until)))
+            });
+
+        let at = match (every, daily) {
+            (Some(every), Some(daily)) => Some(every.min(daily)),
+            (every, daily) => every.or(daily),
+        };
+        if let Some(at) = at {
+            info!("Restarting in {}", countdown(at));
+        }
+
+        // warnings for times already past at startup are skipped
+        let next_warning = at.map_or(COUNTDOWN.len(), |at| {
+            COUNTDOWN
+                .iter()
+                .position(|left| *left < at)
+                .unwrap_or(COUNTDOWN.len())
+        });
+
+        Self { at, next_warning }
+    }
+}
+
+fn countdown(seconds: f64) -> String {
+    let seconds = seconds.max(0.0).round() as u64;
+    if seconds >= 3600 {
+        format!(
+            "{}:{:02}:{:02}",
+            seconds / 3600,
+            seconds / 60 % 60,
+            seconds % 60
+        )
+    } else {
+        format!("{}:{:02}", seconds / 60, seconds % 60)
+    }
+}
+
+// counts down to the scheduled restart in chat, closes logins near the end and then shuts
+// down as on a signal, so the world and scores are saved; the process exits with
+// `RESTART_EXIT_CODE`
+pub fn restart_system(
+    time: Res<Time>,
+    mut schedule: ResMut<RestartSchedule>,
+    mut shutdown: ResMut<Shutdown>,
+    mut inbox: ResMut<Inbox>,
+) {
+    let at = match schedule.at {
+        Some(at) => at,
+        None => return,
+    };
+    let left = at - time.seconds_since_startup();
+
+    while let Some(warning) = COUNTDOWN.get(schedule.next_warning) {
+        if left > *warning {
+            break;
+        }
+        schedule.next_warning += 1;
+        inbox
+            .announcements
+            .push(format!("Server restarts in {}", countdown(*warning)));
+    }
+
+    if left <= LOGIN_CUTOFF && shutdown.accepting_logins() {
+        info!("Closing logins for the scheduled restart");
+        shutdown.close_logins();
+    }
+
+    if left <= 0.0 {
+        info!("Scheduled restart");
+        RESTARTING.store(true, Ordering::SeqCst);
+        shutdown.request();
+        schedule.at = None;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn times_of_day() {
+        assert_eq!(parse_time("00:00").ok(), Some(0.0));
+        assert_eq!(parse_time("07:05").ok(), Some(25500.0));
+        assert_eq!(parse_time("7:5").ok(), Some(25500.0));
+        assert_eq!(parse_time("23:59").ok(), Some(DAY - 60.0));
+    }
+
+    #[test]
+    fn out_of_range_refused() {
+        assert!(parse_time("24:00").is_err());
+        assert!(parse_time("12:60").is_err());
+        assert!(parse_time("-1:00").is_err());
+    }
+
+    #[test]
+    fn malformed_refused() {
+        for time in &["", "12", "12:", ":30", "12:30:00", "ab:cd", "12.5:00"] {
+            assert!(parse_time(time).is_err(), "{:?}", time);
+        }
+    }
+}
@@ -0,0 +1,56 @@
+use crate::{instance::Instance, leave::Leaving, Despawns, Inbox, NetworkIdAllocator};
+use agarlib::*;
+use bevy::prelude::*;
+use bevy_networking_turbulence::NetworkResource;
+
+// the agar of a player whose connection dropped, since when; it stays in the world for
+// `RESUME_GRACE` seconds for a login with its owner token to take back
+pub struct Parked(pub f64);
+
+// removes the agars nobody came back for
+pub fn parked_system(
+    commands: &mut Commands,
+    time: Res<Time>,
+    mut ids: ResMut<NetworkIdAllocator>,
+    mut despawns: ResMut<Despawns>,
+    parked: Query<(Entity, &Parked, &NetworkId, &Instance)>,
+) {
+    let now = time.seconds_since_startup();
+    for (entity, since, id, instance) in parked.iter() {
+        if now - since.0 < RESUME_GRACE {
+            continue;
+        }
+        info!("Removing agar {}; nobody came back for it", id.0);
+        ids.free(id.0);
+        despawns.ids.push((*instance, id.0));
+        commands.despawn(entity);
+    }
+}
+
+// players who logged out aren't coming back: their agars go now and so do their connections
+pub fn logout_system(mut inbox: ResMut<Inbox>, mut leaving: ResMut<Leaving>) {
+    for handle in inbox.logouts.drain(..) {
+        info!("[{}] logged out", handle);
+        leaving.drop_player(handle);
+    }
+}
+
+// the owner token of a new agar goes out once the client encrypts what it sends, and with it
+// what it's sent; at once on a server without encryption
+pub fn owner_token_system(
+    mut inbox: ResMut<Inbox>,
+    mut queue: ResMut<SendQueue>,
+    formats: Res<WireFormats>,
+    net: Res<NetworkResource>,
+) {
+    inbox.owner_tokens.retain(|(handle, owner)| {
+        if !net.connections.contains_key(handle) {
+            return false;
+        }
+        if formats.encryption && !formats.encrypted(*handle) {
+            return true;
+        }
+        queue.push_critical(*handle, ClientMessage::Owner(*owner));
+        false
+    });
+}
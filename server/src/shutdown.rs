@@ -0,0 +1,88 @@
+use agarlib::*;
+use bevy::{app::AppExit, prelude::*};
+use bevy_networking_turbulence::NetworkResource;
+use std::sync::{
+    atomic::{AtomicBool, Ordering},
+    Arc,
+};
+
+// sent on the last tick so state can be flushed before the app exits
+pub struct ServerStopping;
+
+pub struct Shutdown {
+    requested: Arc<AtomicBool>,
+    grace: f64,
+    deadline: Option<f64>,
+    // set ahead of a scheduled restart
+    logins_closed: bool,
+}
+
+impl Shutdown {
+    // SIGINT/SIGTERM start the grace period; a second one exits right away
+    pub fn install(grace: f64) -> Self {
+        let requested = Arc::new(AtomicBool::new(false));
+        let flag = requested.clone();
+
+        let handler = ctrlc::set_handler(move || {
+            if flag.swap(true, Ordering::SeqCst) {
+                std::process::exit(1);
+            }
+        });
+        if let Err(err) = handler {
+            error!("Unable to install signal handler: {}", err);
+        }
+
+        Self {
+            requested,
+            grace,
+            deadline: None,
+            logins_closed: false,
+        }
+    }
+
+    pub fn shutting_down(&self) -> bool {
+        self.deadline.is_some()
+    }
+
+    // same as a signal: the grace period starts on the next tick
+    pub fn request(&self) {
+        self.requested.store(true, Ordering::SeqCst);
+    }
+
+    pub fn close_logins(&mut self) {
+        self.logins_closed = true;
+    }
+
+    pub fn accepting_logins(&self) -> bool {
+        !self.logins_closed && !self.shutting_down()
+    }
+}
+
+pub fn shutdown_system(
+    time: Res<Time>,
+    net: Res<NetworkResource>,
+    mut shutdown: ResMut<Shutdown>,
+    mut queue: ResMut<SendQueue>,
+    mut stopping: ResMut<Events<ServerStopping>>,
+    mut exit: ResMut<Events<AppExit>>,
+) {
+    let now = time.seconds_since_startup();
+
+    match shutdown.deadline {
+        None if shutdown.requested.load(Ordering::SeqCst) => {
+            info!("Shutting down in {} seconds", shutdown.grace);
+            shutdown.deadline = Some(now + shutdown.grace);
+
+            let seconds = shutdown.grace.ceil() as u32;
+            for handle in net.connections.keys() {
+                queue.push(*handle, ClientMessage::ServerShuttingDown { seconds });
+            }
+        }
+        Some(deadline) if now >= deadline => {
+            info!("Shutting down");
+            stopping.send(ServerStopping);
+            exit.send(AppExit);
+        }
+        _ => {}
+    }
+}
@@ -0,0 +1,51 @@
+use bevy::prelude::*;
+use once_cell::sync::Lazy;
+use std::{
+    sync::Mutex,
+    time::{Duration, Instant},
+};
+use tracing::{info_span, Span};
+
+// filter on `connection{handle=3}` or `connection{player="bob"}` in `RUST_LOG`
+pub fn connection_span(handle: u32, player: Option<&str>) -> Span {
+    info_span!("connection", handle, player = player.unwrap_or(""))
+}
+
+// runs of timed systems since the last `take_timings`, from whichever thread ran them
+static TIMINGS: Lazy<Mutex<Vec<(&'static str, Duration)>>> = Lazy::new(|| Mutex::new(Vec::new()));
+
+pub fn take_timings() -> Vec<(&'static str, Duration)> {
+    TIMINGS
+        .lock()
+        .map(|mut timings| std::mem::take(&mut *timings))
+        .unwrap_or_default()
+}
+
+// reports how long a system ran when dropped; bind it for the whole system body
+pub struct SystemTimer {
+    system: &'static str,
+    start: Instant,
+}
+
+impl SystemTimer {
+    pub fn start(system: &'static str) -> Self {
+        Self {
+            system,
+            start: Instant::now(),
+        }
+    }
+}
+
+impl Drop for SystemTimer {
+    fn drop(&mut self) {
+        let elapsed = self.start.elapsed();
+        debug!(
+            system = self.system,
+            elapsed_us = elapsed.as_micros() as u64,
+            "system finished"
+        );
+        if let Ok(mut timings) = TIMINGS.lock() {
+            timings.push((self.system, elapsed));
+        }
+    }
+}
@@ -0,0 +1,153 @@
+use crate::{
+    config::{GameMode, ServerConfig},
+    shutdown::Shutdown,
+    PlayerName, Simulation,
+};
+use agarlib::*;
+use bevy::prelude::*;
+use serde::Serialize;
+use std::{
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        Arc, Mutex,
+    },
+    time::{Duration, Instant},
+};
+
+// seconds between refreshes of the published status
+const STATUS_INTERVAL: f64 = 1.0;
+const TOP_SCORES: usize = 3;
+// a game loop that hasn't ticked for this long is reported as stalled
+const STALL_TIMEOUT: Duration = Duration::from_secs(5);
+
+#[derive(Serialize)]
+struct Score<'a> {
+    name: &'a str,
+    size: f32,
+}
+
+#[derive(Serialize)]
+struct Status<'a> {
+    uptime: f64,
+    #[serde(skip_serializing_if = "str::is_empty")]
+    addr: &'a str,
+    #[serde(skip_serializing_if = "str::is_empty")]
+    region: &'a str,
+    // hex of the public half of the server's identity, for clients to pin
+    #[serde(skip_serializing_if = "str::is_empty")]
+    identity: &'a str,
+    players: usize,
+    max_players: usize,
+    mode: GameMode,
+    paused: bool,
+    top: Vec<Score<'a>>,
+}
+
+// what `GET /readyz` answers from, off the game loop
+#[derive(Default)]
+pub struct Health {
+    last_tick: Mutex<Option<Instant>>,
+    draining: AtomicBool,
+}
+
+impl Health {
+    // whether the server should get new players; `/livez` only needs the process up
+    pub fn ready(&self) -> Result<(), &'static str> {
+        let last_tick = match self.last_tick.lock() {
+            Ok(last_tick) => *last_tick,
+            Err(_) => return Err("game loop panicked"),
+        };
+        match last_tick {
+            None => Err("starting"),
+            Some(tick) if tick.elapsed() > STALL_TIMEOUT => Err("game loop stalled"),
+            // no new players ahead of a restart or while the shutdown grace period runs
+            Some(_) if self.draining.load(Ordering::Relaxed) => Err("shutting down"),
+            Some(_) => Ok(()),
+        }
+    }
+}
+
+// the latest `GET /status` body and health, shared with the http thread
+pub struct StatusBoard {
+    pub identity: String,
+    json: Arc<Mutex<String>>,
+    health: Arc<Health>,
+    next_update: f64,
+}
+
+impl StatusBoard {
+    pub fn new() -> Self {
+        Self {
+            identity: String::new(),
+            json: Arc::new(Mutex::new("{}".to_string())),
+            health: Arc::new(Health::default()),
+            next_update: 0.0,
+        }
+    }
+
+    pub fn shared(&self) -> Arc<Mutex<String>> {
+        self.json.clone()
+    }
+
+    pub fn health(&self) -> Arc<Health> {
+        self.health.clone()
+    }
+}
+
+pub fn status_system(
+    time: Res<Time>,
+    mut board: ResMut<StatusBoard>,
+    config: Res<ServerConfig>,
+    simulation: Res<Simulation>,
+    shutdown: Res<Shutdown>,
+    players: Query<(&PlayerName, &Agar), With<NetworkHandle>>,
+) {
+    if let Ok(mut last_tick) = board.health.last_tick.lock() {
+        *last_tick = Some(Instant::now());
+    }
+    board
+        .health
+        .draining
+        .store(!shutdown.accepting_logins(), Ordering::Relaxed);
+
+    let now = time.seconds_since_startup();
+    if now < board.next_update {
+        return;
+    }
+    board.next_update = now + STATUS_INTERVAL;
+
+    let mut top: Vec<_> = players
+        .iter()
+        .map(|(name, agar)| Score {
+            name: &name.0,
+            size: agar.size,
+        })
+        .collect();
+    let players = top.len();
+    top.sort_by(|a, b| {
+        b.size
+            .partial_cmp(&a.size)
+            .unwrap_or(std::cmp::Ordering::Equal)
+    });
+    top.truncate(TOP_SCORES);
+
+    let status = Status {
+        uptime: now,
+        addr: &config.public_addr,
+        region: &config.region,
+        identity: &board.identity,
+        players,
+        max_players: config.max_players,
+        mode: config.mode,
+        paused: simulation.paused,
+        top,
+    };
+    match serde_json::to_string(&status) {
+        Ok(json) => {
+            if let Ok(mut shared) = board.json.lock() {
+                *shared = json;
+            }
+        }
+        Err(err) => error!("Unable to encode status: {}", err),
+    }
+}
@@ -0,0 +1,257 @@
+use crate::{admin::Bans, shutdown::ServerStopping, PlayerName, Score};
+use agarlib::*;
+use anyhow::{Context, Result};
+use bevy::{prelude::*, tasks::IoTaskPool};
+use rusqlite::{params, Connection};
+use std::{
+    collections::{HashMap, HashSet},
+    net::IpAddr,
+    sync::{
+        mpsc::{channel, Receiver, Sender},
+        Arc, Mutex,
+    },
+    time::{SystemTime, UNIX_EPOCH},
+};
+
+const SCHEMA: &str = "
+CREATE TABLE IF NOT EXISTS scores (
+    name TEXT NOT NULL,
+    size REAL NOT NULL,
+    at INTEGER NOT NULL
+);
+CREATE INDEX IF NOT EXISTS scores_by_size ON scores (size DESC);
+CREATE TABLE IF NOT EXISTS bans (
+    addr TEXT PRIMARY KEY,
+    at INTEGER NOT NULL
+);
+CREATE TABLE IF NOT EXISTS profiles (
+    name TEXT PRIMARY KEY,
+    games INTEGER NOT NULL,
+    best REAL NOT NULL,
+    last_seen INTEGER NOT NULL
+);
+";
+
+// seconds between reloads of `HighScores`
+const REFRESH_INTERVAL: f64 = 10.0;
+const HIGH_SCORES: u32 = 10;
+
+// best sizes ever reached, refreshed from the database in the background
+#[derive(Default, Debug)]
+pub struct HighScores {
+    pub top: Vec<(String, f32)>,
+}
+
+struct Played {
+    name: String,
+    best: f32,
+}
+
+type Job = Box<dyn FnOnce(&Connection) -> rusqlite::Result<Option<HighScores>> + Send>;
+
+pub struct Storage {
+    connection: Arc<Mutex<Connection>>,
+    // results of queries, filled by the task pool and drained every tick
+    results: Mutex<Receiver<HighScores>>,
+    sender: Mutex<Sender<HighScores>>,
+    playing: HashMap<Entity, Played>,
+    saved_bans: HashSet<IpAddr>,
+    next_refresh: f64,
+}
+
+impl Storage {
+    // opening and loading the bans blocks, but only once at startup
+    pub fn open(path: &str, bans: &mut Bans) -> Result<Self> {
+        let connection = Connection::open(path).with_context(|| format!("opening {}", path))?;
+        connection
+            .execute_batch(SCHEMA)
+            .with_context(|| format!("creating tables in {}", path))?;
+
+        let addrs = {
+            let mut statement = connection.prepare("SELECT addr FROM bans")?;
+            let addrs = statement.query_map(params![], |row| row.get::<_, String>(0))?;
+            addrs.collect::<rusqlite::Result<Vec<_>>>()?
+        };
+        for addr in addrs {
+            match addr.parse() {
+                Ok(addr) => {
+                    bans.addrs.insert(addr);
+                }
+                Err(_) => warn!("Ignoring malformed ban {:?} in {}", addr, path),
+            }
+        }
+        info!("Loaded {} bans from {}", bans.addrs.len(), path);
+
+        let (sender, results) = channel();
+        Ok(Self {
+            connection: Arc::new(Mutex::new(connection)),
+            results: Mutex::new(results),
+            sender: Mutex::new(sender),
+            playing: HashMap::new(),
+            saved_bans: bans.addrs.clone(),
+            next_refresh: 0.0,
+        })
+    }
+
+    // runs `job` on the io pool; inline when `blocking`, e.g. while shutting down
+    fn run(&self, pool: &IoTaskPool, blocking: bool, job: Job) {
+        let connection = self.connection.clone();
+        let sender = match self.sender.lock() {
+            Ok(sender) => sender.clone(),
+            Err(_) => return,
+        };
+
+        let work = move || {
+            let result = match connection.lock() {
+                Ok(connection) => job(&connection),
+                Err(_) => return,
+            };
+            match result {
+                Ok(Some(scores)) => {
+                    let _ = sender.send(scores);
+                }
+                Ok(None) => {}
+                Err(err) => error!("Storage query failed: {}", err),
+            }
+        };
+
+        if blocking {
+            work();
+        } else {
+            pool.spawn(async move { work() }).detach();
+        }
+    }
+
+    fn game_over(&self, pool: &IoTaskPool, blocking: bool, played: Played) {
+        let now = unix_time();
+        info!("Saving score {:.1} of {}", played.best, played.name);
+
+        self.run(
+            pool,
+            blocking,
+            Box::new(move |connection| {
+                connection.execute(
+                    "INSERT INTO scores (name, size, at) VALUES (?1, ?2, ?3)",
+                    params![played.name, played.best as f64, now],
+                )?;
+                connection.execute(
+                    "UPDATE profiles SET best = MAX(best, ?2), last_seen = ?3 WHERE name = ?1",
+                    params![played.name, played.best as f64, now],
+                )?;
+                Ok(None)
+            }),
+        );
+    }
+}
+
+fn unix_time() -> i64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map_or(0, |elapsed| elapsed.as_secs() as i64)
+}
+
+// follows players from join to death or disconnect, and mirrors new bans
+pub fn storage_system(
+    time: Res<Time>,
+    pool: Res<IoTaskPool>,
+    mut storage: ResMut<Storage>,
+    mut scores: ResMut<HighScores>,
+    bans: Res<Bans>,
+    mut reader: Local<EventReader<ServerStopping>>,
+    stopping: Res<Events<ServerStopping>>,
+    players: Query<(Entity, &PlayerName, &Score), With<NetworkHandle>>,
+) {
+    let stopping = reader.iter(&stopping).next().is_some();
+    let now = time.seconds_since_startup();
+    let storage = &mut *storage;
+
+    if let Ok(results) = storage.results.lock() {
+        if let Some(latest) = results.try_iter().last() {
+            *scores = latest;
+        }
+    }
+
+    let mut live = HashSet::new();
+    for (entity, name, score) in players.iter() {
+        live.insert(entity);
+
+        match storage.playing.get_mut(&entity) {
+            Some(played) => played.best = score.0,
+            None => {
+                let profile = name.0.clone();
+                let seen = unix_time();
+                storage.run(
+                    &pool,
+                    false,
+                    Box::new(move |connection| {
+                        connection.execute(
+                            "INSERT INTO profiles (name, games, best, last_seen) VALUES (?1, 1, 0, ?2)
+                             ON CONFLICT(name) DO UPDATE SET games = games + 1, last_seen = ?2",
+                            params![profile, seen],
+                        )?;
+                        Ok(None)
+                    }),
+                );
+                storage.playing.insert(
+                    entity,
+                    Played {
+                        name: name.0.clone(),
+                        // from before a restart too
+                        best: score.0,
+                    },
+                );
+            }
+        }
+    }
+
+    let over: Vec<_> = storage
+        .playing
+        .keys()
+        .filter(|entity| stopping || !live.contains(*entity))
+        .copied()
+        .collect();
+    for entity in over {
+        if let Some(played) = storage.playing.remove(&entity) {
+            storage.game_over(&pool, stopping, played);
+        }
+    }
+
+    let new_bans: Vec<_> = bans
+        .addrs
+        .difference(&storage.saved_bans)
+        .copied()
+        .collect();
+    for addr in new_bans {
+        storage.saved_bans.insert(addr);
+        let at = unix_time();
+        storage.run(
+            &pool,
+            stopping,
+            Box::new(move |connection| {
+                connection.execute(
+                    "INSERT OR IGNORE INTO bans (addr, at) VALUES (?1, ?2)",
+                    params![addr.to_string(), at],
+                )?;
+                Ok(None)
+            }),
+        );
+    }
+
+    if now >= storage.next_refresh && !stopping {
+        storage.next_refresh = now + REFRESH_INTERVAL;
+        storage.run(
+            &pool,
+            false,
+            Box::new(|connection| {
+                let mut statement = connection
+                    .prepare("SELECT name, size FROM scores ORDER BY size DESC LIMIT ?1")?;
+                let top = statement
+                    .query_map(params![HIGH_SCORES], |row| {
+                        Ok((row.get::<_, String>(0)?, row.get::<_, f64>(1)? as f32))
+                    })?
+                    .collect::<rusqlite::Result<_>>()?;
+                Ok(Some(HighScores { top }))
+            }),
+        );
+    }
+}
@@ -0,0 +1,215 @@
+use crate::Inbox;
+use agarlib::*;
+use bevy::prelude::*;
+use std::collections::{HashMap, HashSet};
+
+// regions spectators get every cell of, at the full snapshot rate; players with a cell only
+// see around it
+#[derive(Default)]
+pub struct Subscriptions {
+    pub regions: HashMap<u32, Vec<Region>>,
+}
+
+impl Subscriptions {
+    // refused from players with a cell, and for regions `admit` doesn't take
+    pub fn subscribe(
+        &mut self,
+        handle: u32,
+        region: Region,
+        playing: bool,
+        rules: &GameRules,
+    ) -> bool {
+        let region = match admit(region, rules) {
+            Some(region) if !playing => region,
+            _ => return false,
+        };
+        let regions = self.regions.entry(handle).or_default();
+        if regions.contains(&region) {
+            return true;
+        }
+        if regions.len() >= MAX_SUBSCRIPTIONS {
+            regions.remove(0);
+        }
+        regions.push(region);
+        true
+    }
+
+    // the region as the client sent it; it's stored clamped to the world
+    pub fn unsubscribe(&mut self, handle: u32, region: Region, rules: &GameRules) {
+        let region = match admit(region, rules) {
+            Some(region) => region,
+            None => return,
+        };
+        if let Some(regions) = self.regions.get_mut(&handle) {
+            regions.retain(|r| *r != region);
+        }
+    }
+
+    // a spectator that respawned sees around its cell again
+    pub fn drop_players(&mut self, playing: &HashSet<u32>) {
+        self.regions.retain(|handle, _| !playing.contains(handle));
+    }
+
+    pub fn get(&self, handle: u32) -> &[Region] {
+        self.regions.get(&handle).map_or(&[][..], Vec::as_slice)
+    }
+}
+
+// the part of `region` inside the world, if it's finite and no bigger than a spectator
+// zoomed out all the way asks for
+pub fn admit(region: Region, rules: &GameRules) -> Option<Region> {
+    let Region {
+        x,
+        y,
+        width,
+        height,
+    } = region;
+    if ![x, y, width, height].iter().all(|value| value.is_finite()) || width <= 0.0 || height <= 0.0
+    {
+        return None;
+    }
+    let max = rules.max_region();
+    if width > max.x || height > max.y {
+        return None;
+    }
+
+    let (left, bottom) = (x.max(0.0), y.max(0.0));
+    let right = (x + width).min(rules.world_width);
+    let top = (y + height).min(rules.world_height);
+    if right <= left || top <= bottom {
+        return None;
+    }
+    Some(Region {
+        x: left,
+        y: bottom,
+        width: right - left,
+        height: top - bottom,
+    })
+}
+
+pub fn subscription_system(
+    mut inbox: ResMut<Inbox>,
+    mut subscriptions: ResMut<Subscriptions>,
+    rules: Res<GameRules>,
+    agars: Query<&NetworkHandle, With<Agar>>,
+) {
+    let playing: HashSet<_> = agars.iter().map(|handle| handle.id).collect();
+    subscriptions.drop_players(&playing);
+
+    for (handle, region, subscribe) in inbox.subscriptions.drain(..) {
+        if !subscribe {
+            subscriptions.unsubscribe(handle, region, &rules);
+        } else if !subscriptions.subscribe(handle, region, playing.contains(&handle), &rules) {
+            debug!("Refusing subscription of [{}] to {:?}", handle, region);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn region(x: f32, y: f32, width: f32, height: f32) -> Region {
+        Region {
+            x,
+            y,
+            width,
+            height,
+        }
+    }
+
+    fn subscribed(region: Region, playing: bool) -> Vec<Region> {
+        let mut subscriptions = Subscriptions::default();
+        subscriptions.subscribe(1, region, playing, &GameRules::default());
+        subscriptions.get(1).to_vec()
+    }
+
+    #[test]
+    fn view_inside_world_taken() {
+        let view = region(100.0, 200.0, 500.0, 400.0);
+        assert_eq!(subscribed(view, false), vec![view]);
+    }
+
+    #[test]
+    fn players_refused() {
+        assert!(subscribed(region(100.0, 200.0, 500.0, 400.0), true).is_empty());
+    }
+
+    #[test]
+    fn non_finite_refused() {
+        for value in [f32::NAN, f32::INFINITY, f32::NEG_INFINITY].iter() {
+            assert!(subscribed(region(*value, 0.0, 100.0, 100.0), false).is_empty());
+            assert!(subscribed(region(0.0, *value, 100.0, 100.0), false).is_empty());
+            assert!(subscribed(region(0.0, 0.0, *value, 100.0), false).is_empty());
+            assert!(subscribed(region(0.0, 0.0, 100.0, *value), false).is_empty());
+        }
+    }
+
+    #[test]
+    fn empty_refused() {
+        assert!(subscribed(region(0.0, 0.0, 0.0, 100.0), false).is_empty());
+        assert!(subscribed(region(0.0, 0.0, 100.0, -100.0), false).is_empty());
+    }
+
+    #[test]
+    fn oversized_refused() {
+        let max = GameRules::default().max_region();
+        assert!(subscribed(region(0.0, 0.0, max.x + 1.0, 100.0), false).is_empty());
+        assert!(subscribed(region(0.0, 0.0, 100.0, max.y + 1.0), false).is_empty());
+        assert!(subscribed(region(0.0, 0.0, 1e30, 1e30), false).is_empty());
+    }
+
+    #[test]
+    fn outside_world_refused() {
+        let rules = GameRules::default();
+        assert!(subscribed(region(-600.0, 0.0, 500.0, 500.0), false).is_empty());
+        assert!(subscribed(region(rules.world_width, 0.0, 500.0, 500.0), false).is_empty());
+    }
+
+    #[test]
+    fn clamped_to_world() {
+        let rules = GameRules::default();
+        let clamped = subscribed(
+            region(-200.0, rules.world_height - 300.0, 500.0, 500.0),
+            false,
+        );
+        assert_eq!(
+            clamped,
+            vec![region(0.0, rules.world_height - 300.0, 300.0, 300.0)]
+        );
+    }
+
+    #[test]
+    fn unsubscribe_matches_clamped() {
+        let rules = GameRules::default();
+        let view = region(-200.0, -200.0, 500.0, 500.0);
+        let mut subscriptions = Subscriptions::default();
+        assert!(subscriptions.subscribe(1, view, false, &rules));
+        subscriptions.unsubscribe(1, view, &rules);
+        assert!(subscriptions.get(1).is_empty());
+    }
+
+    #[test]
+    fn respawned_players_dropped() {
+        let rules = GameRules::default();
+        let mut subscriptions = Subscriptions::default();
+        subscriptions.subscribe(1, region(0.0, 0.0, 500.0, 500.0), false, &rules);
+        subscriptions.subscribe(2, region(0.0, 0.0, 500.0, 500.0), false, &rules);
+        subscriptions.drop_players(&[1].iter().copied().collect());
+        assert!(subscriptions.get(1).is_empty());
+        assert_eq!(subscriptions.get(2).len(), 1);
+    }
+
+    #[test]
+    fn oldest_replaced_past_limit() {
+        let rules = GameRules::default();
+        let mut subscriptions = Subscriptions::default();
+        for i in 0..=MAX_SUBSCRIPTIONS {
+            let view = region(i as f32 * 100.0, 0.0, 100.0, 100.0);
+            subscriptions.subscribe(1, view, false, &rules);
+        }
+        let regions = subscriptions.get(1);
+        assert_eq!(regions.len(), MAX_SUBSCRIPTIONS);
+        assert_eq!(regions[0], region(100.0, 0.0, 100.0, 100.0));
+    }
+}
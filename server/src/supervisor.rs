@@ -0,0 +1,174 @@
+use crate::{config::ServerConfig, restart::RESTART_EXIT_CODE};
+use bevy::prelude::*;
+use std::{
+    path::Path,
+    process::Command,
+    sync::{
+        atomic::{AtomicBool, AtomicU32, Ordering},
+        Arc,
+    },
+    thread,
+    time::{Duration, Instant},
+};
+
+const MIN_BACKOFF: Duration = Duration::from_secs(1);
+const MAX_BACKOFF: Duration = Duration::from_secs(30);
+// a server that ran this long before crashing gets restarted right away again
+const STABLE_RUN: Duration = Duration::from_secs(60);
+
+// runs the server as a child process and starts it again, from the last snapshot, when it
+// crashes; returns the exit code of the last run once it exits cleanly or on a signal
+pub fn run(config: &ServerConfig) -> i32 {
+    let stopping = Arc::new(AtomicBool::new(false));
+    let flag = stopping.clone();
+    // pid of the running child, 0 between runs
+    let child_pid = Arc::new(AtomicU32::new(0));
+    let pid = child_pid.clone();
+    let handler = ctrlc::set_handler(move || {
+        flag.store(true, Ordering::SeqCst);
+        forward_signal(pid.load(Ordering::SeqCst));
+    });
+    if let Err(err) = handler {
+        error!("Unable to install signal handler: {}", err);
+    }
+
+    let exe = match std::env::current_exe() {
+        Ok(exe) => exe,
+        Err(err) => {
+            error!("Unable to find the server executable: {}", err);
+            return 1;
+        }
+    };
+    let args: Vec<_> = std::env::args()
+        .skip(1)
+        .filter(|arg| arg != "--supervise" && arg != "--restore")
+        .collect();
+
+    let mut backoff = MIN_BACKOFF;
+    let mut restore = config.restore;
+    let mut run = 0;
+    loop {
+        let mut command = Command::new(&exe);
+        command
+            .args(&args)
+            .env("AGAR_SUPERVISE", "false")
+            .env("AGAR_RESTORE", restore.to_string());
+        // a restarted run would truncate the replay of the one that crashed
+        if !config.record.is_empty() {
+            command.env("AGAR_RECORD", rotated(&config.record, run));
+        }
+        own_process_group(&mut command);
+
+        info!("Starting server (restore: {})", restore);
+        let started = Instant::now();
+        let mut child = match command.spawn() {
+            Ok(child) => child,
+            Err(err) => {
+                error!("Unable to start the server: {}", err);
+                return 1;
+            }
+        };
+        child_pid.store(child.id(), Ordering::SeqCst);
+        let status = child.wait();
+        child_pid.store(0, Ordering::SeqCst);
+        let status = match status {
+            Ok(status) => status,
+            Err(err) => {
+                error!("Unable to wait for the server: {}", err);
+                return 1;
+            }
+        };
+
+        let stopped = stopping.load(Ordering::SeqCst);
+        if status.code() == Some(RESTART_EXIT_CODE) && !stopped {
+            info!("Scheduled restart");
+            backoff = MIN_BACKOFF;
+        } else if status.success() || stopped {
+            info!("Server exited ({})", status);
+            return status.code().unwrap_or(1);
+        } else {
+            if started.elapsed() >= STABLE_RUN {
+                backoff = MIN_BACKOFF;
+            }
+            warn!("Server died ({}); restarting in {:?}", status, backoff);
+            thread::sleep(backoff);
+            backoff = (backoff * 2).min(MAX_BACKOFF);
+        }
+
+        // pick up from the last periodic snapshot, if one was written
+        restore = !config.snapshot_path.is_empty() && Path::new(&config.snapshot_path).exists();
+        run += 1;
+    }
+}
+
+// `replay.bin` for the first run, then `replay.1.bin`, `replay.2.bin`, ...
+fn rotated(record: &str, run: u32) -> String {
+    if run == 0 {
+        return record.to_string();
+    }
+    let path = Path::new(record);
+    let stem = path
+        .file_stem()
+        .map_or_else(String::new, |stem| stem.to_string_lossy().into_owned());
+    let name = match path.extension() {
+        Some(extension) => format!("{}.{}.{}", stem, run, extension.to_string_lossy()),
+        None => format!("{}.{}", stem, run),
+    };
+    path.with_file_name(name).display().to_string()
+}
+
+// the child doesn't get the terminal's SIGINT on its own, so a single Ctrl-C doesn't reach it
+// twice (the second one skips the shutdown grace) once it is forwarded below
+#[cfg(unix)]
+fn own_process_group(command: &mut Command) {
+    use std::os::unix::process::CommandExt;
+    unsafe {
+        command.pre_exec(|| {
+            libc::setpgid(0, 0);
+            Ok(())
+        });
+    }
+}
+
+#[cfg(not(unix))]
+fn own_process_group(_command: &mut Command) {}
+
+// SIGINT and SIGTERM both start the child's shutdown grace, so either is passed on as SIGTERM
+#[cfg(unix)]
+fn forward_signal(pid: u32) {
+    if pid != 0 {
+        unsafe {
+            libc::kill(pid as libc::pid_t, libc::SIGTERM);
+        }
+    }
+}
+
+// the console sends Ctrl-C to the child as well
+#[cfg(not(unix))]
+fn forward_signal(_pid: u32) {}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn first_run_keeps_name() {
+        assert_eq!(rotated("replay.bin", 0), "replay.bin");
+    }
+
+    #[test]
+    fn later_runs_numbered() {
+        assert_eq!(rotated("replay.bin", 1), "replay.1.bin");
+        assert_eq!(rotated("replay.bin", 12), "replay.12.bin");
+        assert_eq!(rotated("replay", 2), "replay.2");
+    }
+
+    #[test]
+    fn directory_kept() {
+        assert_eq!(rotated("records/replay.bin", 3), "records/replay.3.bin");
+        assert_eq!(
+            rotated("records/replay.v2.bin", 1),
+            "records/replay.v2.1.bin"
+        );
+    }
+}
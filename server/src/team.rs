@@ -0,0 +1,79 @@
+use crate::{
+    chat::system_line,
+    config::{GameMode, ServerConfig},
+    instance::{Instance, Instances},
+};
+use agarlib::*;
+use bevy::prelude::*;
+use std::collections::HashMap;
+
+// the teams of `GameMode::Teams`, by the name players are told
+const TEAMS: [&str; 3] = ["red", "green", "blue"];
+
+// which team each connection plays for; kept across deaths and respawns, dropped with
+// the connection
+#[derive(Default)]
+pub struct Teams {
+    members: HashMap<u32, u8>,
+}
+
+impl Teams {
+    pub fn of(&self, handle: u32) -> Option<u8> {
+        self.members.get(&handle).copied()
+    }
+
+    // both are on the same team; never true outside team mode
+    pub fn allied(&self, a: u32, b: u32) -> bool {
+        matches!((self.of(a), self.of(b)), (Some(a), Some(b)) if a == b)
+    }
+
+    pub fn teammates(&self, handle: u32, instances: &Instances) -> Vec<u32> {
+        match (self.of(handle), instances.get(handle)) {
+            (Some(team), Some(instance)) => instances
+                .handles(instance)
+                .into_iter()
+                .filter(|h| self.of(*h) == Some(team))
+                .collect(),
+            _ => vec![],
+        }
+    }
+}
+
+// puts players who joined a world in team mode on its smallest team and tells them which
+pub fn team_system(
+    config: Res<ServerConfig>,
+    instances: Res<Instances>,
+    mut teams: ResMut<Teams>,
+    mut queue: ResMut<SendQueue>,
+) {
+    if config.mode != GameMode::Teams {
+        teams.members.clear();
+        return;
+    }
+
+    teams
+        .members
+        .retain(|handle, _| instances.get(*handle).is_some());
+
+    for i in 0..instances.count() {
+        let handles = instances.handles(Instance(i));
+        let mut sizes = [0; TEAMS.len()];
+        for team in handles.iter().filter_map(|h| teams.of(*h)) {
+            sizes[team as usize] += 1;
+        }
+
+        for handle in handles {
+            if teams.of(handle).is_some() {
+                continue;
+            }
+            let team = (0..TEAMS.len()).min_by_key(|t| sizes[*t]).unwrap_or(0);
+            sizes[team] += 1;
+            teams.members.insert(handle, team as u8);
+            info!("[{}] joins team {}", handle, TEAMS[team]);
+            queue.push(
+                handle,
+                system_line(format!("You are on team {}", TEAMS[team])),
+            );
+        }
+    }
+}